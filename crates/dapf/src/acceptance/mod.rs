@@ -76,6 +76,17 @@ impl DaphneMetrics for TestMetrics {
     fn agg_job_started_inc(&self) {}
     fn agg_job_completed_inc(&self) {}
     fn agg_job_put_span_retry_inc(&self) {}
+    fn inter_agg_req_observe_latency(
+        &self,
+        _: daphne::constants::DapMediaType,
+        _: Option<u16>,
+        _: std::time::Duration,
+    ) {
+    }
+    fn storage_req_observe_latency(&self, _: &str, _: std::time::Duration) {}
+    fn vdaf_prep_observe_time(&self, _: std::time::Duration) {}
+    fn coll_job_observe_latency(&self, _: std::time::Duration) {}
+    fn peer_abort_inc(&self, _: &str) {}
 }
 
 pub struct Test {
@@ -335,7 +346,7 @@ impl Test {
             leader_url: Url::parse("https://exampe.com/").unwrap(),
             helper_url: self.helper_url.clone(),
             time_precision: 3600,
-            lifetime: 60,
+            lifetime: 86400 * 14, // two weeks
             min_batch_size: reports_per_batch.try_into().unwrap(),
             query: DapQueryConfig::FixedSize {
                 max_batch_size: Some(reports_per_batch.try_into().unwrap()),
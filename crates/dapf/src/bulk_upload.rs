@@ -0,0 +1,280 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Upload many reports at once, e.g., to backfill historical data or replay test traffic.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use daphne::{client::DapClient, messages::Time, DapError, DapMeasurement};
+use futures::StreamExt;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::HttpTransport;
+
+/// A measurement read from the input file.
+#[derive(Debug, Deserialize)]
+pub struct BulkMeasurement {
+    pub measurement: DapMeasurement,
+
+    /// Time at which the measurement was taken. If not set, then the current time is used.
+    #[serde(default)]
+    pub time: Option<Time>,
+}
+
+/// Format of the input file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One JSON-formatted [`BulkMeasurement`] per line.
+    Jsonl,
+
+    /// A header line naming the columns "measurement" and, optionally, "time", followed by one
+    /// measurement per line. The measurement is either an unsigned integer or a JSON-formatted
+    /// [`DapMeasurement`].
+    Csv,
+}
+
+impl InputFormat {
+    /// Deduce the format from the file extension.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson") => Ok(Self::Jsonl),
+            Some("csv") => Ok(Self::Csv),
+            _ => Err(anyhow!(
+                "cannot deduce the format of {}: expected a .jsonl or .csv file",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Read the measurements from the input file.
+pub fn read_measurements(path: &Path, format: InputFormat) -> anyhow::Result<Vec<BulkMeasurement>> {
+    let input = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    match format {
+        InputFormat::Jsonl => parse_jsonl(&input),
+        InputFormat::Csv => parse_csv(&input),
+    }
+}
+
+fn parse_jsonl(input: &str) -> anyhow::Result<Vec<BulkMeasurement>> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("line {}: invalid measurement", i + 1))
+        })
+        .collect()
+}
+
+fn parse_csv(input: &str) -> anyhow::Result<Vec<BulkMeasurement>> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (_, header) = lines.next().context("missing CSV header")?;
+    let header = parse_csv_record(header).context("line 1")?;
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let measurement_column = column("measurement").context("missing \"measurement\" column")?;
+    let time_column = column("time");
+
+    lines
+        .map(|(i, line)| {
+            let parse = || -> anyhow::Result<BulkMeasurement> {
+                let record = parse_csv_record(line)?;
+                let field = |column: usize| {
+                    record
+                        .get(column)
+                        .map(|field| field.trim())
+                        .ok_or_else(|| anyhow!("expected {} fields", header.len()))
+                };
+
+                let measurement = field(measurement_column)?;
+                let measurement = match measurement.parse() {
+                    Ok(measurement) => DapMeasurement::U64(measurement),
+                    Err(_) => serde_json::from_str(measurement).context("invalid measurement")?,
+                };
+                let time = match time_column.map(field).transpose()? {
+                    Some("") | None => None,
+                    Some(time) => Some(time.parse().context("invalid time")?),
+                };
+                Ok(BulkMeasurement { measurement, time })
+            };
+            parse().with_context(|| format!("line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Split a CSV record into its fields. Fields may be quoted, in which case they may contain commas
+/// and escaped (i.e., doubled) quotes. Quoted fields spanning multiple lines are not supported.
+fn parse_csv_record(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quoted field"));
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Outcome of a bulk upload.
+#[derive(Debug, Default, Serialize)]
+pub struct BulkUploadSummary {
+    /// Number of reports accepted by the Leader.
+    pub accepted: usize,
+
+    /// Number of reports that could not be produced or were rejected by the Leader.
+    pub rejected: usize,
+
+    /// Number of reports that could not be uploaded after retrying.
+    pub failed: usize,
+}
+
+/// Produce reports for the measurements in parallel and upload them to the Leader, with at most
+/// `concurrency` uploads in flight at once. Reports that could not be uploaded after retrying are
+/// left in the Client's offline queue.
+pub async fn upload(
+    client: Arc<DapClient<HttpTransport>>,
+    measurements: Vec<BulkMeasurement>,
+    now: Time,
+    concurrency: usize,
+) -> anyhow::Result<BulkUploadSummary> {
+    let hpke_configs = client
+        .hpke_configs()
+        .await
+        .context("failed to fetch the Aggregators' HPKE configs")?;
+
+    // Producing reports is CPU-bound, so keep it off the async runtime's worker threads.
+    let reports = tokio::task::spawn_blocking({
+        let client = client.clone();
+        move || {
+            measurements
+                .into_par_iter()
+                .map(|m| {
+                    client.produce_report_with_hpke_configs(
+                        &hpke_configs,
+                        m.time.unwrap_or(now),
+                        m.measurement,
+                        Vec::new(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        }
+    })
+    .await
+    .context("failed to produce reports")?;
+
+    let mut summary = BulkUploadSummary::default();
+    let results = futures::stream::iter(reports)
+        .map(|report| {
+            let client = &client;
+            async move { client.upload_encoded(report?).await }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<Result<bool, DapError>>>()
+        .await;
+    for result in results {
+        match result {
+            Ok(true) => summary.accepted += 1,
+            Ok(false) => summary.failed += 1,
+            Err(e) => {
+                tracing::warn!(error = ?e, "report rejected");
+                summary.rejected += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_csv, parse_csv_record, parse_jsonl};
+    use daphne::DapMeasurement;
+
+    #[test]
+    fn jsonl() {
+        let measurements = parse_jsonl(
+            "{\"measurement\": {\"u64\": 1}, \"time\": 1700000000}\n\
+             \n\
+             {\"measurement\": {\"u32_vec\": [1, 0, 1]}}\n",
+        )
+        .unwrap();
+        assert_eq!(measurements.len(), 2);
+        assert!(matches!(
+            measurements[0].measurement,
+            DapMeasurement::U64(1)
+        ));
+        assert_eq!(measurements[0].time, Some(1_700_000_000));
+        assert!(matches!(
+            &measurements[1].measurement,
+            DapMeasurement::U32Vec(v) if *v == [1, 0, 1]
+        ));
+        assert_eq!(measurements[1].time, None);
+
+        assert!(parse_jsonl("{\"measurement\": 1}").is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let measurements = parse_csv(
+            "time,measurement\n\
+             1700000000,1\n\
+             ,\"{\"\"u32_vec\"\": [1, 0, 1]}\"\n",
+        )
+        .unwrap();
+        assert_eq!(measurements.len(), 2);
+        assert!(matches!(
+            measurements[0].measurement,
+            DapMeasurement::U64(1)
+        ));
+        assert_eq!(measurements[0].time, Some(1_700_000_000));
+        assert!(matches!(
+            &measurements[1].measurement,
+            DapMeasurement::U32Vec(v) if *v == [1, 0, 1]
+        ));
+        assert_eq!(measurements[1].time, None);
+
+        // The time column is optional.
+        let measurements = parse_csv("measurement\n0\n").unwrap();
+        assert!(matches!(
+            measurements[0].measurement,
+            DapMeasurement::U64(0)
+        ));
+        assert_eq!(measurements[0].time, None);
+
+        assert!(parse_csv("time\n1700000000\n").is_err());
+        assert!(parse_csv("time,measurement\n1700000000\n").is_err());
+    }
+
+    #[test]
+    fn csv_record() {
+        assert_eq!(parse_csv_record("a,b,,c").unwrap(), ["a", "b", "", "c"]);
+        assert_eq!(
+            parse_csv_record("\"a,b\",\"\"\"c\"\"\"").unwrap(),
+            ["a,b", "\"c\""]
+        );
+        assert!(parse_csv_record("\"a").is_err());
+    }
+}
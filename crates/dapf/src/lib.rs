@@ -2,15 +2,20 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 pub mod acceptance;
+pub mod bulk_upload;
 mod test_durations;
 pub mod test_routes;
 
-use std::{io::Cursor, path::Path};
+use std::{io::Cursor, path::Path, time::Duration};
 
 use anyhow::{anyhow, Context};
+use async_trait::async_trait;
 use daphne::{
+    client::DapHpkeConfigSigningKey,
+    fatal_error,
     messages::{decode_base64url_vec, HpkeConfigList},
-    DapVersion,
+    transport::{DapHttpMethod, DapHttpRequest, DapHttpResponse, DapHttpTransport},
+    DapError, DapVersion,
 };
 use daphne_service_utils::http_headers;
 use prio::codec::Decode;
@@ -70,6 +75,61 @@ impl HttpClientExt for Client {
     }
 }
 
+/// Read the key with which an Aggregator signs its HPKE config from its PEM-encoded certificate.
+pub fn hpke_config_signing_key(certificate_file: &Path) -> anyhow::Result<DapHpkeConfigSigningKey> {
+    let cert = std::fs::read_to_string(certificate_file).context("reading the certificate")?;
+    let (cert_pem, _bytes_read) =
+        Pem::read(Cursor::new(cert.as_bytes())).context("reading PEM certificate")?;
+    let cert = cert_pem.parse_x509().context("parsing PEM certificate")?;
+    Ok(DapHpkeConfigSigningKey::from_sec1(
+        cert.public_key().subject_public_key.data.to_vec(),
+    ))
+}
+
+/// [`DapHttpTransport`] backed by a [`reqwest::Client`].
+pub struct HttpTransport(pub Client);
+
+#[async_trait]
+impl DapHttpTransport for HttpTransport {
+    async fn send(&self, req: DapHttpRequest) -> Result<DapHttpResponse, DapError> {
+        let method = match req.method {
+            DapHttpMethod::Get => reqwest::Method::GET,
+            DapHttpMethod::Post => reqwest::Method::POST,
+            DapHttpMethod::Put => reqwest::Method::PUT,
+            DapHttpMethod::Delete => reqwest::Method::DELETE,
+        };
+        let mut builder = self.0.request(method, req.url).body(req.body);
+        for (name, value) in req.headers {
+            builder = builder.header(name, value);
+        }
+
+        let resp = builder
+            .send()
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "request failed"))?;
+        let status = resp.status().as_u16();
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to read response body"))?
+            .to_vec();
+        Ok(DapHttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
 pub fn deduce_dap_version_from_url(url: &Url) -> anyhow::Result<DapVersion> {
     url.path_segments()
         .context("no version specified in leader url")?
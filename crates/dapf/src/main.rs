@@ -5,15 +5,18 @@ use anyhow::{anyhow, Context, Result};
 use clap::{builder::PossibleValue, Parser, Subcommand, ValueEnum};
 use dapf::{
     acceptance::{load_testing, TestOptions},
-    deduce_dap_version_from_url, HttpClientExt,
+    bulk_upload::{self, InputFormat},
+    deduce_dap_version_from_url, hpke_config_signing_key, HttpClientExt, HttpTransport,
 };
 use daphne::{
+    client::DapClient,
     constants::DapMediaType,
     error::aborts::ProblemDetails,
-    hpke::{HpkeKemId, HpkeReceiverConfig},
-    messages::{Base64Encode, BatchSelector, Collection, CollectionReq, Query, TaskId},
+    hpke::{HpkeConfig, HpkeKemId, HpkeReceiverConfig},
+    messages::{Base64Encode, BatchSelector, Collection, CollectionReq, Query, TaskId, Time},
+    transport::DapRetryPolicy,
     vdaf::VdafConfig,
-    DapAggregationParam, DapMeasurement, DapVersion,
+    DapAggregationParam, DapMeasurement, DapQueryConfig, DapTaskParameters, DapVersion,
 };
 use daphne_service_utils::http_headers;
 use prio::codec::{ParameterizedDecode, ParameterizedEncode};
@@ -24,6 +27,7 @@ use std::{
     path::PathBuf,
     process::Command,
     str::FromStr,
+    sync::Arc,
     time::SystemTime,
 };
 use tracing::level_filters::LevelFilter;
@@ -141,10 +145,91 @@ enum TestAction {
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum TaskAction {
+    /// Create a task to be provisioned with the taskprov extension and write the JSON-formatted
+    /// output to stdout.
+    ///
+    /// The output includes the task ID, the value of the "dap-taskprov" header with which the task
+    /// is advertised, and the VDAF verification key initializer (hex-encoded). It also includes
+    /// the tasks to be loaded by the Leader and the Helper, in the format accepted by the task
+    /// management API, with freshly generated bearer tokens. If no Collector HPKE config is
+    /// provided, then a fresh HPKE receiver config is generated and included in the output.
+    Create {
+        /// Base URL of the Leader
+        #[clap(long, env)]
+        leader_url: Url,
+
+        /// Base URL of the Helper
+        #[clap(long, env)]
+        helper_url: Url,
+
+        /// JSON-formatted VDAF config
+        #[clap(short, long, env)]
+        vdaf_config: CliVdafConfig,
+
+        /// Query type of the task
+        #[arg(short, long, default_value = "time-interval")]
+        query: QueryType,
+
+        /// Report granularity in seconds
+        #[arg(long, default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..))]
+        time_precision: u64,
+
+        /// The smallest batch permitted for the task
+        #[arg(long, default_value_t = 10)]
+        min_batch_size: u64,
+
+        /// The largest batch permitted for the task (fixed-size only)
+        #[arg(long)]
+        max_batch_size: Option<u64>,
+
+        /// Expiration of the task (seconds since the UNIX epoch). Defaults to two weeks from now.
+        #[arg(long)]
+        expiration: Option<Time>,
+
+        /// Opaque task info (UTF-8), included in the taskprov advertisement
+        #[arg(long, default_value = "")]
+        task_info: String,
+
+        /// JSON-formatted HPKE config of the Collector
+        #[arg(long, env, value_parser = parse_hpke_config)]
+        collector_hpke_config: Option<HpkeConfig>,
+
+        /// KEM algorithm of the generated Collector HPKE receiver config, if none is provided
+        #[arg(short, long, default_value = "x25519_hkdf_sha256")]
+        kem_alg: KemAlg,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BulkInputFormat {
+    Jsonl,
+    Csv,
+}
+
+impl From<BulkInputFormat> for InputFormat {
+    fn from(format: BulkInputFormat) -> Self {
+        match format {
+            BulkInputFormat::Jsonl => Self::Jsonl,
+            BulkInputFormat::Csv => Self::Csv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum QueryType {
+    TimeInterval,
+    FixedSize,
+}
+
 #[derive(Debug, Subcommand)]
 enum Action {
     #[command(flatten)]
     Test(TestAction),
+    /// Manage DAP tasks.
+    #[command(subcommand)]
+    Task(TaskAction),
     /// Get the Aggregator's HPKE config and write the JSON-formatted output to stdout.
     GetHpkeConfig {
         #[clap(short = 'u', long, env)]
@@ -154,6 +239,9 @@ enum Action {
         certificate_file: Option<PathBuf>,
     },
     /// Upload a report to a DAP Leader using the JSON-formatted measurement provided on stdin.
+    ///
+    /// If a file is given, then a report is uploaded for each measurement in the file instead and
+    /// a JSON-formatted summary is written to stdout.
     Upload {
         /// Base URL of the Leader
         #[clap(long, env)]
@@ -174,6 +262,31 @@ enum Action {
         /// DAP task ID (base64, URL-safe encoding)
         #[arg(short, long, env, value_parser = parse_id)]
         task_id: TaskId,
+
+        /// Path to a JSONL or CSV file of measurements, each with an optional timestamp
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+
+        /// Format of the measurements file. Deduced from the file extension if not set.
+        #[arg(long, requires = "file")]
+        format: Option<BulkInputFormat>,
+
+        /// Report granularity of the task in seconds, used to truncate report timestamps
+        #[arg(
+            long,
+            requires = "file",
+            default_value_t = 3600,
+            value_parser = clap::value_parser!(u64).range(1..),
+        )]
+        time_precision: u64,
+
+        /// Maximum number of uploads in flight at once
+        #[arg(long, requires = "file", default_value_t = 16)]
+        concurrency: usize,
+
+        /// Number of attempts to upload each report before giving up
+        #[arg(long, requires = "file", default_value_t = 3)]
+        max_attempts: u32,
     },
     /// Collect an aggregate result from the DAP Leader using the JSON-formatted batch selector
     /// provided on stdin.
@@ -346,6 +459,52 @@ async fn main() -> Result<()> {
             vdaf_config,
             certificate_file,
             task_id,
+            file: Some(file),
+            format,
+            time_precision,
+            concurrency,
+            max_attempts,
+        } => {
+            let format = match format {
+                Some(format) => format.into(),
+                None => InputFormat::from_path(&file)?,
+            };
+            let measurements = bulk_upload::read_measurements(&file, format)?;
+
+            let mut client = DapClient::new(
+                HttpTransport(http_client),
+                leader_url.clone(),
+                helper_url,
+                task_id,
+                deduce_dap_version_from_url(&leader_url)?,
+                vdaf_config.into_vdaf(),
+                time_precision,
+            )
+            .with_retry_policy(DapRetryPolicy {
+                max_attempts,
+                ..Default::default()
+            });
+            if let Some(certificate_file) = certificate_file {
+                let signing_key = hpke_config_signing_key(&certificate_file)?;
+                client = client.with_signing_keys(signing_key.clone(), signing_key);
+            }
+
+            let summary =
+                bulk_upload::upload(Arc::new(client), measurements, now, concurrency).await?;
+            println!("{}", serde_json::to_string(&summary)?);
+            if summary.rejected > 0 || summary.failed > 0 {
+                return Err(anyhow!("not all reports were uploaded"));
+            }
+            Ok(())
+        }
+        Action::Upload {
+            leader_url,
+            helper_url,
+            vdaf_config,
+            certificate_file,
+            task_id,
+            file: None,
+            ..
         } => {
             // Read the measurement from stdin.
             let mut buf = String::new();
@@ -674,6 +833,95 @@ async fn main() -> Result<()> {
             .await;
             Ok(())
         }
+        Action::Task(TaskAction::Create {
+            leader_url,
+            helper_url,
+            vdaf_config,
+            query,
+            time_precision,
+            min_batch_size,
+            max_batch_size,
+            expiration,
+            task_info,
+            collector_hpke_config,
+            kem_alg,
+        }) => {
+            let query = match query {
+                QueryType::TimeInterval => {
+                    if max_batch_size.is_some() {
+                        return Err(anyhow!(
+                            "max batch size is only supported by the fixed-size query type"
+                        ));
+                    }
+                    DapQueryConfig::TimeInterval
+                }
+                QueryType::FixedSize => DapQueryConfig::FixedSize { max_batch_size },
+            };
+            let expiration = expiration.unwrap_or(now + 86400 * 14);
+            let lifetime = expiration
+                .checked_sub(now)
+                .ok_or_else(|| anyhow!("task expiration is in the past"))?;
+
+            let collector_hpke_receiver_config = match collector_hpke_config {
+                Some(..) => None,
+                None => Some(
+                    HpkeReceiverConfig::gen(rng.gen(), kem_alg.0)
+                        .with_context(|| "failed to generate HPKE receiver config")?,
+                ),
+            };
+            let collector_hpke_config = collector_hpke_config.unwrap_or_else(|| {
+                collector_hpke_receiver_config
+                    .as_ref()
+                    .unwrap()
+                    .config
+                    .clone()
+            });
+
+            let vdaf_verify_key_init = rng.gen::<[u8; 32]>();
+            let (task_config, task_id, taskprov_advertisement) = DapTaskParameters {
+                version: deduce_dap_version_from_url(&leader_url)?,
+                leader_url,
+                helper_url,
+                time_precision,
+                lifetime,
+                min_batch_size,
+                query,
+                vdaf: vdaf_config.into_vdaf(),
+            }
+            .to_config_with_taskprov(
+                task_info.into_bytes(),
+                now,
+                &vdaf_verify_key_init,
+                &collector_hpke_config,
+            )
+            .with_context(|| "failed to create task")?;
+
+            let leader_authentication_token = hex::encode(rng.gen::<[u8; 16]>());
+            let collector_authentication_token = hex::encode(rng.gen::<[u8; 16]>());
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "task_id": task_id.to_base64url(),
+                    "taskprov_advertisement": taskprov_advertisement,
+                    "vdaf_verify_key_init": hex::encode(vdaf_verify_key_init),
+                    "leader": {
+                        "task_id": task_id.to_base64url(),
+                        "config": task_config,
+                        "leader_authentication_token": leader_authentication_token,
+                        "collector_authentication_token": collector_authentication_token,
+                    },
+                    "helper": {
+                        "task_id": task_id.to_base64url(),
+                        "config": task_config,
+                        "leader_authentication_token": leader_authentication_token,
+                    },
+                    "collector_authentication_token": collector_authentication_token,
+                    "collector_hpke_receiver_config": collector_hpke_receiver_config,
+                }))
+                .with_context(|| "failed to JSON-encode the task")?
+            );
+            Ok(())
+        }
         Action::Test(TestAction::AddHpkeConfig {
             aggregator_url,
             kem_alg,
@@ -692,6 +940,10 @@ async fn main() -> Result<()> {
     }
 }
 
+fn parse_hpke_config(json: &str) -> Result<HpkeConfig> {
+    serde_json::from_str(json).context("expected JSON-formatted HPKE config")
+}
+
 fn parse_id(id_str: &str) -> Result<TaskId> {
     TaskId::try_from_base64url(id_str)
         .ok_or_else(|| anyhow!("failed to decode ID"))
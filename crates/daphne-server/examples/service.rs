@@ -1,22 +1,32 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use clap::Parser;
-use daphne_server::{router, App, StorageProxyConfig};
+use daphne_server::{
+    metrics::MetricsConfig,
+    router,
+    telemetry::{OtlpTracing, TelemetryConfig},
+    App, StorageConfig,
+};
 use daphne_service_utils::{
     config::DaphneServiceConfig, metrics::DaphnePromServiceMetrics, DapRole,
 };
 use serde::Deserialize;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 use url::Url;
 
 #[derive(Debug, Deserialize)]
 struct Config {
     service: DaphneServiceConfig,
     port: u16,
-    storage_proxy: StorageProxyConfig,
+    #[serde(flatten)]
+    storage: StorageConfig,
+    #[serde(default)]
+    metrics: MetricsConfig,
+    #[serde(default)]
+    telemetry: Option<TelemetryConfig>,
 }
 
 impl TryFrom<Args> for Config {
@@ -98,18 +108,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     // Create a new prometheus registry where metrics will be registered and measured
     let registry = prometheus::Registry::new();
     let daphne_service_metrics = DaphnePromServiceMetrics::register(&registry)?;
+    daphne_server::metrics::register_runtime_metrics(&registry)?;
+    let metrics_router = daphne_server::metrics::router(registry, &config.metrics.path);
+
+    // initialize tracing in a very default way, exporting spans if a collector is configured.
+    let otlp = config
+        .telemetry
+        .as_ref()
+        .map(OtlpTracing::new)
+        .transpose()?;
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp.as_ref().map(OtlpTracing::layer))
+        .init();
 
     let role = config.service.role;
+    let retention = config.service.retention.clone();
     // Configure the application
-    let app = App::new(config.storage_proxy, daphne_service_metrics, config.service)?;
+    let app = Arc::new(App::new(
+        config.storage,
+        daphne_service_metrics,
+        config.service,
+    )?);
+
+    // Provision the tasks declared in the configuration
+    app.provision_configured_tasks().await?;
+
+    // Periodically delete the state of expired tasks
+    if let Some(retention) = retention {
+        let app = app.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(retention.interval));
+            loop {
+                interval.tick().await;
+                match app.apply_retention_policy(retention.dry_run).await {
+                    Ok(report) => tracing::info!(?report, "applied retention policy"),
+                    Err(error) => tracing::error!(?error, "failed to apply retention policy"),
+                }
+            }
+        });
+    }
 
     // create the router that will handle the protocol's http requests
     let router = router::new(role, app);
 
-    // initialize tracing in a very default way.
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    // serve the metrics on their own port, so that they aren't exposed with the DAP API
+    let metrics_server = axum::Server::bind(&std::net::SocketAddr::new(
+        "0.0.0.0".parse().unwrap(),
+        config.metrics.port,
+    ))
+    .serve(metrics_router.into_make_service());
+    tokio::spawn(async move {
+        if let Err(error) = metrics_server.await {
+            tracing::error!(?error, "metrics server failed");
+        }
+    });
 
     // hand the router to axum for it to run
     axum::Server::bind(&std::net::SocketAddr::new(
@@ -117,7 +172,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
         config.port,
     ))
     .serve(router.into_make_service())
+    .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
     .await?;
 
+    // export the spans that haven't been exported yet
+    if let Some(otlp) = otlp {
+        tokio::task::spawn_blocking(move || otlp.flush()).await?;
+    }
+
     Ok(())
 }
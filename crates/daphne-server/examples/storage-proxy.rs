@@ -0,0 +1,69 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use daphne_server::SqliteConfig;
+use daphne_service_utils::metrics::DaphnePromServiceMetrics;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// The environment variable read when `--auth-token` is not set. It is also used by the
+/// `daphne-worker` storage proxy.
+const AUTH_TOKEN_VAR: &str = "DAPHNE_SERVER_AUTH_TOKEN";
+
+/// Native storage proxy used in e2e tests and general manual testing
+#[derive(clap::Parser)]
+struct Args {
+    /// The port to listen on.
+    #[arg(short, long, default_value_t = 4000)]
+    port: u16,
+    /// The `SQLite` database file. If not set, the state is kept in memory.
+    #[arg(short, long)]
+    database: Option<PathBuf>,
+    /// The bearer token expected from the Aggregator. Defaults to the value of the
+    /// `DAPHNE_SERVER_AUTH_TOKEN` environment variable.
+    #[arg(short, long)]
+    auth_token: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let args = Args::parse();
+    let auth_token = match args.auth_token {
+        Some(auth_token) => auth_token,
+        None => std::env::var(AUTH_TOKEN_VAR)
+            .map_err(|e| format!("--auth-token is not set and {AUTH_TOKEN_VAR}: {e}"))?,
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Create a new prometheus registry where metrics will be registered and measured
+    let registry = prometheus::Registry::new();
+    let daphne_service_metrics = DaphnePromServiceMetrics::register(&registry)?;
+    let metrics_router = daphne_server::metrics::router(registry, "/metrics");
+
+    let router = daphne_server::storage_proxy::router(
+        &SqliteConfig {
+            path: args.database,
+        },
+        auth_token.into(),
+        daphne_service_metrics,
+    )?
+    .merge(metrics_router);
+
+    axum::Server::bind(&std::net::SocketAddr::new(
+        "0.0.0.0".parse().unwrap(),
+        args.port,
+    ))
+    .serve(router.into_make_service())
+    .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await?;
+
+    Ok(())
+}
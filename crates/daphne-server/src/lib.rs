@@ -3,27 +3,40 @@
 
 use std::sync::Arc;
 
-use daphne::{auth::BearerToken, roles::leader::in_memory_leader::InMemoryLeaderState, DapError};
-use daphne_service_utils::{config::DaphneServiceConfig, metrics::DaphneServiceMetrics};
+use daphne::{
+    audit_log::{AuditLog, NoopAuditLog},
+    auth::BearerToken,
+    fatal_error,
+    roles::leader::in_memory_leader::InMemoryLeaderState,
+    DapError,
+};
+use daphne_service_utils::{
+    audit_log::JsonLinesAuditLog, config::DaphneServiceConfig, metrics::DaphneServiceMetrics,
+};
 use futures::lock::Mutex;
 use serde::{Deserialize, Serialize};
-use storage_proxy_connection::{kv, Do, Kv};
-use tokio::sync::RwLock;
+use storage::{kv, Kv, SqliteStorage, Storage};
+use storage_proxy_connection::StorageProxy;
 use url::Url;
 
+pub mod metrics;
 mod roles;
 pub mod router;
+pub mod telemetry;
+
+pub use roles::{RetentionReport, TaskRetentionReport};
+mod storage;
+pub use storage::{SqliteConfig, StorageConfig};
+pub mod storage_proxy;
 mod storage_proxy_connection;
 
 /// Entrypoint to the server implementation. This struct implements
 /// [`DapLeader`](daphne::roles::DapLeader) and [`DapHelper`](daphne::roles::DapHelper) and can be
 /// passed to the router.
 ///
-/// It depends on a cloudflare worker to do it's storage using durable objects.
-///
 /// It can be constructed from:
-/// - a `url` that points to a cloudflare worker which serves as proxy for the storage
-/// implementation.
+/// - a [`StorageConfig`], either a `url` that points to a cloudflare worker which serves as proxy
+///   for the storage implementation, or an embedded `SQLite` database.
 /// - an implementation of [`DaphneServiceMetrics`].
 /// - a [`DaphneServiceConfig`].
 ///
@@ -57,6 +70,12 @@ mod storage_proxy_connection;
 ///     report_storage_epoch_duration: 300,
 ///     report_storage_max_future_time_skew: 300,
 ///     signing_key: None,
+///     admin_token: None,
+///     tasks: Vec::new(),
+///     retention: None,
+///     audit_log: None,
+///     helper_retry: Default::default(),
+///     kv_cache: Default::default(),
 /// };
 /// let app = App::new(storage_proxy_settings, daphne_service_metrics, service_config)?;
 ///
@@ -67,16 +86,21 @@ mod storage_proxy_connection;
 /// # Ok::<(), daphne::DapError>(())
 /// ```
 pub struct App {
-    storage_proxy_config: StorageProxyConfig,
+    storage: Box<dyn Storage>,
     http: reqwest::Client,
-    cache: RwLock<kv::Cache>,
-    metrics: Box<dyn DaphneServiceMetrics>,
+    cache: tokio::sync::Mutex<kv::Cache>,
+    metrics: Arc<dyn DaphneServiceMetrics>,
+    audit_log: Box<dyn AuditLog + Send + Sync>,
     service_config: DaphneServiceConfig,
 
     /// Volatile memory for the Leader, including the work queue, pending reports, and pending
     /// colleciton requests. Note that in a production Leader, it is necessary to store this state
     /// across requsets.
     test_leader_state: Arc<Mutex<InMemoryLeaderState>>,
+
+    /// Volatile memory for the Collector role of the interop test API.
+    #[cfg(feature = "test-utils")]
+    test_collector_state: Mutex<roles::InteropCollectorState>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -97,29 +121,110 @@ impl router::DaphneService for App {
 
 impl App {
     /// Create a new configured app. See [`App`] for details.
-    pub fn new<M>(
-        storage_proxy_config: StorageProxyConfig,
+    pub fn new<S, M>(
+        storage_config: S,
         daphne_service_metrics: M,
         service_config: DaphneServiceConfig,
     ) -> Result<Self, DapError>
     where
+        S: Into<StorageConfig>,
         M: DaphneServiceMetrics + 'static,
     {
+        let metrics: Arc<dyn DaphneServiceMetrics> = Arc::new(daphne_service_metrics);
+        let storage: Box<dyn Storage> = match storage_config.into() {
+            StorageConfig::StorageProxy(config) => {
+                Box::new(StorageProxy::new(config, metrics.clone()))
+            }
+            StorageConfig::Sqlite(config) => Box::new(
+                SqliteStorage::open(&config, metrics.clone())
+                    .map_err(|e| fatal_error!(err = ?e, "failed to open sqlite database"))?,
+            ),
+        };
+        let audit_log: Box<dyn AuditLog + Send + Sync> = match &service_config.audit_log {
+            Some(config) => Box::new(
+                JsonLinesAuditLog::from_config(config)
+                    .map_err(|e| fatal_error!(err = ?e, "failed to open audit log"))?,
+            ),
+            None => Box::new(NoopAuditLog),
+        };
         Ok(Self {
-            storage_proxy_config,
+            storage,
             http: reqwest::Client::new(),
-            cache: Default::default(),
-            metrics: Box::new(daphne_service_metrics),
+            cache: tokio::sync::Mutex::new(kv::Cache::new(
+                service_config.kv_cache.clone(),
+                metrics.clone(),
+            )),
+            metrics,
+            audit_log,
             service_config,
             test_leader_state: Default::default(),
+            #[cfg(feature = "test-utils")]
+            test_collector_state: Default::default(),
         })
     }
 
-    pub(crate) fn durable(&self) -> Do<'_> {
-        Do::new(&self.storage_proxy_config, &self.http)
+    pub(crate) fn kv(&self) -> Kv<'_> {
+        Kv::new(&*self.storage, &self.cache)
     }
+}
 
-    pub(crate) fn kv(&self) -> Kv<'_> {
-        Kv::new(&self.storage_proxy_config, &self.http, &self.cache)
+#[cfg(test)]
+mod test {
+    use daphne::{hpke::HpkeKemId, DapGlobalConfig, DapVersion};
+    use daphne_service_utils::{
+        config::DaphneServiceConfig, metrics::DaphnePromServiceMetrics, DapRole,
+    };
+    use url::Url;
+
+    use crate::{App, SqliteConfig, StorageProxyConfig};
+
+    pub(crate) fn service_config(role: DapRole) -> DaphneServiceConfig {
+        DaphneServiceConfig {
+            env: "test".into(),
+            role,
+            global: DapGlobalConfig {
+                max_batch_duration: 360_000,
+                min_batch_interval_start: 259_200,
+                max_batch_interval_end: 259_200,
+                supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+                allow_taskprov: false,
+            },
+            base_url: None,
+            taskprov: None,
+            default_version: DapVersion::Draft09,
+            report_storage_epoch_duration: 300,
+            report_storage_max_future_time_skew: 300,
+            signing_key: None,
+            admin_token: None,
+            tasks: Vec::new(),
+            retention: None,
+            audit_log: None,
+            helper_retry: Default::default(),
+            kv_cache: Default::default(),
+        }
+    }
+
+    /// An [`App`] backed by an in-memory `SQLite` database.
+    pub(crate) fn sqlite_app(service_config: DaphneServiceConfig) -> App {
+        App::new(
+            SqliteConfig { path: None },
+            DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap(),
+            service_config,
+        )
+        .unwrap()
+    }
+
+    /// An [`App`] whose storage is unreachable. Useful for testing anything that happens before
+    /// storage is accessed.
+    pub(crate) fn app(service_config: DaphneServiceConfig) -> App {
+        App::new(
+            StorageProxyConfig {
+                url: Url::parse("http://storage-proxy.invalid").unwrap(),
+                auth_token: "storage-proxy-token".into(),
+            },
+            DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap(),
+            service_config,
+        )
+        .unwrap()
     }
 }
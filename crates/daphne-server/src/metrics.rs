@@ -0,0 +1,146 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Exposition of the metrics in a [`prometheus::Registry`] for scraping.
+
+use axum::{
+    body::HttpBody,
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use daphne::{fatal_error, DapError};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Encoder, IntGauge, Registry, TextEncoder,
+};
+use serde::{Deserialize, Serialize};
+
+/// Where to serve the metrics. They are served on their own port, which is not meant to be
+/// exposed publicly, rather than alongside the DAP API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Port on which to serve the metrics.
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
+
+    /// Path at which the metrics are served.
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            port: default_metrics_port(),
+            path: default_metrics_path(),
+        }
+    }
+}
+
+fn default_metrics_port() -> u16 {
+    9464
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".into()
+}
+
+/// Gauges for the tokio runtime the collector was registered from.
+struct TokioCollector {
+    handle: tokio::runtime::Handle,
+    workers: IntGauge,
+}
+
+impl TokioCollector {
+    fn new(handle: tokio::runtime::Handle) -> prometheus::Result<Self> {
+        Ok(Self {
+            handle,
+            workers: IntGauge::new("tokio_workers", "Number of tokio worker threads.")?,
+        })
+    }
+}
+
+impl Collector for TokioCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.workers.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let metrics = self.handle.metrics();
+        self.workers
+            .set(i64::try_from(metrics.num_workers()).unwrap_or(i64::MAX));
+        self.workers.collect()
+    }
+}
+
+/// Register metrics for the current process (Linux only) and for the current tokio runtime.
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime.
+pub fn register_runtime_metrics(registry: &Registry) -> Result<(), DapError> {
+    #[cfg(target_os = "linux")]
+    registry
+        .register(Box::new(
+            prometheus::process_collector::ProcessCollector::for_self(),
+        ))
+        .map_err(|e| fatal_error!(err = ?e, "failed to register process metrics"))?;
+
+    TokioCollector::new(tokio::runtime::Handle::current())
+        .and_then(|collector| registry.register(Box::new(collector)))
+        .map_err(|e| fatal_error!(err = ?e, "failed to register tokio metrics"))
+}
+
+/// A router that serves the metrics in `registry` in the text exposition format at `path`.
+pub fn router<B>(registry: Registry, path: &str) -> axum::Router<(), B>
+where
+    B: Send + HttpBody + 'static,
+{
+    axum::Router::new()
+        .route(path, get(metrics))
+        .with_state(registry)
+}
+
+async fn metrics(State(registry): State<Registry>) -> Response {
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buf) {
+        tracing::error!(error = ?e, "failed to encode metrics");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], buf).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use prometheus::{IntCounter, Registry};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serve_metrics() {
+        let registry = Registry::new();
+        let counter = IntCounter::new("test_counter", "A counter.").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.inc_by(7);
+        super::register_runtime_metrics(&registry).unwrap();
+
+        let resp = super::router::<Body>(registry, "/metrics")
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("test_counter 7"), "{body}");
+        assert!(body.contains("tokio_workers"), "{body}");
+        #[cfg(target_os = "linux")]
+        assert!(body.contains("process_resident_memory_bytes"), "{body}");
+    }
+}
@@ -0,0 +1,700 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Task management API. Unlike the interop test API, this is meant for provisioning tasks in
+//! production.
+
+use std::collections::HashSet;
+
+use daphne::{
+    audit_log::HpkeConfigAuditAction,
+    auth::BearerToken,
+    fatal_error,
+    hpke::HpkeReceiverConfig,
+    messages::{Base64Encode, TaskId, Time},
+    roles::{leader::WorkItem, DapAggregator},
+    DapError, DapTaskConfig, DapTaskConfigMethod, DapVersion,
+};
+use daphne_service_utils::{config::SecretSource, DapRole};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{
+    self,
+    kv::{self, KvPrefix},
+};
+
+/// A task to be provisioned.
+#[derive(Deserialize)]
+pub(crate) struct AdminTask {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    pub(crate) task_id: TaskId,
+    pub(crate) config: DapTaskConfig,
+    pub(crate) leader_authentication_token: BearerToken,
+
+    /// Required by the Leader and rejected by the Helper.
+    #[serde(default)]
+    pub(crate) collector_authentication_token: Option<BearerToken>,
+}
+
+/// Changes to a provisioned task. Fields that are not set are left unchanged.
+#[derive(Default, Deserialize)]
+pub(crate) struct AdminTaskUpdate {
+    #[serde(default)]
+    pub(crate) expiration: Option<Time>,
+    #[serde(default)]
+    pub(crate) leader_authentication_token: Option<BearerToken>,
+    #[serde(default)]
+    pub(crate) collector_authentication_token: Option<BearerToken>,
+}
+
+/// Status of a task.
+#[derive(Debug, Serialize)]
+pub(crate) struct AdminTaskStatus {
+    pub(crate) task_id: String,
+    pub(crate) version: DapVersion,
+    pub(crate) expiration: Time,
+    pub(crate) expired: bool,
+
+    /// Indicates whether the task was configured by draft-wang-ppm-dap-taskprov.
+    pub(crate) taskprov: bool,
+
+    /// Indicates which bearer tokens are configured for the task.
+    pub(crate) authentication_tokens: AdminTaskTokens,
+
+    /// Leader: Number of reports waiting for a collection job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pending_reports: Option<usize>,
+
+    /// Leader: Number of collection jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) collection_jobs: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AdminTaskTokens {
+    pub(crate) leader: bool,
+    pub(crate) collector: bool,
+}
+
+/// A work item in the Leader's dead-letter queue.
+#[derive(Debug, Serialize)]
+pub(crate) struct AdminDeadLetter {
+    /// Identifies the work item when replaying it.
+    pub(crate) id: u64,
+    pub(crate) kind: &'static str,
+    pub(crate) task_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) aggregation_job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) collection_job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reports: Option<usize>,
+    pub(crate) attempts: u32,
+    pub(crate) last_error: Option<String>,
+}
+
+impl AdminDeadLetter {
+    fn new(id: u64, work_item: &WorkItem) -> Self {
+        let (kind, aggregation_job_id, collection_job_id, reports) = match work_item {
+            WorkItem::AggregationJob {
+                agg_job_id,
+                reports,
+                ..
+            } => (
+                "aggregation_job",
+                Some(agg_job_id.to_base64url()),
+                None,
+                Some(reports.len()),
+            ),
+            WorkItem::CollectionJob { coll_job_id, .. } => (
+                "collection_job",
+                None,
+                Some(coll_job_id.to_base64url()),
+                None,
+            ),
+        };
+        Self {
+            id,
+            kind,
+            task_id: work_item.task_id().to_base64url(),
+            aggregation_job_id,
+            collection_job_id,
+            reports,
+            attempts: work_item.retry().attempts,
+            last_error: work_item.retry().last_error.clone(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AdminError {
+    #[error("unrecognized task")]
+    NotFound,
+    #[error("unrecognized dead letter")]
+    DeadLetterNotFound,
+    #[error("task already exists")]
+    Conflict,
+    #[error("unrecognized HPKE config")]
+    HpkeConfigNotFound,
+    #[error("HPKE config already exists")]
+    HpkeConfigConflict,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error(transparent)]
+    Fatal(#[from] DapError),
+}
+
+impl From<AdminError> for DapError {
+    fn from(e: AdminError) -> Self {
+        match e {
+            AdminError::Fatal(e) => e,
+            e => fatal_error!(err = e.to_string()),
+        }
+    }
+}
+
+fn storage_error(e: storage::Error) -> AdminError {
+    AdminError::Fatal(fatal_error!(err = ?e))
+}
+
+impl crate::App {
+    fn check_collector_token(&self, token: Option<&BearerToken>) -> Result<(), AdminError> {
+        match (self.service_config.role, token) {
+            (DapRole::Helper, Some(..)) => Err(AdminError::BadRequest(
+                "unexpected collector authentication token".into(),
+            )),
+            (DapRole::Leader, None) => Err(AdminError::BadRequest(
+                "missing collector authentication token".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_task(&self, task: &AdminTask) -> Result<(), AdminError> {
+        self.check_collector_token(task.collector_authentication_token.as_ref())?;
+        if task.config.time_precision == 0 {
+            return Err(AdminError::BadRequest("time precision is zero".into()));
+        }
+        task.config
+            .vdaf
+            .get_decoded_verify_key(task.config.vdaf_verify_key.as_ref())
+            .map_err(|_| {
+                AdminError::BadRequest("VDAF verify key does not match the VDAF".into())
+            })?;
+        Ok(())
+    }
+
+    async fn put_task_tokens(&self, task: AdminTask) -> Result<(), AdminError> {
+        self.kv()
+            .put::<kv::prefix::LeaderBearerToken>(&task.task_id, task.leader_authentication_token)
+            .await
+            .map_err(storage_error)?;
+        if let Some(token) = task.collector_authentication_token {
+            self.kv()
+                .put::<kv::prefix::CollectorBearerToken>(&task.task_id, token)
+                .await
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    /// Provision a new task. Fails if a task with the same ID already exists.
+    pub(crate) async fn admin_create_task(&self, task: AdminTask) -> Result<(), AdminError> {
+        self.validate_task(&task)?;
+
+        // Store the task config first so that we never overwrite the tokens of an existing task.
+        if self
+            .kv()
+            .put_if_not_exists::<kv::prefix::TaskConfig>(&task.task_id, task.config.clone())
+            .await
+            .map_err(storage_error)?
+            .is_some()
+        {
+            return Err(AdminError::Conflict);
+        }
+
+        self.record_known_tasks([(task.task_id, &task.config)])
+            .await?;
+        self.put_task_tokens(task).await
+    }
+
+    /// Validate the tasks listed in the service configuration and upsert them into storage. No
+    /// task is stored unless every task is valid.
+    pub async fn provision_configured_tasks(&self) -> Result<(), DapError> {
+        let mut task_ids = HashSet::new();
+        let tasks = self
+            .service_config
+            .tasks
+            .iter()
+            .map(|task| {
+                if !task_ids.insert(task.task_id) {
+                    return Err(fatal_error!(
+                        err = format!("task {} is configured more than once", task.task_id)
+                    ));
+                }
+                let read_secret = |secret: &SecretSource| {
+                    secret.read().map(BearerToken::from).map_err(|e| {
+                        fatal_error!(
+                            err = format!("failed to read secret for task {}: {e}", task.task_id)
+                        )
+                    })
+                };
+                let task = AdminTask {
+                    task_id: task.task_id,
+                    config: task.config.clone(),
+                    leader_authentication_token: read_secret(&task.leader_authentication_token)?,
+                    collector_authentication_token: task
+                        .collector_authentication_token
+                        .as_ref()
+                        .map(read_secret)
+                        .transpose()?,
+                };
+                self.validate_task(&task).map_err(|e| {
+                    fatal_error!(
+                        err = format!("invalid configuration for task {}: {e}", task.task_id)
+                    )
+                })?;
+                Ok(task)
+            })
+            .collect::<Result<Vec<_>, DapError>>()?;
+
+        self.record_known_tasks(tasks.iter().map(|task| (task.task_id, &task.config)))
+            .await?;
+        for task in tasks {
+            let task_id = task.task_id;
+            self.kv()
+                .put::<kv::prefix::TaskConfig>(&task_id, task.config.clone())
+                .await
+                .map_err(storage_error)?;
+            self.put_task_tokens(task).await?;
+            tracing::info!(%task_id, "provisioned task");
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn admin_get_task(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<DapTaskConfig, AdminError> {
+        self.kv()
+            .get_cloned::<kv::prefix::TaskConfig>(task_id)
+            .await
+            .map_err(storage_error)?
+            .ok_or(AdminError::NotFound)
+    }
+
+    /// Update the expiration and/or bearer tokens of a task.
+    pub(crate) async fn admin_update_task(
+        &self,
+        task_id: &TaskId,
+        update: AdminTaskUpdate,
+    ) -> Result<(), AdminError> {
+        // Unlike when the task is created, a missing collector token means that it is unchanged.
+        if update.collector_authentication_token.is_some() {
+            self.check_collector_token(update.collector_authentication_token.as_ref())?;
+        }
+        let mut task_config = self
+            .kv()
+            .get_cloned_uncached::<kv::prefix::TaskConfig>(task_id)
+            .await
+            .map_err(storage_error)?
+            .ok_or(AdminError::NotFound)?;
+
+        if let Some(expiration) = update.expiration {
+            task_config.expiration = expiration;
+            self.record_known_tasks([(*task_id, &task_config)]).await?;
+            self.kv()
+                .put::<kv::prefix::TaskConfig>(task_id, task_config)
+                .await
+                .map_err(storage_error)?;
+        }
+        if let Some(token) = update.leader_authentication_token {
+            self.kv()
+                .put::<kv::prefix::LeaderBearerToken>(task_id, token)
+                .await
+                .map_err(storage_error)?;
+        }
+        if let Some(token) = update.collector_authentication_token {
+            self.kv()
+                .put::<kv::prefix::CollectorBearerToken>(task_id, token)
+                .await
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    /// Delete a task and its bearer tokens. Reports and aggregate shares are left to the retention
+    /// policy.
+    pub(crate) async fn admin_delete_task(&self, task_id: &TaskId) -> Result<(), AdminError> {
+        self.admin_get_task(task_id).await?;
+
+        self.kv()
+            .delete::<kv::prefix::TaskConfig>(task_id)
+            .await
+            .map_err(storage_error)?;
+        self.kv()
+            .delete::<kv::prefix::LeaderBearerToken>(task_id)
+            .await
+            .map_err(storage_error)?;
+        self.kv()
+            .delete::<kv::prefix::CollectorBearerToken>(task_id)
+            .await
+            .map_err(storage_error)?;
+
+        Ok(self.record_known_task_deleted(task_id).await?)
+    }
+
+    /// List the tasks provisioned through this API or configured by taskprov.
+    pub(crate) async fn admin_list_tasks(&self) -> Result<Vec<TaskId>, AdminError> {
+        Ok(self
+            .known_tasks()
+            .await?
+            .into_iter()
+            .filter(|known_task| !known_task.task_config_deleted)
+            .map(|known_task| known_task.task_id)
+            .collect())
+    }
+
+    pub(crate) async fn admin_task_status(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<AdminTaskStatus, AdminError> {
+        async fn has_token<P>(app: &crate::App, task_id: &TaskId) -> Result<bool, AdminError>
+        where
+            P: KvPrefix<Key = TaskId>,
+        {
+            Ok(app
+                .kv()
+                .peek::<P, _, _>(task_id, |_| ())
+                .await
+                .map_err(storage_error)?
+                .is_some())
+        }
+
+        let task_config = self.admin_get_task(task_id).await?;
+        let (pending_reports, collection_jobs) = if self.service_config.role == DapRole::Leader {
+            let state = self.test_leader_state.lock().await;
+            (
+                Some(state.pending_reports(task_id)),
+                Some(state.collection_jobs(task_id)),
+            )
+        } else {
+            (None, None)
+        };
+
+        Ok(AdminTaskStatus {
+            task_id: task_id.to_base64url(),
+            version: task_config.version,
+            expiration: task_config.expiration,
+            expired: self.get_current_time() >= task_config.expiration,
+            taskprov: matches!(task_config.method, DapTaskConfigMethod::Taskprov { .. }),
+            authentication_tokens: AdminTaskTokens {
+                leader: has_token::<kv::prefix::LeaderBearerToken>(self, task_id).await?,
+                collector: has_token::<kv::prefix::CollectorBearerToken>(self, task_id).await?,
+            },
+            pending_reports,
+            collection_jobs,
+        })
+    }
+
+    fn check_leader(&self) -> Result<(), AdminError> {
+        if self.service_config.role == DapRole::Leader {
+            Ok(())
+        } else {
+            Err(AdminError::BadRequest(
+                "only the Leader has a dead-letter queue".into(),
+            ))
+        }
+    }
+
+    /// List the work items that the Leader gave up on.
+    pub(crate) async fn admin_list_dead_letters(&self) -> Result<Vec<AdminDeadLetter>, AdminError> {
+        self.check_leader()?;
+        Ok(self
+            .test_leader_state
+            .lock()
+            .await
+            .dead_letters()
+            .map(|(id, work_item)| AdminDeadLetter::new(id, work_item))
+            .collect())
+    }
+
+    /// Move a work item from the dead-letter queue back to the work queue.
+    pub(crate) async fn admin_replay_dead_letter(&self, id: u64) -> Result<(), AdminError> {
+        self.check_leader()?;
+        if self.test_leader_state.lock().await.replay_dead_letter(id) {
+            tracing::info!(id, "replaying dead letter");
+            Ok(())
+        } else {
+            Err(AdminError::DeadLetterNotFound)
+        }
+    }
+
+    /// Add an HPKE receiver config for the version. Fails if there is already a config with the
+    /// same ID.
+    pub(crate) async fn admin_add_hpke_config(
+        &self,
+        version: DapVersion,
+        receiver: HpkeReceiverConfig,
+    ) -> Result<(), AdminError> {
+        let mut config_list = self
+            .kv()
+            .get_cloned_uncached::<kv::prefix::HpkeReceiverConfigSet>(&version)
+            .await
+            .map_err(storage_error)?
+            .unwrap_or_default();
+        let config_id = receiver.config.id;
+        if config_list.iter().any(|r| r.config.id == config_id) {
+            return Err(AdminError::HpkeConfigConflict);
+        }
+        config_list.push(receiver);
+        self.kv()
+            .put::<kv::prefix::HpkeReceiverConfigSet>(&version, config_list)
+            .await
+            .map_err(storage_error)?;
+        self.audit_log
+            .on_hpke_config(self.host(), version, config_id, HpkeConfigAuditAction::Add);
+        Ok(())
+    }
+
+    /// Remove the version's HPKE receiver config with the given ID.
+    pub(crate) async fn admin_remove_hpke_config(
+        &self,
+        version: DapVersion,
+        config_id: u8,
+    ) -> Result<(), AdminError> {
+        let mut config_list = self
+            .kv()
+            .get_cloned_uncached::<kv::prefix::HpkeReceiverConfigSet>(&version)
+            .await
+            .map_err(storage_error)?
+            .unwrap_or_default();
+        let len = config_list.len();
+        config_list.retain(|r| r.config.id != config_id);
+        if config_list.len() == len {
+            return Err(AdminError::HpkeConfigNotFound);
+        }
+        self.kv()
+            .put::<kv::prefix::HpkeReceiverConfigSet>(&version, config_list)
+            .await
+            .map_err(storage_error)?;
+        self.audit_log.on_hpke_config(
+            self.host(),
+            version,
+            config_id,
+            HpkeConfigAuditAction::Remove,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::{
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::TaskId,
+        vdaf::{Prio3Config, VdafConfig, VdafVerifyKey},
+        DapQueryConfig, DapTaskConfig, DapVersion,
+    };
+    use daphne_service_utils::{
+        config::{AuditLogConfig, SecretSource, StaticTaskConfig},
+        DapRole,
+    };
+    use rand::{thread_rng, Rng};
+
+    use super::{AdminError, AdminTaskUpdate};
+    use crate::storage::kv;
+
+    fn static_task(role: DapRole, token_file: &std::path::Path) -> StaticTaskConfig {
+        let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+        StaticTaskConfig {
+            task_id: TaskId(thread_rng().gen()),
+            config: DapTaskConfig {
+                version: DapVersion::Draft09,
+                leader_url: "https://leader.com/".parse().unwrap(),
+                helper_url: "https://helper.org/".parse().unwrap(),
+                time_precision: 3600,
+                min_batch_size: 10,
+                query: DapQueryConfig::TimeInterval,
+                expiration: 1_700_000_000,
+                vdaf_verify_key: vdaf.gen_verify_key(),
+                vdaf,
+                collector_hpke_config: HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                    .unwrap()
+                    .config,
+                method: Default::default(),
+                privacy_pass: None,
+            },
+            leader_authentication_token: SecretSource::File(token_file.into()),
+            collector_authentication_token: (role == DapRole::Leader)
+                .then(|| SecretSource::File(token_file.into())),
+        }
+    }
+
+    #[tokio::test]
+    async fn update_keeps_collector_token() {
+        let token_file = std::env::temp_dir().join(format!(
+            "daphne-server-token-{}",
+            hex::encode(thread_rng().gen::<[u8; 8]>())
+        ));
+        std::fs::write(&token_file, "some-token\n").unwrap();
+        let mut service_config = crate::test::service_config(DapRole::Leader);
+        let task = static_task(DapRole::Leader, &token_file);
+        let task_id = task.task_id;
+        service_config.tasks = vec![task];
+        let app = crate::test::sqlite_app(service_config);
+        app.provision_configured_tasks().await.unwrap();
+        std::fs::remove_file(token_file).unwrap();
+
+        app.admin_update_task(
+            &task_id,
+            AdminTaskUpdate {
+                expiration: Some(1_800_000_000),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            app.admin_get_task(&task_id).await.unwrap().expiration,
+            1_800_000_000
+        );
+        assert!(app
+            .kv()
+            .get::<kv::prefix::CollectorBearerToken>(&task_id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn hpke_config_changes_are_audited() {
+        let audit_file = std::env::temp_dir().join(format!(
+            "daphne-server-audit-{}",
+            hex::encode(thread_rng().gen::<[u8; 8]>())
+        ));
+        let mut service_config = crate::test::service_config(DapRole::Helper);
+        service_config.audit_log = Some(AuditLogConfig::File(audit_file.clone()));
+        let app = crate::test::sqlite_app(service_config);
+        let receiver = HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap();
+
+        app.admin_add_hpke_config(DapVersion::Draft09, receiver.clone())
+            .await
+            .unwrap();
+        assert!(matches!(
+            app.admin_add_hpke_config(DapVersion::Draft09, receiver)
+                .await,
+            Err(AdminError::HpkeConfigConflict)
+        ));
+        app.admin_remove_hpke_config(DapVersion::Draft09, 7)
+            .await
+            .unwrap();
+        assert!(matches!(
+            app.admin_remove_hpke_config(DapVersion::Draft09, 7).await,
+            Err(AdminError::HpkeConfigNotFound)
+        ));
+        assert!(app
+            .kv()
+            .get::<kv::prefix::HpkeReceiverConfigSet>(&DapVersion::Draft09)
+            .await
+            .unwrap()
+            .is_some_and(|config_list| config_list.is_empty()));
+
+        let audit = std::fs::read_to_string(&audit_file).unwrap();
+        std::fs::remove_file(audit_file).unwrap();
+        let actions = audit
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|entry| entry["event"] == "hpke_config")
+            .map(|entry| entry["action"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(actions, ["add", "remove"]);
+    }
+
+    async fn provision_error(role: DapRole, tasks: Vec<StaticTaskConfig>) -> String {
+        let mut service_config = crate::test::service_config(role);
+        service_config.tasks = tasks;
+        let err = crate::test::app(service_config)
+            .provision_configured_tasks()
+            .await
+            .unwrap_err();
+        format!("{err:?}")
+    }
+
+    #[test]
+    fn static_task_from_toml() {
+        let task: StaticTaskConfig = config::Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+                task_id = "8TuT5ZjR7Zkbqrj5P5Pm7TxGnFhwa9H9QUW6mq2i3Aw"
+                leader_authentication_token = { env = "LEADER_TOKEN" }
+                collector_authentication_token = { file = "/run/secrets/collector-token" }
+
+                [config]
+                version = "v09"
+                leader_url = "https://leader.example.com/v09/"
+                helper_url = "https://helper.example.com/v09/"
+                time_precision = 3600
+                min_batch_size = 100
+                query = "time_interval"
+                vdaf = { prio3 = "count" }
+                expiration = 1735689600
+                vdaf_verify_key = { l16 = "00112233445566778899aabbccddeeff" }
+                collector_hpke_config = { id = 1, kem_id = "x25519_hkdf_sha256", kdf_id = "hkdf_sha256", aead_id = "aes128_gcm", public_key = "2b0cd3c3ed0ba3b8a3bb3ee5f3ad1c0dc5a20b58c1ac5fd2ae5e97cb1d04b15c" }
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(task.config.vdaf, VdafConfig::Prio3(Prio3Config::Count));
+        assert!(matches!(
+            task.leader_authentication_token,
+            SecretSource::Env(ref name) if name == "LEADER_TOKEN"
+        ));
+        assert!(matches!(
+            task.collector_authentication_token,
+            Some(SecretSource::File(ref path)) if path.as_os_str() == "/run/secrets/collector-token"
+        ));
+    }
+
+    #[tokio::test]
+    async fn provision_rejects_invalid_tasks() {
+        let token_file = std::env::temp_dir().join(format!(
+            "daphne-server-token-{}",
+            hex::encode(thread_rng().gen::<[u8; 8]>())
+        ));
+        std::fs::write(&token_file, "some-token\n").unwrap();
+
+        let task = static_task(DapRole::Leader, &token_file);
+        assert!(provision_error(DapRole::Leader, vec![task.clone(), task])
+            .await
+            .contains("configured more than once"));
+
+        let task = static_task(DapRole::Leader, &token_file);
+        assert!(provision_error(DapRole::Helper, vec![task])
+            .await
+            .contains("unexpected collector authentication token"));
+
+        let task = static_task(DapRole::Helper, &token_file);
+        assert!(provision_error(DapRole::Leader, vec![task])
+            .await
+            .contains("missing collector authentication token"));
+
+        let mut task = static_task(DapRole::Leader, &token_file);
+        task.config.vdaf_verify_key = VdafVerifyKey::L32([0; 32]);
+        assert!(provision_error(DapRole::Leader, vec![task])
+            .await
+            .contains("VDAF verify key does not match the VDAF"));
+
+        let mut task = static_task(DapRole::Leader, &token_file);
+        task.leader_authentication_token =
+            SecretSource::Env("DAPHNE_SERVER_TEST_UNSET_SECRET".into());
+        assert!(provision_error(DapRole::Leader, vec![task])
+            .await
+            .contains("failed to read secret"));
+
+        std::fs::remove_file(token_file).unwrap();
+    }
+}
@@ -1,31 +1,37 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use std::{borrow::Cow, future::ready, ops::Range, time::SystemTime};
+use std::{
+    borrow::Cow,
+    ops::Range,
+    time::{Instant, SystemTime},
+};
 
 use axum::async_trait;
 use daphne::{
-    audit_log::{AuditLog, NoopAuditLog},
+    audit_log::AuditLog,
     auth::{BearerToken, BearerTokenProvider},
     error::DapAbort,
     fatal_error,
     hpke::{HpkeConfig, HpkeDecrypter, HpkeProvider},
-    messages::{self, BatchId, BatchSelector, HpkeCiphertext, TaskId, Time, TransitionFailure},
+    messages::{
+        self, AggregationJobId, BatchId, BatchSelector, HpkeCiphertext, TaskId, Time,
+        TransitionFailure,
+    },
     metrics::DaphneMetrics,
     roles::{aggregator::MergeAggShareError, DapAggregator, DapReportInitializer},
     DapAggregateShare, DapAggregateSpan, DapAggregationParam, DapBatchBucket, DapError,
-    DapGlobalConfig, DapRequest, DapSender, DapTaskConfig, DapVersion, EarlyReportStateConsumed,
-    EarlyReportStateInitialized,
+    DapGlobalConfig, DapRequest, DapSender, DapTaskConfig, DapVersion, EarlyReportState,
+    EarlyReportStateConsumed, EarlyReportStateInitialized,
 };
 use daphne_service_utils::{
     auth::DaphneAuth,
-    durable_requests::bindings::{self, AggregateStoreMergeReq, AggregateStoreMergeResp},
+    durable_requests::bindings::{AggregateStoreMergeReq, AggregateStoreMergeResp},
 };
-use futures::{future::try_join_all, StreamExt, TryStreamExt};
 use mappable_rc::Marc;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 
-use crate::storage_proxy_connection::kv;
+use crate::storage::kv;
 
 #[async_trait]
 impl DapAggregator<DaphneAuth> for crate::App {
@@ -33,25 +39,43 @@ impl DapAggregator<DaphneAuth> for crate::App {
         &self,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
         agg_share_span: DapAggregateSpan<DapAggregateShare>,
     ) -> DapAggregateSpan<Result<(), MergeAggShareError>> {
-        let task_id_hex = task_id.to_hex();
-        let durable = self.durable();
-
-        futures::stream::iter(agg_share_span)
-            .map(|(bucket, (agg_share, report_metadatas))| async {
-                let result = durable
-                    .request(
-                        bindings::AggregateStore::Merge,
-                        (task_config.version, &task_id_hex, &bucket),
-                    )
-                    .encode_bincode(AggregateStoreMergeReq {
-                        contained_reports: report_metadatas.iter().map(|(id, _)| *id).collect(),
-                        agg_share_delta: agg_share,
-                    })
-                    .send::<AggregateStoreMergeResp>()
-                    .await
-                    .map_err(|e| fatal_error!(err = ?e));
+        let (reqs, buckets): (Vec<_>, Vec<_>) = agg_share_span
+            .into_iter()
+            .map(|(bucket, (agg_share, report_metadatas))| {
+                let req = AggregateStoreMergeReq {
+                    contained_reports: report_metadatas.iter().map(|(id, _)| *id).collect(),
+                    agg_share_delta: agg_share,
+                    agg_job_id: *agg_job_id,
+                };
+                ((bucket.clone(), req), (bucket, report_metadatas))
+            })
+            .unzip();
+
+        let results = match self
+            .storage
+            .aggregate_store_merge_many(task_config.version, task_id, reqs)
+            .await
+        {
+            Ok(results) => results
+                .into_iter()
+                .map(|result| result.map_err(|e| fatal_error!(err = ?e)))
+                .collect(),
+            Err(e) => {
+                let e = format!("{e:?}");
+                buckets
+                    .iter()
+                    .map(|_| Err(fatal_error!(err = e.clone())))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        buckets
+            .into_iter()
+            .zip(results)
+            .map(|((bucket, report_metadatas), result)| {
                 let result = match result {
                     Ok(AggregateStoreMergeResp::Ok) => Ok(()),
                     Ok(AggregateStoreMergeResp::AlreadyCollected) => {
@@ -64,9 +88,7 @@ impl DapAggregator<DaphneAuth> for crate::App {
                 };
                 (bucket, (result, report_metadatas))
             })
-            .buffer_unordered(usize::MAX)
             .collect()
-            .await
     }
 
     async fn get_agg_share(
@@ -77,21 +99,18 @@ impl DapAggregator<DaphneAuth> for crate::App {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
-            requests.push(
-                durable
-                    .request(
-                        bindings::AggregateStore::Get,
-                        (task_config.as_ref().version, &task_id.to_hex(), &bucket),
-                    )
-                    .send(),
-            );
-        }
-        let responses: Vec<DapAggregateShare> = try_join_all(requests)
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+
+        let buckets = task_config
+            .as_ref()
+            .batch_span_for_sel(batch_sel)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        let responses = self
+            .storage
+            .aggregate_store_get_many(task_config.as_ref().version, task_id, &buckets)
             .await
             .map_err(|e| fatal_error!(err = ?e))?;
         let mut agg_share = DapAggregateShare::default();
@@ -106,32 +125,43 @@ impl DapAggregator<DaphneAuth> for crate::App {
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
-    ) -> Result<(), DapError> {
+        request_hash: &[u8; 32],
+    ) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
-            requests.push(
-                durable
-                    .request(
-                        bindings::AggregateStore::MarkCollected,
-                        (task_config.as_ref().version, &task_id.to_hex(), &bucket),
-                    )
-                    .send::<()>(),
-            );
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+
+        let inserted = self
+            .storage
+            .collected_batches_insert(
+                task_config.as_ref().version,
+                task_id,
+                batch_sel,
+                request_hash,
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e))?;
+        if !inserted {
+            return Ok(false);
         }
 
-        try_join_all(requests)
+        let buckets = task_config
+            .as_ref()
+            .batch_span_for_sel(batch_sel)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        self.storage
+            .aggregate_store_mark_collected_many(task_config.as_ref().version, task_id, &buckets)
             .await
             .map_err(|e| fatal_error!(err = ?e))?;
-        Ok(())
+        Ok(true)
     }
 
-    type WrappedDapTaskConfig<'a> = DapTaskConfig
+    type WrappedDapTaskConfig<'a>
+        = DapTaskConfig
     where
         Self: 'a;
 
@@ -245,6 +275,7 @@ impl DapAggregator<DaphneAuth> for crate::App {
         task_config: DapTaskConfig,
     ) -> Result<(), DapError> {
         let task_id = req.task_id().map_err(DapError::Abort)?;
+        self.record_known_tasks([(*task_id, &task_config)]).await?;
 
         if self.service_config.role.is_leader() || req.taskprov.is_none() {
             self.kv()
@@ -284,48 +315,54 @@ impl DapAggregator<DaphneAuth> for crate::App {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-
-        // Check whether the request overlaps with previous requests. This is done by
-        // checking the AggregateStore and seeing whether it requests for aggregate
-        // shares that have already been marked collected.
-        let durable = self.durable();
-        Ok(
-            futures::stream::iter(task_config.batch_span_for_sel(batch_sel)?)
-                .map(|bucket| {
-                    durable
-                        .request(
-                            bindings::AggregateStore::CheckCollected,
-                            (task_config.as_ref().version, &task_id.to_hex(), &bucket),
-                        )
-                        .send()
-                })
-                .buffer_unordered(usize::MAX)
-                .try_any(ready)
-                .await
-                .map_err(|e| fatal_error!(err = ?e))?,
-        )
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+
+        let version = task_config.as_ref().version;
+
+        // Check whether the request overlaps with the batches collected for the task.
+        let overlapping = self
+            .storage
+            .collected_batches_check_overlapping(version, task_id, batch_sel)
+            .await
+            .map_err(|e| fatal_error!(err = ?e))?;
+        if overlapping {
+            return Ok(true);
+        }
+
+        // Also check whether the request asks for aggregate shares that have already been marked
+        // collected, in case they were collected before the task's batches were recorded.
+        let buckets = task_config
+            .batch_span_for_sel(batch_sel)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        Ok(self
+            .storage
+            .aggregate_store_check_collected_many(version, task_id, &buckets)
+            .await
+            .map_err(|e| fatal_error!(err = ?e))?
+            .into_iter()
+            .any(|collected| collected))
     }
 
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-
-        let agg_share: DapAggregateShare = self
-            .durable()
-            .request(
-                bindings::AggregateStore::Get,
-                (
-                    task_config.as_ref().version,
-                    &task_id.to_hex(),
-                    &DapBatchBucket::FixedSize {
-                        batch_id: *batch_id,
-                    },
-                ),
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+
+        let agg_share = self
+            .storage
+            .aggregate_store_get(
+                task_config.as_ref().version,
+                task_id,
+                &DapBatchBucket::FixedSize {
+                    batch_id: *batch_id,
+                },
             )
-            .send()
             .await
             .map_err(|e| fatal_error!(err = ?e))?;
 
@@ -337,7 +374,7 @@ impl DapAggregator<DaphneAuth> for crate::App {
     }
 
     fn audit_log(&self) -> &dyn AuditLog {
-        &NoopAuditLog
+        &*self.audit_log
     }
 
     fn host(&self) -> &str {
@@ -366,7 +403,7 @@ impl DapReportInitializer for crate::App {
         agg_param: &DapAggregationParam,
         consumed_reports: Vec<EarlyReportStateConsumed>,
     ) -> Result<Vec<EarlyReportStateInitialized>, DapError> {
-        tokio::task::spawn_blocking({
+        let initialized_reports = tokio::task::spawn_blocking({
             let vdaf_config = task_config.vdaf;
             let vdaf_verify_key = task_config.vdaf_verify_key.clone();
             let agg_param = agg_param.clone();
@@ -374,19 +411,32 @@ impl DapReportInitializer for crate::App {
                 consumed_reports
                     .into_par_iter()
                     .map(|consumed_report| {
-                        EarlyReportStateInitialized::initialize(
+                        // Only time the reports for which VDAF preparation is initialized.
+                        let start = consumed_report.is_ready().then(Instant::now);
+                        let initialized_report = EarlyReportStateInitialized::initialize(
                             is_leader,
                             &vdaf_verify_key,
                             &vdaf_config,
                             &agg_param,
                             consumed_report,
-                        )
+                        )?;
+                        Ok((initialized_report, start.map(|start| start.elapsed())))
                     })
-                    .collect::<Result<Vec<EarlyReportStateInitialized>, _>>()
+                    .collect::<Result<Vec<_>, DapError>>()
             }
         })
         .await
-        .map_err(|e| fatal_error!(err = ?e, "initialzing reports panicked"))?
+        .map_err(|e| fatal_error!(err = ?e, "initialzing reports panicked"))??;
+
+        Ok(initialized_reports
+            .into_iter()
+            .map(|(initialized_report, prep_time)| {
+                if let Some(prep_time) = prep_time {
+                    self.metrics.vdaf_prep_observe_time(prep_time);
+                }
+                initialized_report
+            })
+            .collect())
     }
 }
 
@@ -417,7 +467,9 @@ impl HpkeProvider for crate::App {
             .get_task_config_for(task_id)
             .await
             .map_err(|e| fatal_error!(err = ?e))?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?
             .version;
 
         Ok(self
@@ -444,7 +496,7 @@ impl HpkeDecrypter for crate::App {
             .get_task_config_for(task_id)
             .await?
             .as_ref()
-            .ok_or(DapAbort::UnrecognizedTask)?
+            .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?
             .version;
         self.kv()
             .peek::<kv::prefix::HpkeReceiverConfigSet, _, _>(&version, |config_list| {
@@ -462,8 +514,10 @@ impl HpkeDecrypter for crate::App {
 
 #[async_trait]
 impl BearerTokenProvider for crate::App {
-    type WrappedBearerToken<'a> = Cow<'a,  BearerToken>
-        where Self: 'a;
+    type WrappedBearerToken<'a>
+        = Cow<'a, BearerToken>
+    where
+        Self: 'a;
 
     async fn get_leader_bearer_token_for<'s>(
         &'s self,
@@ -5,70 +5,170 @@ use axum::async_trait;
 use daphne::{
     error::DapAbort,
     fatal_error,
-    messages::{AggregationJobId, TaskId},
-    roles::{DapAggregator, DapHelper},
-    DapAggregationJobState, DapError,
+    messages::{AggregationJobId, Base64Encode, TaskId},
+    roles::{AggregationJobRecord, DapAggregator, DapHelper},
+    DapError,
 };
-use daphne_service_utils::{auth::DaphneAuth, durable_requests::bindings};
-use prio::codec::Encode;
+use daphne_service_utils::auth::DaphneAuth;
 
 #[async_trait]
 impl DapHelper<DaphneAuth> for crate::App {
-    async fn put_helper_state_if_not_exists(
+    async fn put_agg_job_record_if_not_exists(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-        helper_state: &DapAggregationJobState,
+        record: &AggregationJobRecord,
     ) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-        let helper_state_hex = hex::encode(helper_state.get_encoded().map_err(DapError::encoding)?);
-        Ok(self
-            .durable()
-            .with_retry()
-            .request(
-                bindings::HelperState::PutIfNotExists,
-                (task_config.as_ref().version, task_id, agg_job_id),
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+        let record_json = serde_json::to_string(record).map_err(|e| fatal_error!(err = ?e))?;
+        self.storage
+            .helper_state_put_if_not_exists(
+                task_config.as_ref().version,
+                task_id,
+                agg_job_id,
+                record_json,
             )
-            .encode_bincode(helper_state_hex)
-            .send()
             .await
-            .map_err(|e| fatal_error!(err = ?e))?)
+            .map_err(|e| fatal_error!(err = ?e))
     }
 
-    async fn get_helper_state(
+    async fn put_agg_job_record(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-    ) -> Result<Option<DapAggregationJobState>, DapError> {
+        record: &AggregationJobRecord,
+    ) -> Result<(), DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
-        // TODO(cjpatton) Figure out if retry is safe, since the request is not actually
-        // idempotent. (It removes the helper's state from storage if it exists.)
-        let res: Option<String> = self
-            .durable()
-            .with_retry()
-            .request(
-                bindings::HelperState::Get,
-                (task_config.as_ref().version, task_id, agg_job_id),
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+        let record_json = serde_json::to_string(record).map_err(|e| fatal_error!(err = ?e))?;
+        self.storage
+            .helper_state_put(
+                task_config.as_ref().version,
+                task_id,
+                agg_job_id,
+                record_json,
             )
-            .send()
+            .await
+            .map_err(|e| fatal_error!(err = ?e))
+    }
+
+    async fn get_agg_job_record(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobRecord>, DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+        let res = self
+            .storage
+            .helper_state_get(task_config.as_ref().version, task_id, agg_job_id)
             .await
             .map_err(|e| fatal_error!(err = ?e))?;
 
-        match res {
-            Some(helper_state_hex) => {
-                let data = hex::decode(helper_state_hex)
-                    .map_err(|e| DapAbort::from_hex_error(e, *task_id))?;
-                let helper_state =
-                    DapAggregationJobState::get_decoded(&task_config.as_ref().vdaf, &data)?;
-                Ok(Some(helper_state))
-            }
-            None => Ok(None),
-        }
+        res.map(|record_json| {
+            serde_json::from_str(&record_json).map_err(|e| {
+                // Previous versions stored the hex-encoded state of the aggregation job instead.
+                // The job's response can't be replayed, so its retransmission is rejected.
+                if hex::decode(&record_json).is_ok() {
+                    DapAbort::InvalidMessage {
+                        detail: format!(
+                            "aggregation job {} was initialized by a previous version of the Helper",
+                            agg_job_id.to_base64url()
+                        ),
+                        task_id: Some(*task_id),
+                    }
+                    .into()
+                } else {
+                    fatal_error!(err = ?e, "failed to decode aggregation job record")
+                }
+            })
+        })
+        .transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::{
+        error::DapAbort,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{AggregationJobId, TaskId},
+        roles::DapHelper,
+        vdaf::{Prio3Config, VdafConfig},
+        DapError, DapQueryConfig, DapTaskConfig, DapVersion,
+    };
+    use daphne_service_utils::DapRole;
+
+    use crate::storage::kv;
+
+    #[tokio::test]
+    async fn reject_agg_job_record_of_previous_version() {
+        let app = crate::test::sqlite_app(crate::test::service_config(DapRole::Helper));
+        let task_id = TaskId([1; 32]);
+        let agg_job_id = AggregationJobId([2; 16]);
+        let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+        let task_config = DapTaskConfig {
+            version: DapVersion::Draft09,
+            leader_url: "https://leader.com/".parse().unwrap(),
+            helper_url: "https://helper.org/".parse().unwrap(),
+            time_precision: 3600,
+            min_batch_size: 10,
+            query: DapQueryConfig::TimeInterval,
+            expiration: 1_700_000_000,
+            vdaf_verify_key: vdaf.gen_verify_key(),
+            vdaf,
+            collector_hpke_config: HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config,
+            method: Default::default(),
+            privacy_pass: None,
+        };
+        app.kv()
+            .put::<kv::prefix::TaskConfig>(&task_id, task_config)
+            .await
+            .unwrap();
+
+        // Previous versions stored the hex-encoded aggregation job state.
+        app.storage
+            .helper_state_put(
+                DapVersion::Draft09,
+                &task_id,
+                &agg_job_id,
+                hex::encode(b"aggregation job state"),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            app.get_agg_job_record(&task_id, &agg_job_id).await,
+            Err(DapError::Abort(DapAbort::InvalidMessage { .. }))
+        ));
+
+        // Anything else is an internal error.
+        app.storage
+            .helper_state_put(
+                DapVersion::Draft09,
+                &task_id,
+                &agg_job_id,
+                "not a record".into(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            app.get_agg_job_record(&task_id, &agg_job_id).await,
+            Err(DapError::Fatal(..))
+        ));
     }
 }
@@ -0,0 +1,264 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Client and Collector roles of the interop test API
+//! (draft-dcook-ppm-dap-interop-test-design). These are implemented by [`DapClient`] and
+//! [`DapCollector`].
+
+use std::{collections::HashMap, sync::Arc};
+
+use dapf::HttpTransport;
+use daphne::{
+    auth::BearerToken,
+    client::DapClient,
+    collector::{DapCollectionJobHandle, DapCollectionPoll, DapCollector, DapPollPolicy},
+    fatal_error,
+    hpke::{HpkeConfig, HpkeKemId, HpkeReceiverConfig},
+    messages::{
+        decode_base64url, decode_base64url_vec, BatchId, Interval, PartialBatchSelector, Query,
+        TaskId,
+    },
+    transport::DapRetryPolicy,
+    vdaf::{Prio3Config, VdafConfig},
+    DapAggregateResult, DapAggregationParam, DapError, DapMeasurement, DapVersion,
+};
+use daphne_service_utils::test_route_types::{
+    InternalTestAddCollectorTask, InternalTestCollectionPoll, InternalTestCollectionStart,
+    InternalTestQuery, InternalTestUpload,
+};
+use prio::codec::ParameterizedDecode;
+use rand::{thread_rng, Rng};
+
+use super::test_utils::vdaf_config_from_internal_test;
+
+type InteropCollector = DapCollector<HttpTransport, BearerToken, HpkeReceiverConfig>;
+
+/// Tasks and collection jobs of the Collector.
+#[derive(Default)]
+pub(crate) struct InteropCollectorState {
+    tasks: HashMap<TaskId, Arc<InteropCollector>>,
+    jobs: HashMap<String, (Arc<InteropCollector>, DapCollectionJobHandle)>,
+}
+
+/// Result of a completed collection job.
+pub(crate) struct InteropCollection {
+    pub(crate) report_count: u64,
+    pub(crate) interval: Interval,
+    pub(crate) batch_id: Option<BatchId>,
+    pub(crate) result: DapAggregateResult,
+}
+
+fn u128_from_json(value: &serde_json::Value) -> Result<u128, DapError> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64().map(u128::from),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| fatal_error!(err = "command failed: invalid measurement"))
+}
+
+fn measurement_from_json(
+    vdaf: &VdafConfig,
+    value: &serde_json::Value,
+) -> Result<DapMeasurement, DapError> {
+    match vdaf {
+        VdafConfig::Prio3(
+            Prio3Config::Count | Prio3Config::Sum { .. } | Prio3Config::Histogram { .. },
+        ) => Ok(DapMeasurement::U64(
+            u128_from_json(value)?
+                .try_into()
+                .map_err(|e| fatal_error!(err = ?e))?,
+        )),
+        VdafConfig::Prio3(Prio3Config::SumVec { .. }) => Ok(DapMeasurement::U128Vec(
+            value
+                .as_array()
+                .ok_or_else(|| fatal_error!(err = "command failed: invalid measurement"))?
+                .iter()
+                .map(u128_from_json)
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Err(fatal_error!(err = "command failed: unrecognized VDAF")),
+    }
+}
+
+/// Encode the aggregate result as specified by the interop test API, i.e., with numbers encoded
+/// as strings.
+pub(crate) fn aggregate_result_to_json(result: &DapAggregateResult) -> serde_json::Value {
+    fn vec<T: ToString>(v: &[T]) -> serde_json::Value {
+        v.iter().map(ToString::to_string).collect()
+    }
+    match result {
+        DapAggregateResult::U64(x) => x.to_string().into(),
+        DapAggregateResult::U128(x) => x.to_string().into(),
+        DapAggregateResult::U32Vec(v) => vec(v),
+        DapAggregateResult::U64Vec(v) => vec(v),
+        DapAggregateResult::U128Vec(v) => vec(v),
+    }
+}
+
+fn query_from_internal_test(query: &InternalTestQuery) -> Result<Query, DapError> {
+    match (query.typ, query.subtype) {
+        (1, _) => Ok(Query::TimeInterval {
+            batch_interval: Interval {
+                start: query
+                    .batch_interval_start
+                    .ok_or_else(|| fatal_error!(err = "command failed: missing batch interval"))?,
+                duration: query
+                    .batch_interval_duration
+                    .ok_or_else(|| fatal_error!(err = "command failed: missing batch interval"))?,
+            },
+        }),
+        (2, Some(0)) => Ok(Query::FixedSizeByBatchId {
+            batch_id: query
+                .batch_id
+                .as_ref()
+                .and_then(decode_base64url)
+                .map(BatchId)
+                .ok_or_else(|| fatal_error!(err = "command failed: invalid batch ID"))?,
+        }),
+        (2, Some(1)) => Ok(Query::FixedSizeCurrentBatch),
+        _ => Err(fatal_error!(
+            err = "command failed: unrecognized query type"
+        )),
+    }
+}
+
+impl crate::App {
+    /// Client: Upload a report for the given measurement.
+    pub(crate) async fn internal_upload(
+        &self,
+        version: DapVersion,
+        cmd: InternalTestUpload,
+    ) -> Result<(), DapError> {
+        let vdaf = vdaf_config_from_internal_test(&cmd.vdaf)?;
+        let measurement = measurement_from_json(&vdaf, &cmd.measurement)?;
+        let client = DapClient::new(
+            HttpTransport(self.http.clone()),
+            cmd.leader,
+            cmd.helper,
+            cmd.task_id,
+            version,
+            vdaf,
+            cmd.time_precision,
+        )
+        .with_retry_policy(DapRetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        });
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| fatal_error!(err = ?e))?
+            .as_secs();
+        if !client.upload(cmd.time.unwrap_or(now), measurement).await? {
+            return Err(fatal_error!(
+                err = "command failed: Leader did not accept report"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Collector: Add a task and return the Collector's HPKE config for it.
+    pub(crate) async fn internal_add_collector_task(
+        &self,
+        version: DapVersion,
+        cmd: InternalTestAddCollectorTask,
+    ) -> Result<HpkeConfig, DapError> {
+        if !matches!(cmd.query_type, 1 | 2) {
+            return Err(fatal_error!(
+                err = "command failed: unrecognized query type"
+            ));
+        }
+        let decrypter = HpkeReceiverConfig::gen(thread_rng().gen(), HpkeKemId::X25519HkdfSha256)?;
+        let collector_hpke_config = decrypter.config.clone();
+        let collector = InteropCollector {
+            transport: HttpTransport(self.http.clone()),
+            auth: BearerToken::from(cmd.collector_authentication_token),
+            decrypter,
+            leader_url: cmd.leader,
+            task_id: cmd.task_id,
+            version,
+            vdaf: vdaf_config_from_internal_test(&cmd.vdaf)?,
+            min_batch_size: cmd.min_batch_size.unwrap_or(1),
+            poll_policy: DapPollPolicy::default(),
+        };
+
+        let mut state = self.test_collector_state.lock().await;
+        if state.tasks.contains_key(&cmd.task_id) {
+            return Err(fatal_error!(
+                err = format!(
+                    "command failed: config already exists for the given task ({})",
+                    cmd.task_id
+                )
+            ));
+        }
+        state.tasks.insert(cmd.task_id, Arc::new(collector));
+        Ok(collector_hpke_config)
+    }
+
+    /// Collector: Start a collection job and return its handle.
+    pub(crate) async fn internal_collection_start(
+        &self,
+        cmd: InternalTestCollectionStart,
+    ) -> Result<String, DapError> {
+        let collector = self
+            .test_collector_state
+            .lock()
+            .await
+            .tasks
+            .get(&cmd.task_id)
+            .cloned()
+            .ok_or_else(|| fatal_error!(err = "command failed: unrecognized task"))?;
+        let agg_param = DapAggregationParam::get_decoded_with_param(
+            &collector.vdaf,
+            &decode_base64url_vec(cmd.agg_param.as_bytes()).ok_or_else(|| {
+                fatal_error!(err = "command failed: agg param is not valid URL-safe base64")
+            })?,
+        )
+        .map_err(|e| fatal_error!(err = ?e))?;
+
+        let job = collector
+            .start(query_from_internal_test(&cmd.query)?, agg_param)
+            .await?;
+        let handle = daphne::messages::Base64Encode::to_base64url(&job.coll_job_id);
+        self.test_collector_state
+            .lock()
+            .await
+            .jobs
+            .insert(handle.clone(), (collector, job));
+        Ok(handle)
+    }
+
+    /// Collector: Poll a collection job. Returns `None` if the job is not yet complete.
+    pub(crate) async fn internal_collection_poll(
+        &self,
+        cmd: InternalTestCollectionPoll,
+    ) -> Result<Option<InteropCollection>, DapError> {
+        let (collector, job) = self
+            .test_collector_state
+            .lock()
+            .await
+            .jobs
+            .get(&cmd.handle)
+            .cloned()
+            .ok_or_else(|| fatal_error!(err = "command failed: unrecognized handle"))?;
+
+        let collection = match collector.poll(&job).await? {
+            DapCollectionPoll::Done(collection) => collection,
+            DapCollectionPoll::Pending { .. } => return Ok(None),
+        };
+        let report_count = collection.report_count;
+        let interval = collection.interval;
+        let batch_id = match collection.part_batch_sel {
+            PartialBatchSelector::TimeInterval => None,
+            PartialBatchSelector::FixedSizeByBatchId { batch_id } => Some(batch_id),
+        };
+        let result = collector.consume(&job, collection).await?;
+        Ok(Some(InteropCollection {
+            report_count,
+            interval,
+            batch_id,
+            result,
+        }))
+    }
+}
@@ -3,23 +3,27 @@
 
 #![allow(unused_variables)]
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use axum::{async_trait, http::Method};
 use daphne::{
     auth::BearerTokenProvider,
     constants::DapMediaType,
-    error::DapAbort,
+    error::{DapAbort, PeerAbort},
     fatal_error,
-    messages::{BatchId, BatchSelector, Collection, CollectionJobId, Report, TaskId},
+    messages::{
+        BatchId, BatchSelector, Collection, CollectionJobId, PartialBatchSelector, Report, TaskId,
+    },
+    privacy_pass::PrivacyPassToken,
     roles::{leader::WorkItem, DapAggregator, DapAuthorizedSender, DapLeader},
     DapAggregationParam, DapCollectionJob, DapError, DapRequest, DapResponse, DapTaskConfig,
 };
-use daphne_service_utils::{auth::DaphneAuth, http_headers};
-use tracing::{error, info};
+use daphne_service_utils::{auth::DaphneAuth, config::HelperRetryConfig, http_headers};
+use rand::{thread_rng, Rng};
+use tracing::{error, info, warn};
 use url::Url;
 
-use crate::storage_proxy_connection::method_http_1_0_to_reqwest_0_11;
+use crate::{storage::kv, storage_proxy_connection::method_http_1_0_to_reqwest_0_11};
 
 #[async_trait]
 impl DapAuthorizedSender<DaphneAuth> for crate::App {
@@ -45,23 +49,67 @@ impl DapAuthorizedSender<DaphneAuth> for crate::App {
 
 #[async_trait]
 impl DapLeader<DaphneAuth> for crate::App {
-    async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError> {
+    async fn put_report(
+        &self,
+        report: &Report,
+        task_id: &TaskId,
+        privacy_pass_token: Option<&PrivacyPassToken>,
+    ) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapAbort::UnrecognizedTask)?;
+            .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
 
-        self.test_leader_state
-            .lock()
+        let Some(token) = privacy_pass_token else {
+            return self.test_leader_state.lock().await.put_report(
+                task_id,
+                &task_config,
+                report.clone(),
+                None,
+            );
+        };
+
+        // Redemptions are stored persistently, unlike the reports. Redeem the token first so that
+        // concurrent uploads can't spend it twice, then undo the redemption if the report is
+        // rejected.
+        let nonce = kv::prefix::PrivacyPassNonce {
+            task_id: *task_id,
+            nonce: token.nonce,
+        };
+        if self
+            .kv()
+            .put_if_not_exists::<kv::prefix::PrivacyPassRedemption>(&nonce, self.get_current_time())
             .await
-            .put_report(task_id, &task_config, report.clone())
+            .map_err(|e| fatal_error!(err = ?e))?
+            .is_some()
+        {
+            return Ok(false);
+        }
+        let result = self.test_leader_state.lock().await.put_report(
+            task_id,
+            &task_config,
+            report.clone(),
+            None,
+        );
+        if result.is_err() {
+            if let Err(e) = self
+                .kv()
+                .delete::<kv::prefix::PrivacyPassRedemption>(&nonce)
+                .await
+            {
+                error!("failed to undo the redemption of a Privacy Pass token: {e:?}");
+            }
+        }
+        result
     }
 
     async fn current_batch(&self, task_id: &TaskId) -> Result<BatchId, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
 
         self.test_leader_state
             .lock()
@@ -69,6 +117,30 @@ impl DapLeader<DaphneAuth> for crate::App {
             .current_batch(task_id, &task_config)
     }
 
+    async fn finish_agg_job(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        aggregated: u64,
+        rejected: u64,
+    ) -> Result<(), DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
+
+        self.test_leader_state.lock().await.finish_agg_job(
+            task_id,
+            &task_config,
+            part_batch_sel,
+            aggregated,
+            rejected,
+        );
+        Ok(())
+    }
+
     async fn init_collect_job(
         &self,
         task_id: &TaskId,
@@ -79,14 +151,16 @@ impl DapLeader<DaphneAuth> for crate::App {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapAbort::UnrecognizedTask)?;
+            .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
 
+        let now = self.get_current_time();
         self.test_leader_state.lock().await.init_collect_job(
             task_id,
             &task_config,
             coll_job_id,
             batch_sel,
             agg_param,
+            now,
         )
     }
 
@@ -113,6 +187,18 @@ impl DapLeader<DaphneAuth> for crate::App {
             .finish_collect_job(task_id, coll_job_id, collection)
     }
 
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        failure: &PeerAbort,
+    ) -> Result<(), DapError> {
+        self.test_leader_state
+            .lock()
+            .await
+            .fail_collect_job(task_id, coll_job_id, failure)
+    }
+
     async fn dequeue_work(&self, num_items: usize) -> Result<Vec<WorkItem>, DapError> {
         self.test_leader_state.lock().await.dequeue_work(num_items)
     }
@@ -121,6 +207,23 @@ impl DapLeader<DaphneAuth> for crate::App {
         self.test_leader_state.lock().await.enqueue_work(items)
     }
 
+    async fn put_dead_letters(&self, items: Vec<WorkItem>) -> Result<(), DapError> {
+        self.test_leader_state.lock().await.put_dead_letters(items);
+        Ok(())
+    }
+
+    async fn has_failed_agg_jobs(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError> {
+        Ok(self
+            .test_leader_state
+            .lock()
+            .await
+            .has_failed_agg_jobs(task_id, batch_sel))
+    }
+
     async fn send_http_post(
         &self,
         req: DapRequest<DaphneAuth>,
@@ -149,8 +252,8 @@ impl crate::App {
 
         let method = method_http_1_0_to_reqwest_0_11(method);
 
-        let content_type = req
-            .media_type
+        let req_media_type = req.media_type;
+        let content_type = req_media_type
             .and_then(|mt| mt.as_str_for_version(req.version))
             .ok_or_else(|| {
                 fatal_error!(
@@ -188,22 +291,55 @@ impl crate::App {
                 )?,
             );
         }
+        headers.extend(crate::telemetry::trace_context_headers());
 
-        let req_builder = self
-            .http
-            .request(method, url.clone())
-            .body(req.payload)
-            .headers(headers);
+        // Retransmissions are identical to the first attempt, so that the Helper can recognize
+        // them by the aggregation job ID.
+        let retry = &self.service_config.helper_retry;
+        let attempt_timeout = Duration::from_millis(retry.attempt_timeout_ms);
+        let mut attempt = 1;
+        let (reqwest_resp, latency) = loop {
+            let start = Instant::now();
+            let reqwest_resp = self
+                .http
+                .request(method.clone(), url.clone())
+                .body(req.payload.clone())
+                .headers(headers.clone())
+                .timeout(attempt_timeout)
+                .send()
+                .await;
+            let latency = start.elapsed();
+            let status = reqwest_resp
+                .as_ref()
+                .ok()
+                .map(|resp| resp.status().as_u16());
+            if let Some(req_media_type) = req_media_type {
+                self.metrics
+                    .inter_agg_req_observe_latency(req_media_type, status, latency);
+            }
 
-        let start = Instant::now();
-        let reqwest_resp = req_builder
-            .send()
-            .await
-            .map_err(|e| fatal_error!(err = ?e))?;
-        info!("request to {} completed in {:?}", url, start.elapsed());
+            let retryable = match status {
+                Some(status) => retry.retryable_statuses.contains(&status),
+                None => true,
+            };
+            if !retryable || attempt >= retry.max_attempts {
+                break (reqwest_resp, latency);
+            }
+            let delay = retry_backoff(retry, attempt);
+            warn!(
+                attempt,
+                ?status,
+                error = reqwest_resp.err().map(tracing::field::display),
+                ?delay,
+                "{url}: request failed, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        };
+        let reqwest_resp = reqwest_resp.map_err(|e| fatal_error!(err = ?e))?;
+        info!("request to {} completed in {:?}", url, latency);
         let status = reqwest_resp.status();
 
-        const INT_ERR_PEER_ABORT: &str = "request aborted by peer";
         const INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE: &str = "peer response is missing media type";
 
         if status.is_success() {
@@ -228,23 +364,225 @@ impl crate::App {
                 media_type,
             })
         } else {
-            error!("{}: request failed: {:?}", url, reqwest_resp);
-            if status == 400 {
-                if let Some(content_type) =
-                    reqwest_resp.headers().get(reqwest::header::CONTENT_TYPE)
-                {
-                    if content_type == "application/problem+json" {
-                        error!(
-                            "Problem details: {}",
-                            reqwest_resp
-                                .text()
-                                .await
-                                .map_err(|e| fatal_error!(err = ?e))?
-                        );
-                    }
-                }
-            }
-            Err(fatal_error!(err = INT_ERR_PEER_ABORT))
+            let content_type = reqwest_resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .map(ToString::to_string);
+            let body = reqwest_resp
+                .bytes()
+                .await
+                .map_err(|e| fatal_error!(err = ?e))?;
+            let peer = PeerAbort::from_response(
+                status.as_u16(),
+                content_type.as_deref(),
+                &body,
+                &retry.retryable_statuses,
+            );
+            error!(%peer, "{url}: request failed");
+            Err(DapError::Peer(peer))
+        }
+    }
+}
+
+/// The delay before retrying a request for the given attempt: exponential backoff, capped at the
+/// maximum, with a random jitter of up to half the delay.
+fn retry_backoff(retry: &HelperRetryConfig, attempt: u32) -> Duration {
+    let backoff_ms = retry
+        .initial_backoff_ms
+        .saturating_mul(1 << attempt.saturating_sub(1).min(32))
+        .min(retry.max_backoff_ms);
+    let jitter_ms = thread_rng().gen_range(0..=backoff_ms / 2);
+    Duration::from_millis(backoff_ms - jitter_ms)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, TcpListener},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::{OriginalUri, State},
+        http::{header::CONTENT_TYPE, StatusCode},
+        response::IntoResponse,
+        routing::put,
+    };
+    use daphne::{
+        constants::DapMediaType,
+        messages::{AggregationJobId, Base64Encode, TaskId},
+        roles::DapLeader,
+        DapError, DapRequest, DapResource, DapVersion,
+    };
+    use daphne_service_utils::{config::HelperRetryConfig, DapRole};
+    use tokio::sync::mpsc;
+
+    use super::retry_backoff;
+
+    #[test]
+    fn backoff_is_capped_and_jittered() {
+        let retry = HelperRetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            attempt_timeout_ms: 1_000,
+            retryable_statuses: Vec::new(),
+        };
+        for (attempt, backoff_ms) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1_000),
+            (64, 1_000),
+        ] {
+            let delay = retry_backoff(&retry, attempt).as_millis();
+            assert!(
+                (backoff_ms / 2..=backoff_ms).contains(&delay),
+                "attempt {attempt}: {delay}ms"
+            );
+        }
+    }
+
+    /// Start a stand-in for the Helper that responds to the first `failures` requests with the
+    /// given status, after the given delay, and reports the path and body of each request.
+    fn helper(
+        failures: usize,
+        status: StatusCode,
+        delay: Duration,
+    ) -> (url::Url, mpsc::UnboundedReceiver<(String, Bytes)>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new()
+            .route(
+                "/v09/tasks/:task_id/aggregation_jobs/:agg_job_id",
+                put(
+                    move |State(count): State<Arc<AtomicUsize>>,
+                          OriginalUri(uri): OriginalUri,
+                          body: Bytes| async move {
+                        sender.send((uri.path().to_string(), body)).unwrap();
+                        if count.fetch_add(1, Ordering::Relaxed) < failures {
+                            tokio::time::sleep(delay).await;
+                            status.into_response()
+                        } else {
+                            (
+                                [(CONTENT_TYPE, "application/dap-aggregation-job-resp")],
+                                "response",
+                            )
+                                .into_response()
+                        }
+                    },
+                ),
+            )
+            .with_state(count);
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        (format!("http://{addr}/v09/").parse().unwrap(), receiver)
+    }
+
+    fn retry_config(max_attempts: u32) -> HelperRetryConfig {
+        HelperRetryConfig {
+            max_attempts,
+            initial_backoff_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    async fn send_agg_job_init(
+        helper_retry: HelperRetryConfig,
+        helper_url: &url::Url,
+    ) -> (String, Result<daphne::DapResponse, DapError>) {
+        let mut service_config = crate::test::service_config(DapRole::Leader);
+        service_config.helper_retry = helper_retry;
+        let app = crate::test::app(service_config);
+        let agg_job_id = AggregationJobId([1; 16]);
+        let path = format!(
+            "tasks/{}/aggregation_jobs/{}",
+            TaskId([2; 32]).to_base64url(),
+            agg_job_id.to_base64url()
+        );
+        let req = DapRequest {
+            version: DapVersion::Draft09,
+            media_type: Some(DapMediaType::AggregationJobInitReq),
+            task_id: None,
+            resource: DapResource::AggregationJob(agg_job_id),
+            payload: b"request".to_vec(),
+            sender_auth: None,
+            taskprov: None,
+            privacy_pass_token: None,
+        };
+        let resp = app
+            .send_http_put(req, helper_url.join(&path).unwrap())
+            .await;
+        (format!("/v09/{path}"), resp)
+    }
+
+    #[tokio::test]
+    async fn retransmit_after_transient_failure() {
+        let (helper_url, mut requests) = helper(2, StatusCode::SERVICE_UNAVAILABLE, Duration::ZERO);
+        let (path, resp) = send_agg_job_init(retry_config(3), &helper_url).await;
+
+        let resp = resp.unwrap();
+        assert_eq!(resp.media_type, DapMediaType::AggregationJobResp);
+        assert_eq!(resp.payload, b"response");
+        for _ in 0..3 {
+            let (got_path, body) = requests.recv().await.unwrap();
+            assert_eq!(got_path, path);
+            assert_eq!(&body[..], b"request");
         }
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_attempts() {
+        let (helper_url, mut requests) =
+            helper(usize::MAX, StatusCode::SERVICE_UNAVAILABLE, Duration::ZERO);
+        let (_path, resp) = send_agg_job_init(retry_config(2), &helper_url).await;
+
+        assert!(matches!(resp, Err(DapError::Peer(peer)) if peer.status == 503));
+        assert!(requests.recv().await.is_some());
+        assert!(requests.recv().await.is_some());
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn do_not_retry_abort() {
+        let (helper_url, mut requests) =
+            helper(usize::MAX, StatusCode::BAD_REQUEST, Duration::ZERO);
+        let (_path, resp) = send_agg_job_init(retry_config(3), &helper_url).await;
+
+        assert!(matches!(resp, Err(DapError::Peer(peer)) if peer.status == 400));
+        assert!(requests.recv().await.is_some());
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn retransmit_after_timeout() {
+        let (helper_url, mut requests) =
+            helper(1, StatusCode::SERVICE_UNAVAILABLE, Duration::from_secs(10));
+        let (_path, resp) = send_agg_job_init(
+            HelperRetryConfig {
+                attempt_timeout_ms: 100,
+                ..retry_config(2)
+            },
+            &helper_url,
+        )
+        .await;
+
+        assert_eq!(resp.unwrap().payload, b"response");
+        assert!(requests.recv().await.is_some());
+        assert!(requests.recv().await.is_some());
+        assert!(requests.try_recv().is_err());
     }
 }
@@ -1,9 +1,19 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
+mod admin;
 mod aggregator;
 mod helper;
 mod leader;
+mod retention;
+
+pub(crate) use admin::{AdminError, AdminTask, AdminTaskUpdate};
+pub use retention::{RetentionReport, TaskRetentionReport};
+
+#[cfg(feature = "test-utils")]
+mod interop;
+#[cfg(feature = "test-utils")]
+pub(crate) use interop::{aggregate_result_to_json, InteropCollectorState};
 
 #[cfg(feature = "test-utils")]
 mod test_utils {
@@ -16,43 +26,32 @@ mod test_utils {
         DapError, DapQueryConfig, DapTaskConfig, DapVersion,
     };
     use daphne_service_utils::{
-        test_route_types::{InternalTestAddTask, InternalTestEndpointForTask},
+        test_route_types::{InternalTestAddTask, InternalTestEndpointForTask, InternalTestVdaf},
         DapRole,
     };
     use prio::codec::Decode;
 
-    use crate::storage_proxy_connection::kv;
+    use crate::storage::kv;
 
     impl crate::App {
         pub(crate) async fn internal_delete_all(&self) -> Result<(), DapError> {
             self.test_leader_state.lock().await.delete_all();
 
-            use daphne_service_utils::durable_requests::PURGE_STORAGE;
-            *self.cache.write().await = Default::default();
+            self.kv().invalidate_all().await;
 
-            self.http
-                .delete(self.storage_proxy_config.url.join(PURGE_STORAGE).unwrap())
-                .bearer_auth(&self.storage_proxy_config.auth_token)
-                .send()
+            self.storage
+                .purge()
                 .await
-                .map_err(|e| fatal_error!(err = ?e))?
-                .error_for_status()
                 .map_err(|e| fatal_error!(err = ?e))?;
 
             Ok(())
         }
 
         pub(crate) async fn storage_ready_check(&self) -> Result<(), DapError> {
-            use daphne_service_utils::durable_requests::STORAGE_READY;
-            self.http
-                .get(self.storage_proxy_config.url.join(STORAGE_READY).unwrap())
-                .bearer_auth(&self.storage_proxy_config.auth_token)
-                .send()
+            self.storage
+                .ready()
                 .await
-                .map_err(|e| fatal_error!(err = ?e))?
-                .error_for_status()
-                .map_err(|e| fatal_error!(err  = ?e))?;
-            Ok(())
+                .map_err(|e| fatal_error!(err = ?e))
         }
 
         pub(crate) fn internal_endpoint_for_task(
@@ -75,34 +74,10 @@ mod test_utils {
         pub(crate) async fn internal_add_task(
             &self,
             version: DapVersion,
+            role: DapRole,
             cmd: InternalTestAddTask,
         ) -> Result<(), DapError> {
-            // VDAF config.
-            let vdaf = match (
-                cmd.vdaf.typ.as_ref(),
-                cmd.vdaf.bits,
-                cmd.vdaf.length,
-                cmd.vdaf.chunk_length,
-            ) {
-                ("Prio3Count", None, None, None) => VdafConfig::Prio3(Prio3Config::Count),
-                ("Prio3Sum", Some(bits), None, None) => VdafConfig::Prio3(Prio3Config::Sum {
-                    bits: bits.parse().map_err(|e| fatal_error!(err = ?e))?,
-                }),
-                ("Prio3SumVec", Some(bits), Some(length), Some(chunk_length)) => {
-                    VdafConfig::Prio3(Prio3Config::SumVec {
-                        bits: bits.parse().map_err(|e| fatal_error!(err = ?e))?,
-                        length: length.parse().map_err(|e| fatal_error!(err = ?e))?,
-                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e))?,
-                    })
-                }
-                ("Prio3Histogram", None, Some(length), Some(chunk_length)) => {
-                    VdafConfig::Prio3(Prio3Config::Histogram {
-                        length: length.parse().map_err(|e| fatal_error!(err = ?e))?,
-                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e))?,
-                    })
-                }
-                _ => return Err(fatal_error!(err = "command failed: unrecognized VDAF")),
-            };
+            let vdaf = vdaf_config_from_internal_test(&cmd.vdaf)?;
 
             // VDAF verification key.
             let vdaf_verify_key_data = decode_base64url_vec(cmd.vdaf_verify_key.as_bytes())
@@ -137,7 +112,7 @@ mod test_utils {
             }
 
             // Collector authentication token.
-            match (cmd.role, cmd.collector_authentication_token) {
+            match (role, cmd.collector_authentication_token) {
                 (DapRole::Leader, Some(token_string)) => {
                     let token = BearerToken::from(token_string);
                     if self
@@ -198,6 +173,7 @@ mod test_utils {
                         vdaf_verify_key,
                         collector_hpke_config,
                         method: Default::default(),
+                        privacy_pass: None,
                     },
                 )
                 .await
@@ -220,32 +196,40 @@ mod test_utils {
             version: DapVersion,
             new_receiver: HpkeReceiverConfig,
         ) -> Result<(), DapError> {
-            let mut config_list = self
-                .kv()
-                .get_cloned::<kv::prefix::HpkeReceiverConfigSet>(&version)
-                .await
-                .map_err(|e| fatal_error!(err = ?e))?
-                .unwrap_or_default();
-
-            if config_list
-                .iter()
-                .any(|receiver| new_receiver.config.id == receiver.config.id)
-            {
-                return Err(fatal_error!(
-                    err = format!(
-                        "receiver config with id {} already exists",
-                        new_receiver.config.id
-                    )
-                ));
-            }
-
-            config_list.push(new_receiver);
-
-            self.kv()
-                .put::<kv::prefix::HpkeReceiverConfigSet>(&version, config_list)
-                .await
-                .map_err(|e| fatal_error!(err = ?e))?;
-            Ok(())
+            Ok(self.admin_add_hpke_config(version, new_receiver).await?)
         }
     }
+
+    /// Parse the VDAF config of an interop test command.
+    pub(crate) fn vdaf_config_from_internal_test(
+        vdaf: &InternalTestVdaf,
+    ) -> Result<VdafConfig, DapError> {
+        Ok(
+            match (
+                vdaf.typ.as_ref(),
+                vdaf.bits.as_deref(),
+                vdaf.length.as_deref(),
+                vdaf.chunk_length.as_deref(),
+            ) {
+                ("Prio3Count", None, None, None) => VdafConfig::Prio3(Prio3Config::Count),
+                ("Prio3Sum", Some(bits), None, None) => VdafConfig::Prio3(Prio3Config::Sum {
+                    bits: bits.parse().map_err(|e| fatal_error!(err = ?e))?,
+                }),
+                ("Prio3SumVec", Some(bits), Some(length), Some(chunk_length)) => {
+                    VdafConfig::Prio3(Prio3Config::SumVec {
+                        bits: bits.parse().map_err(|e| fatal_error!(err = ?e))?,
+                        length: length.parse().map_err(|e| fatal_error!(err = ?e))?,
+                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e))?,
+                    })
+                }
+                ("Prio3Histogram", None, Some(length), Some(chunk_length)) => {
+                    VdafConfig::Prio3(Prio3Config::Histogram {
+                        length: length.parse().map_err(|e| fatal_error!(err = ?e))?,
+                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e))?,
+                    })
+                }
+                _ => return Err(fatal_error!(err = "command failed: unrecognized VDAF")),
+            },
+        )
+    }
 }
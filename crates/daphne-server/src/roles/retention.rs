@@ -0,0 +1,514 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Garbage collection of the state of expired tasks, as specified by the
+//! [`RetentionConfig`](daphne_service_utils::config::RetentionConfig).
+//!
+//! KV can't be enumerated by task, so the tasks whose state may need to be deleted are recorded in
+//! [`KnownTasks`] when they are provisioned or configured by taskprov. Each task is recorded under
+//! its own key, so that concurrent updates of different tasks don't conflict.
+
+use daphne::{
+    fatal_error,
+    messages::{Base64Encode, Duration, TaskId, Time},
+    roles::DapAggregator,
+    DapBatchBucket, DapError, DapQueryConfig, DapTaskConfig,
+};
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+
+use crate::storage::kv::{
+    self,
+    prefix::{KnownTask, KnownTasks},
+};
+
+/// Maximum number of aggregate store objects deleted concurrently.
+const DELETE_CONCURRENCY: usize = 32;
+
+/// Maximum number of aggregate store objects that are deleted for a single task. This guards
+/// against tasks with very long lifetimes relative to their time precision.
+const MAX_AGGREGATE_STORE_OBJECTS: u64 = 100_000;
+
+/// The state that was deleted, or would be deleted if this is a dry run.
+#[derive(Debug, Serialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub tasks: Vec<TaskRetentionReport>,
+}
+
+/// The state of a task that was deleted. The number of deleted objects is only known if they were
+/// deleted by the retention policy rather than left to the storage's garbage collection.
+#[derive(Debug, Serialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct TaskRetentionReport {
+    pub task_id: String,
+    pub expiration: Time,
+    pub task_config: bool,
+    pub bearer_tokens: bool,
+    pub aggregate_store: bool,
+    pub aggregate_store_objects: Option<u64>,
+    pub helper_state: bool,
+    pub helper_state_objects: Option<u64>,
+    pub collected_batches: bool,
+    pub collected_batches_objects: Option<u64>,
+}
+
+impl TaskRetentionReport {
+    fn is_empty(&self) -> bool {
+        !(self.task_config
+            || self.bearer_tokens
+            || self.aggregate_store
+            || self.helper_state
+            || self.collected_batches)
+    }
+}
+
+/// The outcome of deleting a task's aggregate store.
+enum AggregateStoreDeletion {
+    /// The given number of buckets were deleted.
+    Deleted(u64),
+    /// The buckets can't be enumerated, so they are left to the storage's garbage collection.
+    LeftToStorage,
+    /// The task has too many buckets to delete them.
+    Skipped,
+}
+
+fn storage_error(e: crate::storage::Error) -> DapError {
+    fatal_error!(err = ?e)
+}
+
+/// The buckets of a time-interval task, i.e., the batch windows from before the task was first
+/// recorded (reports may be timestamped up to `report_storage_epoch_duration` in the past) until
+/// the task's expiration.
+fn time_interval_buckets(
+    task: &KnownTask,
+    time_precision: Duration,
+    report_storage_epoch_duration: Duration,
+) -> impl Iterator<Item = DapBatchBucket> {
+    let start = task
+        .created_at
+        .saturating_sub(report_storage_epoch_duration);
+    let start = start - start % time_precision;
+    (start..task.expiration)
+        .step_by(usize::try_from(time_precision).unwrap_or(usize::MAX))
+        .map(|batch_window| DapBatchBucket::TimeInterval { batch_window })
+}
+
+/// Whether there is no state left to delete for the task.
+fn is_fully_deleted(task: &KnownTask) -> bool {
+    task.task_config_deleted
+        && task.bearer_tokens_deleted
+        && task.aggregate_store_deleted
+        && task.helper_state_deleted
+}
+
+impl crate::App {
+    pub(crate) async fn known_tasks(&self) -> Result<Vec<KnownTask>, DapError> {
+        self.kv().list::<KnownTasks>().await.map_err(storage_error)
+    }
+
+    /// Record the tasks in [`KnownTasks`], or update their records if their configuration
+    /// changed.
+    pub(crate) async fn record_known_tasks<'a>(
+        &self,
+        tasks: impl IntoIterator<Item = (TaskId, &'a DapTaskConfig)>,
+    ) -> Result<(), DapError> {
+        let now = self.get_current_time();
+        let record = |task_id, task_config: &DapTaskConfig, created_at| KnownTask {
+            task_id,
+            version: task_config.version,
+            created_at,
+            expiration: task_config.expiration,
+            time_precision: matches!(task_config.query, DapQueryConfig::TimeInterval)
+                .then_some(task_config.time_precision),
+            task_config_deleted: false,
+            bearer_tokens_deleted: false,
+            aggregate_store_deleted: false,
+            helper_state_deleted: false,
+        };
+
+        for (task_id, task_config) in tasks {
+            // Avoid writing to storage in the common case that nothing changed.
+            let created_at = self
+                .kv()
+                .peek::<KnownTasks, _, _>(&task_id, |known_task| {
+                    (*known_task != record(task_id, task_config, known_task.created_at))
+                        .then_some(known_task.created_at)
+                })
+                .await
+                .map_err(storage_error)?;
+            let created_at = match created_at {
+                None => now,
+                Some(Some(created_at)) => created_at,
+                Some(None) => continue,
+            };
+            self.kv()
+                .put::<KnownTasks>(&task_id, record(task_id, task_config, created_at))
+                .await
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    /// Store the task's updated record, or forget the task if all of its state was deleted.
+    async fn update_known_task(&self, task: &KnownTask) -> Result<(), DapError> {
+        if is_fully_deleted(task) {
+            self.kv().delete::<KnownTasks>(&task.task_id).await
+        } else {
+            self.kv()
+                .put::<KnownTasks>(&task.task_id, task.clone())
+                .await
+        }
+        .map_err(storage_error)
+    }
+
+    /// Record that the task's config and bearer tokens were deleted. The rest of its state is
+    /// left to the retention policy.
+    pub(crate) async fn record_known_task_deleted(&self, task_id: &TaskId) -> Result<(), DapError> {
+        let Some(mut known_task) = self
+            .kv()
+            .get_cloned_uncached::<KnownTasks>(task_id)
+            .await
+            .map_err(storage_error)?
+        else {
+            return Ok(());
+        };
+        known_task.task_config_deleted = true;
+        known_task.bearer_tokens_deleted = true;
+        self.update_known_task(&known_task).await
+    }
+
+    async fn delete_aggregate_store(
+        &self,
+        task: &KnownTask,
+    ) -> Result<AggregateStoreDeletion, DapError> {
+        if let Some(deleted) = self
+            .storage
+            .aggregate_store_delete_task(task.version, &task.task_id)
+            .await
+            .map_err(storage_error)?
+        {
+            return Ok(AggregateStoreDeletion::Deleted(deleted));
+        }
+
+        // The storage can't enumerate the buckets, so enumerate those of time-interval tasks. The
+        // buckets of fixed-size tasks are left to the storage's garbage collection.
+        let Some(time_precision) = task.time_precision.filter(|p| *p > 0) else {
+            tracing::debug!(
+                task_id = %task.task_id,
+                "aggregate store of fixed-size task can't be enumerated, leaving it to storage"
+            );
+            return Ok(AggregateStoreDeletion::LeftToStorage);
+        };
+        let buckets = time_interval_buckets(
+            task,
+            time_precision,
+            self.service_config.report_storage_epoch_duration,
+        )
+        .take(usize::try_from(MAX_AGGREGATE_STORE_OBJECTS + 1).unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+        if buckets.len() as u64 > MAX_AGGREGATE_STORE_OBJECTS {
+            tracing::warn!(
+                task_id = %task.task_id,
+                "too many aggregate store objects to delete, skipping"
+            );
+            return Ok(AggregateStoreDeletion::Skipped);
+        }
+        let deleted = buckets.len() as u64;
+        futures::stream::iter(buckets)
+            .map(|bucket| async move {
+                self.storage
+                    .aggregate_store_delete(task.version, &task.task_id, &bucket)
+                    .await
+            })
+            .buffer_unordered(DELETE_CONCURRENCY)
+            .try_collect::<()>()
+            .await
+            .map_err(storage_error)?;
+        Ok(AggregateStoreDeletion::Deleted(deleted))
+    }
+
+    /// Apply the retention policy: Delete the state of the known tasks that expired long enough
+    /// ago. If `dry_run` is set, then nothing is deleted.
+    pub async fn apply_retention_policy(&self, dry_run: bool) -> Result<RetentionReport, DapError> {
+        let policy = self
+            .service_config
+            .retention
+            .as_ref()
+            .ok_or_else(|| fatal_error!(err = "retention policy not configured"))?;
+        let now = self.get_current_time();
+        let elapsed = |retention: Option<Duration>, expiration: Time| {
+            retention.is_some_and(|retention| now >= expiration.saturating_add(retention))
+        };
+
+        let mut report = RetentionReport {
+            dry_run,
+            tasks: Vec::new(),
+        };
+        for mut task in self.known_tasks().await? {
+            let mut task_report = TaskRetentionReport {
+                task_id: task.task_id.to_base64url(),
+                expiration: task.expiration,
+                task_config: false,
+                bearer_tokens: false,
+                aggregate_store: false,
+                aggregate_store_objects: None,
+                helper_state: false,
+                helper_state_objects: None,
+                collected_batches: false,
+                collected_batches_objects: None,
+            };
+
+            if !task.task_config_deleted && elapsed(policy.task_config, task.expiration) {
+                if !dry_run {
+                    self.kv()
+                        .delete::<kv::prefix::TaskConfig>(&task.task_id)
+                        .await
+                        .map_err(storage_error)?;
+                    task.task_config_deleted = true;
+                }
+                task_report.task_config = true;
+            }
+
+            if !task.bearer_tokens_deleted && elapsed(policy.bearer_tokens, task.expiration) {
+                if !dry_run {
+                    self.kv()
+                        .delete::<kv::prefix::LeaderBearerToken>(&task.task_id)
+                        .await
+                        .map_err(storage_error)?;
+                    self.kv()
+                        .delete::<kv::prefix::CollectorBearerToken>(&task.task_id)
+                        .await
+                        .map_err(storage_error)?;
+                    task.bearer_tokens_deleted = true;
+                }
+                task_report.bearer_tokens = true;
+            }
+
+            if !task.aggregate_store_deleted && elapsed(policy.aggregate_store, task.expiration) {
+                if dry_run {
+                    task_report.aggregate_store = true;
+                    task_report.collected_batches = true;
+                } else {
+                    let deletion = self.delete_aggregate_store(&task).await?;
+                    if let AggregateStoreDeletion::Deleted(deleted) = deletion {
+                        task_report.aggregate_store_objects = Some(deleted);
+                    }
+                    if !matches!(deletion, AggregateStoreDeletion::Skipped) {
+                        // The record of collected batches prevents the aggregate shares from being
+                        // collected again, so it is only deleted along with them.
+                        task_report.collected_batches_objects = self
+                            .storage
+                            .collected_batches_delete_task(task.version, &task.task_id)
+                            .await
+                            .map_err(storage_error)?;
+                        task.aggregate_store_deleted = true;
+                        task_report.aggregate_store = true;
+                        task_report.collected_batches = true;
+                    }
+                }
+            }
+
+            if !task.helper_state_deleted && elapsed(policy.helper_state, task.expiration) {
+                if !dry_run {
+                    task_report.helper_state_objects = self
+                        .storage
+                        .helper_state_delete_task(task.version, &task.task_id)
+                        .await
+                        .map_err(storage_error)?;
+                    task.helper_state_deleted = true;
+                }
+                task_report.helper_state = true;
+            }
+
+            if !task_report.is_empty() {
+                tracing::info!(dry_run, report = ?task_report, "applied retention policy");
+                if !dry_run {
+                    self.update_known_task(&task).await?;
+                }
+                report.tasks.push(task_report);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::{
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{AggregationJobId, BatchId, BatchSelector, ReportId, TaskId},
+        roles::DapAggregator,
+        vdaf::{Prio3Config, VdafConfig},
+        DapAggregateShare, DapBatchBucket, DapQueryConfig, DapTaskConfig, DapVersion,
+    };
+    use daphne_service_utils::{
+        config::RetentionConfig, durable_requests::bindings::AggregateStoreMergeReq, DapRole,
+    };
+
+    use super::time_interval_buckets;
+    use crate::storage::kv::prefix::KnownTask;
+
+    fn task_config(query: DapQueryConfig, expiration: u64) -> DapTaskConfig {
+        let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+        DapTaskConfig {
+            version: DapVersion::Draft09,
+            leader_url: "https://leader.com/".parse().unwrap(),
+            helper_url: "https://helper.org/".parse().unwrap(),
+            time_precision: 3600,
+            min_batch_size: 10,
+            query,
+            expiration,
+            vdaf_verify_key: vdaf.gen_verify_key(),
+            vdaf,
+            collector_hpke_config: HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config,
+            method: Default::default(),
+            privacy_pass: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn deletes_state_of_expired_tasks() {
+        let mut service_config = crate::test::service_config(DapRole::Helper);
+        service_config.retention = Some(RetentionConfig {
+            task_config: Some(0),
+            bearer_tokens: Some(0),
+            aggregate_store: Some(0),
+            helper_state: Some(0),
+            interval: 1,
+            dry_run: false,
+        });
+        let app = crate::test::sqlite_app(service_config);
+        let now = app.get_current_time();
+        let expired_task_id = TaskId([1; 32]);
+        let expired = task_config(
+            DapQueryConfig::FixedSize {
+                max_batch_size: None,
+            },
+            now - 1,
+        );
+        let active_task_id = TaskId([2; 32]);
+        let active = task_config(DapQueryConfig::TimeInterval, now + 3600);
+
+        // Tasks recorded concurrently are all known.
+        futures::try_join!(
+            app.record_known_tasks([(expired_task_id, &expired)]),
+            app.record_known_tasks([(active_task_id, &active)]),
+        )
+        .unwrap();
+        assert_eq!(app.known_tasks().await.unwrap().len(), 2);
+
+        let bucket = DapBatchBucket::FixedSize {
+            batch_id: BatchId([3; 32]),
+        };
+        let batch_sel = BatchSelector::FixedSizeByBatchId {
+            batch_id: BatchId([3; 32]),
+        };
+        let agg_job_id = AggregationJobId([4; 16]);
+        for task_id in [&expired_task_id, &active_task_id] {
+            app.storage
+                .aggregate_store_merge(
+                    DapVersion::Draft09,
+                    task_id,
+                    &bucket,
+                    AggregateStoreMergeReq {
+                        contained_reports: vec![ReportId([5; 16])],
+                        agg_share_delta: DapAggregateShare {
+                            report_count: 1,
+                            ..Default::default()
+                        },
+                        agg_job_id,
+                    },
+                )
+                .await
+                .unwrap();
+            app.storage
+                .helper_state_put_if_not_exists(
+                    DapVersion::Draft09,
+                    task_id,
+                    &agg_job_id,
+                    "record".into(),
+                )
+                .await
+                .unwrap();
+            app.storage
+                .collected_batches_insert(DapVersion::Draft09, task_id, &batch_sel, &[6; 32])
+                .await
+                .unwrap();
+        }
+
+        let remaining_state = |task_id| {
+            let app = &app;
+            let bucket = &bucket;
+            let agg_job_id = &agg_job_id;
+            let batch_sel = &batch_sel;
+            async move {
+                let agg_share = app
+                    .storage
+                    .aggregate_store_get(DapVersion::Draft09, task_id, bucket)
+                    .await
+                    .unwrap();
+                let helper_state = app
+                    .storage
+                    .helper_state_get(DapVersion::Draft09, task_id, agg_job_id)
+                    .await
+                    .unwrap();
+                let collected = app
+                    .storage
+                    .collected_batches_check_overlapping(DapVersion::Draft09, task_id, batch_sel)
+                    .await
+                    .unwrap();
+                (agg_share.report_count, helper_state.is_some(), collected)
+            }
+        };
+
+        // A dry run reports what would be deleted without deleting it.
+        let report = app.apply_retention_policy(true).await.unwrap();
+        assert_eq!(report.tasks.len(), 1);
+        assert!(report.tasks[0].aggregate_store);
+        assert!(report.tasks[0].helper_state);
+        assert!(report.tasks[0].collected_batches);
+        assert_eq!(remaining_state(&expired_task_id).await, (1, true, true));
+
+        let report = app.apply_retention_policy(false).await.unwrap();
+        assert_eq!(report.tasks.len(), 1);
+        assert_eq!(report.tasks[0].aggregate_store_objects, Some(1));
+        assert_eq!(report.tasks[0].helper_state_objects, Some(1));
+        assert_eq!(report.tasks[0].collected_batches_objects, Some(1));
+
+        assert_eq!(remaining_state(&expired_task_id).await, (0, false, false));
+        assert_eq!(remaining_state(&active_task_id).await, (1, true, true));
+
+        // The expired task is forgotten once all of its state is deleted.
+        let known_tasks = app.known_tasks().await.unwrap();
+        assert_eq!(known_tasks.len(), 1);
+        assert_eq!(known_tasks[0].task_id, active_task_id);
+    }
+
+    #[test]
+    fn buckets_span_task_lifetime() {
+        let task = KnownTask {
+            task_id: TaskId([1; 32]),
+            version: DapVersion::Draft09,
+            created_at: 1_000_150,
+            expiration: 1_000_500,
+            time_precision: Some(100),
+            task_config_deleted: false,
+            bearer_tokens_deleted: false,
+            aggregate_store_deleted: false,
+            helper_state_deleted: false,
+        };
+        let batch_windows = time_interval_buckets(&task, 100, 200)
+            .map(|bucket| match bucket {
+                DapBatchBucket::TimeInterval { batch_window } => batch_window,
+                DapBatchBucket::FixedSize { .. } => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            batch_windows,
+            [999_900, 1_000_000, 1_000_100, 1_000_200, 1_000_300, 1_000_400]
+        );
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Routes of the task management API. Every request must carry the bearer token configured by
+//! [`DaphneServiceConfig::admin_token`](daphne_service_utils::config::DaphneServiceConfig) in the
+//! "Authorization" header.
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json,
+};
+use daphne::{
+    auth::BearerToken,
+    hpke::HpkeReceiverConfig,
+    messages::{Base64Encode, TaskId},
+    DapVersion,
+};
+use serde::Deserialize;
+
+use crate::{
+    roles::{AdminError, AdminTask, AdminTaskUpdate},
+    App,
+};
+
+use super::{AxumDapResponse, DaphneService};
+
+pub(super) fn add_admin_routes<B>(router: super::Router<App, B>) -> super::Router<App, B>
+where
+    B: Send + HttpBody + 'static,
+    B::Data: Send,
+    B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    router
+        .route("/admin/tasks", get(list_tasks).post(create_task))
+        .route(
+            "/admin/tasks/:task_id",
+            get(get_task).patch(update_task).delete(delete_task),
+        )
+        .route("/admin/tasks/:task_id/status", get(task_status))
+        .route("/admin/retention", post(apply_retention_policy))
+        .route("/admin/dead_letters", get(list_dead_letters))
+        .route("/admin/dead_letters/:id/replay", post(replay_dead_letter))
+        .route("/admin/hpke_configs/:version", post(add_hpke_config))
+        .route(
+            "/admin/hpke_configs/:version/:config_id",
+            delete(remove_hpke_config),
+        )
+}
+
+/// An axum extractor that rejects requests that don't carry the admin bearer token.
+struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<Arc<App>> for AdminAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app: &Arc<App>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(expected) = app.service_config.admin_token.as_ref() else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(BearerToken::from)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if &token == expected {
+            Ok(Self)
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PathTaskId {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    task_id: TaskId,
+}
+
+fn error_response(app: &App, error: AdminError) -> Response {
+    let status = match error {
+        AdminError::NotFound | AdminError::DeadLetterNotFound | AdminError::HpkeConfigNotFound => {
+            StatusCode::NOT_FOUND
+        }
+        AdminError::Conflict | AdminError::HpkeConfigConflict => StatusCode::CONFLICT,
+        AdminError::BadRequest(..) => StatusCode::BAD_REQUEST,
+        AdminError::Fatal(e) => {
+            return AxumDapResponse::new_error(e, app.server_metrics()).into_response()
+        }
+    };
+    (
+        status,
+        Json(serde_json::json!({ "error": error.to_string() })),
+    )
+        .into_response()
+}
+
+#[tracing::instrument(skip_all)]
+async fn list_tasks(State(app): State<Arc<App>>, _: AdminAuth) -> Response {
+    match app.admin_list_tasks().await {
+        Ok(task_ids) => {
+            let task_ids = task_ids
+                .iter()
+                .map(Base64Encode::to_base64url)
+                .collect::<Vec<_>>();
+            Json(serde_json::json!({ "task_ids": task_ids })).into_response()
+        }
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(task_id = %task.task_id))]
+async fn create_task(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Json(task): Json<AdminTask>,
+) -> Response {
+    match app.admin_create_task(task).await {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn get_task(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+) -> Response {
+    match app.admin_get_task(&task_id).await {
+        Ok(task_config) => Json(task_config).into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app, update))]
+async fn update_task(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+    Json(update): Json<AdminTaskUpdate>,
+) -> Response {
+    match app.admin_update_task(&task_id, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn delete_task(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+) -> Response {
+    match app.admin_delete_task(&task_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn task_status(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+) -> Response {
+    match app.admin_task_status(&task_id).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct RetentionQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[tracing::instrument(skip(app))]
+async fn apply_retention_policy(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Query(RetentionQuery { dry_run }): Query<RetentionQuery>,
+) -> Response {
+    if app.service_config.retention.is_none() {
+        return error_response(
+            &app,
+            AdminError::BadRequest("retention policy not configured".into()),
+        );
+    }
+    match app.apply_retention_policy(dry_run).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn list_dead_letters(State(app): State<Arc<App>>, _: AdminAuth) -> Response {
+    match app.admin_list_dead_letters().await {
+        Ok(dead_letters) => {
+            Json(serde_json::json!({ "dead_letters": dead_letters })).into_response()
+        }
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn replay_dead_letter(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(id): Path<u64>,
+) -> Response {
+    match app.admin_replay_dead_letter(id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+fn parse_version(version: &str) -> Result<DapVersion, AdminError> {
+    version
+        .parse()
+        .map_err(|_| AdminError::BadRequest(format!("unrecognized version: {version}")))
+}
+
+#[tracing::instrument(skip(app, receiver))]
+async fn add_hpke_config(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path(version): Path<String>,
+    Json(receiver): Json<HpkeReceiverConfig>,
+) -> Response {
+    let result = async {
+        app.admin_add_hpke_config(parse_version(&version)?, receiver)
+            .await
+    };
+    match result.await {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn remove_hpke_config(
+    State(app): State<Arc<App>>,
+    _: AdminAuth,
+    Path((version, config_id)): Path<(String, u8)>,
+) -> Response {
+    let result = async {
+        app.admin_remove_hpke_config(parse_version(&version)?, config_id)
+            .await
+    };
+    match result.await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(&app, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+    use daphne_service_utils::DapRole;
+    use tower::ServiceExt;
+
+    fn test_router(admin_token: Option<&str>) -> axum::Router<(), Body> {
+        let mut service_config = crate::test::service_config(DapRole::Leader);
+        service_config.admin_token = admin_token.map(Into::into);
+        super::super::new(DapRole::Leader, crate::test::app(service_config))
+    }
+
+    async fn list_tasks_status(
+        router: axum::Router<(), Body>,
+        authorization: Option<&str>,
+    ) -> StatusCode {
+        let mut req = Request::builder().uri("/admin/tasks");
+        if let Some(authorization) = authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        router
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn disabled_without_admin_token() {
+        assert_eq!(
+            list_tasks_status(test_router(None), Some("Bearer admin-token")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_unauthorized() {
+        let router = test_router(Some("admin-token"));
+        assert_eq!(
+            list_tasks_status(router.clone(), None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_tasks_status(router.clone(), Some("Bearer wrong-token")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_tasks_status(router, Some("admin-token")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn retention_requires_policy() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/admin/retention?dry_run=true")
+            .header(AUTHORIZATION, "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap();
+        let status = test_router(Some("admin-token"))
+            .oneshot(req)
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn dead_letters() {
+        let router = test_router(Some("admin-token"));
+
+        let req = Request::builder()
+            .uri("/admin/dead_letters")
+            .header(AUTHORIZATION, "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "dead_letters": [] })
+        );
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/admin/dead_letters/1337/replay")
+            .header(AUTHORIZATION, "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap();
+        let status = router.oneshot(req).await.unwrap().status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
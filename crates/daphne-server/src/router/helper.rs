@@ -28,7 +28,7 @@ where
     router
         .route(
             "/:version/tasks/:task_id/aggregation_jobs/:agg_job_id",
-            put(agg_job),
+            put(agg_job).post(agg_job),
         )
         .route("/:version/tasks/:task_id/aggregate_shares", post(agg_share))
 }
@@ -57,6 +57,10 @@ where
                 StatusCode::CREATED,
             )
         }
+        Some(DapMediaType::AggregationJobContinueReq) => {
+            let resp = helper::handle_agg_job_cont_req(&*app, &req).await;
+            AxumDapResponse::from_result(resp, app.server_metrics())
+        }
         m => AxumDapResponse::new_error(
             DapAbort::BadRequest(format!("unexpected media type: {m:?}")),
             app.server_metrics(),
@@ -6,7 +6,7 @@ use std::sync::Arc;
 use axum::{
     body::HttpBody,
     extract::State,
-    http::StatusCode,
+    http::{Method, StatusCode},
     response::{IntoResponse, Response},
     routing::{post, put},
 };
@@ -14,6 +14,7 @@ use daphne::{
     constants::DapMediaType,
     error::DapAbort,
     roles::leader::{self, DapLeader},
+    transport::DapHttpMethod,
     DapError, DapVersion,
 };
 use daphne_service_utils::auth::DaphneAuth;
@@ -31,15 +32,27 @@ where
     router
         .route(
             "/:version/collect/task/:task_id/req/:collect_job_id",
-            post(collect),
+            post(collect).get(collect),
         )
-        .route("/:version/tasks/:task_id/reports", put(upload))
+        .route("/:version/tasks/:task_id/reports", put(upload).post(upload))
         .route(
             "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
             put(get_collect_uri),
         )
 }
 
+/// Check whether the request method is the one expected for the version of DAP. The methods for
+/// some requests changed between drafts.
+fn is_method(method: &Method, expected: DapHttpMethod) -> bool {
+    *method
+        == match expected {
+            DapHttpMethod::Get => Method::GET,
+            DapHttpMethod::Post => Method::POST,
+            DapHttpMethod::Put => Method::PUT,
+            DapHttpMethod::Delete => Method::DELETE,
+        }
+}
+
 #[tracing::instrument(
     skip_all,
     fields(
@@ -49,11 +62,15 @@ where
 )]
 async fn upload<A>(
     State(app): State<Arc<A>>,
+    method: Method,
     DapRequestExtractor(req): DapRequestExtractor,
 ) -> Response
 where
     A: DapLeader<DaphneAuth> + DaphneService + Send + Sync,
 {
+    if !is_method(&method, DapHttpMethod::for_upload(req.version)) {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
     match leader::handle_upload_req(&*app, &req).await {
         Ok(()) => StatusCode::OK.into_response(),
         Err(e) => AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
@@ -91,11 +108,15 @@ where
 )]
 async fn collect<A>(
     State(app): State<Arc<A>>,
+    method: Method,
     DapRequestExtractor(req): DapRequestExtractor,
 ) -> Response
 where
     A: DapLeader<DaphneAuth> + DaphneService + Send + Sync,
 {
+    if !is_method(&method, DapHttpMethod::for_collection_poll(req.version)) {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
     let task_id = match req.task_id() {
         Ok(id) => id,
         Err(e) => return AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
@@ -129,6 +150,82 @@ where
             app.server_metrics(),
         )
         .into_response(),
+        Ok(daphne::DapCollectionJob::Failed(peer)) => AxumDapResponse::new_error(
+            DapAbort::BadRequest(format!("collection job failed: Helper aborted: {peer}")),
+            app.server_metrics(),
+        )
+        .into_response(),
         Err(e) => AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use daphne::{
+        async_test_versions,
+        messages::{Base64Encode, CollectionJobId, TaskId},
+        DapVersion,
+    };
+    use daphne_service_utils::DapRole;
+    use rand::{thread_rng, Rng};
+    use tower::ServiceExt;
+
+    async fn status(method: &str, uri: String, content_type: &str) -> StatusCode {
+        let router = super::super::new(
+            DapRole::Leader,
+            crate::test::sqlite_app(crate::test::service_config(DapRole::Leader)),
+        );
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(req).await.unwrap().status()
+    }
+
+    async fn upload_method_depends_on_version(version: DapVersion) {
+        let task_id = TaskId(thread_rng().gen()).to_base64url();
+        let (method, other_method) = match version {
+            DapVersion::Draft09 => ("PUT", "POST"),
+            DapVersion::Latest => ("POST", "PUT"),
+        };
+        let uri = format!("/{version}/tasks/{task_id}/reports");
+
+        assert_eq!(
+            status(other_method, uri.clone(), "application/dap-report").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_ne!(
+            status(method, uri, "application/dap-report").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    async_test_versions! { upload_method_depends_on_version }
+
+    async fn collection_poll_method_depends_on_version(version: DapVersion) {
+        let task_id = TaskId(thread_rng().gen()).to_base64url();
+        let coll_job_id = CollectionJobId(thread_rng().gen()).to_base64url();
+        let (method, other_method) = match version {
+            DapVersion::Draft09 => ("POST", "GET"),
+            DapVersion::Latest => ("GET", "POST"),
+        };
+        let uri = format!("/{version}/collect/task/{task_id}/req/{coll_job_id}");
+
+        assert_eq!(
+            status(other_method, uri.clone(), "application/dap-collect-req").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_ne!(
+            status(method, uri, "application/dap-collect-req").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    async_test_versions! { collection_poll_method_depends_on_version }
+}
@@ -1,30 +1,34 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
+mod admin;
 mod aggregator;
 mod helper;
 mod leader;
 #[cfg(feature = "test-utils")]
 pub mod test_routes;
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use axum::{
     async_trait,
     body::HttpBody,
     extract::{FromRequest, FromRequestParts, Path, State},
-    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderValue, StatusCode,
+    },
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Json,
 };
 use daphne::{
     auth::BearerToken,
     constants::DapMediaType,
-    error::DapAbort,
+    error::{aborts::ProblemDetails, DapAbort},
     fatal_error,
-    messages::{AggregationJobId, CollectionJobId, TaskId},
-    DapError, DapRequest, DapResource, DapResponse, DapVersion,
+    messages::{AggregationJobId, Base64Encode, CollectionJobId, TaskId},
+    privacy_pass, DapError, DapRequest, DapResource, DapResponse, DapVersion,
 };
 use daphne_service_utils::{
     auth::{DaphneAuth, TlsClientAuth},
@@ -34,6 +38,7 @@ use daphne_service_utils::{
 };
 use http::Request;
 use serde::Deserialize;
+use tracing::Instrument;
 
 use crate::App;
 
@@ -62,7 +67,7 @@ where
     }
 }
 
-pub fn new<B>(role: DapRole, aggregator: App) -> axum::Router<(), B>
+pub fn new<B>(role: DapRole, aggregator: impl Into<Arc<App>>) -> axum::Router<(), B>
 where
     B: Send + HttpBody + 'static,
     B::Data: Send,
@@ -72,6 +77,8 @@ where
 
     let router = aggregator::add_aggregator_routes(router);
 
+    let router = admin::add_admin_routes(router);
+
     let router = match role {
         DapRole::Leader => leader::add_leader_routes(router),
         DapRole::Helper => helper::add_helper_routes(router),
@@ -85,32 +92,57 @@ where
         req: Request<B>,
         next: Next<B>,
     ) -> impl IntoResponse {
-        tracing::info!(
-            method = %req.method(),
-            uri = %req.uri(),
-            headers = ?req.headers(),
-            "received request",
-        );
-        let resp = next.run(req).await;
-        app.server_metrics()
-            .count_http_status_code(resp.status().as_u16());
-        tracing::info!(
-            status_code = %resp.status(),
-            headers = ?resp.headers(),
-            "request finished"
-        );
-        resp
+        // Continue the trace of the peer (e.g., the Leader) that sent the request, if any.
+        let span = tracing::info_span!("request", method = %req.method(), uri = %req.uri());
+        crate::telemetry::set_parent_from_headers(&span, req.headers());
+        async move {
+            tracing::info!(headers = ?req.headers(), "received request");
+            let resp = next.run(req).await;
+            app.server_metrics()
+                .count_http_status_code(resp.status().as_u16());
+            tracing::info!(
+                status_code = %resp.status(),
+                headers = ?resp.headers(),
+                "request finished"
+            );
+            resp
+        }
+        .instrument(span)
+        .await
     }
 
-    let app = Arc::new(aggregator);
-    router
-        .with_state(app.clone())
-        .layer(
-            tower::ServiceBuilder::new().layer(axum::middleware::from_fn_with_state(
+    let app = aggregator.into();
+    router.with_state(app.clone()).layer(
+        tower::ServiceBuilder::new()
+            .layer(axum::middleware::from_fn_with_state(
                 app.clone(),
                 request_metrics,
-            )),
-        )
+            ))
+            .layer(axum::middleware::from_fn(identify_aborted_request)),
+    )
+}
+
+/// Fill in the members of the problem details document of an aborted request that identify the
+/// request, i.e., the endpoint and the task and aggregation job it was targeted at.
+async fn identify_aborted_request<B>(
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let instance = req.uri().path().to_string();
+    let mut resp = next.run(req).await;
+    let Some(problem_details) = resp.extensions_mut().remove::<ProblemDetails>() else {
+        return resp;
+    };
+
+    let param = |name| params.as_ref().and_then(|Path(params)| params.get(name));
+    let task_id = param("task_id").and_then(TaskId::try_from_base64url);
+    let agg_job_id = param("agg_job_id").and_then(AggregationJobId::try_from_base64url);
+    let problem_details =
+        problem_details.with_request(&instance, task_id.as_ref(), agg_job_id.as_ref());
+    let headers = [(CONTENT_TYPE, "application/problem+json")];
+
+    (resp.status(), headers, Json(problem_details)).into_response()
 }
 
 struct AxumDapResponse(axum::response::Response);
@@ -149,8 +181,10 @@ impl AxumDapResponse {
     pub fn new_error<E: Into<DapError>>(error: E, metrics: &dyn DaphneServiceMetrics) -> Self {
         // trigger abort if transition failures reach this point.
         let error = match error.into() {
-            DapError::Transition(failure) => DapAbort::report_rejected(failure),
-            DapError::Fatal(e) => Err(e),
+            DapError::Transition(failure) => {
+                DapAbort::report_rejected(failure).map_err(DapError::Fatal)
+            }
+            e @ (DapError::Fatal(_) | DapError::Peer(_)) => Err(e),
             DapError::Abort(abort) => Ok(abort),
         };
         let status = if let Err(_e) = &error {
@@ -167,7 +201,7 @@ impl AxumDapResponse {
             }
             Err(error) => {
                 tracing::error!(?error, "request aborted due to fatal error");
-                DapError::Fatal(error).into_problem_details()
+                error.into_problem_details()
             }
         };
         // this to string is bounded by the
@@ -175,7 +209,11 @@ impl AxumDapResponse {
         metrics.abort_count_inc(&problem_details.title);
         let headers = [(CONTENT_TYPE, "application/problem+json")];
 
-        Self((status, headers, Json(problem_details)).into_response())
+        // The problem details are completed with the request's identifiers by
+        // `identify_aborted_request`.
+        let mut resp = (status, headers, Json(problem_details.clone())).into_response();
+        resp.extensions_mut().insert(problem_details);
+        Self(resp)
     }
 
     pub fn from_result<E>(
@@ -302,6 +340,14 @@ where
 
         let taskprov = extract_header_as_string(http_headers::DAP_TASKPROV);
 
+        let privacy_pass_token = parts
+            .headers
+            .get_all(AUTHORIZATION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(privacy_pass::token_from_authorization_header)
+            .map(ToString::to_string);
+
         // TODO(mendess): this is very eager, we could redesign DapResponse later to allow for
         // streaming of data.
         let payload = hyper::body::to_bytes(body).await;
@@ -315,7 +361,9 @@ where
 
         let (task_id, resource) = {
             let resource = match media_type {
-                Some(DapMediaType::AggregationJobInitReq) => {
+                Some(
+                    DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq,
+                ) => {
                     if let Some(agg_job_id) = agg_job_id {
                         DapResource::AggregationJob(agg_job_id)
                     } else {
@@ -347,6 +395,7 @@ where
             media_type,
             sender_auth: Some(sender_auth),
             taskprov,
+            privacy_pass_token,
         }))
     }
 }
@@ -357,14 +406,15 @@ mod test {
 
     use axum::{
         body::{Body, HttpBody},
-        extract::State,
+        extract::{Path, State},
         http::{header::CONTENT_TYPE, Request, StatusCode},
         response::IntoResponse,
-        routing::get,
+        routing::{get, put},
         Router,
     };
     use daphne::{
         async_test_version, async_test_versions,
+        error::DapAbort,
         messages::{AggregationJobId, Base64Encode, TaskId},
         DapRequest, DapResource, DapVersion,
     };
@@ -510,4 +560,59 @@ mod test {
 
     async_test_version! { parse_agg_job_id, Draft09 }
     async_test_version! { parse_agg_job_id, Latest }
+
+    #[tokio::test]
+    async fn identify_aborted_request() {
+        async fn handler(Path((_, task_id)): Path<(DapVersion, String)>) -> super::AxumDapResponse {
+            let metrics = DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap();
+            super::AxumDapResponse::new_error(
+                DapAbort::UnrecognizedTask {
+                    task_id: TaskId::try_from_base64url(task_id).unwrap(),
+                },
+                &metrics,
+            )
+        }
+
+        let router = Router::new()
+            .route(
+                "/:version/tasks/:task_id/aggregation_jobs/:agg_job_id",
+                put(handler),
+            )
+            .layer(axum::middleware::from_fn(super::identify_aborted_request));
+
+        let task_id = TaskId(thread_rng().gen());
+        let agg_job_id = AggregationJobId(thread_rng().gen());
+        let uri = format!(
+            "/{}/tasks/{}/aggregation_jobs/{}",
+            DapVersion::Latest,
+            task_id.to_base64url(),
+            agg_job_id.to_base64url()
+        );
+        let resp = router
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/problem+json");
+        let problem_details: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(resp.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            problem_details["type"],
+            "urn:ietf:params:ppm:dap:error:unrecognizedTask"
+        );
+        assert_eq!(problem_details["instance"], uri);
+        assert_eq!(problem_details["taskid"], task_id.to_base64url());
+        assert_eq!(
+            problem_details["aggregationjobid"],
+            agg_job_id.to_base64url()
+        );
+    }
 }
@@ -13,17 +13,21 @@ use axum::{
 };
 use daphne::{
     hpke::HpkeReceiverConfig,
-    messages::{Base64Encode, TaskId},
+    messages::{encode_base64url, Base64Encode, TaskId},
     roles::{leader, DapLeader},
-    DapVersion,
+    DapError, DapVersion,
 };
 use daphne_service_utils::{
-    test_route_types::{InternalTestAddTask, InternalTestEndpointForTask},
+    test_route_types::{
+        InternalTestAddTaskKind, InternalTestCollectionPoll, InternalTestCollectionStart,
+        InternalTestEndpointForTask, InternalTestUpload,
+    },
     DapRole,
 };
+use prio::codec::Encode;
 use serde::Deserialize;
 
-use crate::App;
+use crate::{roles::aggregate_result_to_json, App};
 
 use super::{AxumDapResponse, DaphneService};
 
@@ -57,6 +61,10 @@ where
         )
         .route("/internal/test/add_task", post(add_task_default))
         .route("/:version/internal/test/add_task", post(add_task))
+        .route("/internal/test/upload", post(upload_default))
+        .route("/:version/internal/test/upload", post(upload))
+        .route("/internal/test/collection_start", post(collection_start))
+        .route("/internal/test/collection_poll", post(collection_poll))
         .route(
             "/internal/test/add_hpke_config",
             post(add_hpke_config_default),
@@ -136,9 +144,28 @@ async fn endpoint_for_task(
 async fn add_task(
     State(app): State<Arc<App>>,
     Path(version): Path<DapVersion>,
-    Json(cmd): Json<InternalTestAddTask>,
+    Json(cmd): Json<InternalTestAddTaskKind>,
 ) -> impl IntoResponse {
-    match app.internal_add_task(version, cmd).await {
+    let (role, cmd) = match cmd {
+        InternalTestAddTaskKind::Leader(cmd) => (DapRole::Leader, cmd),
+        InternalTestAddTaskKind::Helper(cmd) => (DapRole::Helper, cmd),
+        InternalTestAddTaskKind::Collector(cmd) => {
+            return match app.internal_add_collector_task(version, cmd).await {
+                Ok(collector_hpke_config) => (
+                    StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "collector_hpke_config": encode_base64url(
+                            collector_hpke_config.get_encoded().unwrap()
+                        ),
+                    })),
+                )
+                    .into_response(),
+                Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+            };
+        }
+    };
+    match app.internal_add_task(version, role, cmd).await {
         Ok(()) => (
             StatusCode::OK,
             Json(serde_json::json!({ "status": "success" })),
@@ -151,7 +178,7 @@ async fn add_task(
 #[tracing::instrument(skip(app, json))]
 async fn add_task_default(
     State(app): State<Arc<App>>,
-    json: Json<InternalTestAddTask>,
+    json: Json<InternalTestAddTaskKind>,
 ) -> impl IntoResponse {
     let version = app.service_config.default_version;
     add_task(State(app), Path(version), json).await
@@ -181,3 +208,79 @@ async fn add_hpke_config_default(
     let version = app.service_config.default_version;
     add_hpke_config(State(app), Path(version), json).await
 }
+
+/// Respond to an interop test command with an error, as specified by the interop test API.
+fn interop_error(e: &DapError) -> Response {
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "status": "error", "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+#[tracing::instrument(skip(app, cmd))]
+async fn upload(
+    State(app): State<Arc<App>>,
+    Path(version): Path<DapVersion>,
+    Json(cmd): Json<InternalTestUpload>,
+) -> impl IntoResponse {
+    match app.internal_upload(version, cmd).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "success" })),
+        )
+            .into_response(),
+        Err(e) => interop_error(&e),
+    }
+}
+
+#[tracing::instrument(skip(app, json))]
+async fn upload_default(
+    State(app): State<Arc<App>>,
+    json: Json<InternalTestUpload>,
+) -> impl IntoResponse {
+    let version = app.service_config.default_version;
+    upload(State(app), Path(version), json).await
+}
+
+#[tracing::instrument(skip(app, cmd))]
+async fn collection_start(
+    State(app): State<Arc<App>>,
+    Json(cmd): Json<InternalTestCollectionStart>,
+) -> impl IntoResponse {
+    match app.internal_collection_start(cmd).await {
+        Ok(handle) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "success", "handle": handle })),
+        )
+            .into_response(),
+        Err(e) => interop_error(&e),
+    }
+}
+
+#[tracing::instrument(skip(app, cmd))]
+async fn collection_poll(
+    State(app): State<Arc<App>>,
+    Json(cmd): Json<InternalTestCollectionPoll>,
+) -> impl IntoResponse {
+    match app.internal_collection_poll(cmd).await {
+        Ok(Some(collection)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "complete",
+                "report_count": collection.report_count,
+                "interval_start": collection.interval.start,
+                "interval_duration": collection.interval.duration,
+                "batch_id": collection.batch_id.map(|batch_id| batch_id.to_base64url()),
+                "result": aggregate_result_to_json(&collection.result),
+            })),
+        )
+            .into_response(),
+        Ok(None) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "in progress" })),
+        )
+            .into_response(),
+        Err(e) => interop_error(&e),
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use daphne_service_utils::{
+    config::KvCacheConfig,
+    metrics::{DaphneServiceMetrics, KvCacheEvent},
+};
+use mappable_rc::Marc;
+
+use super::KvPrefix;
+
+type Value = Marc<dyn Any + Send + Sync + 'static>;
+
+struct Entry {
+    prefix: &'static str,
+
+    /// The cached value, or `None` if the key was not found in storage.
+    value: Option<Value>,
+
+    /// `None` if the TTL is too long to be represented.
+    expires_at: Option<Instant>,
+
+    /// The key of this entry in [`Cache::lru`].
+    last_used: u64,
+}
+
+pub struct Cache {
+    config: KvCacheConfig,
+    metrics: Arc<dyn DaphneServiceMetrics>,
+
+    /// The keys are the same as those of KV queries, i.e., prefixed by [`KvPrefix::PREFIX`].
+    entries: HashMap<String, Entry>,
+
+    /// The keys of `entries`, from the least to the most recently used.
+    lru: BTreeMap<u64, String>,
+    clock: u64,
+
+    /// Incremented on every write. See [`Self::generation`].
+    generation: u64,
+
+    /// The generation of the last write to each recently written key, so that a value read from
+    /// storage before a write to its key is not cached after it.
+    writes: HashMap<String, u64>,
+
+    /// The keys of `writes`, by generation. At most `max_entries` writes are tracked.
+    write_order: BTreeMap<u64, String>,
+
+    /// Values read before this generation are not cached, as writes since then may no longer be
+    /// tracked.
+    oldest_tracked: u64,
+}
+
+pub enum GetResult<T: 'static> {
+    /// The key is not cached.
+    Miss,
+    /// The key is cached as not found in storage.
+    Absent,
+    MismatchedType,
+    Found(Marc<T>),
+}
+
+impl Cache {
+    pub fn new(config: KvCacheConfig, metrics: Arc<dyn DaphneServiceMetrics>) -> Self {
+        Self {
+            config,
+            metrics,
+            entries: Default::default(),
+            lru: Default::default(),
+            clock: 0,
+            generation: 0,
+            writes: Default::default(),
+            write_order: Default::default(),
+            oldest_tracked: 0,
+        }
+    }
+
+    pub fn get<P>(&mut self, key: &str) -> GetResult<P::Value>
+    where
+        P: KvPrefix,
+    {
+        let now = Instant::now();
+        let result = match self.entries.get(key) {
+            Some(entry) if entry.expires_at.is_some_and(|expires_at| expires_at <= now) => {
+                self.remove(key);
+                GetResult::Miss
+            }
+            Some(entry) => {
+                let result = match &entry.value {
+                    Some(value) => Marc::try_map(value.clone(), |t| t.downcast_ref::<P::Value>())
+                        .map_or(GetResult::MismatchedType, GetResult::Found),
+                    None => GetResult::Absent,
+                };
+                self.touch(key);
+                result
+            }
+            None => GetResult::Miss,
+        };
+        let event = match result {
+            GetResult::Found(_) | GetResult::Absent => KvCacheEvent::Hit,
+            GetResult::Miss | GetResult::MismatchedType => KvCacheEvent::Miss,
+        };
+        self.metrics.kv_cache_inc(P::PREFIX, event);
+        result
+    }
+
+    /// The current generation of the cache. Read it before fetching a value from storage and pass
+    /// it to [`Self::fill`] once the value is fetched.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Cache a value fetched from storage, or its absence if `value` is `None`, unless the key was
+    /// written to since `generation` was read. Otherwise, a concurrent write could be overwritten
+    /// by the stale value.
+    pub(super) fn fill<P>(&mut self, key: String, value: Option<Marc<P::Value>>, generation: u64)
+    where
+        P: KvPrefix,
+    {
+        if generation < self.oldest_tracked
+            || self
+                .writes
+                .get(&key)
+                .is_some_and(|&written| written > generation)
+        {
+            return;
+        }
+        let ttl = match value {
+            Some(_) => P::cache_ttl(&self.config),
+            None => self.config.negative_ttl,
+        };
+        self.insert(P::PREFIX, key, value.map(to_any::<P>), ttl);
+    }
+
+    /// Cache a value written to storage.
+    pub(super) fn put<P>(&mut self, key: String, value: Marc<P::Value>)
+    where
+        P: KvPrefix,
+    {
+        self.record_write(&key);
+        let ttl = P::cache_ttl(&self.config);
+        self.insert(P::PREFIX, key, Some(to_any::<P>(value)), ttl);
+    }
+
+    /// Drop the cached value, if any, so that the next read fetches it from storage.
+    pub fn invalidate(&mut self, key: &str) {
+        self.record_write(key);
+        self.remove(key);
+    }
+
+    /// Drop all cached values.
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.oldest_tracked = self.generation;
+        self.writes.clear();
+        self.write_order.clear();
+        self.entries.clear();
+        self.lru.clear();
+    }
+
+    fn record_write(&mut self, key: &str) {
+        self.generation += 1;
+        if let Some(written) = self.writes.insert(key.to_string(), self.generation) {
+            self.write_order.remove(&written);
+        }
+        self.write_order.insert(self.generation, key.to_string());
+        while self.writes.len() > self.config.max_entries {
+            let Some((written, key)) = self.write_order.pop_first() else {
+                break;
+            };
+            self.writes.remove(&key);
+            self.oldest_tracked = written;
+        }
+    }
+
+    fn insert(&mut self, prefix: &'static str, key: String, value: Option<Value>, ttl: u64) {
+        self.remove(&key);
+        if ttl == 0 {
+            return;
+        }
+        self.clock += 1;
+        self.lru.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                prefix,
+                value,
+                expires_at: Instant::now().checked_add(Duration::from_secs(ttl)),
+                last_used: self.clock,
+            },
+        );
+        while self.entries.len() > self.config.max_entries {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.metrics
+                    .kv_cache_inc(entry.prefix, KvCacheEvent::Eviction);
+            }
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.clock += 1;
+            if let Some(key) = self.lru.remove(&entry.last_used) {
+                self.lru.insert(self.clock, key);
+            }
+            entry.last_used = self.clock;
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+}
+
+fn to_any<P: KvPrefix>(value: Marc<P::Value>) -> Value {
+    Marc::map(value, |v| v as &(dyn Any + Send + Sync))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use daphne::messages::TaskId;
+    use daphne_service_utils::{config::KvCacheConfig, metrics::DaphnePromServiceMetrics};
+    use mappable_rc::Marc;
+    use prometheus::{Encoder, Registry, TextEncoder};
+
+    use super::{Cache, GetResult};
+    use crate::storage::kv::{prefix, KvPrefix};
+
+    fn cache(config: KvCacheConfig) -> (Cache, Registry) {
+        let registry = Registry::new();
+        let metrics = DaphnePromServiceMetrics::register(&registry).unwrap();
+        (Cache::new(config, Arc::new(metrics)), registry)
+    }
+
+    fn key<P: KvPrefix<Key = TaskId>>(task_id: &TaskId) -> String {
+        format!("{}/{task_id}", P::PREFIX)
+    }
+
+    fn count(registry: &Registry, event: &str) -> usize {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&registry.gather(), &mut buf)
+            .unwrap();
+        String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("kv_cache{") && line.contains(event))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let (mut cache, registry) = cache(KvCacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+        let task_ids = [TaskId([1; 32]), TaskId([2; 32]), TaskId([3; 32])];
+        let keys = task_ids.map(|task_id| key::<prefix::LeaderBearerToken>(&task_id));
+
+        cache.put::<prefix::LeaderBearerToken>(keys[0].clone(), Marc::new("a".into()));
+        cache.put::<prefix::LeaderBearerToken>(keys[1].clone(), Marc::new("b".into()));
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&keys[0]),
+            GetResult::Found(_)
+        ));
+        cache.put::<prefix::LeaderBearerToken>(keys[2].clone(), Marc::new("c".into()));
+
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&keys[0]),
+            GetResult::Found(_)
+        ));
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&keys[1]),
+            GetResult::Miss
+        ));
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&keys[2]),
+            GetResult::Found(_)
+        ));
+        assert_eq!(count(&registry, "eviction"), 1);
+        assert_eq!(count(&registry, "hit"), 3);
+        assert_eq!(count(&registry, "miss"), 1);
+    }
+
+    #[test]
+    fn expire() {
+        let (mut cache, _registry) = cache(KvCacheConfig {
+            bearer_token_ttl: 0,
+            ..Default::default()
+        });
+        let task_id = TaskId([1; 32]);
+
+        cache.put::<prefix::LeaderBearerToken>(
+            key::<prefix::LeaderBearerToken>(&task_id),
+            Marc::new("a".into()),
+        );
+        let hpke_key = format!("{}/v09", prefix::HpkeReceiverConfigSet::PREFIX);
+        cache.put::<prefix::HpkeReceiverConfigSet>(hpke_key.clone(), Marc::new(Vec::new()));
+
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&key::<prefix::LeaderBearerToken>(&task_id)),
+            GetResult::Miss
+        ));
+        assert!(matches!(
+            cache.get::<prefix::HpkeReceiverConfigSet>(&hpke_key),
+            GetResult::Found(_)
+        ));
+    }
+
+    #[test]
+    fn never_expire_if_ttl_overflows() {
+        let (mut cache, _registry) = cache(KvCacheConfig {
+            bearer_token_ttl: u64::MAX,
+            ..Default::default()
+        });
+        let key = key::<prefix::LeaderBearerToken>(&TaskId([1; 32]));
+
+        cache.put::<prefix::LeaderBearerToken>(key.clone(), Marc::new("a".into()));
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&key),
+            GetResult::Found(_)
+        ));
+    }
+
+    #[test]
+    fn cache_absence() {
+        let (mut cache, _registry) = cache(KvCacheConfig::default());
+        let key = key::<prefix::TaskConfig>(&TaskId([1; 32]));
+
+        let generation = cache.generation();
+        cache.fill::<prefix::TaskConfig>(key.clone(), None, generation);
+        assert!(matches!(
+            cache.get::<prefix::TaskConfig>(&key),
+            GetResult::Absent
+        ));
+
+        cache.invalidate(&key);
+        assert!(matches!(
+            cache.get::<prefix::TaskConfig>(&key),
+            GetResult::Miss
+        ));
+    }
+
+    #[test]
+    fn do_not_fill_after_write() {
+        let (mut cache, _registry) = cache(KvCacheConfig::default());
+        let key = key::<prefix::LeaderBearerToken>(&TaskId([1; 32]));
+
+        // A value is read from storage while another request replaces it.
+        let generation = cache.generation();
+        cache.put::<prefix::LeaderBearerToken>(key.clone(), Marc::new("new".into()));
+        cache.fill::<prefix::LeaderBearerToken>(
+            key.clone(),
+            Some(Marc::new("old".into())),
+            generation,
+        );
+
+        let GetResult::Found(token) = cache.get::<prefix::LeaderBearerToken>(&key) else {
+            panic!("token is not cached");
+        };
+        assert_eq!(AsRef::<str>::as_ref(&*token), "new");
+    }
+
+    #[test]
+    fn fill_after_write_to_other_key() {
+        let (mut cache, _registry) = cache(KvCacheConfig::default());
+        let other_key = key::<prefix::LeaderBearerToken>(&TaskId([2; 32]));
+        let key = key::<prefix::LeaderBearerToken>(&TaskId([1; 32]));
+
+        // A value is read from storage while another request writes a different key.
+        let generation = cache.generation();
+        cache.put::<prefix::LeaderBearerToken>(other_key, Marc::new("other".into()));
+        cache.fill::<prefix::LeaderBearerToken>(
+            key.clone(),
+            Some(Marc::new("token".into())),
+            generation,
+        );
+
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&key),
+            GetResult::Found(_)
+        ));
+    }
+
+    #[test]
+    fn do_not_fill_after_untracked_write() {
+        let (mut cache, _registry) = cache(KvCacheConfig {
+            max_entries: 1,
+            ..Default::default()
+        });
+        let other_key = key::<prefix::LeaderBearerToken>(&TaskId([2; 32]));
+        let key = key::<prefix::LeaderBearerToken>(&TaskId([1; 32]));
+
+        // The write to the key is no longer tracked after the write to the other key, so the value
+        // read before it could be stale.
+        let generation = cache.generation();
+        cache.invalidate(&key);
+        cache.invalidate(&other_key);
+        cache.fill::<prefix::LeaderBearerToken>(
+            key.clone(),
+            Some(Marc::new("old".into())),
+            generation,
+        );
+
+        assert!(matches!(
+            cache.get::<prefix::LeaderBearerToken>(&key),
+            GetResult::Miss
+        ));
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+pub(super) mod cache;
+
+use std::{any::Any, fmt::Display};
+
+use daphne_service_utils::config::KvCacheConfig;
+use futures::{StreamExt, TryStreamExt};
+use mappable_rc::Marc;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+
+use super::{Error, Storage};
+pub(crate) use cache::Cache;
+
+/// Maximum number of values fetched concurrently by [`Kv::list`].
+const LIST_CONCURRENCY: usize = 32;
+
+pub(crate) struct Kv<'h> {
+    storage: &'h dyn Storage,
+    cache: &'h Mutex<Cache>,
+}
+
+pub trait KvPrefix {
+    const PREFIX: &'static str;
+
+    type Key: Display;
+    type Value: Any + Send + Sync + Serialize + DeserializeOwned;
+
+    /// How long, in seconds, a value of this prefix is cached. Values are not cached if it is 0.
+    fn cache_ttl(config: &KvCacheConfig) -> u64;
+}
+
+pub mod prefix {
+    use std::fmt;
+
+    use daphne::{
+        auth::BearerToken,
+        messages::{Duration, TaskId, Time},
+        DapTaskConfig, DapVersion,
+    };
+    use daphne_service_utils::config::{HpkeRecieverConfigList, KvCacheConfig};
+    use serde::{Deserialize, Serialize};
+
+    use super::KvPrefix;
+
+    pub struct TaskConfig();
+    impl KvPrefix for TaskConfig {
+        const PREFIX: &'static str = "config/task";
+
+        type Key = TaskId;
+        type Value = DapTaskConfig;
+
+        fn cache_ttl(config: &KvCacheConfig) -> u64 {
+            config.task_config_ttl
+        }
+    }
+
+    pub struct HpkeReceiverConfigSet();
+    impl KvPrefix for HpkeReceiverConfigSet {
+        const PREFIX: &'static str = "hpke_receiver_config_set";
+
+        type Key = DapVersion;
+        type Value = HpkeRecieverConfigList;
+
+        fn cache_ttl(config: &KvCacheConfig) -> u64 {
+            config.hpke_receiver_config_ttl
+        }
+    }
+
+    /// Privacy Pass: The tokens redeemed by Clients. The value is the time of redemption.
+    pub struct PrivacyPassRedemption();
+    impl KvPrefix for PrivacyPassRedemption {
+        const PREFIX: &'static str = "privacy_pass/redemption/task";
+
+        type Key = PrivacyPassNonce;
+        type Value = Time;
+
+        // Redemptions are only ever written, so caching them would just evict other values.
+        fn cache_ttl(_config: &KvCacheConfig) -> u64 {
+            0
+        }
+    }
+
+    pub struct PrivacyPassNonce {
+        pub task_id: TaskId,
+        pub nonce: [u8; 32],
+    }
+
+    impl fmt::Display for PrivacyPassNonce {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}/{}", self.task_id, hex::encode(self.nonce))
+        }
+    }
+
+    pub struct LeaderBearerToken();
+    impl KvPrefix for LeaderBearerToken {
+        const PREFIX: &'static str = "bearer_token/leader/task";
+
+        type Key = TaskId;
+        type Value = BearerToken;
+
+        fn cache_ttl(config: &KvCacheConfig) -> u64 {
+            config.bearer_token_ttl
+        }
+    }
+
+    pub struct CollectorBearerToken();
+    impl KvPrefix for CollectorBearerToken {
+        const PREFIX: &'static str = "bearer_token/collector/task";
+
+        type Key = TaskId;
+        type Value = BearerToken;
+
+        fn cache_ttl(config: &KvCacheConfig) -> u64 {
+            config.bearer_token_ttl
+        }
+    }
+
+    /// Tasks known to this Aggregator, i.e., tasks that were provisioned through the task
+    /// management API or the service configuration, or configured by taskprov. Each task has its
+    /// own key so that concurrent updates of different tasks don't conflict.
+    pub struct KnownTasks();
+    impl KvPrefix for KnownTasks {
+        const PREFIX: &'static str = "admin/known_task";
+
+        type Key = TaskId;
+        type Value = KnownTask;
+
+        fn cache_ttl(config: &KvCacheConfig) -> u64 {
+            config.task_config_ttl
+        }
+    }
+
+    /// A value of [`KnownTasks`], with what is needed to find the task's state once it expires.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(clippy::struct_excessive_bools)]
+    pub struct KnownTask {
+        pub task_id: TaskId,
+        pub version: DapVersion,
+
+        /// The time at which the task was first recorded.
+        pub created_at: Time,
+        pub expiration: Time,
+
+        /// Time-interval tasks: The task's time precision. Not set for fixed-size tasks, whose
+        /// buckets cannot be enumerated.
+        pub time_precision: Option<Duration>,
+
+        /// Indicate which of the task's state was deleted by the retention policy.
+        #[serde(default)]
+        pub task_config_deleted: bool,
+        #[serde(default)]
+        pub bearer_tokens_deleted: bool,
+        #[serde(default)]
+        pub aggregate_store_deleted: bool,
+        #[serde(default)]
+        pub helper_state_deleted: bool,
+    }
+}
+
+impl<'h> Kv<'h> {
+    pub fn new(storage: &'h dyn Storage, cache: &'h Mutex<Cache>) -> Self {
+        Self { storage, cache }
+    }
+
+    pub async fn get<P>(&self, key: &P::Key) -> Result<Option<Marc<P::Value>>, Error>
+    where
+        P: KvPrefix,
+    {
+        self.get_mapped::<P, _, _>(key, |t| Some(t)).await
+    }
+
+    pub async fn get_cloned<P>(&self, key: &P::Key) -> Result<Option<P::Value>, Error>
+    where
+        P: KvPrefix,
+        P::Value: Clone,
+    {
+        Ok(self.get::<P>(key).await?.map(|t| t.as_ref().clone()))
+    }
+
+    /// Same as [`Self::get_cloned`], except that the value is always fetched from storage. Use
+    /// this before updating a value that may have been changed by another instance.
+    pub async fn get_cloned_uncached<P>(&self, key: &P::Key) -> Result<Option<P::Value>, Error>
+    where
+        P: KvPrefix,
+        P::Value: Clone,
+    {
+        self.invalidate::<P>(key).await;
+        self.get_cloned::<P>(key).await
+    }
+
+    pub async fn get_mapped<P, R, F>(
+        &self,
+        key: &P::Key,
+        mapper: F,
+    ) -> Result<Option<Marc<R>>, Error>
+    where
+        P: KvPrefix,
+        F: for<'s> FnOnce(&'s P::Value) -> Option<&'s R>,
+        R: 'static,
+    {
+        Ok(self
+            .get_impl::<P, _, _>(key, |marc| Marc::try_map(marc, mapper).ok())
+            .await?
+            .flatten())
+    }
+
+    pub async fn peek<P, R, F>(&self, key: &P::Key, peeker: F) -> Result<Option<R>, Error>
+    where
+        P: KvPrefix,
+        F: FnOnce(&P::Value) -> R,
+    {
+        self.get_impl::<P, _, _>(key, |marc| peeker(&marc)).await
+    }
+
+    async fn get_impl<P, R, F>(&self, key: &P::Key, mapper: F) -> Result<Option<R>, Error>
+    where
+        P: KvPrefix,
+        F: for<'s> FnOnce(Marc<P::Value>) -> R,
+    {
+        let key = Self::to_key::<P>(key);
+        tracing::debug!(key, "GET");
+        let generation = {
+            let mut cache = self.cache.lock().await;
+            match cache.get::<P>(&key) {
+                cache::GetResult::Miss => {}
+                cache::GetResult::Absent => return Ok(None),
+                cache::GetResult::Found(t) => return Ok(Some(mapper(t))),
+                cache::GetResult::MismatchedType => {
+                    tracing::warn!(
+                        "cache mismatched type, wanted {}",
+                        std::any::type_name::<P::Value>()
+                    );
+                }
+            }
+            cache.generation()
+        };
+        let Some(bytes) = self.storage.kv_get(&key).await? else {
+            self.cache.lock().await.fill::<P>(key, None, generation);
+            return Ok(None);
+        };
+        let t = Marc::new(serde_json::from_slice::<P::Value>(&bytes)?);
+        let r = mapper(t.clone());
+        self.cache.lock().await.fill::<P>(key, Some(t), generation);
+        Ok(Some(r))
+    }
+
+    pub async fn put<P>(&self, key: &P::Key, value: P::Value) -> Result<(), Error>
+    where
+        P: KvPrefix,
+    {
+        let key = Self::to_key::<P>(key);
+        tracing::debug!(key, "PUT");
+        self.storage
+            .kv_put(&key, serde_json::to_vec(&value).unwrap())
+            .await?;
+        self.cache.lock().await.put::<P>(key, value.into());
+        Ok(())
+    }
+
+    /// Stores a value in kv if it doesn't already exist.
+    ///
+    /// If the value already exists, returns the passed in value inside the Ok variant.
+    pub async fn put_if_not_exists<P>(
+        &self,
+        key: &P::Key,
+        value: P::Value,
+    ) -> Result<Option<P::Value>, Error>
+    where
+        P: KvPrefix,
+    {
+        let key = Self::to_key::<P>(key);
+
+        tracing::debug!(key, "PUT if not exists");
+        if self
+            .storage
+            .kv_put_if_not_exists(&key, serde_json::to_vec(&value).unwrap())
+            .await?
+        {
+            self.cache.lock().await.put::<P>(key, value.into());
+            Ok(None)
+        } else {
+            // The cached value, if any, is stale.
+            self.cache.lock().await.invalidate(&key);
+            Ok(Some(value))
+        }
+    }
+
+    /// Deletes a value from kv. Deleting a key that does not exist is not an error.
+    pub async fn delete<P>(&self, key: &P::Key) -> Result<(), Error>
+    where
+        P: KvPrefix,
+    {
+        let key = Self::to_key::<P>(key);
+        tracing::debug!(key, "DELETE");
+        self.storage.kv_delete(&key).await?;
+        self.cache.lock().await.invalidate(&key);
+        Ok(())
+    }
+
+    /// Get all the values of the prefix. The values are always fetched from storage, so this is
+    /// meant for administrative tasks rather than for serving requests.
+    pub async fn list<P>(&self) -> Result<Vec<P::Value>, Error>
+    where
+        P: KvPrefix,
+    {
+        let prefix = format!("{}/", P::PREFIX);
+        tracing::debug!(prefix, "LIST");
+        let keys = self.storage.kv_list(&prefix).await?;
+        let storage = self.storage;
+        let values = futures::stream::iter(keys)
+            .map(|key| async move { storage.kv_get(&key).await })
+            .buffered(LIST_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+        values
+            .into_iter()
+            // Values deleted since the keys were listed are skipped.
+            .flatten()
+            .map(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            .collect()
+    }
+
+    pub async fn only_cache_put<P>(&self, key: &P::Key, value: P::Value)
+    where
+        P: KvPrefix,
+    {
+        let key = Self::to_key::<P>(key);
+        self.cache.lock().await.put::<P>(key, value.into());
+    }
+
+    /// Drop the cached value, if any, so that the next read fetches it from storage. Call this
+    /// after writing to the key other than through this type.
+    pub async fn invalidate<P>(&self, key: &P::Key)
+    where
+        P: KvPrefix,
+    {
+        self.cache.lock().await.invalidate(&Self::to_key::<P>(key));
+    }
+
+    /// Drop all cached values. Call this after writing to storage other than through this type,
+    /// e.g., after purging it.
+    pub async fn invalidate_all(&self) {
+        self.cache.lock().await.clear();
+    }
+
+    fn to_key<P: KvPrefix>(key: &P::Key) -> String {
+        format!("{}/{key}", P::PREFIX)
+    }
+}
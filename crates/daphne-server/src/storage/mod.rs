@@ -0,0 +1,284 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! The storage of the Aggregator's state.
+//!
+//! [`Storage`] is implemented by the [storage proxy](crate::storage_proxy_connection), which
+//! forwards each operation to a Cloudflare worker, and by [`SqliteStorage`], which keeps the state
+//! in an embedded database.
+
+pub(crate) mod kv;
+mod sqlite;
+
+use axum::{async_trait, http::StatusCode};
+use daphne::{
+    messages::{AggregationJobId, BatchSelector, TaskId},
+    DapAggregateShare, DapBatchBucket, DapError, DapVersion,
+};
+use daphne_service_utils::durable_requests::bindings::{
+    AggregateStoreMergeReq, AggregateStoreMergeResp,
+};
+use futures::future::{join_all, try_join_all};
+use serde::{Deserialize, Serialize};
+
+pub(crate) use kv::Kv;
+pub use sqlite::SqliteConfig;
+pub(crate) use sqlite::SqliteStorage;
+
+use crate::StorageProxyConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("network error: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("http error. request returned status code {status} with the body {body}")]
+    Http { status: StatusCode, body: String },
+    #[error("the storage proxy responded to {got} requests of a batch of {expected}")]
+    BatchLen { expected: usize, got: usize },
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("failed to merge aggregate share: {0}")]
+    Merge(#[from] DapError),
+    #[error("storage task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+/// Where the Aggregator keeps its state.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// A cloudflare worker that serves as proxy for the storage implementation.
+    StorageProxy(StorageProxyConfig),
+
+    /// An embedded `SQLite` database.
+    Sqlite(SqliteConfig),
+}
+
+impl From<StorageProxyConfig> for StorageConfig {
+    fn from(config: StorageProxyConfig) -> Self {
+        Self::StorageProxy(config)
+    }
+}
+
+impl From<SqliteConfig> for StorageConfig {
+    fn from(config: SqliteConfig) -> Self {
+        Self::Sqlite(config)
+    }
+}
+
+/// The operations on the Aggregator's state.
+///
+/// The `kv_*` methods store the values of the [KV prefixes](kv::prefix), which are typed and
+/// cached by [`Kv`]. The remaining methods correspond to the methods of the durable objects
+/// defined in [`bindings`](daphne_service_utils::durable_requests::bindings) and must have the
+/// same semantics. The `*_many` methods apply a method to several buckets; by default, they call
+/// it once per bucket.
+#[async_trait]
+pub(crate) trait Storage: Send + Sync {
+    /// Get the value stored under `key`.
+    async fn kv_get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Store a value under `key`, replacing the existing value, if any.
+    async fn kv_put(&self, key: &str, value: Vec<u8>) -> Result<(), Error>;
+
+    /// Store a value under `key` unless there is one already. Returns `false` if the key already
+    /// exists.
+    async fn kv_put_if_not_exists(&self, key: &str, value: Vec<u8>) -> Result<bool, Error>;
+
+    /// Delete the value stored under `key`. Deleting a key that does not exist is not an error.
+    async fn kv_delete(&self, key: &str) -> Result<(), Error>;
+
+    /// List the keys that start with `prefix`.
+    async fn kv_list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    /// Merge an aggregate share into the bucket's aggregate share, unless the bucket has been
+    /// collected or some of the reports have already been aggregated. Merging the same
+    /// aggregation job again succeeds without changing the aggregate share.
+    async fn aggregate_store_merge(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+        req: AggregateStoreMergeReq,
+    ) -> Result<AggregateStoreMergeResp, Error>;
+
+    /// Get the bucket's aggregate share.
+    async fn aggregate_store_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<DapAggregateShare, Error>;
+
+    /// Mark the bucket as collected.
+    async fn aggregate_store_mark_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error>;
+
+    /// Check whether the bucket has been collected.
+    async fn aggregate_store_check_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<bool, Error>;
+
+    /// Delete the bucket's aggregate share, aggregated report and aggregation job IDs and
+    /// collected flag.
+    async fn aggregate_store_delete(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error>;
+
+    /// Delete the aggregate store of all of the task's buckets, including those of fixed-size
+    /// batches, which can't be enumerated from the task's configuration. Returns the number of
+    /// deleted buckets, or `None` if the storage can't enumerate its objects, in which case they
+    /// are left to be garbage collected by the storage itself.
+    async fn aggregate_store_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error>;
+
+    /// Same as [`Self::aggregate_store_merge`], for several buckets. The result of each merge is
+    /// returned in the same order as the requests.
+    async fn aggregate_store_merge_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        reqs: Vec<(DapBatchBucket, AggregateStoreMergeReq)>,
+    ) -> Result<Vec<Result<AggregateStoreMergeResp, Error>>, Error> {
+        Ok(join_all(reqs.into_iter().map(|(bucket, req)| async move {
+            self.aggregate_store_merge(version, task_id, &bucket, req)
+                .await
+        }))
+        .await)
+    }
+
+    /// Same as [`Self::aggregate_store_get`], for several buckets.
+    async fn aggregate_store_get_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<Vec<DapAggregateShare>, Error> {
+        try_join_all(
+            buckets
+                .iter()
+                .map(|bucket| self.aggregate_store_get(version, task_id, bucket)),
+        )
+        .await
+    }
+
+    /// Same as [`Self::aggregate_store_mark_collected`], for several buckets.
+    async fn aggregate_store_mark_collected_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<(), Error> {
+        try_join_all(
+            buckets
+                .iter()
+                .map(|bucket| self.aggregate_store_mark_collected(version, task_id, bucket)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Same as [`Self::aggregate_store_check_collected`], for several buckets.
+    async fn aggregate_store_check_collected_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<Vec<bool>, Error> {
+        try_join_all(
+            buckets
+                .iter()
+                .map(|bucket| self.aggregate_store_check_collected(version, task_id, bucket)),
+        )
+        .await
+    }
+
+    /// Store the Helper's serialized record of an aggregation job unless there is one already.
+    /// Returns `false` if the record already exists.
+    async fn helper_state_put_if_not_exists(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<bool, Error>;
+
+    /// Store the Helper's serialized record of an aggregation job, replacing the existing one.
+    async fn helper_state_put(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<(), Error>;
+
+    /// Get the Helper's serialized record of an aggregation job.
+    async fn helper_state_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<String>, Error>;
+
+    /// Delete the helper state of all of the task's aggregation jobs. Returns the number of
+    /// deleted records, or `None` if the storage can't enumerate its objects, in which case they
+    /// are left to be garbage collected by the storage itself.
+    async fn helper_state_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error>;
+
+    /// Check whether the batch overlaps with a previously collected batch of the task.
+    async fn collected_batches_check_overlapping(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, Error>;
+
+    /// Record the collection of a batch by the aggregate share request with the given hash, unless
+    /// it overlaps with a previously collected batch of the task or exceeds its query limit.
+    /// Returns `false` if it does. See [`daphne::DapCollectedBatches::insert`].
+    async fn collected_batches_insert(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        request_hash: &[u8; 32],
+    ) -> Result<bool, Error>;
+
+    /// Delete the record of the task's collected batches. Returns the number of deleted records,
+    /// or `None` if the storage can't enumerate its objects, in which case it is left to be
+    /// garbage collected by the storage itself.
+    async fn collected_batches_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error>;
+
+    /// Delete all state.
+    #[cfg(feature = "test-utils")]
+    async fn purge(&self) -> Result<(), Error>;
+
+    /// Check that the storage is ready to serve requests.
+    #[cfg(feature = "test-utils")]
+    async fn ready(&self) -> Result<(), Error>;
+}
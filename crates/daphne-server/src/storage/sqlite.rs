@@ -0,0 +1,1069 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! A [`Storage`] backed by an embedded `SQLite` database.
+//!
+//! The durable objects are stored in one table per binding, keyed by the name the storage proxy
+//! would derive the object's ID from (see
+//! [`DurableMethod::name`](daphne_service_utils::durable_requests::bindings::DurableMethod::name)).
+//! Operations that read and then update an object run in a transaction, which plays the role of
+//! the durable object's single-threaded execution.
+
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::async_trait;
+use daphne::{
+    messages::{AggregationJobId, BatchSelector, ReportId, TaskId},
+    DapAggregateShare, DapBatchBucket, DapCollectedBatches, DapVersion,
+};
+use daphne_service_utils::{
+    durable_requests::bindings::{
+        self, AggregateStoreMergeReq, AggregateStoreMergeResp, CollectedBatchesInsertReq,
+        DurableMethod,
+    },
+    metrics::DaphneServiceMetrics,
+};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Storage};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS kv (
+        key TEXT PRIMARY KEY NOT NULL,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS aggregate_store (
+        name TEXT PRIMARY KEY NOT NULL,
+        agg_share BLOB,
+        collected INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS aggregate_store_report_ids (
+        name TEXT NOT NULL,
+        report_id BLOB NOT NULL,
+        PRIMARY KEY (name, report_id)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS aggregate_store_agg_job_ids (
+        name TEXT NOT NULL,
+        agg_job_id BLOB NOT NULL,
+        PRIMARY KEY (name, agg_job_id)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS helper_state (
+        name TEXT PRIMARY KEY NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS collected_batches (
+        name TEXT PRIMARY KEY NOT NULL,
+        batches BLOB NOT NULL
+    );
+";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SqliteConfig {
+    /// The database file, created if it does not exist. If not set, the state is kept in memory
+    /// and lost when the process exits.
+    pub path: Option<PathBuf>,
+}
+
+pub(crate) struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+    metrics: Arc<dyn DaphneServiceMetrics>,
+}
+
+impl SqliteStorage {
+    pub fn open(
+        config: &SqliteConfig,
+        metrics: Arc<dyn DaphneServiceMetrics>,
+    ) -> Result<Self, Error> {
+        let conn = match &config.path {
+            Some(path) => Connection::open(path)?,
+            None => Connection::open_in_memory()?,
+        };
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            metrics,
+        })
+    }
+
+    /// Run `op` on the connection, off the async runtime. `method` identifies the operation in
+    /// the storage latency metric.
+    async fn run<R, F>(&self, method: &str, op: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Connection) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let start = Instant::now();
+        let result = tokio::task::spawn_blocking(move || {
+            // A panic while holding the lock can't leave the database in an inconsistent
+            // state, since every update is done in a single statement or transaction.
+            let mut conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            op(&mut conn)
+        })
+        .await?;
+        self.metrics
+            .daphne()
+            .storage_req_observe_latency(method, start.elapsed());
+        result
+    }
+}
+
+fn aggregate_store_name(version: DapVersion, task_id: &TaskId, bucket: &DapBatchBucket) -> String {
+    bindings::AggregateStore::name((version, &task_id.to_hex(), bucket)).unwrap_from_name()
+}
+
+/// The prefix of the names of the task's durable objects.
+fn task_name_prefix(version: DapVersion, task_id: &TaskId) -> String {
+    format!(
+        "{}/",
+        bindings::durable_name_task(version, &task_id.to_hex())
+    )
+}
+
+fn get_agg_share(conn: &Connection, name: &str) -> Result<DapAggregateShare, Error> {
+    let agg_share: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT agg_share FROM aggregate_store WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(agg_share
+        .map(|bytes| bincode::deserialize(&bytes))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn get_collected_batches(conn: &Connection, name: &str) -> Result<DapCollectedBatches, Error> {
+    let batches: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT batches FROM collected_batches WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(batches
+        .map(|bytes| bincode::deserialize(&bytes))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// The methods of the durable objects, addressed by the object's name. These back both the
+/// [`Storage`] implementation and the [storage proxy server](crate::storage_proxy).
+impl SqliteStorage {
+    pub async fn aggregate_store_merge_by_name(
+        &self,
+        name: String,
+        req: AggregateStoreMergeReq,
+    ) -> Result<AggregateStoreMergeResp, Error> {
+        let method = bindings::AggregateStore::Merge.to_uri();
+        self.run(method, move |conn| {
+            let AggregateStoreMergeReq {
+                contained_reports,
+                agg_share_delta,
+                agg_job_id,
+            } = req;
+
+            // The transaction is rolled back when dropped, i.e., unless it's committed.
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            // The aggregation job was already merged, e.g., by an attempt that was interrupted.
+            if tx
+                .prepare_cached(
+                    "SELECT 1 FROM aggregate_store_agg_job_ids WHERE name = ?1 AND agg_job_id = ?2",
+                )?
+                .exists(params![name, &agg_job_id.0[..]])?
+            {
+                return Ok(AggregateStoreMergeResp::Ok);
+            }
+
+            let collected = tx
+                .query_row(
+                    "SELECT collected FROM aggregate_store WHERE name = ?1",
+                    [&name],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(false);
+            if collected {
+                return Ok(AggregateStoreMergeResp::AlreadyCollected);
+            }
+
+            {
+                // check for replays
+                let mut exists = tx.prepare_cached(
+                    "SELECT 1 FROM aggregate_store_report_ids WHERE name = ?1 AND report_id = ?2",
+                )?;
+                let mut repeat_ids = HashSet::<ReportId>::new();
+                for id in &contained_reports {
+                    if exists.exists(params![name, &id.0[..]])? {
+                        repeat_ids.insert(*id);
+                    }
+                }
+                if !repeat_ids.is_empty() {
+                    return Ok(AggregateStoreMergeResp::ReplaysDetected(repeat_ids));
+                }
+
+                let mut insert = tx.prepare_cached(
+                    "INSERT INTO aggregate_store_report_ids (name, report_id) VALUES (?1, ?2)
+                     ON CONFLICT DO NOTHING",
+                )?;
+                for id in &contained_reports {
+                    insert.execute(params![name, &id.0[..]])?;
+                }
+                tx.execute(
+                    "INSERT INTO aggregate_store_agg_job_ids (name, agg_job_id) VALUES (?1, ?2)",
+                    params![name, &agg_job_id.0[..]],
+                )?;
+            }
+
+            let mut agg_share = get_agg_share(&tx, &name)?;
+            agg_share.merge(agg_share_delta)?;
+            tx.execute(
+                "INSERT INTO aggregate_store (name, agg_share) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET agg_share = excluded.agg_share",
+                params![name, bincode::serialize(&agg_share)?],
+            )?;
+
+            tx.commit()?;
+            Ok(AggregateStoreMergeResp::Ok)
+        })
+        .await
+    }
+
+    pub async fn aggregate_store_get_by_name(
+        &self,
+        name: String,
+    ) -> Result<DapAggregateShare, Error> {
+        let method = bindings::AggregateStore::Get.to_uri();
+        self.run(method, move |conn| get_agg_share(conn, &name))
+            .await
+    }
+
+    pub async fn aggregate_store_get_merged_by_name(
+        &self,
+        name: String,
+    ) -> Result<HashSet<ReportId>, Error> {
+        let method = bindings::AggregateStore::GetMerged.to_uri();
+        self.run(method, move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT report_id FROM aggregate_store_report_ids WHERE name = ?1")?;
+            let report_ids = stmt
+                .query_map([name], |row| row.get::<_, [u8; 16]>(0).map(ReportId))?
+                .collect::<Result<_, _>>()?;
+            Ok(report_ids)
+        })
+        .await
+    }
+
+    pub async fn aggregate_store_mark_collected_by_name(&self, name: String) -> Result<(), Error> {
+        let method = bindings::AggregateStore::MarkCollected.to_uri();
+        self.run(method, move |conn| {
+            conn.execute(
+                "INSERT INTO aggregate_store (name, collected) VALUES (?1, 1)
+                 ON CONFLICT (name) DO UPDATE SET collected = 1",
+                [name],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn aggregate_store_check_collected_by_name(
+        &self,
+        name: String,
+    ) -> Result<bool, Error> {
+        let method = bindings::AggregateStore::CheckCollected.to_uri();
+        self.run(method, move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT collected FROM aggregate_store WHERE name = ?1",
+                    [name],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(false))
+        })
+        .await
+    }
+
+    pub async fn aggregate_store_delete_by_name(&self, name: String) -> Result<(), Error> {
+        let method = bindings::AggregateStore::Delete.to_uri();
+        self.run(method, move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM aggregate_store WHERE name = ?1", [&name])?;
+            tx.execute(
+                "DELETE FROM aggregate_store_report_ids WHERE name = ?1",
+                [&name],
+            )?;
+            tx.execute(
+                "DELETE FROM aggregate_store_agg_job_ids WHERE name = ?1",
+                [&name],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Delete the aggregate store of the buckets whose name starts with `prefix`. Returns the
+    /// number of deleted buckets.
+    pub async fn aggregate_store_delete_by_prefix(&self, prefix: String) -> Result<u64, Error> {
+        self.run("aggregate_store/delete_by_prefix", move |conn| {
+            let tx = conn.transaction()?;
+            let deleted = tx.execute(
+                "DELETE FROM aggregate_store WHERE substr(name, 1, length(?1)) = ?1",
+                [&prefix],
+            )?;
+            tx.execute(
+                "DELETE FROM aggregate_store_report_ids WHERE substr(name, 1, length(?1)) = ?1",
+                [&prefix],
+            )?;
+            tx.execute(
+                "DELETE FROM aggregate_store_agg_job_ids WHERE substr(name, 1, length(?1)) = ?1",
+                [&prefix],
+            )?;
+            tx.commit()?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+
+    pub async fn helper_state_put_if_not_exists_by_name(
+        &self,
+        name: String,
+        record: String,
+    ) -> Result<bool, Error> {
+        let method = bindings::HelperState::PutIfNotExists.to_uri();
+        self.run(method, move |conn| {
+            let inserted = conn.execute(
+                "INSERT INTO helper_state (name, record) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO NOTHING",
+                params![name, record],
+            )?;
+            Ok(inserted == 1)
+        })
+        .await
+    }
+
+    pub async fn helper_state_put_by_name(
+        &self,
+        name: String,
+        record: String,
+    ) -> Result<(), Error> {
+        let method = bindings::HelperState::Put.to_uri();
+        self.run(method, move |conn| {
+            conn.execute(
+                "INSERT INTO helper_state (name, record) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET record = excluded.record",
+                params![name, record],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn helper_state_get_by_name(&self, name: String) -> Result<Option<String>, Error> {
+        let method = bindings::HelperState::Get.to_uri();
+        self.run(method, move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT record FROM helper_state WHERE name = ?1",
+                    [name],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    /// Delete the helper state of the aggregation jobs whose name starts with `prefix`. Returns
+    /// the number of deleted records.
+    pub async fn helper_state_delete_by_prefix(&self, prefix: String) -> Result<u64, Error> {
+        self.run("helper_state/delete_by_prefix", move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM helper_state WHERE substr(name, 1, length(?1)) = ?1",
+                [prefix],
+            )?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+
+    pub async fn collected_batches_check_overlapping_by_name(
+        &self,
+        name: String,
+        batch_sel: BatchSelector,
+    ) -> Result<bool, Error> {
+        let method = bindings::CollectedBatches::CheckOverlapping.to_uri();
+        self.run(method, move |conn| {
+            Ok(get_collected_batches(conn, &name)?.is_overlapping(&batch_sel))
+        })
+        .await
+    }
+
+    pub async fn collected_batches_insert_by_name(
+        &self,
+        name: String,
+        req: CollectedBatchesInsertReq,
+    ) -> Result<bool, Error> {
+        let method = bindings::CollectedBatches::Insert.to_uri();
+        self.run(method, move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut collected = get_collected_batches(&tx, &name)?;
+            if !collected.insert(&req.batch_sel, &req.request_hash) {
+                return Ok(false);
+            }
+            tx.execute(
+                "INSERT INTO collected_batches (name, batches) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET batches = excluded.batches",
+                params![name, bincode::serialize(&collected)?],
+            )?;
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Delete the records of collected batches whose name starts with `prefix`. Returns the number
+    /// of deleted records.
+    pub async fn collected_batches_delete_by_prefix(&self, prefix: String) -> Result<u64, Error> {
+        self.run("collected_batches/delete_by_prefix", move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM collected_batches WHERE substr(name, 1, length(?1)) = ?1",
+                [prefix],
+            )?;
+            Ok(deleted as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn kv_get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let key = key.to_owned();
+        self.run("kv/get", move |conn| {
+            Ok(conn
+                .query_row("SELECT value FROM kv WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn kv_put(&self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        let key = key.to_owned();
+        self.run("kv/put", move |conn| {
+            conn.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn kv_put_if_not_exists(&self, key: &str, value: Vec<u8>) -> Result<bool, Error> {
+        let key = key.to_owned();
+        self.run("kv/put_if_not_exists", move |conn| {
+            let inserted = conn.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO NOTHING",
+                params![key, value],
+            )?;
+            Ok(inserted == 1)
+        })
+        .await
+    }
+
+    async fn kv_delete(&self, key: &str) -> Result<(), Error> {
+        let key = key.to_owned();
+        self.run("kv/delete", move |conn| {
+            conn.execute("DELETE FROM kv WHERE key = ?1", [key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn kv_list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let prefix = prefix.to_owned();
+        self.run("kv/list", move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT key FROM kv WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")?;
+            let keys = stmt
+                .query_map([prefix], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn aggregate_store_merge(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+        req: AggregateStoreMergeReq,
+    ) -> Result<AggregateStoreMergeResp, Error> {
+        self.aggregate_store_merge_by_name(aggregate_store_name(version, task_id, bucket), req)
+            .await
+    }
+
+    async fn aggregate_store_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<DapAggregateShare, Error> {
+        self.aggregate_store_get_by_name(aggregate_store_name(version, task_id, bucket))
+            .await
+    }
+
+    async fn aggregate_store_mark_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error> {
+        self.aggregate_store_mark_collected_by_name(aggregate_store_name(version, task_id, bucket))
+            .await
+    }
+
+    async fn aggregate_store_check_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<bool, Error> {
+        self.aggregate_store_check_collected_by_name(aggregate_store_name(version, task_id, bucket))
+            .await
+    }
+
+    async fn aggregate_store_delete(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error> {
+        self.aggregate_store_delete_by_name(aggregate_store_name(version, task_id, bucket))
+            .await
+    }
+
+    async fn aggregate_store_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.aggregate_store_delete_by_prefix(task_name_prefix(version, task_id))
+            .await
+            .map(Some)
+    }
+
+    async fn helper_state_put_if_not_exists(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<bool, Error> {
+        self.helper_state_put_if_not_exists_by_name(
+            bindings::HelperState::name((version, task_id, agg_job_id)).unwrap_from_name(),
+            record,
+        )
+        .await
+    }
+
+    async fn helper_state_put(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<(), Error> {
+        self.helper_state_put_by_name(
+            bindings::HelperState::name((version, task_id, agg_job_id)).unwrap_from_name(),
+            record,
+        )
+        .await
+    }
+
+    async fn helper_state_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<String>, Error> {
+        self.helper_state_get_by_name(
+            bindings::HelperState::name((version, task_id, agg_job_id)).unwrap_from_name(),
+        )
+        .await
+    }
+
+    async fn helper_state_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.helper_state_delete_by_prefix(task_name_prefix(version, task_id))
+            .await
+            .map(Some)
+    }
+
+    async fn collected_batches_check_overlapping(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, Error> {
+        self.collected_batches_check_overlapping_by_name(
+            bindings::CollectedBatches::name((version, task_id)).unwrap_from_name(),
+            batch_sel.clone(),
+        )
+        .await
+    }
+
+    async fn collected_batches_insert(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        request_hash: &[u8; 32],
+    ) -> Result<bool, Error> {
+        self.collected_batches_insert_by_name(
+            bindings::CollectedBatches::name((version, task_id)).unwrap_from_name(),
+            CollectedBatchesInsertReq {
+                batch_sel: batch_sel.clone(),
+                request_hash: *request_hash,
+            },
+        )
+        .await
+    }
+
+    async fn collected_batches_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.collected_batches_delete_by_prefix(task_name_prefix(version, task_id))
+            .await
+            .map(Some)
+    }
+
+    #[cfg(feature = "test-utils")]
+    async fn purge(&self) -> Result<(), Error> {
+        self.run("purge", |conn| {
+            conn.execute_batch(
+                "BEGIN;
+                 DELETE FROM kv;
+                 DELETE FROM aggregate_store;
+                 DELETE FROM aggregate_store_report_ids;
+                 DELETE FROM aggregate_store_agg_job_ids;
+                 DELETE FROM helper_state;
+                 DELETE FROM collected_batches;
+                 COMMIT;",
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg(feature = "test-utils")]
+    async fn ready(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc};
+
+    use daphne::{
+        messages::{AggregationJobId, BatchId, BatchSelector, Interval, ReportId, TaskId},
+        DapAggregateShare, DapBatchBucket, DapVersion,
+    };
+    use daphne_service_utils::{
+        durable_requests::bindings::{AggregateStoreMergeReq, AggregateStoreMergeResp},
+        metrics::DaphnePromServiceMetrics,
+    };
+
+    use super::{SqliteConfig, SqliteStorage};
+    use crate::storage::Storage;
+
+    fn storage() -> SqliteStorage {
+        SqliteStorage::open(
+            &SqliteConfig::default(),
+            Arc::new(DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap()),
+        )
+        .unwrap()
+    }
+
+    /// A request to merge the reports on behalf of a new aggregation job.
+    fn merge_req(report_ids: &[ReportId], report_count: u64) -> AggregateStoreMergeReq {
+        AggregateStoreMergeReq {
+            contained_reports: report_ids.to_vec(),
+            agg_share_delta: DapAggregateShare {
+                report_count,
+                min_time: 1_000,
+                max_time: 1_000,
+                ..Default::default()
+            },
+            agg_job_id: AggregationJobId(rand::random()),
+        }
+    }
+
+    #[tokio::test]
+    async fn kv_put_if_not_exists() {
+        let storage = storage();
+        assert!(storage
+            .kv_put_if_not_exists("some/key", b"one".to_vec())
+            .await
+            .unwrap());
+        assert!(!storage
+            .kv_put_if_not_exists("some/key", b"two".to_vec())
+            .await
+            .unwrap());
+        assert_eq!(
+            storage.kv_get("some/key").await.unwrap().as_deref(),
+            Some(&b"one"[..])
+        );
+
+        storage.kv_delete("some/key").await.unwrap();
+        assert_eq!(storage.kv_get("some/key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn aggregate_store_merge_detects_replays() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let bucket = DapBatchBucket::TimeInterval { batch_window: 0 };
+        let version = DapVersion::Draft09;
+
+        let resp = storage
+            .aggregate_store_merge(
+                version,
+                &task_id,
+                &bucket,
+                merge_req(&[ReportId([1; 16]), ReportId([2; 16])], 2),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+
+        // Nothing is merged if any of the reports was aggregated before.
+        let resp = storage
+            .aggregate_store_merge(
+                version,
+                &task_id,
+                &bucket,
+                merge_req(&[ReportId([2; 16]), ReportId([3; 16])], 2),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            resp,
+            AggregateStoreMergeResp::ReplaysDetected(replays)
+                if replays == HashSet::from([ReportId([2; 16])])
+        ));
+        let agg_share = storage
+            .aggregate_store_get(version, &task_id, &bucket)
+            .await
+            .unwrap();
+        assert_eq!(agg_share.report_count, 2);
+
+        // Report IDs are scoped to the bucket.
+        let other_bucket = DapBatchBucket::TimeInterval { batch_window: 3600 };
+        let resp = storage
+            .aggregate_store_merge(
+                version,
+                &task_id,
+                &other_bucket,
+                merge_req(&[ReportId([2; 16])], 1),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+    }
+
+    #[tokio::test]
+    async fn aggregate_store_merge_is_idempotent_per_agg_job() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let bucket = DapBatchBucket::TimeInterval { batch_window: 0 };
+        let version = DapVersion::Draft09;
+
+        let req = || AggregateStoreMergeReq {
+            agg_job_id: AggregationJobId([7; 16]),
+            ..merge_req(&[ReportId([1; 16])], 1)
+        };
+        for _ in 0..2 {
+            let resp = storage
+                .aggregate_store_merge(version, &task_id, &bucket, req())
+                .await
+                .unwrap();
+            assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+        }
+        let agg_share = storage
+            .aggregate_store_get(version, &task_id, &bucket)
+            .await
+            .unwrap();
+        assert_eq!(agg_share.report_count, 1);
+
+        // The aggregation job was merged before the bucket was collected.
+        storage
+            .aggregate_store_mark_collected(version, &task_id, &bucket)
+            .await
+            .unwrap();
+        let resp = storage
+            .aggregate_store_merge(version, &task_id, &bucket, req())
+            .await
+            .unwrap();
+        assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+    }
+
+    #[tokio::test]
+    async fn aggregate_store_merge_after_collected() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let bucket = DapBatchBucket::TimeInterval { batch_window: 0 };
+        let version = DapVersion::Draft09;
+
+        assert!(!storage
+            .aggregate_store_check_collected(version, &task_id, &bucket)
+            .await
+            .unwrap());
+        storage
+            .aggregate_store_merge(
+                version,
+                &task_id,
+                &bucket,
+                merge_req(&[ReportId([1; 16])], 1),
+            )
+            .await
+            .unwrap();
+        storage
+            .aggregate_store_mark_collected(version, &task_id, &bucket)
+            .await
+            .unwrap();
+        assert!(storage
+            .aggregate_store_check_collected(version, &task_id, &bucket)
+            .await
+            .unwrap());
+
+        let resp = storage
+            .aggregate_store_merge(
+                version,
+                &task_id,
+                &bucket,
+                merge_req(&[ReportId([2; 16])], 1),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(resp, AggregateStoreMergeResp::AlreadyCollected));
+
+        // Deleting the bucket resets its state.
+        storage
+            .aggregate_store_delete(version, &task_id, &bucket)
+            .await
+            .unwrap();
+        assert!(!storage
+            .aggregate_store_check_collected(version, &task_id, &bucket)
+            .await
+            .unwrap());
+        assert!(storage
+            .aggregate_store_get(version, &task_id, &bucket)
+            .await
+            .unwrap()
+            .empty());
+    }
+
+    #[tokio::test]
+    async fn kv_list() {
+        let storage = storage();
+        for key in ["a/1", "a/2", "ab/3", "b/4"] {
+            storage.kv_put(key, b"value".to_vec()).await.unwrap();
+        }
+        assert_eq!(storage.kv_list("a/").await.unwrap(), ["a/1", "a/2"]);
+        assert!(storage.kv_list("c/").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_task() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let other_task_id = TaskId([2; 32]);
+        let version = DapVersion::Draft09;
+        let buckets = [
+            DapBatchBucket::TimeInterval { batch_window: 0 },
+            DapBatchBucket::FixedSize {
+                batch_id: BatchId([3; 32]),
+            },
+        ];
+        let batch_sel = BatchSelector::FixedSizeByBatchId {
+            batch_id: BatchId([3; 32]),
+        };
+
+        for (task_id, bucket) in [&task_id, &other_task_id]
+            .into_iter()
+            .flat_map(|task_id| buckets.iter().map(move |bucket| (task_id, bucket)))
+        {
+            storage
+                .aggregate_store_merge(version, task_id, bucket, merge_req(&[ReportId([1; 16])], 1))
+                .await
+                .unwrap();
+            storage
+                .helper_state_put_if_not_exists(
+                    version,
+                    task_id,
+                    &AggregationJobId([4; 16]),
+                    "record".into(),
+                )
+                .await
+                .unwrap();
+            storage
+                .collected_batches_insert(version, task_id, &batch_sel, &[5; 32])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            storage
+                .aggregate_store_delete_task(version, &task_id)
+                .await
+                .unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            storage
+                .helper_state_delete_task(version, &task_id)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            storage
+                .collected_batches_delete_task(version, &task_id)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        for bucket in &buckets {
+            assert!(storage
+                .aggregate_store_get(version, &task_id, bucket)
+                .await
+                .unwrap()
+                .empty());
+            // The report IDs were deleted too.
+            let resp = storage
+                .aggregate_store_merge(
+                    version,
+                    &task_id,
+                    bucket,
+                    merge_req(&[ReportId([1; 16])], 1),
+                )
+                .await
+                .unwrap();
+            assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+            assert_eq!(
+                storage
+                    .aggregate_store_get(version, &other_task_id, bucket)
+                    .await
+                    .unwrap()
+                    .report_count,
+                1
+            );
+        }
+        assert_eq!(
+            storage
+                .helper_state_get(version, &task_id, &AggregationJobId([4; 16]))
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(storage
+            .helper_state_get(version, &other_task_id, &AggregationJobId([4; 16]))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(!storage
+            .collected_batches_check_overlapping(version, &task_id, &batch_sel)
+            .await
+            .unwrap());
+        assert!(storage
+            .collected_batches_check_overlapping(version, &other_task_id, &batch_sel)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn helper_state_put_if_not_exists() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let agg_job_id = AggregationJobId([2; 16]);
+        let version = DapVersion::Draft09;
+
+        assert!(storage
+            .helper_state_put_if_not_exists(version, &task_id, &agg_job_id, "first".into())
+            .await
+            .unwrap());
+        assert!(!storage
+            .helper_state_put_if_not_exists(version, &task_id, &agg_job_id, "second".into())
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .helper_state_get(version, &task_id, &agg_job_id)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("first")
+        );
+    }
+
+    #[tokio::test]
+    async fn collected_batches_insert_rejects_overlapping() {
+        let storage = storage();
+        let task_id = TaskId([1; 32]);
+        let version = DapVersion::Draft09;
+        let batch_sel = |start, duration| BatchSelector::TimeInterval {
+            batch_interval: Interval { start, duration },
+        };
+
+        assert!(storage
+            .collected_batches_insert(version, &task_id, &batch_sel(0, 7200), &[1; 32])
+            .await
+            .unwrap());
+        assert!(storage
+            .collected_batches_check_overlapping(version, &task_id, &batch_sel(3600, 7200))
+            .await
+            .unwrap());
+        assert!(!storage
+            .collected_batches_insert(version, &task_id, &batch_sel(3600, 7200), &[2; 32])
+            .await
+            .unwrap());
+        assert!(storage
+            .collected_batches_insert(version, &task_id, &batch_sel(7200, 3600), &[3; 32])
+            .await
+            .unwrap());
+
+        // Recording the same request again, i.e., on retransmission, succeeds.
+        assert!(storage
+            .collected_batches_insert(version, &task_id, &batch_sel(0, 7200), &[1; 32])
+            .await
+            .unwrap());
+
+        // Any other query for the same batch is rejected.
+        assert!(!storage
+            .collected_batches_insert(version, &task_id, &batch_sel(0, 7200), &[4; 32])
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,782 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! A native implementation of the storage proxy, i.e., of the HTTP protocol served by the
+//! `daphne-worker` storage proxy, backed by an embedded `SQLite` database instead of Workers KV and
+//! Durable Objects. An [`App`](crate::App) configured with a
+//! [`StorageProxyConfig`](crate::StorageProxyConfig) can use it without changes.
+//!
+//! Every request must carry the configured bearer token in the "Authorization" header.
+//!
+//! - `GET`, `POST`, `PUT` and `DELETE` requests to `{KV_PATH_PREFIX}/path/to/key` respectively get,
+//!   put, put if not exists, and delete a key.
+//! - `POST` requests to `{DO_PATH_PREFIX}{DURABLE_OBJECT_METHOD}` carry a [`DurableRequest`] and
+//!   call a method of the `AggregateStore`, `HelperState` or `CollectedBatches` durable objects.
+//!   The request body is encoded with bincode and the response with JSON.
+//! - `POST` requests to [`DO_BATCH_PATH`] carry a [`DurableRequestBatch`], whose requests are
+//!   handled concurrently. The response is a JSON array of [`DurableResponse`]s.
+//! - `GET` requests to `{KV_LIST_PATH_PREFIX}/path/to/prefix` list the keys with the prefix.
+//! - `DELETE` requests to `{DO_TASK_PATH_PREFIX}/{binding}/{version}/{task_id_hex}` delete the
+//!   task's `AggregateStore`, `HelperState` or `CollectedBatches` objects and respond with the
+//!   number of deleted objects.
+//!
+//! Unlike durable objects, the objects stored by this server are never garbage collected.
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequestParts, Path, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        request::Parts,
+        StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use daphne::{auth::BearerToken, fatal_error, messages::TaskId, DapError, DapVersion};
+use daphne_service_utils::{
+    durable_requests::{
+        bindings::{self, DurableMethod},
+        DurableRequest, DurableRequestBatch, DurableResponse, ObjectIdFrom, DO_BATCH_PATH,
+        DO_PATH_PREFIX, DO_TASK_PATH_PREFIX, KV_LIST_PATH_PREFIX, KV_PATH_PREFIX,
+    },
+    metrics::DaphneServiceMetrics,
+};
+use futures::future::join_all;
+use hex::FromHex;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::storage::{self, SqliteConfig, SqliteStorage, Storage};
+
+struct ProxyState {
+    storage: SqliteStorage,
+    auth_token: BearerToken,
+}
+
+/// Create a router that serves the storage proxy protocol, storing the state in the database
+/// configured by `config`. Requests must be authorized with `auth_token`.
+pub fn router<M>(
+    config: &SqliteConfig,
+    auth_token: BearerToken,
+    metrics: M,
+) -> Result<Router, DapError>
+where
+    M: DaphneServiceMetrics + 'static,
+{
+    let storage = SqliteStorage::open(config, Arc::new(metrics))
+        .map_err(|e| fatal_error!(err = ?e, "failed to open sqlite database"))?;
+    let router = Router::new()
+        .route(
+            &format!("{KV_PATH_PREFIX}/*key"),
+            get(kv_get)
+                .post(kv_put)
+                .put(kv_put_if_not_exists)
+                .delete(kv_delete),
+        )
+        .route(&format!("{KV_LIST_PATH_PREFIX}/*prefix"), get(kv_list))
+        .route(&format!("{DO_PATH_PREFIX}/*method"), post(durable))
+        .route(DO_BATCH_PATH, post(durable_batch))
+        .route(
+            &format!("{DO_TASK_PATH_PREFIX}/:binding/:version/:task_id"),
+            delete(durable_delete_task),
+        );
+
+    #[cfg(feature = "test-utils")]
+    let router = {
+        use daphne_service_utils::durable_requests::{PURGE_STORAGE, STORAGE_READY};
+        router
+            .route(PURGE_STORAGE, axum::routing::any(purge))
+            .route(STORAGE_READY, get(|_: ProxyAuth| async { StatusCode::OK }))
+    };
+
+    Ok(router.with_state(Arc::new(ProxyState {
+        storage,
+        auth_token,
+    })))
+}
+
+/// An axum extractor that rejects requests that don't carry the storage proxy's bearer token.
+struct ProxyAuth;
+
+#[async_trait]
+impl FromRequestParts<Arc<ProxyState>> for ProxyAuth {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ProxyState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(BearerToken::from)
+            .ok_or((
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized: missing or malformed Authorization header",
+            ))?;
+        if token == state.auth_token {
+            Ok(Self)
+        } else {
+            Err((
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized: Incorrect authorization token",
+            ))
+        }
+    }
+}
+
+fn storage_error(e: storage::Error) -> (StatusCode, String) {
+    tracing::error!(error = ?e, "storage request failed");
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// The key of a KV request, i.e., the path without the [`KV_PATH_PREFIX`].
+fn kv_key(uri: &Uri) -> &str {
+    uri.path()
+        .strip_prefix(KV_PATH_PREFIX)
+        .and_then(|key| key.strip_prefix('/'))
+        .unwrap_or_default()
+}
+
+async fn kv_get(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+) -> Result<Response, (StatusCode, String)> {
+    match state
+        .storage
+        .kv_get(kv_key(&uri))
+        .await
+        .map_err(storage_error)?
+    {
+        Some(value) => Ok(value.into_response()),
+        None => Ok((StatusCode::NOT_FOUND, "value not found").into_response()),
+    }
+}
+
+async fn kv_put(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .storage
+        .kv_put(kv_key(&uri), body.to_vec())
+        .await
+        .map_err(storage_error)?;
+    Ok(StatusCode::OK)
+}
+
+async fn kv_put_if_not_exists(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let inserted = state
+        .storage
+        .kv_put_if_not_exists(kv_key(&uri), body.to_vec())
+        .await
+        .map_err(storage_error)?;
+    Ok(if inserted {
+        StatusCode::OK
+    } else {
+        StatusCode::CONFLICT
+    })
+}
+
+async fn kv_delete(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .storage
+        .kv_delete(kv_key(&uri))
+        .await
+        .map_err(storage_error)?;
+    Ok(StatusCode::OK)
+}
+
+async fn kv_list(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let prefix = uri
+        .path()
+        .strip_prefix(KV_LIST_PATH_PREFIX)
+        .and_then(|prefix| prefix.strip_prefix('/'))
+        .unwrap_or_default();
+    Ok(Json(
+        state.storage.kv_list(prefix).await.map_err(storage_error)?,
+    ))
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, (StatusCode, String)> {
+    bincode::deserialize(body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("failed to deserialize bincode: {e:?}"),
+        )
+    })
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// Handle a durable object request.
+async fn durable(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    uri: Uri,
+    body: Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    let method = uri.path().strip_prefix(DO_PATH_PREFIX).unwrap_or_default();
+    let req = DurableRequest::try_from(&body[..])
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid format: {e:?}")))?;
+    let json = call_durable(&state.storage, method, &req).await?;
+    Ok(([(CONTENT_TYPE, "application/json")], json).into_response())
+}
+
+/// Handle a batch of durable object requests. A request that fails doesn't fail the others.
+async fn durable_batch(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    body: Bytes,
+) -> Result<Json<Vec<DurableResponse>>, (StatusCode, String)> {
+    let batch = DurableRequestBatch::try_from(&body[..])
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid format: {e:?}")))?;
+    let responses = join_all(batch.requests().map(|(method, req)| {
+        let storage = &state.storage;
+        async move {
+            let req = req.map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid format: {e:?}")));
+            let result = match req {
+                Ok(req) => call_durable(storage, method, &req).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(body) => DurableResponse {
+                    status: StatusCode::OK.as_u16(),
+                    body,
+                },
+                Err((status, body)) => DurableResponse {
+                    status: status.as_u16(),
+                    body,
+                },
+            }
+        }
+    }))
+    .await;
+    Ok(Json(responses))
+}
+
+/// Call the method on the object with the request's name and return the JSON-encoded result.
+async fn call_durable(
+    storage: &SqliteStorage,
+    method: &str,
+    req: &DurableRequest<&[u8]>,
+) -> Result<String, (StatusCode, String)> {
+    let name = match &req.id {
+        ObjectIdFrom::Name(name) | ObjectIdFrom::Hex(name) => name.clone(),
+    };
+    tracing::debug!(binding = req.binding, method, name, "handling DO request");
+
+    let unexpected = || {
+        (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "unexpected request: binding={:?}; method={method:?}",
+                req.binding
+            ),
+        )
+    };
+    let resp = match req.binding.as_str() {
+        bindings::AggregateStore::BINDING => {
+            match bindings::AggregateStore::try_from_uri(method).ok_or_else(unexpected)? {
+                bindings::AggregateStore::GetMerged => storage
+                    .aggregate_store_get_merged_by_name(name)
+                    .await
+                    .map(|report_ids| to_json(&report_ids)),
+                bindings::AggregateStore::Get => storage
+                    .aggregate_store_get_by_name(name)
+                    .await
+                    .map(|agg_share| to_json(&agg_share)),
+                bindings::AggregateStore::Merge => storage
+                    .aggregate_store_merge_by_name(name, parse(req.body())?)
+                    .await
+                    .map(|resp| to_json(&resp)),
+                bindings::AggregateStore::MarkCollected => storage
+                    .aggregate_store_mark_collected_by_name(name)
+                    .await
+                    .map(|()| to_json(&())),
+                bindings::AggregateStore::CheckCollected => storage
+                    .aggregate_store_check_collected_by_name(name)
+                    .await
+                    .map(|collected| to_json(&collected)),
+                bindings::AggregateStore::Delete => storage
+                    .aggregate_store_delete_by_name(name)
+                    .await
+                    .map(|()| to_json(&())),
+            }
+        }
+        bindings::HelperState::BINDING => {
+            match bindings::HelperState::try_from_uri(method).ok_or_else(unexpected)? {
+                bindings::HelperState::PutIfNotExists => storage
+                    .helper_state_put_if_not_exists_by_name(name, parse(req.body())?)
+                    .await
+                    .map(|success| to_json(&success)),
+                bindings::HelperState::Put => storage
+                    .helper_state_put_by_name(name, parse(req.body())?)
+                    .await
+                    .map(|()| to_json(&())),
+                bindings::HelperState::Get => storage
+                    .helper_state_get_by_name(name)
+                    .await
+                    .map(|record| to_json(&record)),
+            }
+        }
+        bindings::CollectedBatches::BINDING => {
+            match bindings::CollectedBatches::try_from_uri(method).ok_or_else(unexpected)? {
+                bindings::CollectedBatches::CheckOverlapping => storage
+                    .collected_batches_check_overlapping_by_name(name, parse(req.body())?)
+                    .await
+                    .map(|overlapping| to_json(&overlapping)),
+                bindings::CollectedBatches::Insert => storage
+                    .collected_batches_insert_by_name(name, parse(req.body())?)
+                    .await
+                    .map(|success| to_json(&success)),
+            }
+        }
+        _ => return Err(unexpected()),
+    };
+    resp.map_err(storage_error)
+}
+
+/// Delete all of the task's objects of a durable object binding.
+async fn durable_delete_task(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+    Path((binding, version, task_id)): Path<(String, String, String)>,
+) -> Result<Json<u64>, (StatusCode, String)> {
+    let version = version.parse::<DapVersion>().map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid version: {version}"),
+        )
+    })?;
+    let task_id = <[u8; 32]>::from_hex(&task_id).map(TaskId).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid task ID: {task_id}"),
+        )
+    })?;
+    let prefix = format!(
+        "{}/",
+        bindings::durable_name_task(version, &task_id.to_hex())
+    );
+    let deleted = match binding.as_str() {
+        bindings::AggregateStore::BINDING => {
+            state.storage.aggregate_store_delete_by_prefix(prefix).await
+        }
+        bindings::HelperState::BINDING => state.storage.helper_state_delete_by_prefix(prefix).await,
+        bindings::CollectedBatches::BINDING => {
+            state
+                .storage
+                .collected_batches_delete_by_prefix(prefix)
+                .await
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("unexpected binding: {binding:?}"),
+            ))
+        }
+    };
+    Ok(Json(deleted.map_err(storage_error)?))
+}
+
+#[cfg(feature = "test-utils")]
+async fn purge(
+    _: ProxyAuth,
+    State(state): State<Arc<ProxyState>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state.storage.purge().await.map_err(storage_error)?;
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc};
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        Router,
+    };
+    use daphne::{
+        messages::{AggregationJobId, BatchSelector, Interval, ReportId, TaskId},
+        DapAggregateShare, DapBatchBucket, DapVersion,
+    };
+    use daphne_service_utils::{
+        durable_requests::{
+            bindings::{self, AggregateStoreMergeReq, AggregateStoreMergeResp, DurableMethod},
+            DurableRequest, DurableRequestBatch, DurableResponse, DO_BATCH_PATH, DO_PATH_PREFIX,
+            KV_PATH_PREFIX,
+        },
+        metrics::DaphnePromServiceMetrics,
+    };
+    use serde::de::DeserializeOwned;
+    use tower::ServiceExt;
+
+    use crate::{
+        storage::Storage, storage_proxy_connection::StorageProxy, SqliteConfig, StorageProxyConfig,
+    };
+
+    fn test_router() -> Router {
+        super::router(
+            &SqliteConfig::default(),
+            "storage-proxy-token".into(),
+            DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    async fn send(router: &Router, req: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let resp = router.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    fn kv_req(method: &str, key: &str, body: &'static str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(format!("{KV_PATH_PREFIX}/{key}"))
+            .header(AUTHORIZATION, "Bearer storage-proxy-token")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn merge<R: DeserializeOwned>(
+        router: &Router,
+        bucket: &DapBatchBucket,
+        agg_job_id: AggregationJobId,
+        report_ids: &[ReportId],
+    ) -> R {
+        let (durable_request, uri) = DurableRequest::new(
+            bindings::AggregateStore::Merge,
+            (DapVersion::Draft09, &TaskId([1; 32]).to_hex(), bucket),
+        );
+        let body = bincode::serialize(&AggregateStoreMergeReq {
+            contained_reports: report_ids.to_vec(),
+            agg_share_delta: DapAggregateShare {
+                report_count: report_ids.len().try_into().unwrap(),
+                ..Default::default()
+            },
+            agg_job_id,
+        })
+        .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("{DO_PATH_PREFIX}{uri}"))
+            .header(AUTHORIZATION, "Bearer storage-proxy-token")
+            .body(Body::from(durable_request.with_body(body).into_bytes()))
+            .unwrap();
+        let (status, body) = send(router, req).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reject_unauthorized() {
+        let req = Request::builder()
+            .uri(format!("{KV_PATH_PREFIX}/some/key"))
+            .header(AUTHORIZATION, "Bearer wrong-token")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&test_router(), req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn kv() {
+        let router = test_router();
+        assert_eq!(
+            send(&router, kv_req("GET", "some/key", "")).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(&router, kv_req("PUT", "some/key", "one")).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&router, kv_req("PUT", "some/key", "two")).await.0,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            send(&router, kv_req("GET", "some/key", "")).await,
+            (StatusCode::OK, b"one".to_vec())
+        );
+        assert_eq!(
+            send(&router, kv_req("POST", "some/key", "three")).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&router, kv_req("GET", "some/key", "")).await,
+            (StatusCode::OK, b"three".to_vec())
+        );
+        assert_eq!(
+            send(&router, kv_req("DELETE", "some/key", "")).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&router, kv_req("GET", "some/key", "")).await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregate_store_merge() {
+        let router = test_router();
+        let bucket = DapBatchBucket::TimeInterval { batch_window: 0 };
+
+        let resp: AggregateStoreMergeResp = merge(
+            &router,
+            &bucket,
+            AggregationJobId([1; 16]),
+            &[ReportId([1; 16]), ReportId([2; 16])],
+        )
+        .await;
+        assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+
+        // A retransmitted merge of the same aggregation job changes nothing.
+        let resp: AggregateStoreMergeResp = merge(
+            &router,
+            &bucket,
+            AggregationJobId([1; 16]),
+            &[ReportId([1; 16]), ReportId([2; 16])],
+        )
+        .await;
+        assert!(matches!(resp, AggregateStoreMergeResp::Ok));
+
+        let resp: AggregateStoreMergeResp = merge(
+            &router,
+            &bucket,
+            AggregationJobId([2; 16]),
+            &[ReportId([2; 16]), ReportId([3; 16])],
+        )
+        .await;
+        assert!(matches!(
+            resp,
+            AggregateStoreMergeResp::ReplaysDetected(replays)
+                if replays == HashSet::from([ReportId([2; 16])])
+        ));
+
+        let (durable_request, uri) = DurableRequest::new(
+            bindings::AggregateStore::Get,
+            (DapVersion::Draft09, &TaskId([1; 32]).to_hex(), &bucket),
+        );
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("{DO_PATH_PREFIX}{uri}"))
+            .header(AUTHORIZATION, "Bearer storage-proxy-token")
+            .body(Body::from(durable_request.into_bytes()))
+            .unwrap();
+        let (status, body) = send(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let agg_share: DapAggregateShare = serde_json::from_slice(&body).unwrap();
+        assert_eq!(agg_share.report_count, 2);
+    }
+
+    #[tokio::test]
+    async fn reject_unexpected_method() {
+        let (durable_request, _) = DurableRequest::new(
+            bindings::AggregateStore::Get,
+            (
+                DapVersion::Draft09,
+                &TaskId([1; 32]).to_hex(),
+                &DapBatchBucket::TimeInterval { batch_window: 0 },
+            ),
+        );
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!(
+                "{DO_PATH_PREFIX}{}",
+                bindings::HelperState::Get.to_uri()
+            ))
+            .header(AUTHORIZATION, "Bearer storage-proxy-token")
+            .body(Body::from(durable_request.into_bytes()))
+            .unwrap();
+        let (status, _) = send(&test_router(), req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn batch() {
+        let router = test_router();
+        let buckets = [0, 1].map(|batch_window| DapBatchBucket::TimeInterval { batch_window });
+        let _: AggregateStoreMergeResp = merge(
+            &router,
+            &buckets[0],
+            AggregationJobId([1; 16]),
+            &[ReportId([1; 16])],
+        )
+        .await;
+
+        let mut batch = DurableRequestBatch::default();
+        for bucket in &buckets {
+            let (durable_request, uri) = DurableRequest::new(
+                bindings::AggregateStore::Get,
+                (DapVersion::Draft09, &TaskId([1; 32]).to_hex(), bucket),
+            );
+            batch.push(uri, durable_request);
+        }
+        let (durable_request, _) = DurableRequest::new(
+            bindings::AggregateStore::Get,
+            (DapVersion::Draft09, &TaskId([1; 32]).to_hex(), &buckets[0]),
+        );
+        batch.push(bindings::HelperState::Get.to_uri(), durable_request);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(DO_BATCH_PATH)
+            .header(AUTHORIZATION, "Bearer storage-proxy-token")
+            .body(Body::from(batch.into_bytes()))
+            .unwrap();
+        let (status, body) = send(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let resps: Vec<DurableResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resps.len(), 3);
+
+        let report_counts = resps[..2]
+            .iter()
+            .map(|resp| {
+                assert!(resp.is_success());
+                serde_json::from_str::<DapAggregateShare>(&resp.body)
+                    .unwrap()
+                    .report_count
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(report_counts, [1, 0]);
+        assert_eq!(resps[2].status, StatusCode::BAD_REQUEST.as_u16());
+    }
+
+    #[tokio::test]
+    async fn storage_proxy_client() {
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(test_router().into_make_service());
+        let url = format!("http://{}", server.local_addr()).parse().unwrap();
+        tokio::spawn(server);
+
+        let storage = StorageProxy::new(
+            StorageProxyConfig {
+                url,
+                auth_token: "storage-proxy-token".into(),
+            },
+            Arc::new(DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap()),
+        );
+        let task_id = TaskId([1; 32]);
+
+        // More buckets than fit in a single batch.
+        let buckets = (0..200)
+            .map(|batch_window| DapBatchBucket::TimeInterval { batch_window })
+            .collect::<Vec<_>>();
+        let results = storage
+            .aggregate_store_merge_many(
+                DapVersion::Draft09,
+                &task_id,
+                buckets
+                    .iter()
+                    .zip(0u8..)
+                    .map(|(bucket, i)| {
+                        let req = AggregateStoreMergeReq {
+                            contained_reports: vec![ReportId([i; 16])],
+                            agg_share_delta: DapAggregateShare {
+                                report_count: 1,
+                                ..Default::default()
+                            },
+                            agg_job_id: AggregationJobId([1; 16]),
+                        };
+                        (bucket.clone(), req)
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(results.len(), buckets.len());
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Ok(AggregateStoreMergeResp::Ok))));
+
+        storage
+            .aggregate_store_mark_collected_many(DapVersion::Draft09, &task_id, &buckets[..1])
+            .await
+            .unwrap();
+        let collected = storage
+            .aggregate_store_check_collected_many(DapVersion::Draft09, &task_id, &buckets)
+            .await
+            .unwrap();
+        assert_eq!(collected.iter().filter(|collected| **collected).count(), 1);
+        assert!(collected[0]);
+
+        let agg_shares = storage
+            .aggregate_store_get_many(DapVersion::Draft09, &task_id, &buckets)
+            .await
+            .unwrap();
+        assert!(agg_shares
+            .iter()
+            .all(|agg_share| agg_share.report_count == 1));
+
+        assert_eq!(
+            storage
+                .aggregate_store_delete_task(DapVersion::Draft09, &task_id)
+                .await
+                .unwrap(),
+            Some(200)
+        );
+
+        let batch_sel = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: 0,
+                duration: 1,
+            },
+        };
+        assert!(storage
+            .collected_batches_insert(DapVersion::Draft09, &task_id, &batch_sel, &[1; 32])
+            .await
+            .unwrap());
+        assert!(!storage
+            .collected_batches_insert(DapVersion::Draft09, &task_id, &batch_sel, &[2; 32])
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .collected_batches_delete_task(DapVersion::Draft09, &task_id)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert!(!storage
+            .collected_batches_check_overlapping(DapVersion::Draft09, &task_id, &batch_sel)
+            .await
+            .unwrap());
+
+        storage
+            .kv_put("some/prefix/key", b"value".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.kv_list("some/prefix/").await.unwrap(),
+            ["some/prefix/key"]
+        );
+    }
+}
@@ -5,42 +5,54 @@
 #![allow(clippy::unused_async)]
 #![allow(dead_code)]
 
-pub(crate) mod kv;
+use std::{fmt::Debug, sync::Arc, time::Instant};
 
-use std::fmt::Debug;
-
-use axum::http::{Method, StatusCode};
-use daphne_service_utils::durable_requests::{
-    bindings::DurableMethod, DurableRequest, ObjectIdFrom, DO_PATH_PREFIX,
+use axum::{
+    async_trait,
+    http::{Method, StatusCode},
+};
+use daphne::{
+    messages::{AggregationJobId, BatchSelector, TaskId},
+    metrics::DaphneMetrics,
+    DapAggregateShare, DapBatchBucket, DapVersion,
+};
+use daphne_service_utils::{
+    durable_requests::{
+        bindings::{
+            self, AggregateStoreMergeReq, AggregateStoreMergeResp, CollectedBatchesInsertReq,
+            DurableMethod,
+        },
+        DurableRequest, DurableRequestBatch, DurableResponse, ObjectIdFrom, DO_BATCH_PATH,
+        DO_PATH_PREFIX, DO_TASK_PATH_PREFIX, KV_LIST_PATH_PREFIX, KV_PATH_PREFIX,
+    },
+    metrics::DaphneServiceMetrics,
 };
+use futures::future::try_join_all;
 use serde::{de::DeserializeOwned, Serialize};
 
-pub(crate) use kv::Kv;
-
-use crate::StorageProxyConfig;
-
-#[derive(Debug, thiserror::Error)]
-pub(crate) enum Error {
-    #[error("serialization error: {0}")]
-    Serde(#[from] serde_json::Error),
-    #[error("network error: {0}")]
-    Reqwest(#[from] reqwest::Error),
-    #[error("http error. request returned status code {status} with the body {body}")]
-    Http { status: StatusCode, body: String },
-}
+use crate::{
+    storage::{Error, Storage},
+    StorageProxyConfig,
+};
 
 #[derive(Clone, Copy)]
 pub(crate) struct Do<'h> {
     config: &'h StorageProxyConfig,
     http: &'h reqwest::Client,
+    metrics: &'h dyn DaphneMetrics,
     retry: bool,
 }
 
 impl<'h> Do<'h> {
-    pub fn new(config: &'h StorageProxyConfig, client: &'h reqwest::Client) -> Self {
+    pub fn new(
+        config: &'h StorageProxyConfig,
+        client: &'h reqwest::Client,
+        metrics: &'h dyn DaphneMetrics,
+    ) -> Self {
         Self {
             config,
             http: client,
+            metrics,
             retry: false,
         }
     }
@@ -75,23 +87,32 @@ impl<'d, B: DurableMethod + Debug, P: AsRef<[u8]>> RequestBuilder<'d, B, P> {
             .url
             .join(&format!("{DO_PATH_PREFIX}{}", self.path.to_uri()))
             .unwrap();
-        let resp = self
-            .durable
-            .http
-            .post(url)
-            .body(self.request.into_bytes())
-            .bearer_auth(&self.durable.config.auth_token)
-            .send()
-            .await?;
+        let start = Instant::now();
+        let resp = async {
+            let resp = self
+                .durable
+                .http
+                .post(url)
+                .body(self.request.into_bytes())
+                .bearer_auth(&self.durable.config.auth_token)
+                .headers(crate::telemetry::trace_context_headers())
+                .send()
+                .await?;
 
-        if resp.status().is_success() {
-            Ok(resp.json().await?)
-        } else {
-            Err(Error::Http {
-                status: status_reqwest_0_11_to_http_1_0(resp.status()),
-                body: resp.text().await?,
-            })
+            if resp.status().is_success() {
+                Ok(resp.json().await?)
+            } else {
+                Err(Error::Http {
+                    status: status_reqwest_0_11_to_http_1_0(resp.status()),
+                    body: resp.text().await?,
+                })
+            }
         }
+        .await;
+        self.durable
+            .metrics
+            .storage_req_observe_latency(self.path.to_uri(), start.elapsed());
+        resp
     }
 }
 
@@ -145,6 +166,519 @@ impl<'w> Do<'w> {
     }
 }
 
+/// The maximum number of requests in a [`DurableRequestBatch`], which bounds the number of
+/// subrequests made by the storage proxy to handle it.
+const MAX_BATCH_LEN: usize = 128;
+
+/// A [`Storage`] that forwards each operation to the storage proxy, a cloudflare worker that
+/// stores the KV values in Workers KV and implements the durable objects.
+pub(crate) struct StorageProxy {
+    config: StorageProxyConfig,
+    http: reqwest::Client,
+    metrics: Arc<dyn DaphneServiceMetrics>,
+}
+
+impl StorageProxy {
+    pub fn new(config: StorageProxyConfig, metrics: Arc<dyn DaphneServiceMetrics>) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metrics,
+        }
+    }
+
+    fn durable(&self) -> Do<'_> {
+        Do::new(&self.config, &self.http, self.metrics.daphne())
+    }
+
+    /// Send durable object requests in batches of at most [`MAX_BATCH_LEN`] requests. Returns the
+    /// result of each request, in the same order as the requests.
+    async fn send_batch<R>(
+        &self,
+        requests: Vec<(DurableRequest<Vec<u8>>, &'static str)>,
+    ) -> Result<Vec<Result<R, Error>>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        let mut batches = Vec::new();
+        for (i, (request, uri)) in requests.into_iter().enumerate() {
+            if i % MAX_BATCH_LEN == 0 {
+                batches.push(DurableRequestBatch::default());
+            }
+            batches.last_mut().unwrap().push(uri, request);
+        }
+
+        let url = self.config.url.join(DO_BATCH_PATH).unwrap();
+        let responses = try_join_all(batches.into_iter().map(|batch| async {
+            let len = batch.len();
+            tracing::debug!(len, "requesting DO batch");
+            let start = Instant::now();
+            let resp = async {
+                let resp = self
+                    .http
+                    .post(url.clone())
+                    .body(batch.into_bytes())
+                    .bearer_auth(&self.config.auth_token)
+                    .headers(crate::telemetry::trace_context_headers())
+                    .send()
+                    .await?;
+
+                if resp.status().is_success() {
+                    Ok(resp.json::<Vec<DurableResponse>>().await?)
+                } else {
+                    Err(Error::Http {
+                        status: status_reqwest_0_11_to_http_1_0(resp.status()),
+                        body: resp.text().await?,
+                    })
+                }
+            }
+            .await;
+            self.metrics
+                .storage_req_observe_latency(DO_BATCH_PATH, start.elapsed());
+            let resp = resp?;
+            if resp.len() == len {
+                Ok(resp)
+            } else {
+                Err(Error::BatchLen {
+                    expected: len,
+                    got: resp.len(),
+                })
+            }
+        }))
+        .await?;
+
+        Ok(responses
+            .into_iter()
+            .flatten()
+            .map(|resp| {
+                if resp.is_success() {
+                    Ok(serde_json::from_str(&resp.body)?)
+                } else {
+                    Err(Error::Http {
+                        status: StatusCode::from_u16(resp.status)
+                            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                        body: resp.body,
+                    })
+                }
+            })
+            .collect())
+    }
+
+    /// Send a batch with the same method for each bucket and fail if any of the requests fails.
+    async fn send_batch_for_buckets<R>(
+        &self,
+        method: bindings::AggregateStore,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<Vec<R>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        let task_id_hex = task_id.to_hex();
+        self.send_batch(
+            buckets
+                .iter()
+                .map(|bucket| {
+                    let (request, uri) =
+                        DurableRequest::new(method, (version, &task_id_hex, bucket));
+                    (request.with_body(Vec::new()), uri)
+                })
+                .collect(),
+        )
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    fn kv_url(&self, key: &str) -> url::Url {
+        self.config
+            .url
+            .join(&format!("{KV_PATH_PREFIX}/{key}"))
+            .unwrap()
+    }
+
+    /// Delete all of the task's objects of a durable object binding. Returns `None` if the
+    /// storage proxy can't enumerate the objects.
+    async fn delete_task_objects(
+        &self,
+        binding: &str,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        let url = self
+            .config
+            .url
+            .join(&format!(
+                "{DO_TASK_PATH_PREFIX}/{binding}/{}/{}",
+                version.as_ref(),
+                task_id.to_hex()
+            ))
+            .unwrap();
+        let resp = self
+            .http
+            .delete(url)
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .send()
+            .await?;
+        if resp.status() == status_http_1_0_to_reqwest_0_11(StatusCode::NOT_IMPLEMENTED) {
+            Ok(None)
+        } else {
+            Ok(Some(resp.error_for_status()?.json().await?))
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for StorageProxy {
+    async fn kv_get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let resp = self
+            .http
+            .get(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .send()
+            .await?;
+        if resp.status() == status_http_1_0_to_reqwest_0_11(StatusCode::NOT_FOUND) {
+            Ok(None)
+        } else {
+            Ok(Some(resp.error_for_status()?.bytes().await?.to_vec()))
+        }
+    }
+
+    async fn kv_put(&self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.http
+            .post(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .body(value)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn kv_put_if_not_exists(&self, key: &str, value: Vec<u8>) -> Result<bool, Error> {
+        let response = self
+            .http
+            .put(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .body(value)
+            .send()
+            .await?;
+        if response.status() == status_http_1_0_to_reqwest_0_11(StatusCode::CONFLICT) {
+            Ok(false)
+        } else {
+            response.error_for_status()?;
+            Ok(true)
+        }
+    }
+
+    async fn kv_delete(&self, key: &str) -> Result<(), Error> {
+        self.http
+            .delete(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn kv_list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .http
+            .get(
+                self.config
+                    .url
+                    .join(&format!("{KV_LIST_PATH_PREFIX}/{prefix}"))
+                    .unwrap(),
+            )
+            .bearer_auth(&self.config.auth_token)
+            .headers(crate::telemetry::trace_context_headers())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn aggregate_store_merge(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+        req: AggregateStoreMergeReq,
+    ) -> Result<AggregateStoreMergeResp, Error> {
+        // Merges are idempotent, since the aggregate store records the aggregation job.
+        self.durable()
+            .with_retry()
+            .request(
+                bindings::AggregateStore::Merge,
+                (version, &task_id.to_hex(), bucket),
+            )
+            .encode_bincode(req)
+            .send()
+            .await
+    }
+
+    async fn aggregate_store_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<DapAggregateShare, Error> {
+        self.durable()
+            .request(
+                bindings::AggregateStore::Get,
+                (version, &task_id.to_hex(), bucket),
+            )
+            .send()
+            .await
+    }
+
+    async fn aggregate_store_mark_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error> {
+        self.durable()
+            .request(
+                bindings::AggregateStore::MarkCollected,
+                (version, &task_id.to_hex(), bucket),
+            )
+            .send()
+            .await
+    }
+
+    async fn aggregate_store_check_collected(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<bool, Error> {
+        self.durable()
+            .request(
+                bindings::AggregateStore::CheckCollected,
+                (version, &task_id.to_hex(), bucket),
+            )
+            .send()
+            .await
+    }
+
+    async fn aggregate_store_delete(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket,
+    ) -> Result<(), Error> {
+        self.durable()
+            .request(
+                bindings::AggregateStore::Delete,
+                (version, &task_id.to_hex(), bucket),
+            )
+            .send()
+            .await
+    }
+
+    async fn aggregate_store_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.delete_task_objects(bindings::AggregateStore::BINDING, version, task_id)
+            .await
+    }
+
+    async fn aggregate_store_merge_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        reqs: Vec<(DapBatchBucket, AggregateStoreMergeReq)>,
+    ) -> Result<Vec<Result<AggregateStoreMergeResp, Error>>, Error> {
+        let task_id_hex = task_id.to_hex();
+        self.send_batch(
+            reqs.into_iter()
+                .map(|(bucket, req)| {
+                    let (request, uri) = DurableRequest::new(
+                        bindings::AggregateStore::Merge,
+                        (version, &task_id_hex, &bucket),
+                    );
+                    (request.with_body(bincode::serialize(&req).unwrap()), uri)
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    async fn aggregate_store_get_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<Vec<DapAggregateShare>, Error> {
+        self.send_batch_for_buckets(bindings::AggregateStore::Get, version, task_id, buckets)
+            .await
+    }
+
+    async fn aggregate_store_mark_collected_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<(), Error> {
+        self.send_batch_for_buckets::<()>(
+            bindings::AggregateStore::MarkCollected,
+            version,
+            task_id,
+            buckets,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn aggregate_store_check_collected_many(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        buckets: &[DapBatchBucket],
+    ) -> Result<Vec<bool>, Error> {
+        self.send_batch_for_buckets(
+            bindings::AggregateStore::CheckCollected,
+            version,
+            task_id,
+            buckets,
+        )
+        .await
+    }
+
+    async fn helper_state_put_if_not_exists(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<bool, Error> {
+        self.durable()
+            .with_retry()
+            .request(
+                bindings::HelperState::PutIfNotExists,
+                (version, task_id, agg_job_id),
+            )
+            .encode_bincode(record)
+            .send()
+            .await
+    }
+
+    async fn helper_state_put(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: String,
+    ) -> Result<(), Error> {
+        self.durable()
+            .with_retry()
+            .request(bindings::HelperState::Put, (version, task_id, agg_job_id))
+            .encode_bincode(record)
+            .send()
+            .await
+    }
+
+    async fn helper_state_get(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<String>, Error> {
+        self.durable()
+            .with_retry()
+            .request(bindings::HelperState::Get, (version, task_id, agg_job_id))
+            .send()
+            .await
+    }
+
+    async fn helper_state_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.delete_task_objects(bindings::HelperState::BINDING, version, task_id)
+            .await
+    }
+
+    async fn collected_batches_check_overlapping(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, Error> {
+        self.durable()
+            .with_retry()
+            .request(
+                bindings::CollectedBatches::CheckOverlapping,
+                (version, task_id),
+            )
+            .encode_bincode(batch_sel)
+            .send()
+            .await
+    }
+
+    async fn collected_batches_insert(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        request_hash: &[u8; 32],
+    ) -> Result<bool, Error> {
+        self.durable()
+            .with_retry()
+            .request(bindings::CollectedBatches::Insert, (version, task_id))
+            .encode_bincode(CollectedBatchesInsertReq {
+                batch_sel: batch_sel.clone(),
+                request_hash: *request_hash,
+            })
+            .send()
+            .await
+    }
+
+    async fn collected_batches_delete_task(
+        &self,
+        version: DapVersion,
+        task_id: &TaskId,
+    ) -> Result<Option<u64>, Error> {
+        self.delete_task_objects(bindings::CollectedBatches::BINDING, version, task_id)
+            .await
+    }
+
+    #[cfg(feature = "test-utils")]
+    async fn purge(&self) -> Result<(), Error> {
+        use daphne_service_utils::durable_requests::PURGE_STORAGE;
+        self.http
+            .delete(self.config.url.join(PURGE_STORAGE).unwrap())
+            .bearer_auth(&self.config.auth_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "test-utils")]
+    async fn ready(&self) -> Result<(), Error> {
+        use daphne_service_utils::durable_requests::STORAGE_READY;
+        self.http
+            .get(self.config.url.join(STORAGE_READY).unwrap())
+            .bearer_auth(&self.config.auth_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
 /// this is needed while [reqwest#2039](https://github.com/seanmonstar/reqwest/issues/2039) isn't
 /// completed.
 ///
@@ -0,0 +1,225 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! OpenTelemetry tracing: Export of spans with OTLP and propagation of the W3C trace context
+//! ("traceparent" header) to the Helper and to storage, so that an aggregation job results in a
+//! single trace across both Aggregators.
+
+use daphne::{fatal_error, DapError};
+use http::{header::HeaderName, HeaderMap, HeaderValue};
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    trace::{TraceContextExt, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use serde::{Deserialize, Serialize};
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+use url::Url;
+
+/// Where to export spans to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// URL to which spans are exported with OTLP over HTTP, e.g.,
+    /// `http://localhost:4318/v1/traces`.
+    pub otlp_endpoint: Url,
+
+    /// Name of the service the spans are attributed to.
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_service_name() -> String {
+    "daphne-server".into()
+}
+
+/// Exports spans to an OTLP collector. Spans are exported in batches in the background, so this
+/// must be created from within a tokio runtime and kept alive for as long as spans are recorded.
+pub struct OtlpTracing {
+    provider: TracerProvider,
+}
+
+impl OtlpTracing {
+    pub fn new(config: &TelemetryConfig) -> Result<Self, DapError> {
+        let exporter = opentelemetry_otlp::SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(config.otlp_endpoint.as_str()),
+        )
+        .build_span_exporter()
+        .map_err(|e| fatal_error!(err = ?e, "failed to build OTLP exporter"))?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            .with_config(
+                opentelemetry_sdk::trace::config().with_resource(Resource::new([KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+            )
+            .build();
+        Ok(Self { provider })
+    }
+
+    /// A [`tracing_subscriber::Layer`] that exports the spans it records.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("daphne-server"))
+    }
+
+    /// Export the spans recorded so far. This blocks until the spans are exported, so it must not
+    /// be called from an async context.
+    pub fn flush(&self) {
+        for result in self.provider.force_flush() {
+            if let Err(error) = result {
+                tracing::warn!(?error, "failed to export spans");
+            }
+        }
+    }
+}
+
+struct HeaderInjector<'h>(&'h mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'h>(&'h HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// The trace context of the current span as HTTP headers. These are empty if spans are not
+/// exported.
+pub(crate) fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    TraceContextPropagator::new().inject_context(
+        &Span::current().context(),
+        &mut HeaderInjector(&mut headers),
+    );
+    headers
+}
+
+/// Make `span` a child of the span in the trace context of the request headers, if any.
+pub(crate) fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if context.span().span_context().is_valid() {
+        span.set_parent(context);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, TcpListener},
+        time::Duration,
+    };
+
+    use axum::{body::Bytes, http::HeaderMap, routing::post};
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tokio::sync::mpsc;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{set_parent_from_headers, trace_context_headers, OtlpTracing, TelemetryConfig};
+
+    fn trace_id(span: &tracing::Span) -> opentelemetry::trace::TraceId {
+        span.context().span().span_context().trace_id()
+    }
+
+    #[test]
+    fn propagate_trace_context() {
+        let provider = TracerProvider::builder().build();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(
+                opentelemetry::trace::TracerProvider::tracer(&provider, "test"),
+            ));
+        tracing::subscriber::with_default(subscriber, || {
+            // Spans are not exported, so there's no trace context to propagate.
+            assert!(trace_context_headers().is_empty());
+
+            let leader_span = tracing::info_span!("leader");
+            let headers = leader_span.in_scope(trace_context_headers);
+            assert!(headers.contains_key("traceparent"));
+
+            let helper_span = tracing::info_span!("helper");
+            set_parent_from_headers(&helper_span, &headers);
+            assert_eq!(trace_id(&helper_span), trace_id(&leader_span));
+
+            let unrelated_span = tracing::info_span!("unrelated");
+            set_parent_from_headers(&unrelated_span, &HeaderMap::new());
+            assert_ne!(trace_id(&unrelated_span), trace_id(&leader_span));
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn export_to_collector() {
+        // A stand-in for an OTLP collector that forwards the requests it receives.
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = axum::Router::new().route(
+            "/v1/traces",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                sender.send((headers, body)).unwrap();
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(collector.into_make_service()),
+        );
+
+        let otlp = OtlpTracing::new(&TelemetryConfig {
+            otlp_endpoint: format!("http://{addr}/v1/traces").parse().unwrap(),
+            service_name: "test-service".into(),
+        })
+        .unwrap();
+        let subscriber = tracing_subscriber::registry().with(otlp.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("aggregation_job").in_scope(|| {
+                tracing::info_span!("send_http_put").in_scope(|| {});
+            });
+        });
+        tokio::task::block_in_place(|| otlp.flush());
+
+        // The spans may be exported in separate requests.
+        let contains =
+            |body: &Bytes, needle: &[u8]| body.windows(needle.len()).any(|w| w == needle);
+        let mut bodies = Vec::new();
+        while !(bodies.iter().any(|body| contains(body, b"aggregation_job"))
+            && bodies.iter().any(|body| contains(body, b"send_http_put")))
+        {
+            let (headers, body) = tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(headers["content-type"], "application/x-protobuf");
+            assert!(contains(&body, b"test-service"));
+            bodies.push(body);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! An [`AuditLog`] that writes one JSON object per event and line, e.g.,
+//!
+//! ```json
+//! {"time":1717171717,"host":"helper","event":"aggregate_share","task_id":"...","batch_selector":{...},"report_count":100}
+//! ```
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use daphne::{
+    audit_log::{
+        AggregationJobAuditAction, AuditLog, CollectionJobAuditAction, HpkeConfigAuditAction,
+        TaskprovAuditAction,
+    },
+    constants::DapMediaType,
+    messages::{Base64Encode, BatchSelector, CollectionJobId, TaskId},
+    DapTaskConfig, DapVersion,
+};
+use serde::Serialize;
+
+use crate::config::AuditLogConfig;
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: u64,
+    host: &'a str,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    AggregationJob {
+        task_id: String,
+        version: DapVersion,
+        action: &'static str,
+        report_count: u64,
+    },
+    CollectionJob {
+        task_id: String,
+        coll_job_id: String,
+        batch_selector: &'a BatchSelector,
+        action: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        report_count: Option<u64>,
+    },
+    AggregateShare {
+        task_id: String,
+        batch_selector: &'a BatchSelector,
+        report_count: u64,
+    },
+    Taskprov {
+        task_id: String,
+        action: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<&'a str>,
+    },
+    UnauthorizedRequest {
+        task_id: String,
+        media_type: Option<&'static str>,
+        reason: &'a str,
+    },
+    HpkeConfig {
+        version: DapVersion,
+        config_id: u8,
+        action: &'static str,
+    },
+}
+
+/// Writes the audit log as JSON lines to stdout or to a file.
+pub struct JsonLinesAuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesAuditLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Open the audit log. Files are appended to.
+    pub fn from_config(config: &AuditLogConfig) -> io::Result<Self> {
+        match config {
+            AuditLogConfig::Stdout => Ok(Self::new(io::stdout())),
+            AuditLogConfig::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(Self::new)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display()))),
+        }
+    }
+
+    fn record(&self, host: &str, event: Event<'_>) {
+        let entry = Entry {
+            time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            host,
+            event,
+        };
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(error) => {
+                tracing::error!(?error, "failed to serialize audit log entry");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        // The entry is written at once so that concurrent entries are not interleaved.
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Err(error) = writer.write_all(&line).and_then(|()| writer.flush()) {
+            tracing::error!(?error, "failed to write audit log entry");
+        }
+    }
+}
+
+impl AuditLog for JsonLinesAuditLog {
+    fn on_aggregation_job(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        report_count: u64,
+        action: AggregationJobAuditAction,
+    ) {
+        self.record(
+            host,
+            Event::AggregationJob {
+                task_id: task_id.to_base64url(),
+                version: task_config.version,
+                action: match action {
+                    AggregationJobAuditAction::Init => "init",
+                    AggregationJobAuditAction::Continue => "continue",
+                },
+                report_count,
+            },
+        );
+    }
+
+    fn on_collection_job(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        coll_job_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        action: CollectionJobAuditAction,
+    ) {
+        let (action, report_count) = match action {
+            CollectionJobAuditAction::Create => ("create", None),
+            CollectionJobAuditAction::Complete { report_count } => ("complete", Some(report_count)),
+        };
+        self.record(
+            host,
+            Event::CollectionJob {
+                task_id: task_id.to_base64url(),
+                coll_job_id: coll_job_id.to_base64url(),
+                batch_selector: batch_sel,
+                action,
+                report_count,
+            },
+        );
+    }
+
+    fn on_aggregate_share(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        batch_sel: &BatchSelector,
+        report_count: u64,
+    ) {
+        self.record(
+            host,
+            Event::AggregateShare {
+                task_id: task_id.to_base64url(),
+                batch_selector: batch_sel,
+                report_count,
+            },
+        );
+    }
+
+    fn on_taskprov(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        action: TaskprovAuditAction<'_>,
+    ) {
+        let (action, reason) = match action {
+            TaskprovAuditAction::OptIn => ("opt_in", None),
+            TaskprovAuditAction::OptOut { reason } => ("opt_out", Some(reason)),
+        };
+        self.record(
+            host,
+            Event::Taskprov {
+                task_id: task_id.to_base64url(),
+                action,
+                reason,
+            },
+        );
+    }
+
+    fn on_unauthorized_request(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        version: DapVersion,
+        media_type: Option<DapMediaType>,
+        reason: &str,
+    ) {
+        self.record(
+            host,
+            Event::UnauthorizedRequest {
+                task_id: task_id.to_base64url(),
+                media_type: media_type
+                    .and_then(|media_type| media_type.as_str_for_version(version)),
+                reason,
+            },
+        );
+    }
+
+    fn on_hpke_config(
+        &self,
+        host: &str,
+        version: DapVersion,
+        config_id: u8,
+        action: HpkeConfigAuditAction,
+    ) {
+        self.record(
+            host,
+            Event::HpkeConfig {
+                version,
+                config_id,
+                action: match action {
+                    HpkeConfigAuditAction::Add => "add",
+                    HpkeConfigAuditAction::Remove => "remove",
+                },
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use daphne::{
+        audit_log::{AuditLog, CollectionJobAuditAction, TaskprovAuditAction},
+        constants::DapMediaType,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{BatchId, BatchSelector, CollectionJobId, TaskId},
+        vdaf::{Prio3Config, VdafConfig},
+        DapQueryConfig, DapTaskConfig, DapVersion,
+    };
+    use serde_json::json;
+
+    use super::JsonLinesAuditLog;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_json_lines() {
+        let buf = SharedBuf::default();
+        let audit_log = JsonLinesAuditLog::new(buf.clone());
+        let task_id = TaskId([1; 32]);
+        let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+        let task_config = DapTaskConfig {
+            version: DapVersion::Draft09,
+            leader_url: "https://leader.com/".parse().unwrap(),
+            helper_url: "https://helper.org/".parse().unwrap(),
+            time_precision: 3600,
+            min_batch_size: 10,
+            query: DapQueryConfig::FixedSize {
+                max_batch_size: None,
+            },
+            expiration: 1_700_000_000,
+            vdaf_verify_key: vdaf.gen_verify_key(),
+            vdaf,
+            collector_hpke_config: HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config,
+            method: Default::default(),
+            privacy_pass: None,
+        };
+        let batch_sel = BatchSelector::FixedSizeByBatchId {
+            batch_id: BatchId([2; 32]),
+        };
+
+        audit_log.on_collection_job(
+            "leader",
+            &task_id,
+            &task_config,
+            &CollectionJobId([3; 16]),
+            &batch_sel,
+            CollectionJobAuditAction::Complete { report_count: 100 },
+        );
+        audit_log.on_taskprov(
+            "leader",
+            &task_id,
+            &task_config,
+            TaskprovAuditAction::OptOut {
+                reason: "no thanks",
+            },
+        );
+        audit_log.on_unauthorized_request(
+            "leader",
+            &task_id,
+            DapVersion::Draft09,
+            Some(DapMediaType::CollectReq),
+            "incorrect token",
+        );
+
+        let buf = buf.0.lock().unwrap();
+        let entries = std::str::from_utf8(&buf)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut entry = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                assert!(entry["time"].as_u64().unwrap() > 0);
+                entry.as_object_mut().unwrap().remove("time");
+                entry
+            })
+            .collect::<Vec<_>>();
+        let task_id = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE";
+        assert_eq!(
+            entries,
+            [
+                json!({
+                    "host": "leader",
+                    "event": "collection_job",
+                    "task_id": task_id,
+                    "coll_job_id": "AwMDAwMDAwMDAwMDAwMDAw",
+                    "batch_selector": { "fixed_size_by_batch_id": { "batch_id": hex::encode([2; 32]) } },
+                    "action": "complete",
+                    "report_count": 100,
+                }),
+                json!({
+                    "host": "leader",
+                    "event": "taskprov",
+                    "task_id": task_id,
+                    "action": "opt_out",
+                    "reason": "no thanks",
+                }),
+                json!({
+                    "host": "leader",
+                    "event": "unauthorized_request",
+                    "task_id": task_id,
+                    "media_type": "application/dap-collect-req",
+                    "reason": "incorrect token",
+                }),
+            ]
+        );
+    }
+}
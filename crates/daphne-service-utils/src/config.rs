@@ -1,9 +1,13 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
+use std::path::PathBuf;
+
 use daphne::{
+    auth::BearerToken,
     hpke::{HpkeConfig, HpkeReceiverConfig},
-    DapGlobalConfig, DapVersion,
+    messages::TaskId,
+    DapGlobalConfig, DapTaskConfig, DapVersion,
 };
 use p256::ecdsa::SigningKey;
 use serde::{Deserialize, Serialize};
@@ -33,6 +37,239 @@ pub struct TaskprovConfig {
 
 pub type HpkeRecieverConfigList = Vec<HpkeReceiverConfig>;
 
+/// Retention policy for the state of expired tasks. Each duration is the number of seconds after a
+/// task's expiration at which the corresponding state is deleted. State for which no duration is
+/// set is kept until the storage layer deletes it on its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionConfig {
+    /// The task's configuration.
+    #[serde(default)]
+    pub task_config: Option<daphne::messages::Duration>,
+
+    /// The Leader's and Collector's bearer tokens for the task.
+    #[serde(default)]
+    pub bearer_tokens: Option<daphne::messages::Duration>,
+
+    /// Aggregate shares and the IDs of aggregated reports, including those of fixed-size batches,
+    /// along with the record of collected batches. If the storage can't enumerate its objects,
+    /// then only the aggregate store of time-interval tasks is deleted; the rest is left to the
+    /// storage's garbage collection.
+    #[serde(default)]
+    pub aggregate_store: Option<daphne::messages::Duration>,
+
+    /// Helper: The records of the aggregation jobs. If the storage can't enumerate its objects,
+    /// then they are left to the storage's garbage collection.
+    #[serde(default)]
+    pub helper_state: Option<daphne::messages::Duration>,
+
+    /// How often, in seconds, to apply the policy.
+    #[serde(default = "default_retention_interval")]
+    pub interval: daphne::messages::Duration,
+
+    /// If set, then applying the policy only reports what would be deleted.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_retention_interval() -> daphne::messages::Duration {
+    60 * 60 * 24 // one day
+}
+
+/// Leader: Policy for retrying requests to the Helper that fail transiently, i.e., because of a
+/// network error or because the Helper responded with a retryable status. Retransmissions reuse
+/// the same URL, and hence aggregation job ID, and the same body.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HelperRetryConfig {
+    /// The maximum number of attempts, including the first. If set to 1, then requests are not
+    /// retried.
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, in milliseconds. The delay doubles with each retry, and a
+    /// random jitter of up to half the delay is subtracted from it.
+    #[serde(default = "default_retry_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+
+    /// Maximum delay between retries, in milliseconds.
+    #[serde(default = "default_retry_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+
+    /// Time after which an attempt is abandoned, in milliseconds. This covers the whole attempt,
+    /// from connecting to reading the response body. An attempt that times out before the Helper
+    /// responds is retried.
+    #[serde(default = "default_retry_attempt_timeout_ms")]
+    pub attempt_timeout_ms: u64,
+
+    /// HTTP status codes of the Helper's responses that are retried, both right away and, for
+    /// failed work items, by the Leader's work queue. Other statuses fail the work item for good.
+    #[serde(default = "default_retry_statuses")]
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl Default for HelperRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            initial_backoff_ms: default_retry_initial_backoff_ms(),
+            max_backoff_ms: default_retry_max_backoff_ms(),
+            attempt_timeout_ms: default_retry_attempt_timeout_ms(),
+            retryable_statuses: default_retry_statuses(),
+        }
+    }
+}
+
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+    10_000
+}
+
+fn default_retry_attempt_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_retry_statuses() -> Vec<u16> {
+    daphne::error::DEFAULT_RETRYABLE_STATUSES.to_vec()
+}
+
+/// Policy for caching, in memory, the values read from KV: task configurations, bearer tokens and
+/// HPKE receiver configurations. Each duration is the number of seconds for which a value is
+/// served from the cache before it is read again from storage. Writes made by this instance are
+/// visible right away; writes made by other instances are visible once the cached value expires.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KvCacheConfig {
+    /// The maximum number of cached values. Once it is reached, the least recently used value is
+    /// evicted.
+    #[serde(default = "default_kv_cache_max_entries")]
+    pub max_entries: usize,
+
+    /// Task configurations and the list of known tasks.
+    #[serde(default = "default_kv_cache_task_config_ttl")]
+    pub task_config_ttl: daphne::messages::Duration,
+
+    /// The Leader's and Collector's bearer tokens. This bounds the time it takes for a revoked
+    /// token to be rejected.
+    #[serde(default = "default_kv_cache_bearer_token_ttl")]
+    pub bearer_token_ttl: daphne::messages::Duration,
+
+    /// HPKE receiver configurations.
+    #[serde(default = "default_kv_cache_hpke_receiver_config_ttl")]
+    pub hpke_receiver_config_ttl: daphne::messages::Duration,
+
+    /// Keys that were not found in storage, e.g., unknown task IDs.
+    #[serde(default = "default_kv_cache_negative_ttl")]
+    pub negative_ttl: daphne::messages::Duration,
+}
+
+impl Default for KvCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_kv_cache_max_entries(),
+            task_config_ttl: default_kv_cache_task_config_ttl(),
+            bearer_token_ttl: default_kv_cache_bearer_token_ttl(),
+            hpke_receiver_config_ttl: default_kv_cache_hpke_receiver_config_ttl(),
+            negative_ttl: default_kv_cache_negative_ttl(),
+        }
+    }
+}
+
+fn default_kv_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_kv_cache_task_config_ttl() -> daphne::messages::Duration {
+    300
+}
+
+fn default_kv_cache_bearer_token_ttl() -> daphne::messages::Duration {
+    60
+}
+
+fn default_kv_cache_hpke_receiver_config_ttl() -> daphne::messages::Duration {
+    300
+}
+
+fn default_kv_cache_negative_ttl() -> daphne::messages::Duration {
+    5
+}
+
+/// Where to write the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditLogConfig {
+    Stdout,
+
+    /// The path of a file, which is appended to.
+    File(PathBuf),
+}
+
+/// A secret that is not stored in the configuration file itself, but read from the environment or
+/// from a file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSource {
+    /// The name of an environment variable.
+    Env(String),
+
+    /// The path of a file. Trailing whitespace, e.g., a newline, is ignored.
+    File(PathBuf),
+}
+
+impl SecretSource {
+    pub fn read(&self) -> std::io::Result<String> {
+        match self {
+            Self::Env(name) => std::env::var(name).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("environment variable {name}: {e}"),
+                )
+            }),
+            Self::File(path) => std::fs::read_to_string(path)
+                .map(|secret| secret.trim_end().to_string())
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display()))),
+        }
+    }
+}
+
+/// A task that is provisioned when the service starts.
+#[derive(Deserialize, Clone)]
+pub struct StaticTaskConfig {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    pub task_id: TaskId,
+
+    pub config: DapTaskConfig,
+
+    /// Leader, Helper: Bearer token used by the Leader to authorize its requests to the Helper.
+    pub leader_authentication_token: SecretSource,
+
+    /// Leader: Bearer token used by the Collector to authorize its requests. Must not be set for
+    /// the Helper.
+    #[serde(default)]
+    pub collector_authentication_token: Option<SecretSource>,
+}
+
+impl std::fmt::Debug for StaticTaskConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticTaskConfig")
+            .field("task_id", &self.task_id)
+            .field(
+                "leader_authentication_token",
+                &self.leader_authentication_token,
+            )
+            .field(
+                "collector_authentication_token",
+                &self.collector_authentication_token,
+            )
+            .finish_non_exhaustive()
+    }
+}
+
 /// Daphne service configuration, including long-lived parameters used across DAP tasks.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DaphneServiceConfig {
@@ -86,6 +323,34 @@ pub struct DaphneServiceConfig {
         skip_serializing
     )]
     pub signing_key: Option<SigningKey>,
+
+    /// Bearer token for authorizing requests to the task management API. If not set, then the
+    /// API is disabled.
+    #[serde(default, skip_serializing)]
+    pub admin_token: Option<BearerToken>,
+
+    /// Tasks to provision at startup. The tasks are validated and then upserted into storage,
+    /// replacing the configuration and bearer tokens of existing tasks with the same IDs.
+    #[serde(default, skip_serializing)]
+    pub tasks: Vec<StaticTaskConfig>,
+
+    /// Retention policy for the state of expired tasks. If not set, then the state of expired
+    /// tasks is not deleted.
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+
+    /// Where to write the audit log of security-relevant events, e.g., aggregate shares sent to
+    /// the Leader or Collector. If not set, then no audit log is written.
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+
+    /// Leader: Policy for retrying requests to the Helper.
+    #[serde(default)]
+    pub helper_retry: HelperRetryConfig,
+
+    /// Policy for caching the values read from KV.
+    #[serde(default)]
+    pub kv_cache: KvCacheConfig,
 }
 
 fn default_report_storage_max_future_time_skew() -> daphne::messages::Duration {
@@ -9,7 +9,7 @@
 use std::collections::HashSet;
 
 use daphne::{
-    messages::{AggregationJobId, ReportId, TaskId},
+    messages::{AggregationJobId, BatchSelector, ReportId, TaskId},
     DapAggregateShare, DapBatchBucket, DapVersion,
 };
 use serde::{Deserialize, Serialize};
@@ -94,6 +94,7 @@ define_do_binding! {
         Merge = "/internal/do/aggregate_store/merge",
         MarkCollected = "/internal/do/aggregate_store/mark_collected",
         CheckCollected = "/internal/do/aggregate_store/check_collected",
+        Delete = "/internal/do/aggregate_store/delete",
     }
 
     fn name((version, task_id_hex, bucket): (DapVersion, &'n str, &'n DapBatchBucket)) -> ObjectIdFrom {
@@ -108,7 +109,8 @@ define_do_binding! {
     }
 }
 
-fn durable_name_task(version: DapVersion, task_id_hex: &str) -> String {
+/// The name of the task, which prefixes the names of all the task's durable objects.
+pub fn durable_name_task(version: DapVersion, task_id_hex: &str) -> String {
     format!("{}/task/{}", version.as_ref(), task_id_hex)
 }
 
@@ -116,6 +118,9 @@ fn durable_name_task(version: DapVersion, task_id_hex: &str) -> String {
 pub struct AggregateStoreMergeReq {
     pub contained_reports: Vec<ReportId>,
     pub agg_share_delta: DapAggregateShare,
+    /// The aggregation job the aggregate share belongs to. If this job was already merged into
+    /// the bucket, then the merge succeeds without changing anything.
+    pub agg_job_id: AggregationJobId,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -129,6 +134,7 @@ define_do_binding! {
     const BINDING = "DAP_HELPER_STATE_STORE";
     enum HelperState {
         PutIfNotExists = "/internal/do/helper_state/put_if_not_exists",
+        Put = "/internal/do/helper_state/put",
         Get = "/internal/do/helper_state/get",
     }
 
@@ -143,6 +149,28 @@ define_do_binding! {
 
 }
 
+define_do_binding! {
+    const BINDING = "DAP_COLLECTED_BATCHES_STORE";
+    enum CollectedBatches {
+        CheckOverlapping = "/internal/do/collected_batches/check_overlapping",
+        Insert = "/internal/do/collected_batches/insert",
+    }
+
+    fn name((version, task_id): (DapVersion, &'n TaskId)) -> ObjectIdFrom {
+        ObjectIdFrom::Name(format!(
+            "{}/collected_batches",
+            durable_name_task(version, &task_id.to_hex()),
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollectedBatchesInsertReq {
+    pub batch_sel: BatchSelector,
+    /// The SHA-256 hash of the aggregate share request that collected the batch.
+    pub request_hash: [u8; 32],
+}
+
 #[cfg(feature = "test-utils")]
 define_do_binding! {
     const BINDING = "DAP_TEST_STATE_CLEANER";
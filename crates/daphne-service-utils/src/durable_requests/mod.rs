@@ -52,6 +52,23 @@
 //!    |                                       |              Http Response |         |
 //!    |<--------------------------------------|<---------------------------|<--------+
 //!```
+//!
+//! # Batches
+//!
+//! Several durable object requests can be sent in a single HTTP request, a `POST` to
+//! [`DO_BATCH_PATH`] whose body is a [`DurableRequestBatch`]. The storage proxy handles the
+//! requests concurrently and responds with a JSON array containing a [`DurableResponse`] for each
+//! request, in the same order as the requests.
+//!
+//! # Listing and task-wide deletion
+//!
+//! A `GET` request to `{KV_LIST_PATH_PREFIX}/path/to/prefix` responds with a JSON array of the KV
+//! keys that start with the prefix.
+//!
+//! A `DELETE` request to `{DO_TASK_PATH_PREFIX}/{binding}/{version}/{task_id_hex}` deletes all the
+//! objects of the binding that belong to the task and responds with the number of deleted
+//! objects, encoded with JSON. Storage that can't enumerate its objects responds with
+//! `501 Not Implemented`; it is then up to the objects to delete themselves.
 
 pub mod bindings;
 
@@ -68,6 +85,12 @@ use crate::durable_request_capnp;
 pub const KV_PATH_PREFIX: &str = "/v1/kv";
 /// The base of a request path that points to a durable object.
 pub const DO_PATH_PREFIX: &str = "/v1/do";
+/// The path of a batch of durable object requests.
+pub const DO_BATCH_PATH: &str = "/v1/do_batch";
+/// The base of a request path that lists the keys in KV with a given prefix.
+pub const KV_LIST_PATH_PREFIX: &str = "/v1/kv_list";
+/// The base of a request path that points to all the durable objects of a task.
+pub const DO_TASK_PATH_PREFIX: &str = "/v1/do_task";
 #[cfg(feature = "test-utils")]
 /// The path of the purge request, which wipes all storage. This is meant for tests only.
 pub const PURGE_STORAGE: &str = "/v1/purge";
@@ -284,13 +307,73 @@ where
     }
 }
 
+/// A batch of durable object requests, sent to the storage proxy in a single HTTP request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DurableRequestBatch {
+    /// The URI of each request, i.e., the [`DurableMethod::to_uri`] of its method, and the
+    /// encoded request.
+    requests: Vec<(String, Vec<u8>)>,
+}
+
+impl DurableRequestBatch {
+    /// Add a request to the batch.
+    pub fn push<P: AsRef<[u8]>>(&mut self, uri: &str, request: DurableRequest<P>) {
+        self.requests.push((uri.to_owned(), request.into_bytes()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Iterate over the URI and the decoded request of each request of the batch.
+    pub fn requests(
+        &self,
+    ) -> impl Iterator<Item = (&str, Result<DurableRequest<&[u8]>, capnp::Error>)> {
+        self.requests
+            .iter()
+            .map(|(uri, request)| (uri.as_str(), DurableRequest::try_from(request)))
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        bincode::serialize(&self).unwrap()
+    }
+}
+
+impl TryFrom<&[u8]> for DurableRequestBatch {
+    type Error = bincode::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// The response of the durable object to one of the requests of a [`DurableRequestBatch`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DurableResponse {
+    /// The HTTP status code.
+    pub status: u16,
+
+    /// The body, which is JSON if the request succeeded.
+    pub body: String,
+}
+
+impl DurableResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use daphne::{DapBatchBucket, DapVersion};
 
     use crate::durable_requests::bindings::AggregateStore;
 
-    use super::{bindings, DurableRequest};
+    use super::{bindings, DurableRequest, DurableRequestBatch};
 
     #[test]
     fn roundrip_without_body() {
@@ -326,4 +409,31 @@ mod test {
         let got = DurableRequest::try_from(&req_bytes).unwrap();
         assert_eq!(want, got);
     }
+
+    #[test]
+    fn roundtrip_batch() {
+        let requests = [0, 1].map(|batch_window| {
+            DurableRequest::new(
+                AggregateStore::Get,
+                (
+                    DapVersion::Draft09,
+                    "some-task-id-hex",
+                    &DapBatchBucket::TimeInterval { batch_window },
+                ),
+            )
+        });
+
+        let mut batch = DurableRequestBatch::default();
+        for (request, uri) in requests.clone() {
+            batch.push(uri, request.with_body(b"body".to_vec()));
+        }
+
+        let batch_bytes = batch.into_bytes();
+        let got = DurableRequestBatch::try_from(&batch_bytes[..]).unwrap();
+        assert_eq!(got.len(), 2);
+        for ((want, want_uri), (got_uri, got)) in requests.into_iter().zip(got.requests()) {
+            assert_eq!(want_uri, got_uri);
+            assert_eq!(want.with_body(b"body".to_vec()), got.unwrap());
+        }
+    }
 }
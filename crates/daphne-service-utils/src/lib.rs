@@ -5,6 +5,7 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+pub mod audit_log;
 pub mod auth;
 pub mod config;
 #[cfg(feature = "durable_requests")]
@@ -10,6 +10,7 @@ pub trait DaphneServiceMetrics: DaphneMetrics {
     fn count_http_status_code(&self, status_code: u16);
     fn daphne(&self) -> &dyn DaphneMetrics;
     fn auth_method_inc(&self, method: AuthMethod);
+    fn kv_cache_inc(&self, prefix: &str, event: KvCacheEvent);
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -18,10 +19,23 @@ pub enum AuthMethod {
     TlsClientAuth,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KvCacheEvent {
+    /// The value, or the absence of the value, was cached.
+    Hit,
+    /// The value was not cached or had expired.
+    Miss,
+    /// The value was evicted to make room for another one.
+    Eviction,
+}
+
 #[cfg(any(feature = "prometheus", feature = "test-utils", test))]
 mod prometheus {
+    use std::time::Duration;
+
     use super::DaphneServiceMetrics;
     use daphne::{
+        constants::DapMediaType,
         fatal_error,
         metrics::{prometheus::DaphnePromMetrics, DaphneMetrics, ReportStatus},
         DapError,
@@ -52,6 +66,32 @@ mod prometheus {
         fn agg_job_put_span_retry_inc(&self) {
             self.daphne.agg_job_put_span_retry_inc();
         }
+
+        fn inter_agg_req_observe_latency(
+            &self,
+            media_type: DapMediaType,
+            status: Option<u16>,
+            latency: Duration,
+        ) {
+            self.daphne
+                .inter_agg_req_observe_latency(media_type, status, latency);
+        }
+
+        fn storage_req_observe_latency(&self, method: &str, latency: Duration) {
+            self.daphne.storage_req_observe_latency(method, latency);
+        }
+
+        fn vdaf_prep_observe_time(&self, time: Duration) {
+            self.daphne.vdaf_prep_observe_time(time);
+        }
+
+        fn coll_job_observe_latency(&self, latency: Duration) {
+            self.daphne.coll_job_observe_latency(latency);
+        }
+
+        fn peer_abort_inc(&self, abort_type: &str) {
+            self.daphne.peer_abort_inc(abort_type);
+        }
     }
 
     impl DaphneServiceMetrics for DaphnePromServiceMetrics {
@@ -73,6 +113,15 @@ mod prometheus {
             self.auth_method.with_label_values(&[method]).inc();
         }
 
+        fn kv_cache_inc(&self, prefix: &str, event: super::KvCacheEvent) {
+            let event = match event {
+                super::KvCacheEvent::Hit => "hit",
+                super::KvCacheEvent::Miss => "miss",
+                super::KvCacheEvent::Eviction => "eviction",
+            };
+            self.kv_cache.with_label_values(&[prefix, event]).inc();
+        }
+
         fn daphne(&self) -> &dyn DaphneMetrics {
             self
         }
@@ -91,6 +140,9 @@ mod prometheus {
 
         /// Counts the used authentication methods
         auth_method: IntCounterVec,
+
+        /// Hits, misses and evictions of the KV cache.
+        kv_cache: IntCounterVec,
     }
 
     impl DaphnePromServiceMetrics {
@@ -119,6 +171,14 @@ mod prometheus {
             )
             .map_err(|e| fatal_error!(err = ?e, "failed to register dap_abort"))?;
 
+            let kv_cache = register_int_counter_vec_with_registry!(
+                "kv_cache",
+                "Hits, misses and evictions of the KV cache.",
+                &["prefix", "event"],
+                registry
+            )
+            .map_err(|e| fatal_error!(err = ?e, "failed to register kv_cache"))?;
+
             let daphne = DaphnePromMetrics::register(registry)?;
 
             Ok(Self {
@@ -126,6 +186,7 @@ mod prometheus {
                 http_status_code_counter,
                 dap_abort_counter,
                 auth_method,
+                kv_cache,
             })
         }
     }
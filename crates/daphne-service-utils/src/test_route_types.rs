@@ -34,7 +34,6 @@ pub struct InternalTestAddTask {
     pub leader_authentication_token: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub collector_authentication_token: Option<String>,
-    pub role: super::DapRole,
     pub vdaf_verify_key: String, // base64url
     pub query_type: u8,
     pub min_batch_size: u64,
@@ -44,3 +43,111 @@ pub struct InternalTestAddTask {
     pub collector_hpke_config: String, // base64url
     pub task_expiration: Time,
 }
+
+/// Command for adding a task to the Collector. Unlike [`InternalTestAddTask`], the Collector
+/// generates its own HPKE receiver config and responds with its public part.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestAddCollectorTask {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    pub task_id: TaskId, // base64url
+    pub leader: Url,
+    pub vdaf: InternalTestVdaf,
+    pub collector_authentication_token: String,
+    pub query_type: u8,
+    #[serde(default)]
+    pub min_batch_size: Option<u64>,
+}
+
+/// The tasks accepted by the `add_task` command, distinguished by the "role" field.
+#[derive(Deserialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum InternalTestAddTaskKind {
+    Leader(InternalTestAddTask),
+    Helper(InternalTestAddTask),
+    Collector(InternalTestAddCollectorTask),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestUpload {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    pub task_id: TaskId, // base64url
+    pub leader: Url,
+    pub helper: Url,
+    pub vdaf: InternalTestVdaf,
+    /// A number, a string-encoded number, or an array thereof, depending on the VDAF.
+    pub measurement: serde_json::Value,
+    #[serde(default)]
+    pub time: Option<Time>,
+    pub time_precision: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestQuery {
+    #[serde(rename = "type")]
+    pub typ: u8,
+    #[serde(default)]
+    pub batch_interval_start: Option<Time>,
+    #[serde(default)]
+    pub batch_interval_duration: Option<Duration>,
+    #[serde(default)]
+    pub subtype: Option<u8>,
+    #[serde(default)]
+    pub batch_id: Option<String>, // base64url
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestCollectionStart {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    pub task_id: TaskId, // base64url
+    pub agg_param: String, // base64url
+    pub query: InternalTestQuery,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestCollectionPoll {
+    pub handle: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::InternalTestAddTaskKind;
+
+    #[test]
+    fn add_task_kind_is_tagged_by_role() {
+        let task = |role: &str| {
+            serde_json::from_value::<InternalTestAddTaskKind>(serde_json::json!({
+                "role": role,
+                "task_id": "8TuT5ZjR7Zkbqrj5P5Pm7TxGnFhwa9H9QUW6mq2i3Aw",
+                "leader": "https://leader.example/",
+                "helper": "https://helper.example/",
+                "vdaf": { "type": "Prio3Count" },
+                "leader_authentication_token": "leader-token",
+                "collector_authentication_token": "collector-token",
+                "vdaf_verify_key": "AAAAAAAAAAAAAAAAAAAAAA",
+                "query_type": 1,
+                "min_batch_size": 10,
+                "time_precision": 3600,
+                "collector_hpke_config": "",
+                "task_expiration": 1_700_000_000,
+            }))
+        };
+        assert!(matches!(
+            task("leader"),
+            Ok(InternalTestAddTaskKind::Leader(_))
+        ));
+        assert!(matches!(
+            task("helper"),
+            Ok(InternalTestAddTaskKind::Helper(_))
+        ));
+        assert!(matches!(
+            task("collector"),
+            Ok(InternalTestAddTaskKind::Collector(_))
+        ));
+        assert!(task("client").is_err());
+    }
+}
@@ -7,8 +7,9 @@ use daphne::{
     async_test_versions,
     constants::DapMediaType,
     messages::{
-        decode_base64url_vec, Base64Encode, BatchSelector, Collection, CollectionReq, Extension,
-        HpkeCiphertext, Interval, Query, Report, ReportId, ReportMetadata, TaskId,
+        decode_base64url_vec, encode_base64url, Base64Encode, BatchSelector, Collection,
+        CollectionReq, Extension, HpkeCiphertext, Interval, Query, Report, ReportId,
+        ReportMetadata, TaskId,
     },
     DapAggregateResult, DapAggregationParam, DapMeasurement, DapQueryConfig, DapTaskParameters,
     DapVersion,
@@ -206,7 +207,7 @@ async fn leader_upload(version: DapVersion) {
     .await
     .unwrap();
 
-    // Try uploading a report for which the leader's share is encrypted under the wrong public key.
+    // Try uploading a report for which the leader's share is encrypted under an unknown HPKE config.
     let mut report = t
         .task_config
         .vdaf
@@ -226,7 +227,7 @@ async fn leader_upload(version: DapVersion) {
         DapMediaType::Report,
         report.get_encoded_with_param(&version).unwrap(),
         400,
-        "reportRejected",
+        "outdatedConfig",
     )
     .await
     .unwrap();
@@ -281,7 +282,10 @@ async fn leader_upload(version: DapVersion) {
             .parse()
             .unwrap(),
     );
-    let builder = client.put(url.as_str());
+    let builder = match version {
+        DapVersion::Draft09 => client.put(url.as_str()),
+        DapVersion::Latest => client.post(url.as_str()),
+    };
     let resp = builder
         .body(
             Report {
@@ -601,6 +605,227 @@ async fn leader_collect_ok(version: DapVersion) {
 
 async_test_versions! { leader_collect_ok }
 
+async fn interop_client_and_collector(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen());
+    let vdaf = json!({ "type": "Prio3Count" });
+
+    // Collector: Add the task. The Collector responds with its HPKE config.
+    let res: serde_json::Value = t
+        .leader_post_internal(
+            &format!("{version}/internal/test/add_task"),
+            &json!({
+                "task_id": task_id.to_base64url(),
+                "leader": t.leader_url,
+                "vdaf": vdaf,
+                "collector_authentication_token": t.collector_bearer_token,
+                "query_type": 1,
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res["status"], "success", "{res}");
+    let collector_hpke_config = res["collector_hpke_config"].as_str().unwrap();
+
+    // Configure the Aggregators with the task.
+    for role in ["leader", "helper"] {
+        let mut cmd = json!({
+            "task_id": task_id.to_base64url(),
+            "leader": t.leader_url,
+            "helper": t.helper_url,
+            "vdaf": vdaf,
+            "leader_authentication_token": t.leader_bearer_token,
+            "role": role,
+            "vdaf_verify_key": encode_base64url(t.task_config.vdaf_verify_key.as_ref()),
+            "query_type": 1,
+            "min_batch_size": t.task_config.min_batch_size,
+            "time_precision": t.task_config.time_precision,
+            "collector_hpke_config": collector_hpke_config,
+            "task_expiration": t.task_config.expiration,
+        });
+        let path = format!("{version}/internal/test/add_task");
+        let res: serde_json::Value = if role == "leader" {
+            cmd["collector_authentication_token"] = t.collector_bearer_token.clone().into();
+            t.leader_post_internal(&path, &cmd).await.unwrap()
+        } else {
+            t.helper_post_internal(&path, &cmd).await.unwrap()
+        };
+        assert_eq!(res["status"], "success", "{res}");
+    }
+
+    // Client: Upload reports.
+    let batch_interval = t.batch_interval();
+    for _ in 0..t.task_config.min_batch_size {
+        let res: serde_json::Value = t
+            .leader_post_internal(
+                &format!("{version}/internal/test/upload"),
+                &json!({
+                    "task_id": task_id.to_base64url(),
+                    "leader": t.leader_url,
+                    "helper": t.helper_url,
+                    "vdaf": vdaf,
+                    "measurement": 1,
+                    "time": batch_interval.start,
+                    "time_precision": t.task_config.time_precision,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res["status"], "success", "{res}");
+    }
+
+    // Collector: Start the collection job and poll it until it is complete.
+    let res: serde_json::Value = t
+        .leader_post_internal(
+            "/internal/test/collection_start",
+            &json!({
+                "task_id": task_id.to_base64url(),
+                "agg_param": "",
+                "query": {
+                    "type": 1,
+                    "batch_interval_start": batch_interval.start,
+                    "batch_interval_duration": batch_interval.duration,
+                },
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res["status"], "success", "{res}");
+    let handle = res["handle"].as_str().unwrap();
+
+    t.internal_process(client).await.unwrap();
+    let res: serde_json::Value = t
+        .leader_post_internal(
+            "/internal/test/collection_poll",
+            &json!({ "handle": handle }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res["status"], "complete", "{res}");
+    assert_eq!(res["report_count"], t.task_config.min_batch_size);
+    assert_eq!(res["interval_start"], batch_interval.start);
+    assert_eq!(
+        res["result"],
+        t.task_config.min_batch_size.to_string().as_str()
+    );
+}
+
+async_test_versions! { interop_client_and_collector }
+
+async fn admin_task_lifecycle(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen());
+    let admin_url = |path: &str| {
+        let mut url = t.leader_url.clone();
+        url.set_path(path);
+        url
+    };
+    let tasks_url = admin_url("admin/tasks");
+    let task_url = admin_url(&format!("admin/tasks/{}", task_id.to_base64url()));
+    let admin_token = "this-is-the-admin-token";
+
+    // Requests without the admin token are rejected.
+    let resp = client.get(tasks_url.clone()).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+
+    // Create the task.
+    let task = json!({
+        "task_id": task_id.to_base64url(),
+        "config": t.task_config,
+        "leader_authentication_token": t.leader_bearer_token,
+        "collector_authentication_token": t.collector_bearer_token,
+    });
+    let resp = client
+        .post(tasks_url.clone())
+        .bearer_auth(admin_token)
+        .json(&task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+
+    // Creating it again is a conflict.
+    let resp = client
+        .post(tasks_url.clone())
+        .bearer_auth(admin_token)
+        .json(&task)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 409);
+
+    let res: serde_json::Value = client
+        .get(tasks_url.clone())
+        .bearer_auth(admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(res["task_ids"]
+        .as_array()
+        .unwrap()
+        .contains(&json!(task_id.to_base64url())));
+
+    let mut status_url = task_url.clone();
+    status_url.set_path(&format!("{}/status", task_url.path()));
+    let res: serde_json::Value = client
+        .get(status_url)
+        .bearer_auth(admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(res["expiration"], t.task_config.expiration);
+    assert_eq!(res["expired"], false);
+    assert_eq!(res["authentication_tokens"]["leader"], true);
+    assert_eq!(res["authentication_tokens"]["collector"], true);
+
+    // Extend the task's lifetime.
+    let expiration = t.task_config.expiration + 86400;
+    let resp = client
+        .patch(task_url.clone())
+        .bearer_auth(admin_token)
+        .json(&json!({ "expiration": expiration }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+    let res: serde_json::Value = client
+        .get(task_url.clone())
+        .bearer_auth(admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(res["expiration"], expiration);
+
+    // Delete the task.
+    let resp = client
+        .delete(task_url.clone())
+        .bearer_auth(admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = client
+        .get(task_url)
+        .bearer_auth(admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+async_test_versions! { admin_task_lifecycle }
+
 // Test that collect jobs complete even if the request is issued after all reports for the task
 // have been processed.
 async fn leader_collect_ok_interleaved(version: DapVersion) {
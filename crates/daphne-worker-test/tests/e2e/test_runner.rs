@@ -108,6 +108,7 @@ impl TestRunner {
             vdaf_verify_key: VDAF_CONFIG.gen_verify_key(),
             collector_hpke_config: collector_hpke_receiver.config.clone(),
             method: Default::default(),
+            privacy_pass: None,
         };
 
         // This block needs to be kept in-sync with daphne-worker-test/wrangler.toml.
@@ -488,7 +489,7 @@ impl TestRunner {
         }
 
         let resp = client
-            .put(url.as_str())
+            .request(self.leader_put_method(media_type), url.as_str())
             .body(data)
             .headers(headers)
             .send()
@@ -534,7 +535,7 @@ impl TestRunner {
         }
 
         let resp = client
-            .put(url.as_str())
+            .request(self.leader_put_method(media_type), url.as_str())
             .body(data)
             .headers(headers)
             .send()
@@ -743,6 +744,14 @@ impl TestRunner {
         )
     }
 
+    /// Return the method of a request to the Leader. Reports are uploaded with POST since draft10.
+    fn leader_put_method(&self, media_type: DapMediaType) -> reqwest::Method {
+        match (media_type, self.version) {
+            (DapMediaType::Report, DapVersion::Latest) => reqwest::Method::POST,
+            _ => reqwest::Method::PUT,
+        }
+    }
+
     pub fn upload_path(&self) -> String {
         Self::upload_path_for_task(&self.task_id)
     }
@@ -752,7 +761,11 @@ impl TestRunner {
         client: &reqwest::Client,
         url: &Url,
     ) -> anyhow::Result<reqwest::Response> {
-        let builder = client.post(url.as_str());
+        // Collection jobs are polled with GET since draft10.
+        let builder = match self.version {
+            DapVersion::Draft09 => client.post(url.as_str()),
+            DapVersion::Latest => client.get(url.as_str()),
+        };
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             reqwest::header::CONTENT_TYPE,
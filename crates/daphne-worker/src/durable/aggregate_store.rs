@@ -6,10 +6,12 @@
 //! This object defines the following API endpoints:
 //!
 //! - `DURABLE_AGGREGATE_STORE_GET`: Return the current value of the aggregate share.
-//! - `DURABLE_AGGREGATE_STORE_MERGE`: Update the aggregate share.
+//! - `DURABLE_AGGREGATE_STORE_MERGE`: Update the aggregate share, unless the aggregation job was
+//!   already merged.
 //! - `DURABLE_AGGREGATE_STORE_MARK_COLLECTED`: Mark the bucket as having been collected.
 //! - `DURABLE_AGGREGATE_STORE_CHECK_COLLECTED`: Return a boolean indicating if the bucket has been
 //!   collected.
+//! - `DURABLE_AGGREGATE_STORE_DELETE`: Delete all state stored by this DO.
 //!
 //! The schema for the data stored by this DO is as follows:
 //!
@@ -19,15 +21,19 @@
 //!     chunk_v2_{000..008} -> slice of VdafAggregateShare
 //! [Seen Report Ids]
 //!     aggregated_report_ids_{000..002} -> slice of ReportId
+//! [Merged Aggregation Job Ids]
+//!     merged_agg_job_ids_{000..004} -> slice of AggregationJobId
 //! [Collected flag]
 //!     collected -> bool
 //! ```
 
-use std::{collections::HashSet, io::Cursor, mem::size_of, sync::OnceLock, time::Duration};
+use std::{
+    collections::HashSet, hash::Hash, io::Cursor, mem::size_of, sync::OnceLock, time::Duration,
+};
 
 use crate::int_err;
 use daphne::{
-    messages::{ReportId, Time},
+    messages::{AggregationJobId, ReportId, Time},
     vdaf::VdafAggregateShare,
     DapAggregateShare,
 };
@@ -53,6 +59,10 @@ const MAX_AGG_SHARE_CHUNK_KEY_COUNT: usize = 8;
 /// Minimum number of chunks needed to store `40_000` report ids.
 const MAX_REPORT_ID_CHUNK_KEY_COUNT: usize = 5;
 
+/// Every aggregation job merges at least one report, so there are no more aggregation job ids than
+/// report ids.
+const MAX_AGG_JOB_ID_CHUNK_KEY_COUNT: usize = MAX_REPORT_ID_CHUNK_KEY_COUNT;
+
 /// The maximum chunk size as documented in
 /// [the public docs](https://developers.cloudflare.com/durable-objects/platform/limits/)
 const MAX_CHUNK_SIZE: usize = 128_000;
@@ -141,6 +151,12 @@ impl AggregateStore {
             .collect()
     }
 
+    fn merged_agg_jobs_keys() -> Vec<String> {
+        (0..MAX_AGG_JOB_ID_CHUNK_KEY_COUNT)
+            .map(|n| format!("merged_agg_job_ids_{n:03}"))
+            .collect()
+    }
+
     async fn get_agg_share(&self, keys: &[String]) -> Result<DapAggregateShare> {
         let all_keys = keys
             .iter()
@@ -206,23 +222,36 @@ impl AggregateStore {
     }
 
     async fn load_aggregated_report_ids(&self) -> Result<HashSet<ReportId>> {
-        let chunks_map = self
-            .state
-            .storage()
-            .get_multiple(Self::aggregated_reports_keys())
-            .await?;
-
-        let bytes = js_map_to_chunks::<u8>(&Self::aggregated_reports_keys(), chunks_map);
-
-        assert_eq!(bytes.len() % std::mem::size_of::<ReportId>(), 0);
-        let mut ids = HashSet::with_capacity(bytes.len() / size_of::<ReportId>());
-        for chunk in bytes.chunks_exact(size_of::<ReportId>()) {
-            ids.insert(ReportId::get_decoded(chunk).map_err(|_| Error::BadEncoding)?);
+        self.load_ids(&Self::aggregated_reports_keys()).await
+    }
+
+    async fn load_merged_agg_job_ids(&self) -> Result<HashSet<AggregationJobId>> {
+        self.load_ids(&Self::merged_agg_jobs_keys()).await
+    }
+
+    async fn load_ids<T: Decode + Eq + Hash>(&self, keys: &[String]) -> Result<HashSet<T>> {
+        let chunks_map = self.state.storage().get_multiple(keys.to_vec()).await?;
+
+        let bytes = js_map_to_chunks::<u8>(keys, chunks_map);
+
+        assert_eq!(bytes.len() % size_of::<T>(), 0);
+        let mut ids = HashSet::with_capacity(bytes.len() / size_of::<T>());
+        for chunk in bytes.chunks_exact(size_of::<T>()) {
+            ids.insert(T::get_decoded(chunk).map_err(|_| Error::BadEncoding)?);
         }
         Ok(ids)
     }
 }
 
+fn encode_ids<T: Encode>(ids: impl ExactSizeIterator<Item = T>) -> Result<Vec<u8>> {
+    let mut as_bytes = Vec::with_capacity(ids.len() * size_of::<T>());
+    for id in ids {
+        id.encode(&mut as_bytes)
+            .map_err(|e| Error::RustError(format!("failed to encode ID: {e}")))?;
+    }
+    Ok(as_bytes)
+}
+
 fn shard_bytes_to_object(
     keys: &[String],
     bytes: Vec<u8>,
@@ -303,19 +332,26 @@ impl GcDurableObject for AggregateStore {
             Some(bindings::AggregateStore::GetMerged) => {
                 Response::from_json(&self.load_aggregated_report_ids().await?)
             }
-            // Merge an aggregate share into the stored aggregate.
+            // Merge an aggregate share into the stored aggregate, unless the aggregation job was
+            // already merged.
             //
-            // Non-idempotent (do not retry)
-            // Input: `agg_share_dellta: DapAggregateShare`
-            // Output: `()`
+            // Idempotent
+            // Input: `AggregateStoreMergeReq`
+            // Output: `AggregateStoreMergeResp`
             Some(bindings::AggregateStore::Merge) => {
                 let AggregateStoreMergeReq {
                     contained_reports,
                     agg_share_delta,
+                    agg_job_id,
                 } = req_parse(&mut req).await?;
 
                 let chunks_map = js_sys::Object::default();
 
+                let mut merged_agg_job_ids = self.load_merged_agg_job_ids().await?;
+                if merged_agg_job_ids.contains(&agg_job_id) {
+                    return Response::from_json(&AggregateStoreMergeResp::Ok);
+                }
+
                 if self.is_collected().await? {
                     return Response::from_json(&AggregateStoreMergeResp::AlreadyCollected);
                 }
@@ -334,14 +370,17 @@ impl GcDurableObject for AggregateStore {
                         ));
                     }
                     merged_report_ids.extend(contained_reports);
-                    let mut as_bytes =
-                        Vec::with_capacity(merged_report_ids.len() * size_of::<ReportId>());
-                    merged_report_ids.into_iter().try_for_each(|id| {
-                        id.encode(&mut as_bytes).map_err(|e| {
-                            Error::RustError(format!("failed to encode report ID: {e}"))
-                        })
-                    })?;
-                    shard_bytes_to_object(&Self::aggregated_reports_keys(), as_bytes, &chunks_map)?;
+                    shard_bytes_to_object(
+                        &Self::aggregated_reports_keys(),
+                        encode_ids(merged_report_ids.into_iter())?,
+                        &chunks_map,
+                    )?;
+                    merged_agg_job_ids.insert(agg_job_id);
+                    shard_bytes_to_object(
+                        &Self::merged_agg_jobs_keys(),
+                        encode_ids(merged_agg_job_ids.into_iter())?,
+                        &chunks_map,
+                    )?;
                 };
 
                 let keys = Self::agg_share_shard_keys();
@@ -401,6 +440,16 @@ impl GcDurableObject for AggregateStore {
                 Response::from_json(&self.is_collected().await?)
             }
 
+            // Delete all state, e.g., once the task has expired.
+            //
+            // Idempotent
+            // Output: `()`
+            Some(bindings::AggregateStore::Delete) => {
+                self.state.storage().delete_all().await?;
+                self.collected = None;
+                Response::from_json(&())
+            }
+
             _ => Err(int_err(format!(
                 "AggregatesStore: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -0,0 +1,106 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Durable Object (DO) for storing the batches of a task that have been collected.
+//!
+//! This object implements the following API endpoints:
+//!
+//! - `DURABLE_COLLECTED_BATCHES_CHECK_OVERLAPPING`: Return a boolean indicating if the batch
+//!   overlaps with a previously collected batch.
+//! - `DURABLE_COLLECTED_BATCHES_INSERT`: Record the collection of a batch by an aggregate share
+//!   request unless it overlaps with a previously collected batch or exceeds its query limit.
+//!   Returns a boolean indicating whether the operation succeeded.
+//!
+//! The record is stored in `collected_batches`. Unlike the aggregate store, this object outlives
+//! the buckets of the task: it is only deleted if
+//! `DAP_DURABLE_COLLECTED_BATCHES_STORE_GC_AFTER_SECS` is set.
+
+use std::{sync::OnceLock, time::Duration};
+
+use crate::int_err;
+use daphne::{messages::BatchSelector, DapCollectedBatches};
+use daphne_service_utils::durable_requests::bindings::{
+    self, CollectedBatchesInsertReq, DurableMethod,
+};
+use worker::{
+    async_trait, js_sys, wasm_bindgen, wasm_bindgen_futures, worker_sys, Env, Request, Response,
+    Result, ScheduledTime, State,
+};
+
+use super::{req_parse, GcDurableObject};
+
+const COLLECTED_BATCHES_KEY: &str = "collected_batches";
+
+crate::mk_durable_object! {
+    struct CollectedBatchesStore {
+        state: State,
+        env: Env,
+    }
+}
+
+impl GcDurableObject for CollectedBatchesStore {
+    type DurableMethod = bindings::CollectedBatches;
+
+    fn with_state_and_env(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn handle(&mut self, mut req: Request) -> Result<Response> {
+        match bindings::CollectedBatches::try_from_uri(&req.path()) {
+            // Check whether the batch overlaps with a previously collected batch.
+            //
+            // Idempotent
+            // Input: `batch_sel: BatchSelector`
+            // Output: `bool`
+            Some(bindings::CollectedBatches::CheckOverlapping) => {
+                let batch_sel: BatchSelector = req_parse(&mut req).await?;
+                let collected: DapCollectedBatches =
+                    self.get_or_default(COLLECTED_BATCHES_KEY).await?;
+                Response::from_json(&collected.is_overlapping(&batch_sel))
+            }
+
+            // Record the collection of a batch. Recording the same request again succeeds.
+            //
+            // Idempotent
+            // Input: `CollectedBatchesInsertReq`
+            // Output: `bool`
+            Some(bindings::CollectedBatches::Insert) => {
+                let CollectedBatchesInsertReq {
+                    batch_sel,
+                    request_hash,
+                } = req_parse(&mut req).await?;
+                let mut collected: DapCollectedBatches =
+                    self.get_or_default(COLLECTED_BATCHES_KEY).await?;
+                let success = collected.insert(&batch_sel, &request_hash);
+                if success {
+                    self.state
+                        .storage()
+                        .put(COLLECTED_BATCHES_KEY, &collected)
+                        .await?;
+                }
+                Response::from_json(&success)
+            }
+
+            _ => Err(int_err(format!(
+                "CollectedBatchesStore: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+
+    fn should_cleanup_at(&self) -> Option<ScheduledTime> {
+        const VAR_NAME: &str = "DAP_DURABLE_COLLECTED_BATCHES_STORE_GC_AFTER_SECS";
+        static SELF_DELETE_AFTER: OnceLock<Option<Duration>> = OnceLock::new();
+
+        let duration = SELF_DELETE_AFTER.get_or_init(|| {
+            self.env.var(VAR_NAME).ok().map(|v| {
+                Duration::from_secs(v.to_string().parse().unwrap_or_else(|e| {
+                    panic!("{VAR_NAME} could not be parsed as a number of seconds: {e}")
+                }))
+            })
+        });
+
+        duration.map(ScheduledTime::from)
+    }
+}
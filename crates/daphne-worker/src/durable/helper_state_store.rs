@@ -1,15 +1,17 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-//! Durable Object (DO) for storing the Helper's state for a given aggregation job.
+//! Durable Object (DO) for storing the Helper's record of a given aggregation job, i.e., the hash
+//! of the Leader's request and the Helper's response.
 //!
 //! This object implements the following API endpoints:
 //!
-//! - `DURABLE_HELPER_STATE_PUT_IF_NOT_EXISTS`: Stores Helper's hex-encoded state unless the state
+//! - `DURABLE_HELPER_STATE_PUT_IF_NOT_EXISTS`: Stores Helper's serialized record unless the record
 //!    already exists. Returns a boolean indicating whether the operation succeeded.
-//! - `DURABLE_HELPER_STATE_GET`: Drains the Helper's hex-encoded state.
+//! - `DURABLE_HELPER_STATE_PUT`: Stores Helper's serialized record, replacing the existing one.
+//! - `DURABLE_HELPER_STATE_GET`: Gets the Helper's serialized record.
 //!
-//! The state blob is stored in `helper_state`.
+//! The record is stored in `helper_state`.
 
 use std::{sync::OnceLock, time::Duration};
 
@@ -38,24 +40,39 @@ impl GcDurableObject for HelperStateStore {
 
     async fn handle(&mut self, mut req: Request) -> Result<Response> {
         match bindings::HelperState::try_from_uri(&req.path()) {
-            // Store the Helper's state.
+            // Store the Helper's record unless there is one already.
             //
-            // Non-idempotent
-            // Input: `helper_state_hex: String` (hex-encoded state)
+            // Idempotent: repeating the request leaves the stored record unchanged, though the
+            // output is then `false`.
+            // Input: `helper_state: String` (serialized record)
             // Output: `bool`
             Some(bindings::HelperState::PutIfNotExists) => {
-                let helper_state_hex: String = req_parse(&mut req).await?;
+                let helper_state: String = req_parse(&mut req).await?;
                 let success = self
-                    .set_if_not_exists("helper_state", &helper_state_hex)
+                    .set_if_not_exists("helper_state", &helper_state)
                     .await?
                     .is_none();
                 Response::from_json(&success)
             }
 
-            // Get the Helper's state.
+            // Store the Helper's record, replacing the existing one.
             //
             // Idempotent
-            // Output: `String` (hex-encoded state)
+            // Input: `helper_state: String` (serialized record)
+            // Output: `()`
+            Some(bindings::HelperState::Put) => {
+                let helper_state: String = req_parse(&mut req).await?;
+                self.state
+                    .storage()
+                    .put("helper_state", &helper_state)
+                    .await?;
+                Response::from_json(&())
+            }
+
+            // Get the Helper's record.
+            //
+            // Idempotent
+            // Output: `Option<String>` (serialized record)
             Some(bindings::HelperState::Get) => {
                 let helper_state: Option<String> = self.get("helper_state").await?;
                 Response::from_json(&helper_state)
@@ -2,11 +2,12 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 pub(crate) mod aggregate_store;
+pub(crate) mod collected_batches_store;
 pub(crate) mod helper_state_store;
 #[cfg(feature = "test-utils")]
 pub(crate) mod test_state_cleaner;
 
-use crate::tracing_utils::shorten_paths;
+use crate::tracing_utils::{record_trace_context, shorten_paths};
 use daphne_service_utils::durable_requests::bindings::DurableMethod;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::info_span;
@@ -178,7 +179,13 @@ async fn req_parse<T: DeserializeOwned>(req: &mut Request) -> Result<T> {
 
 fn create_span_from_request(req: &Request) -> tracing::Span {
     let path = req.path();
-    let span = info_span!("DO span", p = %shorten_paths(path.split('/')).display());
+    let span = info_span!(
+        "DO span",
+        p = %shorten_paths(path.split('/')).display(),
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+    );
+    record_trace_context(&span, req.headers());
     span.in_scope(|| tracing::info!("{}", path));
     span
 }
@@ -48,7 +48,9 @@ impl TestStateCleaner {
             Some(bindings::TestStateCleaner::Put) => {
                 let durable_ref: DurableReference = req_parse(&mut req).await?;
                 match durable_ref.binding.as_ref() {
-                    bindings::AggregateStore::BINDING | bindings::HelperState::BINDING => (),
+                    bindings::AggregateStore::BINDING
+                    | bindings::CollectedBatches::BINDING
+                    | bindings::HelperState::BINDING => (),
                     s => {
                         let message = format!("GarbageCollector: unrecognized binding: {s}");
                         error!("{}", message);
@@ -28,6 +28,11 @@
 //!
 //! Make a `DELETE` request with uri `{KV_PATH_PREFIX}/path/to/key`.
 //!
+//! ## Listing keys
+//!
+//! Make a `GET` request with uri `{KV_LIST_PATH_PREFIX}/path/to/prefix`. The response is a JSON
+//! array of the keys that start with the prefix.
+//!
 //!
 //! # Durable Objects
 //!
@@ -67,6 +72,14 @@
 //!     .send();
 //! ```
 //!
+//! Several durable object requests can be sent at once as a [`DurableRequestBatch`], the body of a
+//! `POST` request to [`DO_BATCH_PATH`]. The requests are sent to the durable objects concurrently
+//! and the response is a JSON array of [`DurableResponse`]s, in the same order as the requests.
+//!
+//! Durable objects can't be enumerated, so `DELETE` requests to [`DO_TASK_PATH_PREFIX`], which
+//! delete all the objects of a task, are answered with `501 Not Implemented`. The objects delete
+//! themselves once their `DAP_DURABLE_*_GC_AFTER_SECS` elapse.
+//!
 //! [to_uri]: daphne_service_utils::durable_requests::bindings::DurableMethod::to_uri
 
 mod metrics;
@@ -75,9 +88,11 @@ use std::{sync::OnceLock, time::Duration};
 
 use daphne::auth::BearerToken;
 use daphne_service_utils::durable_requests::{
-    DurableRequest, ObjectIdFrom, DO_PATH_PREFIX, KV_PATH_PREFIX,
+    DurableRequest, DurableRequestBatch, DurableResponse, ObjectIdFrom, DO_BATCH_PATH,
+    DO_PATH_PREFIX, DO_TASK_PATH_PREFIX, KV_LIST_PATH_PREFIX, KV_PATH_PREFIX,
 };
-use tracing::warn;
+use futures::future::join_all;
+use tracing::{info_span, warn, Instrument};
 use url::Url;
 use worker::{js_sys::Uint8Array, Delay, Env, Request, RequestInit, Response};
 
@@ -137,6 +152,14 @@ pub async fn handle_request(
         env,
     };
 
+    // continue the trace of the Aggregator that sent the request, if any
+    let span = info_span!(
+        "storage proxy",
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+    );
+    crate::tracing_utils::record_trace_context(&span, ctx.req.headers());
+
     // make an async block in order to avoid early returning and skipping the push_metrics
     // statement
     let response = async {
@@ -150,6 +173,18 @@ pub async fn handle_request(
             .and_then(|s| s.strip_prefix('/'))
         {
             handle_kv_request(&mut ctx, uri).await
+        } else if let Some(prefix) = path
+            .strip_prefix(KV_LIST_PATH_PREFIX)
+            .and_then(|s| s.strip_prefix('/'))
+        {
+            handle_kv_list_request(&ctx, prefix).await
+        } else if path.starts_with(DO_TASK_PATH_PREFIX) {
+            Response::error(
+                "durable objects can't be enumerated, they are garbage collected instead",
+                501, /* Not Implemented */
+            )
+        } else if path == DO_BATCH_PATH {
+            handle_do_batch_request(&mut ctx).await
         } else if let Some(uri) = path.strip_prefix(DO_PATH_PREFIX) {
             handle_do_request(&mut ctx, uri).await
         } else {
@@ -168,6 +203,7 @@ pub async fn handle_request(
             Response::error("invalid base path", 400)
         }
     }
+    .instrument(span)
     .await;
 
     if let Some(metrics) = ctx.metrics {
@@ -262,15 +298,28 @@ async fn handle_kv_request(ctx: &mut RequestContext, key: &str) -> worker::Resul
     }
 }
 
+/// List the KV keys that start with `prefix`.
+async fn handle_kv_list_request(ctx: &RequestContext, prefix: &str) -> worker::Result<Response> {
+    let kv = ctx.env.kv(KV_BINDING_DAP_CONFIG)?;
+    let mut keys = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(prefix.into());
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+        let resp = list.execute().await?;
+        keys.extend(resp.keys.into_iter().map(|key| key.name));
+        if resp.list_complete || resp.cursor.is_none() {
+            break;
+        }
+        cursor = resp.cursor;
+    }
+    Response::from_json(&keys)
+}
+
 /// Handle a durable object request
 async fn handle_do_request(ctx: &mut RequestContext, uri: &str) -> worker::Result<Response> {
-    const RETRY_DELAYS: &[Duration] = &[
-        Duration::from_millis(100),
-        Duration::from_millis(500),
-        Duration::from_millis(1_000),
-        Duration::from_millis(3_000),
-    ];
-
     let buf = ctx.req.bytes().await.map_err(|e| {
         tracing::error!(error = ?e, "failed to get bytes");
         e
@@ -279,6 +328,60 @@ async fn handle_do_request(ctx: &mut RequestContext, uri: &str) -> worker::Resul
     let parsed_req = DurableRequest::try_from(&buf)
         .map_err(|e| worker::Error::RustError(format!("invalid format: {e:?}")))?;
 
+    send_do_request(ctx, uri, &parsed_req).await
+}
+
+/// Handle a batch of durable object requests. A request that fails doesn't fail the others.
+async fn handle_do_batch_request(ctx: &mut RequestContext) -> worker::Result<Response> {
+    let buf = ctx.req.bytes().await.map_err(|e| {
+        tracing::error!(error = ?e, "failed to get bytes");
+        e
+    })?;
+    let batch = DurableRequestBatch::try_from(&buf[..])
+        .map_err(|e| worker::Error::RustError(format!("invalid format: {e:?}")))?;
+    tracing::debug!(len = batch.len(), "handling do batch request");
+
+    let ctx = &*ctx;
+    let responses = join_all(batch.requests().map(|(uri, parsed_req)| async move {
+        let parsed_req = match parsed_req {
+            Ok(parsed_req) => parsed_req,
+            Err(e) => {
+                return DurableResponse {
+                    status: 400,
+                    body: format!("invalid format: {e:?}"),
+                }
+            }
+        };
+        let resp = match send_do_request(ctx, uri, &parsed_req).await {
+            Ok(mut resp) => resp.text().await.map(|body| DurableResponse {
+                status: resp.status_code(),
+                body,
+            }),
+            Err(e) => Err(e),
+        };
+        resp.unwrap_or_else(|e| DurableResponse {
+            status: 500,
+            body: e.to_string(),
+        })
+    }))
+    .await;
+
+    Response::from_json(&responses)
+}
+
+/// Send a durable object request to the object it identifies, retrying if requested.
+async fn send_do_request(
+    ctx: &RequestContext,
+    uri: &str,
+    parsed_req: &DurableRequest<&[u8]>,
+) -> worker::Result<Response> {
+    const RETRY_DELAYS: &[Duration] = &[
+        Duration::from_millis(100),
+        Duration::from_millis(500),
+        Duration::from_millis(1_000),
+        Duration::from_millis(3_000),
+    ];
+
     let binding = ctx.env.durable_object(&parsed_req.binding)?;
 
     let mut do_req = RequestInit::new();
@@ -179,6 +179,44 @@ where
     .collect::<PathBuf>()
 }
 
+/// The trace ID and parent span ID of a W3C trace context `traceparent` header, i.e.,
+/// `{version}-{trace_id}-{parent_id}-{trace_flags}`.
+pub(crate) fn parse_traceparent(traceparent: &str) -> Option<(&str, &str)> {
+    fn is_hex_id(s: &str, len: usize) -> bool {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
+
+    let mut parts = traceparent.trim().split('-');
+    let (Some(version), Some(trace_id), Some(parent_id), Some(trace_flags)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let is_valid = is_hex_id(version, 2)
+        && version != "ff"
+        && is_hex_id(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && is_hex_id(parent_id, 16)
+        && parent_id.bytes().any(|b| b != b'0')
+        && is_hex_id(trace_flags, 2)
+        // Later versions may append fields, but only version 00 is known to have four.
+        && (version != "00" || parts.next().is_none());
+    is_valid.then_some((trace_id, parent_id))
+}
+
+/// Record the trace context of the request, if any, in the `trace_id` and `parent_id` fields of
+/// `span`. The span must declare these fields, e.g., as [`tracing::field::Empty`].
+pub(crate) fn record_trace_context(span: &tracing::Span, headers: &worker::Headers) {
+    if let Ok(Some(traceparent)) = headers.get("traceparent") {
+        if let Some((trace_id, parent_id)) = parse_traceparent(&traceparent) {
+            span.record("trace_id", trace_id);
+            span.record("parent_id", parent_id);
+        }
+    }
+}
+
 /// Setup logging.
 ///
 /// Initialize tracing using configuration from `DAP_TRACING` in the environment
@@ -236,7 +274,7 @@ pub fn initialize_tracing(env: &Env) {
 mod test {
     use std::path::PathBuf;
 
-    use super::shorten_paths;
+    use super::{parse_traceparent, shorten_paths};
 
     #[test]
     fn shorten_paths_simple() {
@@ -253,4 +291,27 @@ mod test {
 
         assert_eq!(got, expect);
     }
+
+    #[test]
+    fn parse_traceparent_valid() {
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some(("4bf92f3577b34da6a3ce929d0e0e4736", "00f067aa0ba902b7"))
+        );
+    }
+
+    #[test]
+    fn parse_traceparent_invalid() {
+        for traceparent in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(parse_traceparent(traceparent), None, "{traceparent}");
+        }
+    }
 }
@@ -1,13 +1,39 @@
 // Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::{messages::TaskId, DapTaskConfig};
+use crate::{
+    constants::DapMediaType,
+    messages::{BatchSelector, CollectionJobId, TaskId},
+    DapTaskConfig, DapVersion,
+};
 
 pub enum AggregationJobAuditAction {
     Init,
     Continue,
 }
 
+pub enum CollectionJobAuditAction {
+    /// The Collector created the collection job.
+    Create,
+
+    /// The collection job completed, i.e., the aggregate shares were made available to the
+    /// Collector.
+    Complete { report_count: u64 },
+}
+
+/// draft-wang-ppm-dap-taskprov: The decision to opt in or out of an advertised task.
+pub enum TaskprovAuditAction<'a> {
+    OptIn,
+    OptOut { reason: &'a str },
+}
+
+pub enum HpkeConfigAuditAction {
+    Add,
+    Remove,
+}
+
+/// Hooks for recording security-relevant events, in particular every aggregate that leaves the
+/// Aggregator.
 pub trait AuditLog {
     fn on_aggregation_job(
         &self,
@@ -17,6 +43,55 @@ pub trait AuditLog {
         report_count: u64,
         action: AggregationJobAuditAction,
     );
+
+    /// Leader: A collection job was created or completed.
+    fn on_collection_job(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        coll_job_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        action: CollectionJobAuditAction,
+    );
+
+    /// Helper: An aggregate share was sent to the Leader.
+    fn on_aggregate_share(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        batch_sel: &BatchSelector,
+        report_count: u64,
+    );
+
+    /// A task advertised by taskprov was opted into or out of.
+    fn on_taskprov(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        action: TaskprovAuditAction<'_>,
+    );
+
+    /// A request of the given DAP version was rejected because its sender is not authorized.
+    fn on_unauthorized_request(
+        &self,
+        host: &str,
+        task_id: &TaskId,
+        version: DapVersion,
+        media_type: Option<DapMediaType>,
+        reason: &str,
+    );
+
+    /// An HPKE receiver configuration was added or removed.
+    fn on_hpke_config(
+        &self,
+        host: &str,
+        version: DapVersion,
+        config_id: u8,
+        action: HpkeConfigAuditAction,
+    );
 }
 
 /// Default implementation of the trait, which is a no-op.
@@ -32,4 +107,53 @@ impl AuditLog for NoopAuditLog {
         _action: AggregationJobAuditAction,
     ) {
     }
+
+    fn on_collection_job(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _coll_job_id: &CollectionJobId,
+        _batch_sel: &BatchSelector,
+        _action: CollectionJobAuditAction,
+    ) {
+    }
+
+    fn on_aggregate_share(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _batch_sel: &BatchSelector,
+        _report_count: u64,
+    ) {
+    }
+
+    fn on_taskprov(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _action: TaskprovAuditAction<'_>,
+    ) {
+    }
+
+    fn on_unauthorized_request(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _version: DapVersion,
+        _media_type: Option<DapMediaType>,
+        _reason: &str,
+    ) {
+    }
+
+    fn on_hpke_config(
+        &self,
+        _host: &str,
+        _version: DapVersion,
+        _config_id: u8,
+        _action: HpkeConfigAuditAction,
+    ) {
+    }
 }
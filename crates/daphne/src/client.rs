@@ -0,0 +1,362 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! A DAP Client. [`DapClient`] fetches the Aggregators' HPKE configurations, produces reports, and
+//! uploads them to the Leader. Reports that could not be uploaded due to a transient failure are
+//! kept in an offline queue so that they can be retried later.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use prio::codec::{Decode, ParameterizedEncode};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    constants::DapMediaType,
+    fatal_error,
+    hpke::{HpkeConfig, HpkeKemId},
+    messages::{decode_base64url_vec, Base64Encode, Extension, HpkeConfigList, TaskId, Time},
+    transport::{DapHttpMethod, DapHttpRequest, DapHttpTransport, DapRetryPolicy},
+    DapError, DapMeasurement, DapVersion, VdafConfig,
+};
+
+/// Header in which an Aggregator sends the signature over its HPKE configuration.
+const HPKE_SIGNATURE: &str = "x-hpke-config-signature";
+
+/// Public key with which an Aggregator signs its HPKE configuration, i.e., the ECDSA P-256 public
+/// key of the Aggregator's signing certificate.
+#[derive(Clone, Debug)]
+pub struct DapHpkeConfigSigningKey {
+    sec1: Vec<u8>,
+}
+
+impl DapHpkeConfigSigningKey {
+    /// Construct the key from its SEC1 encoding, e.g., the public key of the certificate.
+    pub fn from_sec1(sec1: Vec<u8>) -> Self {
+        Self { sec1 }
+    }
+
+    fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &self.sec1)
+            .verify(payload, signature)
+            .is_ok()
+    }
+}
+
+/// A Client for a single task.
+pub struct DapClient<T> {
+    /// Transport used to talk to the Aggregators.
+    pub transport: T,
+
+    /// Base URLs of the Leader and Helper, including the version path segment.
+    pub leader_url: Url,
+    pub helper_url: Url,
+
+    /// Keys with which the Leader and Helper sign their HPKE configurations. If set, then the
+    /// signature is required to be valid.
+    pub leader_signing_key: Option<DapHpkeConfigSigningKey>,
+    pub helper_signing_key: Option<DapHpkeConfigSigningKey>,
+
+    /// Same as [`DapTaskConfig`](crate::DapTaskConfig).
+    pub task_id: TaskId,
+    pub version: DapVersion,
+    pub vdaf: VdafConfig,
+    pub time_precision: u64,
+
+    pub retry_policy: DapRetryPolicy,
+
+    hpke_configs: Mutex<Option<[HpkeConfig; 2]>>,
+    offline_queue: Mutex<VecDeque<Vec<u8>>>,
+}
+
+/// Outcome of an attempt to upload a report.
+enum UploadOutcome {
+    Done,
+    /// The upload failed, but may succeed if retried later.
+    Retry,
+}
+
+impl<T: DapHttpTransport> DapClient<T> {
+    /// Create a new Client.
+    pub fn new(
+        transport: T,
+        leader_url: Url,
+        helper_url: Url,
+        task_id: TaskId,
+        version: DapVersion,
+        vdaf: VdafConfig,
+        time_precision: u64,
+    ) -> Self {
+        Self {
+            transport,
+            leader_url,
+            helper_url,
+            leader_signing_key: None,
+            helper_signing_key: None,
+            task_id,
+            version,
+            vdaf,
+            time_precision,
+            retry_policy: DapRetryPolicy::default(),
+            hpke_configs: Mutex::default(),
+            offline_queue: Mutex::default(),
+        }
+    }
+
+    /// Require the Aggregators to sign their HPKE configurations.
+    #[must_use]
+    pub fn with_signing_keys(
+        mut self,
+        leader_signing_key: DapHpkeConfigSigningKey,
+        helper_signing_key: DapHpkeConfigSigningKey,
+    ) -> Self {
+        self.leader_signing_key = Some(leader_signing_key);
+        self.helper_signing_key = Some(helper_signing_key);
+        self
+    }
+
+    /// Set the policy for retrying uploads.
+    #[must_use]
+    pub fn with_retry_policy(mut self, retry_policy: DapRetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    async fn fetch_hpke_config(
+        &self,
+        base_url: &Url,
+        signing_key: Option<&DapHpkeConfigSigningKey>,
+    ) -> Result<HpkeConfig, DapError> {
+        let mut url = base_url
+            .join("hpke_config")
+            .map_err(|e| fatal_error!(err = ?e))?;
+        url.query_pairs_mut()
+            .append_pair("task_id", &self.task_id.to_base64url());
+
+        let resp = self
+            .transport
+            .send(DapHttpRequest {
+                method: DapHttpMethod::Get,
+                url,
+                headers: Vec::new(),
+                body: Vec::new(),
+            })
+            .await?;
+        resp.expect_status(&[200])?;
+
+        if let Some(signing_key) = signing_key {
+            let signature = resp
+                .header(HPKE_SIGNATURE)
+                .and_then(decode_base64url_vec)
+                .ok_or_else(|| fatal_error!(err = "Aggregator did not sign its HPKE config"))?;
+            if !signing_key.verify(&resp.body, &signature) {
+                return Err(fatal_error!(err = "HPKE config signature not verified"));
+            }
+        }
+
+        HpkeConfigList::get_decoded(&resp.body)
+            .map_err(DapError::encoding)?
+            .hpke_configs
+            .into_iter()
+            .find(|hpke_config| !matches!(hpke_config.kem_id, HpkeKemId::NotImplemented(..)))
+            .ok_or_else(|| fatal_error!(err = "Aggregator advertised no supported HPKE config"))
+    }
+
+    /// Return the Aggregators' HPKE configurations, fetching them if they are not cached.
+    pub async fn hpke_configs(&self) -> Result<[HpkeConfig; 2], DapError> {
+        if let Some(ref hpke_configs) = *self
+            .hpke_configs
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+        {
+            return Ok(hpke_configs.clone());
+        }
+
+        let hpke_configs = [
+            self.fetch_hpke_config(&self.leader_url, self.leader_signing_key.as_ref())
+                .await?,
+            self.fetch_hpke_config(&self.helper_url, self.helper_signing_key.as_ref())
+                .await?,
+        ];
+        *self
+            .hpke_configs
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))? = Some(hpke_configs.clone());
+        Ok(hpke_configs)
+    }
+
+    /// Discard the cached HPKE configurations, e.g., after an Aggregator rotated its keys.
+    pub fn clear_hpke_configs(&self) -> Result<(), DapError> {
+        *self
+            .hpke_configs
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))? = None;
+        Ok(())
+    }
+
+    /// Produce an encoded report for the measurement. The timestamp is rounded down to a multiple
+    /// of the task's time precision.
+    pub async fn produce_report(
+        &self,
+        time: Time,
+        measurement: DapMeasurement,
+        extensions: Vec<Extension>,
+    ) -> Result<Vec<u8>, DapError> {
+        let hpke_configs = self.hpke_configs().await?;
+        self.produce_report_with_hpke_configs(&hpke_configs, time, measurement, extensions)
+    }
+
+    /// Same as [`Self::produce_report`], except that the HPKE configurations are given by the
+    /// caller. This is useful for producing many reports in parallel.
+    pub fn produce_report_with_hpke_configs(
+        &self,
+        hpke_configs: &[HpkeConfig; 2],
+        time: Time,
+        measurement: DapMeasurement,
+        extensions: Vec<Extension>,
+    ) -> Result<Vec<u8>, DapError> {
+        let time = time
+            .checked_rem(self.time_precision)
+            .map(|rem| time - rem)
+            .ok_or_else(|| fatal_error!(err = "time precision must not be 0"))?;
+        self.vdaf
+            .produce_report_with_extensions(
+                hpke_configs,
+                time,
+                &self.task_id,
+                measurement,
+                extensions,
+                self.version,
+            )?
+            .get_encoded_with_param(&self.version)
+            .map_err(DapError::encoding)
+    }
+
+    async fn try_upload(&self, report: Vec<u8>) -> Result<UploadOutcome, DapError> {
+        let url = self
+            .leader_url
+            .join(&format!("tasks/{}/reports", self.task_id.to_base64url()))
+            .map_err(|e| fatal_error!(err = ?e))?;
+        let content_type = DapMediaType::Report
+            .as_str_for_version(self.version)
+            .ok_or_else(|| fatal_error!(err = "unsupported media type for version"))?;
+
+        let resp = match self
+            .transport
+            .send(DapHttpRequest {
+                method: DapHttpMethod::for_upload(self.version),
+                url,
+                headers: vec![("content-type".into(), content_type.into())],
+                body: report,
+            })
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!(error = ?e, "failed to send report");
+                return Ok(UploadOutcome::Retry);
+            }
+        };
+
+        match resp.status {
+            200 | 201 => Ok(UploadOutcome::Done),
+            429 | 500..=599 => {
+                warn!(status = resp.status, "Leader failed to accept report");
+                Ok(UploadOutcome::Retry)
+            }
+            _ => {
+                // The Leader may have rejected the report because we encrypted it under a stale
+                // HPKE config.
+                self.clear_hpke_configs()?;
+                resp.expect_status(&[]).map(|()| UploadOutcome::Done)
+            }
+        }
+    }
+
+    /// Upload an encoded report, retrying transient failures as specified by the
+    /// [`DapRetryPolicy`]. If every attempt fails, then the report is added to the offline queue.
+    /// Returns `true` if the report was uploaded and `false` if it was queued.
+    pub async fn upload_encoded(&self, report: Vec<u8>) -> Result<bool, DapError> {
+        let mut delay = self.retry_policy.initial_delay;
+        for attempt in 0..self.retry_policy.max_attempts {
+            if attempt > 0 {
+                self.transport.sleep(delay).await;
+                delay = self.retry_policy.next_delay(delay);
+            }
+            if let UploadOutcome::Done = self.try_upload(report.clone()).await? {
+                return Ok(true);
+            }
+        }
+
+        debug!("queueing report for later upload");
+        self.offline_queue
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .push_back(report);
+        Ok(false)
+    }
+
+    /// Produce a report for the measurement and upload it. Same as [`Self::upload_encoded`]
+    /// otherwise.
+    pub async fn upload(&self, time: Time, measurement: DapMeasurement) -> Result<bool, DapError> {
+        let report = self.produce_report(time, measurement, Vec::new()).await?;
+        self.upload_encoded(report).await
+    }
+
+    /// Number of reports in the offline queue.
+    pub fn queued_reports(&self) -> Result<usize, DapError> {
+        Ok(self
+            .offline_queue
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .len())
+    }
+
+    /// Remove the reports from the offline queue, e.g., in order to persist them.
+    pub fn take_queued_reports(&self) -> Result<Vec<Vec<u8>>, DapError> {
+        Ok(self
+            .offline_queue
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .drain(..)
+            .collect())
+    }
+
+    /// Add encoded reports to the offline queue, e.g., after restoring them from persistent
+    /// storage.
+    pub fn queue_reports(
+        &self,
+        reports: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<(), DapError> {
+        self.offline_queue
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .extend(reports);
+        Ok(())
+    }
+
+    /// Try once to upload each report in the offline queue. Reports that fail due to a transient
+    /// error remain in the queue. Returns the number of reports that were uploaded.
+    pub async fn flush_queued_reports(&self) -> Result<usize, DapError> {
+        let reports = self.take_queued_reports()?;
+        let mut uploaded = 0;
+        let mut reports = reports.into_iter();
+        while let Some(report) = reports.next() {
+            match self.try_upload(report.clone()).await {
+                Ok(UploadOutcome::Done) => uploaded += 1,
+                Ok(UploadOutcome::Retry) => {
+                    // Stop at the first transient failure, since the Leader is likely still
+                    // unavailable.
+                    self.queue_reports(std::iter::once(report).chain(reports))?;
+                    break;
+                }
+                Err(e) => {
+                    self.queue_reports(reports)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(uploaded)
+    }
+}
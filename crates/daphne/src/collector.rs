@@ -0,0 +1,529 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! A DAP Collector. [`DapCollector`] drives a collection job through its lifecycle: It issues the
+//! [`CollectionReq`] to the Leader, polls the collection job until the [`Collection`] is ready,
+//! checks that the [`Collection`] matches the query, and finally decrypts and unshards the
+//! aggregate result.
+
+use std::time::Duration;
+
+use prio::codec::{Encode, ParameterizedDecode, ParameterizedEncode};
+use rand::prelude::*;
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    constants::DapMediaType,
+    fatal_error,
+    hpke::HpkeDecrypter,
+    messages::{
+        Base64Encode, BatchSelector, Collection, CollectionJobId, CollectionReq,
+        PartialBatchSelector, Query, TaskId,
+    },
+    transport::{
+        DapHttpAuth, DapHttpMethod, DapHttpRequest, DapHttpResponse, DapHttpTransport,
+        DapRetryPolicy,
+    },
+    DapAggregateResult, DapAggregationParam, DapError, DapVersion, VdafConfig,
+};
+
+/// How to poll a collection job that is not yet complete. The delay is doubled after each attempt,
+/// unless the Leader indicates a delay via the "Retry-After" header.
+pub type DapPollPolicy = DapRetryPolicy;
+
+/// A collection job started by the Collector.
+#[derive(Clone, Debug)]
+pub struct DapCollectionJobHandle {
+    pub coll_job_id: CollectionJobId,
+
+    /// The URI at which the collection job is polled.
+    pub uri: Url,
+
+    pub query: Query,
+    pub agg_param: DapAggregationParam,
+}
+
+/// The status of a collection job.
+#[derive(Debug)]
+pub enum DapCollectionPoll {
+    /// The collection is ready.
+    Done(Collection),
+
+    /// The collection is not ready yet. The Leader may indicate how long to wait before polling
+    /// again.
+    Pending { retry_after: Option<Duration> },
+}
+
+/// A Collector for a single task.
+pub struct DapCollector<T, A, D> {
+    /// Transport used to talk to the Leader.
+    pub transport: T,
+
+    /// Authorization for requests sent to the Leader.
+    pub auth: A,
+
+    /// Decrypter for the aggregate shares, i.e., the Collector's HPKE receiver configuration.
+    pub decrypter: D,
+
+    /// Base URL of the Leader, including the version path segment.
+    pub leader_url: Url,
+
+    /// Same as [`DapTaskConfig`](crate::DapTaskConfig).
+    pub task_id: TaskId,
+    pub version: DapVersion,
+    pub vdaf: VdafConfig,
+    pub min_batch_size: u64,
+
+    pub poll_policy: DapPollPolicy,
+}
+
+impl<T, A, D> DapCollector<T, A, D>
+where
+    T: DapHttpTransport,
+    A: DapHttpAuth,
+    D: HpkeDecrypter,
+{
+    async fn send(
+        &self,
+        method: DapHttpMethod,
+        url: Url,
+        media_type: Option<DapMediaType>,
+        body: Vec<u8>,
+    ) -> Result<DapHttpResponse, DapError> {
+        let mut headers = self.auth.headers(&self.task_id).await?;
+        if let Some(media_type) = media_type {
+            let content_type = media_type
+                .as_str_for_version(self.version)
+                .ok_or_else(|| fatal_error!(err = "unsupported media type for version"))?;
+            headers.push(("content-type".into(), content_type.into()));
+        }
+        self.transport
+            .send(DapHttpRequest {
+                method,
+                url,
+                headers,
+                body,
+            })
+            .await
+    }
+
+    /// Start a collection job for the given query.
+    pub async fn start(
+        &self,
+        query: Query,
+        agg_param: DapAggregationParam,
+    ) -> Result<DapCollectionJobHandle, DapError> {
+        let coll_job_id = CollectionJobId(thread_rng().gen());
+        let coll_job_url = self
+            .leader_url
+            .join(&format!(
+                "tasks/{}/collection_jobs/{}",
+                self.task_id.to_base64url(),
+                coll_job_id.to_base64url()
+            ))
+            .map_err(|e| fatal_error!(err = ?e))?;
+
+        let coll_job_req = CollectionReq {
+            query,
+            agg_param: agg_param.get_encoded().map_err(DapError::encoding)?,
+        };
+        let resp = self
+            .send(
+                DapHttpMethod::Put,
+                coll_job_url.clone(),
+                Some(DapMediaType::CollectReq),
+                coll_job_req
+                    .get_encoded_with_param(&self.version)
+                    .map_err(DapError::encoding)?,
+            )
+            .await?;
+        resp.expect_status(&[200, 201])?;
+
+        // Daphne responds with the URI at which the collection job is to be polled. Other
+        // implementations expect the collection job to be polled at the URI it was created at.
+        let uri = serde_json::from_slice(&resp.body).unwrap_or(coll_job_url);
+        debug!("started collection job {}", coll_job_id.to_base64url());
+
+        Ok(DapCollectionJobHandle {
+            coll_job_id,
+            uri,
+            query,
+            agg_param,
+        })
+    }
+
+    /// Poll the collection job once.
+    pub async fn poll(&self, job: &DapCollectionJobHandle) -> Result<DapCollectionPoll, DapError> {
+        let resp = self
+            .send(
+                DapHttpMethod::for_collection_poll(self.version),
+                job.uri.clone(),
+                Some(DapMediaType::CollectReq),
+                Vec::new(),
+            )
+            .await?;
+        resp.expect_status(&[200, 202])?;
+        if resp.status == 202 {
+            return Ok(DapCollectionPoll::Pending {
+                retry_after: resp.retry_after(),
+            });
+        }
+
+        let collection = Collection::get_decoded_with_param(&self.version, &resp.body)
+            .map_err(DapError::encoding)?;
+        Ok(DapCollectionPoll::Done(collection))
+    }
+
+    /// Poll the collection job until it is complete, backing off between attempts as specified by
+    /// the [`DapPollPolicy`].
+    pub async fn wait(&self, job: &DapCollectionJobHandle) -> Result<Collection, DapError> {
+        let mut delay = self.poll_policy.initial_delay;
+        for _ in 0..self.poll_policy.max_attempts {
+            match self.poll(job).await? {
+                DapCollectionPoll::Done(collection) => return Ok(collection),
+                DapCollectionPoll::Pending { retry_after } => {
+                    self.transport.sleep(retry_after.unwrap_or(delay)).await;
+                    delay = self.poll_policy.next_delay(delay);
+                }
+            }
+        }
+
+        Err(fatal_error!(
+            err = "collection job did not complete",
+            coll_job_id = %job.coll_job_id.to_base64url(),
+            attempts = self.poll_policy.max_attempts,
+        ))
+    }
+
+    /// Delete the collection job. The Leader discards the collection job and its results.
+    pub async fn delete(&self, job: &DapCollectionJobHandle) -> Result<(), DapError> {
+        let resp = self
+            .send(DapHttpMethod::Delete, job.uri.clone(), None, Vec::new())
+            .await?;
+        resp.expect_status(&[200, 204])
+    }
+
+    /// Check that the collection is consistent with the query, then decrypt and unshard the
+    /// aggregate result.
+    pub async fn consume(
+        &self,
+        job: &DapCollectionJobHandle,
+        collection: Collection,
+    ) -> Result<DapAggregateResult, DapError> {
+        let batch_sel = self.check_collection(job, &collection)?;
+        self.vdaf
+            .consume_encrypted_agg_shares(
+                &self.decrypter,
+                &self.task_id,
+                &batch_sel,
+                collection.report_count,
+                &job.agg_param,
+                collection.encrypted_agg_shares.to_vec(),
+                self.version,
+            )
+            .await
+    }
+
+    /// Run a collection job to completion and return the aggregate result.
+    pub async fn collect(
+        &self,
+        query: Query,
+        agg_param: DapAggregationParam,
+    ) -> Result<DapAggregateResult, DapError> {
+        let job = self.start(query, agg_param).await?;
+        let collection = self.wait(&job).await?;
+        self.consume(&job, collection).await
+    }
+
+    /// Check the collection against the query and return the batch selector to use for
+    /// decryption.
+    fn check_collection(
+        &self,
+        job: &DapCollectionJobHandle,
+        collection: &Collection,
+    ) -> Result<BatchSelector, DapError> {
+        let batch_sel = match (&job.query, &collection.part_batch_sel) {
+            (Query::TimeInterval { batch_interval }, PartialBatchSelector::TimeInterval) => {
+                if collection.interval.start < batch_interval.start
+                    || collection.interval.end() > batch_interval.end()
+                {
+                    return Err(fatal_error!(
+                        err = "collection interval is not contained in the batch interval",
+                        interval = ?collection.interval,
+                        batch_interval = ?batch_interval,
+                    ));
+                }
+                BatchSelector::TimeInterval {
+                    batch_interval: *batch_interval,
+                }
+            }
+            (
+                Query::FixedSizeByBatchId { batch_id: expected },
+                PartialBatchSelector::FixedSizeByBatchId { batch_id },
+            ) if expected == batch_id => BatchSelector::FixedSizeByBatchId {
+                batch_id: *batch_id,
+            },
+            (
+                Query::FixedSizeCurrentBatch,
+                PartialBatchSelector::FixedSizeByBatchId { batch_id },
+            ) => BatchSelector::FixedSizeByBatchId {
+                batch_id: *batch_id,
+            },
+            (query, part_batch_sel) => {
+                return Err(fatal_error!(
+                    err = "collection does not match the query",
+                    query = ?query,
+                    part_batch_sel = ?part_batch_sel,
+                ))
+            }
+        };
+
+        if collection.report_count == 0 || collection.report_count < self.min_batch_size {
+            return Err(fatal_error!(
+                err = "collection has too few reports",
+                report_count = collection.report_count,
+                min_batch_size = self.min_batch_size,
+            ));
+        }
+
+        if collection.interval.duration == 0 {
+            return Err(fatal_error!(err = "collection interval is empty"));
+        }
+
+        Ok(batch_sel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+    use assert_matches::assert_matches;
+    use async_trait::async_trait;
+    use prio::codec::ParameterizedEncode;
+    use rand::prelude::*;
+    use url::Url;
+
+    use super::{DapCollectionJobHandle, DapCollector, DapPollPolicy};
+    use crate::{
+        auth::BearerToken,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{
+            BatchId, Collection, CollectionJobId, HpkeCiphertext, Interval, PartialBatchSelector,
+            Query, TaskId,
+        },
+        transport::{DapHttpRequest, DapHttpResponse, DapHttpTransport},
+        DapAggregationParam, DapError, DapVersion, Prio3Config, VdafConfig,
+    };
+
+    /// Transport that replays a fixed sequence of responses.
+    #[derive(Default)]
+    struct ScriptedTransport {
+        responses: Mutex<VecDeque<DapHttpResponse>>,
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    #[async_trait]
+    impl DapHttpTransport for ScriptedTransport {
+        async fn send(&self, _req: DapHttpRequest) -> Result<DapHttpResponse, DapError> {
+            Ok(self.responses.lock().unwrap().pop_front().unwrap())
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+        }
+    }
+
+    fn collector(
+        responses: impl IntoIterator<Item = DapHttpResponse>,
+    ) -> DapCollector<ScriptedTransport, BearerToken, HpkeReceiverConfig> {
+        DapCollector {
+            transport: ScriptedTransport {
+                responses: Mutex::new(responses.into_iter().collect()),
+                ..Default::default()
+            },
+            auth: BearerToken::from("collector token"),
+            decrypter: HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256).unwrap(),
+            leader_url: Url::parse("https://leader.com/v09/").unwrap(),
+            task_id: TaskId(thread_rng().gen()),
+            version: DapVersion::Draft09,
+            vdaf: VdafConfig::Prio3(Prio3Config::Count),
+            min_batch_size: 10,
+            poll_policy: DapPollPolicy {
+                initial_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(3),
+                max_attempts: 4,
+            },
+        }
+    }
+
+    fn job(query: Query) -> DapCollectionJobHandle {
+        DapCollectionJobHandle {
+            coll_job_id: CollectionJobId(thread_rng().gen()),
+            uri: Url::parse("https://leader.com/v09/collection_job").unwrap(),
+            query,
+            agg_param: DapAggregationParam::Empty,
+        }
+    }
+
+    fn collection(part_batch_sel: PartialBatchSelector, report_count: u64) -> Collection {
+        let ciphertext = HpkeCiphertext {
+            config_id: 23,
+            enc: Vec::new(),
+            payload: Vec::new(),
+        };
+        Collection {
+            part_batch_sel,
+            report_count,
+            interval: Interval {
+                start: 3600,
+                duration: 3600,
+            },
+            encrypted_agg_shares: [ciphertext.clone(), ciphertext],
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_backs_off() {
+        let pending = DapHttpResponse {
+            status: 202,
+            ..Default::default()
+        };
+        let c = collector([
+            pending.clone(),
+            DapHttpResponse {
+                status: 202,
+                headers: vec![("Retry-After".into(), "10".into())],
+                ..Default::default()
+            },
+            pending.clone(),
+            pending,
+        ]);
+
+        assert_matches!(
+            c.wait(&job(Query::FixedSizeCurrentBatch)).await,
+            Err(DapError::Fatal(..))
+        );
+        assert_eq!(
+            *c.transport.sleeps.lock().unwrap(),
+            [1, 10, 3, 3].map(Duration::from_secs)
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_done() {
+        let want = collection(
+            PartialBatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId(thread_rng().gen()),
+            },
+            10,
+        );
+        let c = collector([
+            DapHttpResponse {
+                status: 202,
+                ..Default::default()
+            },
+            DapHttpResponse {
+                status: 200,
+                body: want.get_encoded_with_param(&DapVersion::Draft09).unwrap(),
+                ..Default::default()
+            },
+        ]);
+
+        let got = c.wait(&job(Query::FixedSizeCurrentBatch)).await.unwrap();
+        assert_eq!(got, want);
+    }
+
+    #[tokio::test]
+    async fn consume_rejects_unexpected_collection() {
+        let c = collector([]);
+        let batch_id = BatchId(thread_rng().gen());
+        let time_interval = Query::TimeInterval {
+            batch_interval: Interval {
+                start: 7200,
+                duration: 3600,
+            },
+        };
+
+        // Collection interval is not contained in the batch interval.
+        assert_matches!(
+            c.consume(
+                &job(time_interval),
+                collection(PartialBatchSelector::TimeInterval, 10)
+            )
+            .await,
+            Err(DapError::Fatal(e)) if e.to_string().contains("not contained")
+        );
+
+        // Query type mismatch.
+        assert_matches!(
+            c.consume(
+                &job(time_interval),
+                collection(PartialBatchSelector::FixedSizeByBatchId { batch_id }, 10)
+            )
+            .await,
+            Err(DapError::Fatal(e)) if e.to_string().contains("does not match")
+        );
+
+        // Batch ID mismatch.
+        assert_matches!(
+            c.consume(
+                &job(Query::FixedSizeByBatchId {
+                    batch_id: BatchId(thread_rng().gen())
+                }),
+                collection(PartialBatchSelector::FixedSizeByBatchId { batch_id }, 10)
+            )
+            .await,
+            Err(DapError::Fatal(e)) if e.to_string().contains("does not match")
+        );
+
+        // Too few reports.
+        assert_matches!(
+            c.consume(
+                &job(Query::FixedSizeByBatchId { batch_id }),
+                collection(PartialBatchSelector::FixedSizeByBatchId { batch_id }, 9)
+            )
+            .await,
+            Err(DapError::Fatal(e)) if e.to_string().contains("too few reports")
+        );
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let c = collector([
+            DapHttpResponse {
+                status: 204,
+                ..Default::default()
+            },
+            DapHttpResponse {
+                status: 405,
+                ..Default::default()
+            },
+        ]);
+        let job = job(Query::FixedSizeCurrentBatch);
+        c.delete(&job).await.unwrap();
+        assert_matches!(c.delete(&job).await, Err(DapError::Peer(peer)) if peer.status == 405);
+    }
+
+    #[tokio::test]
+    async fn poll_abort() {
+        let c = collector([DapHttpResponse {
+            status: 400,
+            headers: vec![(
+                "Content-Type".into(),
+                "application/problem+json; charset=utf-8".into(),
+            )],
+            body: br#"{
+                "type": "urn:ietf:params:ppm:dap:error:batchInvalid",
+                "title": "Batch invalid"
+            }"#
+            .to_vec(),
+        }]);
+
+        assert_matches!(
+            c.poll(&job(Query::FixedSizeCurrentBatch)).await,
+            Err(DapError::Peer(peer)) if peer.abort_type() == "batchInvalid"
+        );
+    }
+}
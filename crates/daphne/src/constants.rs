@@ -5,8 +5,9 @@
 
 use crate::{DapSender, DapVersion};
 
-// Media types for HTTP requests.
+// Media types for HTTP requests. These are the same for all supported versions of DAP.
 const MEDIA_TYPE_AGG_JOB_INIT_REQ: &str = "application/dap-aggregation-job-init-req";
+const MEDIA_TYPE_AGG_JOB_CONT_REQ: &str = "application/dap-aggregation-job-continue-req";
 const MEDIA_TYPE_AGG_JOB_RESP: &str = "application/dap-aggregation-job-resp";
 const MEDIA_TYPE_AGG_SHARE_REQ: &str = "application/dap-aggregate-share-req";
 const MEDIA_TYPE_AGG_SHARE: &str = "application/dap-aggregate-share";
@@ -20,6 +21,7 @@ const MEDIA_TYPE_REPORT: &str = "application/dap-report";
 #[cfg_attr(test, derive(strum::EnumIter))]
 pub enum DapMediaType {
     AggregationJobInitReq,
+    AggregationJobContinueReq,
     AggregationJobResp,
     AggregateShareReq,
     AggregateShare,
@@ -35,6 +37,7 @@ impl DapMediaType {
     pub fn sender(&self) -> DapSender {
         match self {
             Self::AggregationJobInitReq
+            | Self::AggregationJobContinueReq
             | Self::AggregateShareReq
             | Self::Collection
             | Self::HpkeConfigList => DapSender::Leader,
@@ -49,6 +52,7 @@ impl DapMediaType {
         let (content_type, _) = content_type.split_once(';').unwrap_or((content_type, ""));
         let media_type = match content_type {
             MEDIA_TYPE_AGG_JOB_INIT_REQ => Self::AggregationJobInitReq,
+            MEDIA_TYPE_AGG_JOB_CONT_REQ => Self::AggregationJobContinueReq,
             MEDIA_TYPE_AGG_JOB_RESP => Self::AggregationJobResp,
             MEDIA_TYPE_AGG_SHARE => Self::AggregateShare,
             MEDIA_TYPE_COLLECTION => Self::Collection,
@@ -66,6 +70,7 @@ impl DapMediaType {
     pub fn as_str_for_version(&self, _version: DapVersion) -> Option<&'static str> {
         match self {
             Self::AggregationJobInitReq => Some(MEDIA_TYPE_AGG_JOB_INIT_REQ),
+            Self::AggregationJobContinueReq => Some(MEDIA_TYPE_AGG_JOB_CONT_REQ),
             Self::AggregationJobResp => Some(MEDIA_TYPE_AGG_JOB_RESP),
             Self::AggregateShareReq => Some(MEDIA_TYPE_AGG_SHARE_REQ),
             Self::AggregateShare => Some(MEDIA_TYPE_AGG_SHARE),
@@ -128,6 +133,13 @@ mod test {
             DapMediaType::from_str_for_version(DapVersion::Draft09, "application/dap-collection"),
             Some(DapMediaType::Collection),
         );
+        assert_eq!(
+            DapMediaType::from_str_for_version(
+                DapVersion::Draft09,
+                "application/dap-aggregation-job-continue-req"
+            ),
+            Some(DapMediaType::AggregationJobContinueReq),
+        );
 
         // Invalid media type
         assert_eq!(
@@ -153,6 +165,17 @@ mod test {
 
     test_versions! { round_trip }
 
+    #[test]
+    fn media_types_are_the_same_for_all_versions() {
+        for media_type in DapMediaType::iter() {
+            assert_eq!(
+                media_type.as_str_for_version(DapVersion::Draft09),
+                media_type.as_str_for_version(DapVersion::Latest),
+                "{media_type:?}"
+            );
+        }
+    }
+
     fn media_type_parsing_ignores_content_type_paramters(version: DapVersion) {
         assert_eq!(
             DapMediaType::from_str_for_version(
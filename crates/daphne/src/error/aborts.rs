@@ -5,7 +5,7 @@
 
 use crate::{
     fatal_error,
-    messages::{Base64Encode, TaskId, TransitionFailure},
+    messages::{AggregationJobId, Base64Encode, TaskId, TransitionFailure},
     DapError, DapMediaType, DapRequest, DapVersion,
 };
 use hex::FromHexError;
@@ -44,6 +44,11 @@ pub enum DapAbort {
     #[error("invalidBatchSize")]
     InvalidBatchSize { detail: String, task_id: TaskId },
 
+    /// Invalid aggregation parameter. Sent in response to a request whose aggregation parameter
+    /// can't be decoded or is not valid for the task's VDAF.
+    #[error("invalidAggregationParameter")]
+    InvalidAggregationParameter { detail: String, task_id: TaskId },
+
     /// taskprov: Invalid DAP task. Sent when a server opts out of a taskprov task configuration.
     #[error("invalidTask")]
     InvalidTask { detail: String, task_id: TaskId },
@@ -52,6 +57,11 @@ pub enum DapAbort {
     #[error("missingTaskID")]
     MissingTaskId,
 
+    /// Outdated configuration. Sent in response to an upload request containing a Report that was
+    /// encrypted with an HPKE configuration the Aggregator no longer has.
+    #[error("outdatedConfig")]
+    OutdatedConfig { detail: String, task_id: TaskId },
+
     /// Query mismatch. Sent in response to a [`CollectionReq`](crate::messages::CollectionReq) or
     /// [`AggregateShareReq`](crate::messages::AggregateShareReq).
     #[error("queryMismatch")]
@@ -65,16 +75,15 @@ pub enum DapAbort {
     /// Report too late. Sent in response to an upload request for a task that is known to have
     /// expired.
     #[error("reportTooLate")]
-    ReportTooLate,
+    ReportTooLate { task_id: TaskId },
 
-    /// Round mismatch. The aggregators disagree on the current round of the VDAF preparation protocol.
-    /// This abort occurs during the aggregation sub-protocol.
-    #[error("roundMismatch")]
-    RoundMismatch {
+    /// Step mismatch. The aggregators disagree on the current step of the VDAF preparation
+    /// protocol. This abort occurs during the aggregation sub-protocol.
+    #[error("stepMismatch")]
+    StepMismatch {
         detail: String,
         task_id: TaskId,
-        // TODO draft02 cleanup: Use `AggregationJobId`
-        agg_job_id_base64url: String,
+        agg_job_id: AggregationJobId,
     },
 
     /// Unauthorized HTTP request.
@@ -86,8 +95,7 @@ pub enum DapAbort {
     #[error("unrecognizedAggregationJob")]
     UnrecognizedAggregationJob {
         task_id: TaskId,
-        // TODO draft02 cleanup: Use `AggregationJobId`
-        agg_job_id_base64url: String,
+        agg_job_id: AggregationJobId,
     },
 
     /// Invalid message. Sent in response to a malformed or unexpected message.
@@ -99,20 +107,23 @@ pub enum DapAbort {
 
     /// Unrecognized DAP task. Sent in response to a request indicating an unrecognized task ID.
     #[error("unrecognizedTask")]
-    UnrecognizedTask,
+    UnrecognizedTask { task_id: TaskId },
 }
 
 impl DapAbort {
-    /// Construct a problem details JSON object for this abort. `url` is the URL to which the
-    /// request was targeted and `task_id` is the associated `TaskID`.
+    /// Construct a problem details JSON object for this abort. The `instance` member, and the
+    /// `taskid` and `aggregationjobid` members of aborts that don't indicate them, are filled in
+    /// from the request with [`ProblemDetails::with_request`].
     pub fn into_problem_details(self) -> ProblemDetails {
         let (title, typ) = self.title_and_type();
-        let (task_id, detail, agg_job_id_base64url) = match self {
+        let (task_id, detail, agg_job_id) = match self {
             Self::BatchInvalid { detail, task_id }
+            | Self::InvalidAggregationParameter { detail, task_id }
             | Self::InvalidTask { detail, task_id }
             | Self::BatchMismatch { detail, task_id }
             | Self::BatchOverlap { detail, task_id }
             | Self::InvalidBatchSize { detail, task_id }
+            | Self::OutdatedConfig { detail, task_id }
             | Self::QueryMismatch { detail, task_id }
             | Self::UnauthorizedRequest { detail, task_id } => (Some(task_id), Some(detail), None),
             Self::MissingTaskId => (
@@ -123,29 +134,34 @@ impl DapAbort {
             Self::BadRequest(detail) | Self::ReportRejected { detail } => {
                 (None, Some(detail), None)
             }
-            Self::RoundMismatch {
+            Self::ReportTooLate { task_id } => (
+                Some(task_id),
+                Some("The report's timestamp is after the task's expiration.".into()),
+                None,
+            ),
+            Self::StepMismatch {
                 detail,
                 task_id,
-                agg_job_id_base64url,
-            } => (Some(task_id), Some(detail), Some(agg_job_id_base64url)),
+                agg_job_id,
+            } => (Some(task_id), Some(detail), Some(agg_job_id)),
             Self::UnrecognizedAggregationJob {
                 task_id,
-                agg_job_id_base64url,
+                agg_job_id,
             } => (
                 Some(task_id),
                 Some("The request indicates an aggregation job that does not exist.".into()),
-                Some(agg_job_id_base64url),
+                Some(agg_job_id),
             ),
             Self::InvalidMessage { detail, task_id } => (task_id, Some(detail), None),
-            Self::ReportTooLate | Self::UnrecognizedTask => (None, None, None),
+            Self::UnrecognizedTask { task_id } => (Some(task_id), None, None),
         };
 
         ProblemDetails {
             typ,
             title: title.to_string(),
             task_id: task_id.map(|id| id.to_base64url()),
-            agg_job_id: agg_job_id_base64url,
-            instance: None, // TODO interop: Implement as specified.
+            agg_job_id: agg_job_id.map(|id| id.to_base64url()),
+            instance: None,
             detail,
         }
     }
@@ -243,12 +259,19 @@ impl DapAbort {
                 Some(self.to_string()),
             ),
             Self::InvalidBatchSize { .. } => ("Batch size is invalid", Some(self.to_string())),
+            Self::InvalidAggregationParameter { .. } => {
+                ("Aggregation parameter is invalid", Some(self.to_string()))
+            }
             Self::InvalidTask { .. } => ("Opted out of Taskprov task", Some(self.to_string())),
+            Self::OutdatedConfig { .. } => (
+                "Report was encrypted with an outdated HPKE configuration",
+                Some(self.to_string()),
+            ),
             Self::QueryMismatch { .. } => {
                 ("Query type does not match the task", Some(self.to_string()))
             }
-            Self::RoundMismatch { .. } => (
-                "Aggregation round indicated by peer does not match host",
+            Self::StepMismatch { .. } => (
+                "Aggregation step indicated by peer does not match host",
                 Some(self.to_string()),
             ),
             Self::MissingTaskId => (
@@ -256,7 +279,7 @@ impl DapAbort {
                 Some(self.to_string()),
             ),
             Self::ReportRejected { .. } => ("Report rejected", Some(self.to_string())),
-            Self::ReportTooLate => (
+            Self::ReportTooLate { .. } => (
                 "The requested task expires after report timestamp",
                 Some(self.to_string()),
             ),
@@ -267,7 +290,7 @@ impl DapAbort {
                 ("Unrecognized aggregation job", Some(self.to_string()))
             }
             Self::InvalidMessage { .. } => ("Malformed or invalid message", Some(self.to_string())),
-            Self::UnrecognizedTask => (
+            Self::UnrecognizedTask { .. } => (
                 "Task indicated by request is not recognized",
                 Some(self.to_string()),
             ),
@@ -289,6 +312,13 @@ impl DapAbort {
         }
     }
 
+    pub fn invalid_agg_param(e: CodecError, task_id: TaskId) -> Self {
+        Self::InvalidAggregationParameter {
+            detail: format!("codec error: {e}"),
+            task_id,
+        }
+    }
+
     pub fn from_hex_error(e: FromHexError, task_id: TaskId) -> Self {
         Self::InvalidMessage {
             detail: format!("invalid hexadecimal string {e:?}"),
@@ -298,7 +328,7 @@ impl DapAbort {
 }
 
 /// A problem details document compatible with RFC 7807.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ProblemDetails {
     pub title: String,
 
@@ -320,3 +350,72 @@ pub struct ProblemDetails {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
 }
+
+impl ProblemDetails {
+    /// Identify the request that was aborted: `instance` is the endpoint to which the request was
+    /// targeted, and the task ID and aggregation job ID are those indicated by the request, if
+    /// any. Members that the abort already set are kept.
+    #[must_use]
+    pub fn with_request(
+        mut self,
+        instance: &str,
+        task_id: Option<&TaskId>,
+        agg_job_id: Option<&AggregationJobId>,
+    ) -> Self {
+        self.instance.get_or_insert_with(|| instance.to_string());
+        if self.typ.is_some() {
+            // Only DAP aborts are specific to a task.
+            if let Some(task_id) = task_id {
+                self.task_id.get_or_insert_with(|| task_id.to_base64url());
+            }
+            if let Some(agg_job_id) = agg_job_id {
+                self.agg_job_id
+                    .get_or_insert_with(|| agg_job_id.to_base64url());
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DapAbort;
+    use crate::messages::{AggregationJobId, Base64Encode, TaskId};
+
+    #[test]
+    fn problem_details_identify_request() {
+        let task_id = TaskId([1; 32]);
+        let agg_job_id = AggregationJobId([2; 16]);
+        let instance = format!(
+            "/v09/tasks/{}/aggregation_jobs/{}",
+            task_id.to_base64url(),
+            agg_job_id.to_base64url()
+        );
+
+        let problem_details = DapAbort::InvalidAggregationParameter {
+            detail: "invalid aggregation parameter".into(),
+            task_id,
+        }
+        .into_problem_details()
+        .with_request(&instance, Some(&task_id), Some(&agg_job_id));
+        assert_eq!(
+            serde_json::to_value(problem_details).unwrap(),
+            serde_json::json!({
+                "type": "urn:ietf:params:ppm:dap:error:invalidAggregationParameter",
+                "title": "Aggregation parameter is invalid",
+                "taskid": task_id.to_base64url(),
+                "aggregationjobid": agg_job_id.to_base64url(),
+                "instance": instance,
+                "detail": "invalid aggregation parameter",
+            })
+        );
+
+        // Not a DAP abort, so there's no task or aggregation job to indicate.
+        let problem_details = DapAbort::BadRequest("bad".into())
+            .into_problem_details()
+            .with_request(&instance, Some(&task_id), Some(&agg_job_id));
+        assert_eq!(problem_details.task_id, None);
+        assert_eq!(problem_details.agg_job_id, None);
+        assert_eq!(problem_details.instance, Some(instance));
+    }
+}
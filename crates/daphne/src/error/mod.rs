@@ -5,9 +5,13 @@ pub mod aborts;
 
 use std::fmt::{Debug, Display};
 
-use crate::{messages::TransitionFailure, vdaf::VdafError};
+use crate::{
+    messages::{Base64Encode, TaskId, TransitionFailure},
+    vdaf::VdafError,
+};
 pub use aborts::DapAbort;
 use prio::codec::CodecError;
+use serde::{Deserialize, Serialize};
 
 use self::aborts::ProblemDetails;
 
@@ -29,6 +33,10 @@ pub enum DapError {
     /// certain conditions, trigger an abort.
     #[error("transition error: {0}")]
     Transition(#[from] TransitionFailure),
+
+    /// Leader: The Helper failed to handle a request.
+    #[error("peer aborted: {0}")]
+    Peer(#[from] PeerAbort),
 }
 
 impl DapError {
@@ -55,6 +63,115 @@ impl DapError {
     }
 }
 
+/// Leader: The Helper's response to a request that failed, as indicated by the problem details
+/// document in the body of the response.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct PeerAbort {
+    /// HTTP status code of the response.
+    pub status: u16,
+
+    /// The type of the abort, e.g., "unrecognizedTask", if the Helper aborted the DAP protocol.
+    pub typ: Option<String>,
+
+    pub detail: Option<String>,
+
+    pub task_id: Option<TaskId>,
+
+    /// Whether sending the same request again may succeed, as decided by the Leader's retry
+    /// policy for the response's status.
+    #[serde(default)]
+    pub retryable: bool,
+}
+
+/// The HTTP statuses of the Helper's responses that are retried by default. An abort of the DAP
+/// protocol is final, whereas a server error or rate limiting may be transient.
+pub const DEFAULT_RETRYABLE_STATUSES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+impl PeerAbort {
+    /// Parse the body of a response with the given status and content type. If the body is not a
+    /// problem details document, then only the status is kept. The abort is retryable if the
+    /// status is one of `retryable_statuses`.
+    pub fn from_response(
+        status: u16,
+        content_type: Option<&str>,
+        body: &[u8],
+        retryable_statuses: &[u16],
+    ) -> Self {
+        // Ignore parameters of the media type, e.g., the charset.
+        let media_type = content_type.map(|content_type| {
+            let (media_type, _) = content_type.split_once(';').unwrap_or((content_type, ""));
+            media_type.trim()
+        });
+        match media_type {
+            Some("application/problem+json") => match serde_json::from_slice(body) {
+                Ok(problem_details) => {
+                    Self::from_problem_details(status, problem_details, retryable_statuses)
+                }
+                Err(error) => {
+                    tracing::warn!(?error, "peer sent malformed problem details");
+                    Self::from_status(status, retryable_statuses)
+                }
+            },
+            _ => Self::from_status(status, retryable_statuses),
+        }
+    }
+
+    pub fn from_problem_details(
+        status: u16,
+        problem_details: ProblemDetails,
+        retryable_statuses: &[u16],
+    ) -> Self {
+        Self {
+            status,
+            typ: problem_details.typ.map(|typ| {
+                typ.strip_prefix("urn:ietf:params:ppm:dap:error:")
+                    .map(str::to_string)
+                    .unwrap_or(typ)
+            }),
+            detail: problem_details.detail,
+            task_id: problem_details.task_id.and_then(TaskId::try_from_base64url),
+            retryable: retryable_statuses.contains(&status),
+        }
+    }
+
+    fn from_status(status: u16, retryable_statuses: &[u16]) -> Self {
+        Self {
+            status,
+            typ: None,
+            detail: None,
+            task_id: None,
+            retryable: retryable_statuses.contains(&status),
+        }
+    }
+
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+
+    /// The type of the abort, or "none" if the Helper did not indicate one. This is used as a
+    /// metrics label.
+    pub fn abort_type(&self) -> &str {
+        self.typ.as_deref().unwrap_or("none")
+    }
+}
+
+impl Display for PeerAbort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "status {}, type {}", self.status, self.abort_type())?;
+        if let Some(task_id) = &self.task_id {
+            write!(f, ", task {task_id}")?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, ": {detail}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PeerAbort {}
+
 impl FatalDapError {
     #[doc(hidden)]
     pub fn __use_the_macro(s: String) -> Self {
@@ -174,3 +291,61 @@ macro_rules! __fatal_error_impl {
 // tracing directly
 #[doc(hidden)]
 pub use tracing;
+
+#[cfg(test)]
+mod test {
+    use super::{PeerAbort, DEFAULT_RETRYABLE_STATUSES};
+    use crate::messages::TaskId;
+
+    #[test]
+    fn peer_abort_from_response() {
+        let task_id = TaskId([1; 32]);
+        let peer = PeerAbort::from_response(
+            400,
+            Some("application/problem+json"),
+            br#"{
+                "type": "urn:ietf:params:ppm:dap:error:unrecognizedTask",
+                "title": "An endpoint received a message with an unknown task ID.",
+                "taskid": "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE",
+                "detail": "unknown task"
+            }"#,
+            DEFAULT_RETRYABLE_STATUSES,
+        );
+        assert_eq!(
+            peer,
+            PeerAbort {
+                status: 400,
+                typ: Some("unrecognizedTask".into()),
+                detail: Some("unknown task".into()),
+                task_id: Some(task_id),
+                retryable: false,
+            }
+        );
+        assert!(!peer.is_retryable());
+
+        let peer = PeerAbort::from_response(
+            503,
+            Some("text/plain"),
+            b"Service Unavailable",
+            DEFAULT_RETRYABLE_STATUSES,
+        );
+        assert_eq!(peer.abort_type(), "none");
+        assert!(peer.is_retryable());
+
+        // Parameters of the media type are ignored.
+        let peer = PeerAbort::from_response(
+            400,
+            Some("application/problem+json; charset=utf-8"),
+            br#"{
+                "type": "urn:ietf:params:ppm:dap:error:invalidMessage",
+                "title": "Invalid message"
+            }"#,
+            DEFAULT_RETRYABLE_STATUSES,
+        );
+        assert_eq!(peer.abort_type(), "invalidMessage");
+
+        // Whether a status is retryable is up to the caller.
+        let peer = PeerAbort::from_response(503, Some("text/plain"), b"", &[429]);
+        assert!(!peer.is_retryable());
+    }
+}
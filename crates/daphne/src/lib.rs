@@ -7,12 +7,16 @@
 //! [VDAFs](https://github.com/cfrg/draft-irtf-cfrg-vdaf).
 //!
 //! Daphne implements:
-//! * draft-ietf-ppm-dap-02
-//!    * VDAF: draft-irtf-cfrg-vdaf-03
-//!    * Taskprov extension: draft-wang-ppm-dap-taskprov-02
 //! * draft-ietf-ppm-dap-09
 //!    * VDAF: draft-irtf-cfrg-vdaf-08
 //!    * Taskprov extension: draft-wang-ppm-dap-taskprov-06
+//! * draft-ietf-ppm-dap-10 ([`DapVersion::Latest`])
+//!    * VDAF: draft-irtf-cfrg-vdaf-08
+//!    * Taskprov extension: draft-wang-ppm-dap-taskprov-06
+//!
+//! Draft-10 changes the HTTP methods for uploading reports and polling collection jobs and the
+//! version tag in the HPKE application info. Messages and media types are the same as in
+//! draft-09, so their codecs don't depend on the version.
 //!
 //! Daphne does not provide the complete, end-to-end functionality of any party in the protocol.
 //! Instead, it defines traits for the functionalities that a concrete instantiation of the
@@ -21,19 +25,8 @@
 //!
 //! Daphne is not yet feature complete. Known issues include:
 //!
-//! * The collect sub-protocol has not yet been fully implemented. In particular, Daphne Aggreators
-//! do not check properly if batch intervals overlap across collect requests. Note that this
-//! feature is privacy-critical and implementation is planned. See
-//! <https://github.com/cloudflare/daphne/issues/45> for details.
-//!
 //! * Daphne is not compatible with DAP tasks whose maximum batch lifetime is longer than one.
 //!
-//! * Aborts are not handled precisely as specified. In particular, some fields in the "Problem
-//! Details" document are omitted.
-//!
-//! * Daphne does not implement a complete DAP Client or Collector. However, methods are provided
-//! on [`VdafConfig`] for producing reports and consuming aggregate results.
-//!
 //! * Daphne does not yet support deletion of collection jobs:
 //!
 //!     > The leader MUST remove a collect job's results when the collector sends an HTTP DELETE
@@ -42,16 +35,20 @@
 
 pub mod audit_log;
 pub mod auth;
+pub mod client;
+pub mod collector;
 pub mod constants;
 pub mod error;
 pub mod hpke;
 pub mod messages;
 pub mod metrics;
+pub mod privacy_pass;
 pub(crate) mod protocol;
 pub mod roles;
 pub mod taskprov;
 #[cfg(any(test, feature = "test-utils"))]
 pub mod testing;
+pub mod transport;
 pub mod vdaf;
 
 use crate::{
@@ -61,13 +58,14 @@ use crate::{
         AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId, Duration, Interval,
         PartialBatchSelector, ReportId, TaskId, Time,
     },
+    privacy_pass::PrivacyPassConfig,
     vdaf::{
         Prio3Config, VdafAggregateShare, VdafConfig, VdafPrepMessage, VdafPrepState, VdafVerifyKey,
     },
 };
 use constants::DapMediaType;
 pub use error::DapError;
-use error::FatalDapError;
+use error::{FatalDapError, PeerAbort};
 use hpke::{HpkeConfig, HpkeKemId};
 use messages::encode_base64url;
 #[cfg(any(test, feature = "test-utils"))]
@@ -79,7 +77,7 @@ use prio::{
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::{max, min},
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Debug,
     str::FromStr,
 };
@@ -272,6 +270,83 @@ impl std::fmt::Display for DapBatchBucket {
     }
 }
 
+/// Maximum number of times a batch may be queried. Daphne is not compatible with tasks whose
+/// batches may be collected more than once.
+pub const DAP_MAX_BATCH_QUERY_COUNT: u16 = 1;
+
+/// The batches of a task that have been collected.
+///
+/// Time-interval queries are recorded as intervals, so that any query overlapping a collected
+/// interval is detected, regardless of which buckets have been aggregated. Fixed-size queries are
+/// recorded as the number of times each batch ID was queried.
+///
+/// Each collection is recorded along with the hash of the aggregate share request that collected
+/// the batch. Recording a request whose hash is already recorded succeeds without changing the
+/// record, so that a retransmitted request is handled like the original. This doesn't release
+/// anything new: the batch's reports are marked as collected, so its aggregate share can't change.
+/// Any other request, even for the same batch, is a new query and counts towards the limit.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct DapCollectedBatches {
+    /// Collected batch intervals, sorted by start time.
+    intervals: Vec<Interval>,
+
+    /// Number of times each fixed-size batch has been collected.
+    batch_query_counts: BTreeMap<BatchId, u16>,
+
+    /// SHA-256 hashes of the aggregate share requests that collected the batches.
+    #[serde(default)]
+    req_hashes: BTreeSet<[u8; 32]>,
+}
+
+impl DapCollectedBatches {
+    /// Check whether collecting the batch would overlap with a previously collected batch interval
+    /// or exceed the batch's query limit.
+    pub fn is_overlapping(&self, batch_sel: &BatchSelector) -> bool {
+        match batch_sel {
+            BatchSelector::TimeInterval { batch_interval } => {
+                // Find the first interval that ends after the queried one starts.
+                let i = self
+                    .intervals
+                    .partition_point(|interval| interval.end() <= batch_interval.start);
+                self.intervals
+                    .get(i)
+                    .is_some_and(|interval| interval.start < batch_interval.end())
+            }
+            BatchSelector::FixedSizeByBatchId { batch_id } => self
+                .batch_query_counts
+                .get(batch_id)
+                .is_some_and(|count| *count >= DAP_MAX_BATCH_QUERY_COUNT),
+        }
+    }
+
+    /// Record the collection of a batch by the aggregate share request with the given hash.
+    /// Returns `false`, and leaves the record unchanged, if the batch overlaps with a previously
+    /// collected batch or exceeds its query limit, unless the request was already recorded.
+    pub fn insert(&mut self, batch_sel: &BatchSelector, req_hash: &[u8; 32]) -> bool {
+        if self.req_hashes.contains(req_hash) {
+            return true;
+        }
+        if self.is_overlapping(batch_sel) {
+            return false;
+        }
+
+        match batch_sel {
+            BatchSelector::TimeInterval { batch_interval } => {
+                let i = self
+                    .intervals
+                    .partition_point(|interval| interval.end() <= batch_interval.start);
+                self.intervals.insert(i, *batch_interval);
+            }
+            BatchSelector::FixedSizeByBatchId { batch_id } => {
+                *self.batch_query_counts.entry(*batch_id).or_default() += 1;
+            }
+        }
+        self.req_hashes.insert(*req_hash);
+        true
+    }
+}
+
 // We can't derive default because it will require T to be Default, which we don't need.
 impl<T> Default for DapAggregateSpan<T> {
     fn default() -> Self {
@@ -452,7 +527,6 @@ pub struct DapTaskParameters {
     pub vdaf: VdafConfig,
 }
 
-#[cfg(any(test, feature = "test-utils"))]
 impl DapTaskParameters {
     /// Construct a new task config using the taskprov extension. Return the task ID and the
     /// taskprov advertisement encoded as a base64url string.
@@ -475,10 +549,13 @@ impl DapTaskParameters {
             query_config: messages::taskprov::QueryConfig {
                 time_precision: self.time_precision,
                 max_batch_query_count: 1,
-                min_batch_size: self.min_batch_size.try_into().unwrap(),
+                min_batch_size: self
+                    .min_batch_size
+                    .try_into()
+                    .map_err(|_| fatal_error!(err = "min batch size is too large for taskprov"))?,
                 var: (&self.query).try_into()?,
             },
-            task_expiration: now + 86400 * 14, // expires in two weeks
+            task_expiration: now + self.lifetime,
             vdaf_config: messages::taskprov::VdafConfig {
                 dp_config: messages::taskprov::DpConfig::None,
                 var: (&self.vdaf).try_into()?,
@@ -497,8 +574,7 @@ impl DapTaskParameters {
             taskprov_config,
             vdaf_verify_key_init,
             collector_hpke_config,
-        )
-        .unwrap();
+        )?;
 
         let taskprov_advertisement = encode_base64url(&encoded_taskprov_config);
 
@@ -548,6 +624,11 @@ pub struct DapTaskConfig {
     /// Method by which the task was configured.
     #[serde(default)]
     pub method: DapTaskConfigMethod,
+
+    /// Privacy Pass: If set, then the Leader requires a token from one of the configured issuers
+    /// to accompany each uploaded report.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy_pass: Option<PrivacyPassConfig>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -564,6 +645,8 @@ struct ShadowDapTaskConfig {
     collector_hpke_config: HpkeConfig,
     #[serde(default)]
     method: DapTaskConfigMethod,
+    #[serde(default)]
+    privacy_pass: Option<PrivacyPassConfig>,
 
     // Deprecated. Indicates that the task was configured via draft-wang-ppm-taskprov. This flag
     // was replaced by `method`.
@@ -592,6 +675,7 @@ impl From<ShadowDapTaskConfig> for DapTaskConfig {
                 }
                 method => method,
             },
+            privacy_pass: shadow.privacy_pass,
         }
     }
 }
@@ -609,6 +693,12 @@ impl deepsize::DeepSizeOf for DapTaskConfig {
             + self.vdaf.deep_size_of_children(context)
             + self.vdaf_verify_key.deep_size_of_children(context)
             + self.collector_hpke_config.deep_size_of_children(context)
+            + self.privacy_pass.as_ref().map_or(0, |config| {
+                std::mem::size_of_val(config)
+                    + config.issuer_name.len()
+                    + config.origin_info.len()
+                    + config.issuer_keys.len() * std::mem::size_of::<[u8; 32]>()
+            })
     }
 }
 
@@ -1024,6 +1114,10 @@ pub struct DapRequest<S> {
 
     /// taskprov: The task advertisement, sent in the `dap-taskprov` header.
     pub taskprov: Option<String>,
+
+    /// Privacy Pass: The token presented by the Client on upload, sent in the `Authorization`
+    /// header.
+    pub privacy_pass_token: Option<String>,
 }
 
 #[cfg(test)]
@@ -1037,6 +1131,7 @@ impl<S> Default for DapRequest<S> {
             payload: Default::default(),
             sender_auth: Default::default(),
             taskprov: Default::default(),
+            privacy_pass_token: Default::default(),
         }
     }
 }
@@ -1086,6 +1181,8 @@ pub enum DapCollectionJob {
     Done(Collection),
     Pending,
     Unknown,
+    /// The Helper aborted the aggregate share request for the collection job.
+    Failed(PeerAbort),
 }
 
 /// Telemetry information for the leader's processing loop.
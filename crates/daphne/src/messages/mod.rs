@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 //! Messages in the DAP protocol.
+//!
+//! The encoding of the messages is the same for all supported versions of DAP. The codecs are
+//! parameterized by the [`DapVersion`] so that messages can change in later drafts.
 
 pub mod taskprov;
 
@@ -519,6 +522,64 @@ impl ParameterizedDecode<DapVersion> for AggregationJobInitReq {
     }
 }
 
+/// The `PrepareContinue` message consisting of the Leader's prep message for a report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct PrepareContinue {
+    pub report_id: ReportId,
+    pub payload: Vec<u8>,
+}
+
+impl Encode for PrepareContinue {
+    fn encode(&self, bytes: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.report_id.encode(bytes)?;
+        encode_u32_bytes(bytes, &self.payload)?;
+        Ok(())
+    }
+}
+
+impl Decode for PrepareContinue {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        Ok(Self {
+            report_id: ReportId::decode(bytes)?,
+            payload: decode_u32_bytes(bytes)?,
+        })
+    }
+}
+
+/// Aggregate continuation request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregationJobContinueReq {
+    /// The step of the aggregation job the Leader is advancing to. The initialization request is
+    /// step 0.
+    pub step: u16,
+    pub prep_continues: Vec<PrepareContinue>,
+}
+
+impl ParameterizedEncode<DapVersion> for AggregationJobContinueReq {
+    fn encode_with_param(
+        &self,
+        _version: &DapVersion,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        self.step.encode(bytes)?;
+        encode_u32_items(bytes, &(), &self.prep_continues)?;
+        Ok(())
+    }
+}
+
+impl ParameterizedDecode<DapVersion> for AggregationJobContinueReq {
+    fn decode_with_param(
+        _version: &DapVersion,
+        bytes: &mut Cursor<&[u8]>,
+    ) -> Result<Self, CodecError> {
+        Ok(Self {
+            step: u16::decode(bytes)?,
+            prep_continues: decode_u32_items(&(), bytes)?,
+        })
+    }
+}
+
 /// Transition message. This conveyes a message sent from one Aggregator to another during the
 /// preparation phase of VDAF evaluation.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -1362,6 +1423,31 @@ mod test {
 
     test_versions! { roundtrip_agg_job_init_req }
 
+    fn roundtrip_agg_job_cont_req(version: DapVersion) {
+        let want = AggregationJobContinueReq {
+            step: 1,
+            prep_continues: vec![
+                PrepareContinue {
+                    report_id: ReportId([99; 16]),
+                    payload: b"prep msg".to_vec(),
+                },
+                PrepareContinue {
+                    report_id: ReportId([17; 16]),
+                    payload: b"another prep msg".to_vec(),
+                },
+            ],
+        };
+
+        let got = AggregationJobContinueReq::get_decoded_with_param(
+            &version,
+            &want.get_encoded_with_param(&version).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(got, want);
+    }
+
+    test_versions! { roundtrip_agg_job_cont_req }
+
     #[test]
     fn read_agg_job_resp() {
         const TEST_DATA: &[u8] = &[
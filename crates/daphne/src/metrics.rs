@@ -4,9 +4,9 @@
 //! Daphne metrics.
 
 use core::fmt;
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
-use crate::messages::TransitionFailure;
+use crate::{constants::DapMediaType, messages::TransitionFailure};
 
 pub trait DaphneMetrics: Send + Sync {
     fn inbound_req_inc(&self, request_type: DaphneRequestType);
@@ -15,6 +15,27 @@ pub trait DaphneMetrics: Send + Sync {
     fn agg_job_started_inc(&self);
     fn agg_job_completed_inc(&self);
     fn agg_job_put_span_retry_inc(&self);
+
+    /// Leader: Observe the latency of a request to the Helper. The status is the HTTP status code
+    /// of the response, if one was received.
+    fn inter_agg_req_observe_latency(
+        &self,
+        media_type: DapMediaType,
+        status: Option<u16>,
+        latency: Duration,
+    );
+
+    /// Observe the latency of a request to storage. The method identifies the kind of request.
+    fn storage_req_observe_latency(&self, method: &str, latency: Duration);
+
+    /// Observe the time spent initializing VDAF preparation for a report.
+    fn vdaf_prep_observe_time(&self, time: Duration);
+
+    /// Leader: Observe the time from the creation of a collection job to its completion.
+    fn coll_job_observe_latency(&self, latency: Duration);
+
+    /// Leader: Count a request that the Helper aborted, by the type of the abort.
+    fn peer_abort_inc(&self, abort_type: &str);
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -54,12 +75,15 @@ pub enum DaphneRequestType {
 
 #[cfg(any(feature = "prometheus", feature = "test-utils", test))]
 pub mod prometheus {
+    use std::time::Duration;
+
     use super::{DaphneMetrics, DaphneRequestType, ReportStatus};
-    use crate::{fatal_error, DapError};
+    use crate::{constants::DapMediaType, fatal_error, DapError};
     use ::prometheus::{
-        exponential_buckets, register_histogram_with_registry,
-        register_int_counter_vec_with_registry, register_int_counter_with_registry, Histogram,
-        IntCounter, IntCounterVec, Registry,
+        exponential_buckets, register_histogram_vec_with_registry,
+        register_histogram_with_registry, register_int_counter_vec_with_registry,
+        register_int_counter_with_registry, Histogram, HistogramVec, IntCounter, IntCounterVec,
+        Registry,
     };
 
     #[derive(Clone)]
@@ -79,6 +103,21 @@ pub mod prometheus {
 
         /// Helper: Number of times replays caused the aggregation to be retried.
         aggregation_job_put_span_retry_counter: IntCounter,
+
+        /// Leader: Latency of requests to the Helper, broken down by media type and status.
+        inter_aggregator_request_latency_histogram: HistogramVec,
+
+        /// Latency of requests to storage, broken down by method.
+        storage_request_latency_histogram: HistogramVec,
+
+        /// Time spent initializing VDAF preparation for a report.
+        vdaf_prep_time_histogram: Histogram,
+
+        /// Leader: Time from the creation of a collection job to its completion.
+        collection_job_latency_histogram: Histogram,
+
+        /// Leader: Requests aborted by the Helper, broken down by the type of the abort.
+        peer_abort_counter: IntCounterVec,
     }
 
     impl DaphnePromMetrics {
@@ -132,12 +171,78 @@ pub mod prometheus {
                 )
                 .map_err(|e| fatal_error!(err = ?e, "failed to register aggregation_job_put_span_retry_counter"))?;
 
+            #[allow(clippy::ignored_unit_patterns)]
+            let inter_aggregator_request_latency_histogram = register_histogram_vec_with_registry!(
+                "inter_aggregator_request_latency_seconds",
+                "Latency of requests to the Helper.",
+                &["media_type", "status"],
+                // <5ms, <10ms, <20ms, ... <41s, +Inf
+                exponential_buckets(0.005, 2.0, 14)
+                    .expect("this shouldn't panic for these hardcoded values"),
+                registry
+            )
+            .map_err(|e| {
+                fatal_error!(err = ?e, "failed to register inter_aggregator_request_latency_seconds")
+            })?;
+
+            #[allow(clippy::ignored_unit_patterns)]
+            let storage_request_latency_histogram = register_histogram_vec_with_registry!(
+                "storage_request_latency_seconds",
+                "Latency of requests to storage.",
+                &["method"],
+                // <1ms, <2ms, <4ms, ... <8s, +Inf
+                exponential_buckets(0.001, 2.0, 14)
+                    .expect("this shouldn't panic for these hardcoded values"),
+                registry
+            )
+            .map_err(
+                |e| fatal_error!(err = ?e, "failed to register storage_request_latency_seconds"),
+            )?;
+
+            #[allow(clippy::ignored_unit_patterns)]
+            let vdaf_prep_time_histogram = register_histogram_with_registry!(
+                "vdaf_prep_time_seconds",
+                "Time spent initializing VDAF preparation for a report.",
+                // <10us, <20us, <40us, ... <330ms, +Inf
+                exponential_buckets(0.000_01, 2.0, 16)
+                    .expect("this shouldn't panic for these hardcoded values"),
+                registry
+            )
+            .map_err(|e| fatal_error!(err = ?e, "failed to register vdaf_prep_time_seconds"))?;
+
+            #[allow(clippy::ignored_unit_patterns)]
+            let collection_job_latency_histogram = register_histogram_with_registry!(
+                "collection_job_latency_seconds",
+                "Time from the creation of a collection job to its completion.",
+                // <1s, <2s, <4s, ... <9h, +Inf
+                exponential_buckets(1.0, 2.0, 16)
+                    .expect("this shouldn't panic for these hardcoded values"),
+                registry
+            )
+            .map_err(
+                |e| fatal_error!(err = ?e, "failed to register collection_job_latency_seconds"),
+            )?;
+
+            #[allow(clippy::ignored_unit_patterns)]
+            let peer_abort_counter = register_int_counter_vec_with_registry!(
+                "peer_abort_counter",
+                "Total number of requests aborted by the Helper.",
+                &["type"],
+                registry
+            )
+            .map_err(|e| fatal_error!(err = ?e, "failed to register peer_abort_counter"))?;
+
             Ok(Self {
                 inbound_request_counter,
                 report_counter,
                 aggregation_job_counter,
                 aggregation_job_batch_size_histogram,
                 aggregation_job_put_span_retry_counter,
+                inter_aggregator_request_latency_histogram,
+                storage_request_latency_histogram,
+                vdaf_prep_time_histogram,
+                collection_job_latency_histogram,
+                peer_abort_counter,
             })
         }
     }
@@ -182,5 +287,49 @@ pub mod prometheus {
         fn agg_job_put_span_retry_inc(&self) {
             self.aggregation_job_put_span_retry_counter.inc();
         }
+
+        fn inter_agg_req_observe_latency(
+            &self,
+            media_type: DapMediaType,
+            status: Option<u16>,
+            latency: Duration,
+        ) {
+            let media_type = match media_type {
+                DapMediaType::AggregationJobInitReq => "aggregation_job_init_req",
+                DapMediaType::AggregationJobContinueReq => "aggregation_job_continue_req",
+                DapMediaType::AggregationJobResp => "aggregation_job_resp",
+                DapMediaType::AggregateShareReq => "aggregate_share_req",
+                DapMediaType::AggregateShare => "aggregate_share",
+                DapMediaType::CollectReq => "collect_req",
+                DapMediaType::Collection => "collection",
+                DapMediaType::HpkeConfigList => "hpke_config_list",
+                DapMediaType::Report => "report",
+            };
+            let status = status.map_or_else(|| "none".into(), |status| status.to_string());
+            self.inter_aggregator_request_latency_histogram
+                .with_label_values(&[media_type, &status])
+                .observe(latency.as_secs_f64());
+        }
+
+        fn storage_req_observe_latency(&self, method: &str, latency: Duration) {
+            self.storage_request_latency_histogram
+                .with_label_values(&[method])
+                .observe(latency.as_secs_f64());
+        }
+
+        fn vdaf_prep_observe_time(&self, time: Duration) {
+            self.vdaf_prep_time_histogram.observe(time.as_secs_f64());
+        }
+
+        fn coll_job_observe_latency(&self, latency: Duration) {
+            self.collection_job_latency_histogram
+                .observe(latency.as_secs_f64());
+        }
+
+        fn peer_abort_inc(&self, abort_type: &str) {
+            self.peer_abort_counter
+                .with_label_values(&[abort_type])
+                .inc();
+        }
     }
 }
@@ -0,0 +1,405 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Privacy Pass: Verification of publicly verifiable tokens (RFC 9577, RFC 9578) presented by
+//! Clients on upload. A Leader may require each report to be accompanied by a token issued by a
+//! trusted issuer in order to limit the rate at which a single Client can upload reports, without
+//! learning the Client's identity.
+//!
+//! Only the "Blind RSA (2048-bit)" token type is supported. The token is carried in the
+//! `Authorization` header of the upload request using the `PrivateToken` authentication scheme.
+
+use std::io::{Cursor, Read};
+
+use prio::codec::{CodecError, Decode, Encode};
+use ring::{
+    digest,
+    signature::{UnparsedPublicKey, RSA_PSS_2048_8192_SHA384},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::messages::{decode_base64url_vec, encode_base64url, encode_u16_bytes};
+
+/// Token type for publicly verifiable tokens based on Blind RSA (2048-bit).
+pub const TOKEN_TYPE_BLIND_RSA: u16 = 0x0002;
+
+/// Length of the token authenticator, i.e., the RSA modulus length in bytes.
+const TOKEN_AUTHENTICATOR_LEN: usize = 256;
+
+/// Authentication scheme used for presenting a token in the `Authorization` header.
+const AUTH_SCHEME: &str = "PrivateToken";
+
+/// A Privacy Pass token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrivacyPassToken {
+    pub token_type: u16,
+    pub nonce: [u8; 32],
+    pub challenge_digest: [u8; 32],
+    pub token_key_id: [u8; 32],
+    pub authenticator: Vec<u8>,
+}
+
+impl PrivacyPassToken {
+    /// The portion of the token covered by the authenticator.
+    fn token_input(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 + 3 * 32);
+        bytes.extend_from_slice(&self.token_type.to_be_bytes());
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.challenge_digest);
+        bytes.extend_from_slice(&self.token_key_id);
+        bytes
+    }
+
+    /// Decode a token from its URL-safe, base64 encoding.
+    pub fn try_from_base64url(token_base64url: &str) -> Option<Self> {
+        let bytes = decode_base64url_vec(token_base64url.trim_end_matches('='))?;
+        Self::get_decoded(&bytes).ok()
+    }
+
+    /// Encode the token with URL-safe base64.
+    pub fn to_base64url(&self) -> String {
+        encode_base64url(self.get_encoded().expect("failed to encode token"))
+    }
+}
+
+impl Encode for PrivacyPassToken {
+    fn encode(&self, bytes: &mut Vec<u8>) -> Result<(), CodecError> {
+        bytes.extend_from_slice(&self.token_input());
+        bytes.extend_from_slice(&self.authenticator);
+        Ok(())
+    }
+}
+
+impl Decode for PrivacyPassToken {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let token_type = u16::decode(bytes)?;
+        if token_type != TOKEN_TYPE_BLIND_RSA {
+            return Err(CodecError::UnexpectedValue);
+        }
+        let mut token = Self {
+            token_type,
+            nonce: [0; 32],
+            challenge_digest: [0; 32],
+            token_key_id: [0; 32],
+            authenticator: vec![0; TOKEN_AUTHENTICATOR_LEN],
+        };
+        bytes.read_exact(&mut token.nonce)?;
+        bytes.read_exact(&mut token.challenge_digest)?;
+        bytes.read_exact(&mut token.token_key_id)?;
+        bytes.read_exact(&mut token.authenticator)?;
+        Ok(token)
+    }
+}
+
+/// Extract the token from the value of an `Authorization` header that uses the `PrivateToken`
+/// authentication scheme, e.g., `PrivateToken token="abc..."`. Returns `None` if a different
+/// scheme is used or the token parameter is missing.
+pub fn token_from_authorization_header(value: &str) -> Option<&str> {
+    let (scheme, params) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case(AUTH_SCHEME) {
+        return None;
+    }
+    params.split(',').find_map(|param| {
+        let (name, value) = param.trim().split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("token")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Public key of a token issuer, encoded as a DER-encoded `SubjectPublicKeyInfo`. This is the
+/// "token-key" advertised in the issuer's directory.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct PrivacyPassIssuerKey {
+    spki: Vec<u8>,
+    token_key_id: [u8; 32],
+}
+
+impl PrivacyPassIssuerKey {
+    /// Construct an issuer key from a DER-encoded `SubjectPublicKeyInfo`.
+    pub fn from_spki(spki: Vec<u8>) -> Option<Self> {
+        rsa_public_key_from_spki(&spki)?;
+        let token_key_id = digest::digest(&digest::SHA256, &spki)
+            .as_ref()
+            .try_into()
+            .expect("SHA-256 digest has unexpected length");
+        Some(Self { spki, token_key_id })
+    }
+
+    /// The identifier of this key, i.e., the SHA-256 hash of the encoded public key.
+    pub fn token_key_id(&self) -> &[u8; 32] {
+        &self.token_key_id
+    }
+
+    fn verify(&self, token: &PrivacyPassToken) -> bool {
+        let Some(rsa_public_key) = rsa_public_key_from_spki(&self.spki) else {
+            return false;
+        };
+        UnparsedPublicKey::new(&RSA_PSS_2048_8192_SHA384, rsa_public_key)
+            .verify(&token.token_input(), &token.authenticator)
+            .is_ok()
+    }
+}
+
+impl TryFrom<String> for PrivacyPassIssuerKey {
+    type Error = String;
+
+    fn try_from(spki_base64url: String) -> Result<Self, Self::Error> {
+        decode_base64url_vec(spki_base64url.trim_end_matches('='))
+            .and_then(Self::from_spki)
+            .ok_or_else(|| "invalid Privacy Pass issuer key".into())
+    }
+}
+
+impl From<PrivacyPassIssuerKey> for String {
+    fn from(key: PrivacyPassIssuerKey) -> Self {
+        encode_base64url(key.spki)
+    }
+}
+
+/// Per-task Privacy Pass configuration. If set, then the Leader requires each report uploaded for
+/// the task to be accompanied by a valid token.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PrivacyPassConfig {
+    /// Name of the token issuer, as it appears in the token challenge.
+    pub issuer_name: String,
+
+    /// Origin info of the token challenge. If empty, then tokens are not bound to a particular
+    /// origin.
+    #[serde(default)]
+    pub origin_info: String,
+
+    /// Public keys of the issuer. More than one key may be configured in order to support key
+    /// rotation.
+    pub issuer_keys: Vec<PrivacyPassIssuerKey>,
+}
+
+impl PrivacyPassConfig {
+    /// Compute the digest of the token challenge that Clients are expected to redeem tokens
+    /// against. The challenge has an empty redemption context, so tokens may be fetched ahead of
+    /// time.
+    pub fn challenge_digest(&self) -> [u8; 32] {
+        let mut challenge = Vec::new();
+        challenge.extend_from_slice(&TOKEN_TYPE_BLIND_RSA.to_be_bytes());
+        encode_u16_bytes(&mut challenge, self.issuer_name.as_bytes())
+            .expect("issuer name is too long");
+        challenge.push(0); // empty redemption context
+        encode_u16_bytes(&mut challenge, self.origin_info.as_bytes())
+            .expect("origin info is too long");
+        digest::digest(&digest::SHA256, &challenge)
+            .as_ref()
+            .try_into()
+            .expect("SHA-256 digest has unexpected length")
+    }
+
+    /// Verify a token. If verification fails, then the reason is returned.
+    pub fn verify(&self, token: &PrivacyPassToken) -> Result<(), String> {
+        if token.token_type != TOKEN_TYPE_BLIND_RSA {
+            return Err(format!("unsupported token type {}", token.token_type));
+        }
+
+        if token.challenge_digest != self.challenge_digest() {
+            return Err("token was issued for an unexpected challenge".into());
+        }
+
+        let issuer_key = self
+            .issuer_keys
+            .iter()
+            .find(|key| key.token_key_id() == &token.token_key_id)
+            .ok_or_else(|| "token was issued under an unrecognized key".to_string())?;
+
+        if !issuer_key.verify(token) {
+            return Err("token authenticator is invalid".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a DER-encoded TLV with the expected tag, returning its contents and advancing `bytes` past
+/// it.
+fn der_read<'a>(bytes: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    let (&t, rest) = bytes.split_first()?;
+    if t != tag {
+        return None;
+    }
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first & 0x80 == 0 {
+        usize::from(first)
+    } else {
+        let num_len_bytes = usize::from(first & 0x7f);
+        if num_len_bytes == 0 || num_len_bytes > 4 || rest.len() < num_len_bytes {
+            return None;
+        }
+        let (len_bytes, r) = rest.split_at(num_len_bytes);
+        rest = r;
+        len_bytes
+            .iter()
+            .fold(0, |len, b| (len << 8) | usize::from(*b))
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+    *bytes = rest;
+    Some(contents)
+}
+
+/// Extract the DER-encoded `RSAPublicKey` from a `SubjectPublicKeyInfo`. The algorithm identifier
+/// is ignored, so that both rsaEncryption and RSASSA-PSS keys are accepted.
+fn rsa_public_key_from_spki(spki: &[u8]) -> Option<&[u8]> {
+    let mut outer = spki;
+    let mut spki = der_read(&mut outer, 0x30)?;
+    der_read(&mut spki, 0x30)?; // AlgorithmIdentifier
+    let bit_string = der_read(&mut spki, 0x03)?;
+    let (&unused_bits, rsa_public_key) = bit_string.split_first()?;
+    (unused_bits == 0 && outer.is_empty() && spki.is_empty()).then_some(rsa_public_key)
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+    //! A local token issuer for tests.
+
+    use rand::prelude::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{RsaKeyPair, RSA_PSS_SHA384},
+    };
+
+    use super::{PrivacyPassConfig, PrivacyPassIssuerKey, PrivacyPassToken, TOKEN_TYPE_BLIND_RSA};
+
+    const ISSUER_PRIVATE_KEY: &[u8] = include_bytes!("test_vec/privacy_pass_issuer_key.der");
+    const ISSUER_SPKI: &[u8] = include_bytes!("test_vec/privacy_pass_issuer_key.spki");
+
+    /// A token issuer backed by a fixed RSA key pair.
+    ///
+    /// Tokens are produced by signing the token input directly rather than running the blind
+    /// signature protocol with a Client. The unblinded output of the protocol is an ordinary
+    /// RSASSA-PSS signature, so the resulting tokens are indistinguishable from real ones.
+    pub struct TestIssuer {
+        key_pair: RsaKeyPair,
+        config: PrivacyPassConfig,
+    }
+
+    impl Default for TestIssuer {
+        fn default() -> Self {
+            Self {
+                key_pair: RsaKeyPair::from_der(ISSUER_PRIVATE_KEY)
+                    .expect("invalid test issuer key"),
+                config: PrivacyPassConfig {
+                    issuer_name: "issuer.example.com".into(),
+                    origin_info: String::new(),
+                    issuer_keys: vec![PrivacyPassIssuerKey::from_spki(ISSUER_SPKI.to_vec())
+                        .expect("invalid test issuer key")],
+                },
+            }
+        }
+    }
+
+    impl TestIssuer {
+        /// The task configuration that accepts tokens from this issuer.
+        pub fn config(&self) -> &PrivacyPassConfig {
+            &self.config
+        }
+
+        /// Issue a fresh token.
+        pub fn issue(&self) -> PrivacyPassToken {
+            let mut token = PrivacyPassToken {
+                token_type: TOKEN_TYPE_BLIND_RSA,
+                nonce: thread_rng().gen(),
+                challenge_digest: self.config.challenge_digest(),
+                token_key_id: *self.config.issuer_keys[0].token_key_id(),
+                authenticator: vec![0; self.key_pair.public().modulus_len()],
+            };
+            self.key_pair
+                .sign(
+                    &RSA_PSS_SHA384,
+                    &SystemRandom::new(),
+                    &token.token_input(),
+                    &mut token.authenticator,
+                )
+                .expect("failed to sign token");
+            token
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prio::codec::{Decode, Encode};
+
+    use super::{
+        test_utils::TestIssuer, token_from_authorization_header, PrivacyPassConfig,
+        PrivacyPassToken,
+    };
+
+    #[test]
+    fn verify() {
+        let issuer = TestIssuer::default();
+        let token = issuer.issue();
+        assert_eq!(issuer.config().verify(&token), Ok(()));
+
+        // Token bound to a different challenge.
+        let config = PrivacyPassConfig {
+            issuer_name: "other.example.com".into(),
+            ..issuer.config().clone()
+        };
+        assert!(config.verify(&token).is_err());
+
+        // Token issued under an unknown key.
+        let mut bad_token = token.clone();
+        bad_token.token_key_id[0] ^= 1;
+        assert!(issuer.config().verify(&bad_token).is_err());
+
+        // Tampered nonce.
+        let mut bad_token = token.clone();
+        bad_token.nonce[0] ^= 1;
+        assert!(issuer.config().verify(&bad_token).is_err());
+
+        // Tampered authenticator.
+        let mut bad_token = token;
+        bad_token.authenticator[0] ^= 1;
+        assert!(issuer.config().verify(&bad_token).is_err());
+    }
+
+    #[test]
+    fn roundtrip_encoding() {
+        let token = TestIssuer::default().issue();
+        assert_eq!(
+            PrivacyPassToken::get_decoded(&token.get_encoded().unwrap()).unwrap(),
+            token
+        );
+        assert_eq!(
+            PrivacyPassToken::try_from_base64url(&token.to_base64url()).unwrap(),
+            token
+        );
+    }
+
+    #[test]
+    fn config_json_roundtrip() {
+        let config = TestIssuer::default().config().clone();
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PrivacyPassConfig>(&json).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn parse_authorization_header() {
+        assert_eq!(
+            token_from_authorization_header(r#"PrivateToken token="abc""#),
+            Some("abc")
+        );
+        assert_eq!(
+            token_from_authorization_header("privatetoken token=abc"),
+            Some("abc")
+        );
+        assert_eq!(
+            token_from_authorization_header(r#"Bearer token="abc""#),
+            None
+        );
+        assert_eq!(token_from_authorization_header("PrivateToken"), None);
+    }
+}
@@ -35,8 +35,8 @@ use std::{
 };
 
 use super::{
-    CTX_AGG_SHARE_DRAFT09, CTX_INPUT_SHARE_DRAFT09, CTX_ROLE_CLIENT, CTX_ROLE_COLLECTOR,
-    CTX_ROLE_HELPER, CTX_ROLE_LEADER,
+    ctx_agg_share, ctx_input_share, CTX_ROLE_CLIENT, CTX_ROLE_COLLECTOR, CTX_ROLE_HELPER,
+    CTX_ROLE_LEADER,
 };
 
 // Ping-pong message framing as defined in draft-irtf-cfrg-vdaf-08, Section 5.8. We do not
@@ -131,7 +131,7 @@ impl EarlyReportStateConsumed {
             });
         }
 
-        let input_share_text = CTX_INPUT_SHARE_DRAFT09;
+        let input_share_text = ctx_input_share(task_config.version);
         let n: usize = input_share_text.len();
         let mut info = Vec::with_capacity(n + 2);
         info.extend_from_slice(input_share_text);
@@ -537,7 +537,7 @@ impl DapTaskConfig {
 
         let agg_param =
             DapAggregationParam::get_decoded_with_param(&self.vdaf, &agg_job_init_req.agg_param)
-                .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+                .map_err(|e| DapAbort::invalid_agg_param(e, *task_id))?;
 
         let initialized_reports = initializer
             .initialize_reports(false, self, &agg_param, consumed_reports)
@@ -805,7 +805,7 @@ fn produce_encrypted_agg_share(
         .get_encoded()
         .map_err(DapError::encoding)?;
 
-    let agg_share_text = CTX_AGG_SHARE_DRAFT09;
+    let agg_share_text = ctx_agg_share(version);
     let n: usize = agg_share_text.len();
     let mut info = Vec::with_capacity(n + 2);
     info.extend_from_slice(agg_share_text);
@@ -16,7 +16,7 @@ use crate::{
 use prio::codec::{Encode, ParameterizedEncode};
 use rand::prelude::*;
 
-use super::{CTX_INPUT_SHARE_DRAFT09, CTX_ROLE_CLIENT, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
+use super::{ctx_input_share, CTX_ROLE_CLIENT, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
 
 impl VdafConfig {
     /// Generate a report for a measurement. This method is run by the Client.
@@ -98,7 +98,7 @@ impl VdafConfig {
             plaintext_input_share.get_encoded_with_param(&version)
         });
 
-        let input_share_text = CTX_INPUT_SHARE_DRAFT09;
+        let input_share_text = ctx_input_share(version);
         let n: usize = input_share_text.len();
         let mut info = Vec::with_capacity(n + 2);
         info.extend_from_slice(input_share_text);
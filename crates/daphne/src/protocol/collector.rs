@@ -12,7 +12,7 @@ use crate::{
 };
 use prio::codec::Encode;
 
-use super::{CTX_AGG_SHARE_DRAFT09, CTX_ROLE_COLLECTOR, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
+use super::{ctx_agg_share, CTX_ROLE_COLLECTOR, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
 
 impl VdafConfig {
     /// Decrypt and unshard a sequence of aggregate shares. This method is run by the Collector
@@ -47,7 +47,7 @@ impl VdafConfig {
             ));
         }
 
-        let agg_share_text = CTX_AGG_SHARE_DRAFT09;
+        let agg_share_text = ctx_agg_share(version);
         let n: usize = agg_share_text.len();
         let mut info = Vec::with_capacity(n + 2);
         info.extend_from_slice(agg_share_text);
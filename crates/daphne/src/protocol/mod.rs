@@ -5,13 +5,33 @@ pub(crate) mod aggregator;
 mod client;
 mod collector;
 
+use crate::DapVersion;
+
 const CTX_INPUT_SHARE_DRAFT09: &[u8] = b"dap-09 input share";
+const CTX_INPUT_SHARE_DRAFT_LATEST: &[u8] = b"dap-10 input share";
 const CTX_AGG_SHARE_DRAFT09: &[u8] = b"dap-09 aggregate share";
+const CTX_AGG_SHARE_DRAFT_LATEST: &[u8] = b"dap-10 aggregate share";
 const CTX_ROLE_COLLECTOR: u8 = 0;
 const CTX_ROLE_CLIENT: u8 = 1;
 const CTX_ROLE_LEADER: u8 = 2;
 const CTX_ROLE_HELPER: u8 = 3;
 
+/// The HPKE application info prefix for input shares.
+fn ctx_input_share(version: DapVersion) -> &'static [u8] {
+    match version {
+        DapVersion::Draft09 => CTX_INPUT_SHARE_DRAFT09,
+        DapVersion::Latest => CTX_INPUT_SHARE_DRAFT_LATEST,
+    }
+}
+
+/// The HPKE application info prefix for aggregate shares.
+fn ctx_agg_share(version: DapVersion) -> &'static [u8] {
+    match version {
+        DapVersion::Draft09 => CTX_AGG_SHARE_DRAFT09,
+        DapVersion::Latest => CTX_AGG_SHARE_DRAFT_LATEST,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
@@ -36,6 +56,7 @@ mod test {
     use assert_matches::assert_matches;
     use hpke_rs::HpkePublicKey;
     use prio::{
+        codec::{ParameterizedDecode, ParameterizedEncode},
         field::Field64,
         vdaf::{
             prio3::Prio3, AggregateShare, Aggregator as VdafAggregator, Collector as VdafCollector,
@@ -47,6 +68,13 @@ mod test {
 
     const TEST_VDAF: &VdafConfig = &VdafConfig::Prio3(Prio3Config::Count);
 
+    fn other_version(version: DapVersion) -> DapVersion {
+        match version {
+            DapVersion::Draft09 => DapVersion::Latest,
+            DapVersion::Latest => DapVersion::Draft09,
+        }
+    }
+
     async fn roundtrip_report(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let report = t
@@ -168,6 +196,36 @@ mod test {
 
     async_test_versions! { roundtrip_report }
 
+    // Draft-10 changed the HPKE application info, but not the encoding of messages.
+    fn report_encoding_is_the_same_for_other_version(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let report = t
+            .task_config
+            .vdaf
+            .produce_report(
+                &t.client_hpke_config_list,
+                t.now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+
+        let encoded = report.get_encoded_with_param(&version).unwrap();
+        assert_eq!(
+            report
+                .get_encoded_with_param(&other_version(version))
+                .unwrap(),
+            encoded
+        );
+        assert_eq!(
+            Report::get_decoded_with_param(&other_version(version), &encoded).unwrap(),
+            report
+        );
+    }
+
+    test_versions! { report_encoding_is_the_same_for_other_version }
+
     fn roundtrip_report_unsupported_hpke_suite(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
 
@@ -705,6 +763,59 @@ mod test {
 
     async_test_versions! { encrypted_agg_share }
 
+    async fn encrypted_agg_share_for_other_version(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let agg_share = DapAggregateShare {
+            report_count: 50,
+            min_time: 1_637_359_200,
+            max_time: 1_637_359_200,
+            checksum: [0; 32],
+            data: Some(VdafAggregateShare::Field64(AggregateShare::from(
+                OutputShare::from(vec![Field64::from(23)]),
+            ))),
+        };
+        let batch_selector = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: 1_637_359_200,
+                duration: 7200,
+            },
+        };
+        let encrypted_agg_shares = vec![
+            t.produce_leader_encrypted_agg_share(
+                &batch_selector,
+                &DapAggregationParam::Empty,
+                &agg_share,
+            ),
+            t.produce_helper_encrypted_agg_share(
+                &batch_selector,
+                &DapAggregationParam::Empty,
+                &agg_share,
+            ),
+        ];
+
+        // The aggregate shares are bound to the version of the task.
+        let err = t
+            .task_config
+            .vdaf
+            .consume_encrypted_agg_shares(
+                &t.collector_hpke_receiver_config,
+                &t.task_id,
+                &batch_selector,
+                50,
+                &DapAggregationParam::Empty,
+                encrypted_agg_shares,
+                other_version(version),
+            )
+            .await
+            .unwrap_err();
+        assert_matches!(
+            err,
+            DapError::Transition(TransitionFailure::HpkeDecryptError)
+        );
+    }
+
+    async_test_versions! { encrypted_agg_share_for_other_version }
+
     async fn handle_unrecognized_report_extensions(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let report = t
@@ -749,6 +860,52 @@ mod test {
 
     async_test_versions! { handle_unrecognized_report_extensions }
 
+    async fn handle_report_for_other_version(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let report = t
+            .task_config
+            .vdaf
+            .produce_report(
+                &t.client_hpke_config_list,
+                t.now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+
+        // The input shares are bound to the version of the task.
+        let mut task_config = t.task_config.clone();
+        task_config.version = other_version(version);
+
+        let [leader_share, _] = report.encrypted_input_shares;
+        let consumed_report = EarlyReportStateConsumed::consume(
+            &t.leader_hpke_receiver_config,
+            &t,
+            true,
+            &t.task_id,
+            &task_config,
+            ReportShare {
+                report_metadata: report.report_metadata,
+                public_share: report.public_share,
+                encrypted_input_share: leader_share,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            consumed_report,
+            EarlyReportStateConsumed::Rejected {
+                failure: TransitionFailure::HpkeDecryptError,
+                ..
+            }
+        ));
+    }
+
+    async_test_versions! { handle_report_for_other_version }
+
     async fn handle_repeated_report_extensions(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let report = t
@@ -11,7 +11,7 @@ use crate::{
     constants::DapMediaType,
     error::DapAbort,
     hpke::{HpkeConfig, HpkeProvider},
-    messages::{BatchId, BatchSelector, HpkeConfigList, ReportId, TaskId, Time},
+    messages::{AggregationJobId, BatchId, BatchSelector, HpkeConfigList, ReportId, TaskId, Time},
     metrics::{DaphneMetrics, DaphneRequestType},
     protocol::aggregator::{EarlyReportStateConsumed, EarlyReportStateInitialized},
     DapAggregateShare, DapAggregateSpan, DapAggregationParam, DapError, DapGlobalConfig,
@@ -102,7 +102,9 @@ pub trait DapAggregator<S: Sync>: HpkeProvider + DapReportInitializer + Sized {
     fn get_current_time(&self) -> Time;
 
     /// Check whether the batch determined by the collect request would overlap with a previously
-    /// collected batch.
+    /// collected batch. A time-interval batch overlaps if any part of its interval was collected,
+    /// and a fixed-size batch overlaps if it was collected as often as its query limit allows. See
+    /// [`DapCollectedBatches`](crate::DapCollectedBatches).
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
@@ -118,6 +120,10 @@ pub trait DapAggregator<S: Sync>: HpkeProvider + DapReportInitializer + Sized {
     /// If any report within a bucket has already been aggregated (is a replay) then that entire
     /// bucket must be skipped without changing any state, such that this operation is idempotent.
     ///
+    /// The aggregate shares are merged on behalf of the aggregation job `agg_job_id`. A bucket into
+    /// which this aggregation job was already merged must be left unchanged and reported as
+    /// `Ok(())`, so that the job can be processed again after being interrupted.
+    ///
     /// # Returns
     ///
     /// A span with the same buckets as the input `agg_share_span` where the value is one of 3
@@ -132,6 +138,7 @@ pub trait DapAggregator<S: Sync>: HpkeProvider + DapReportInitializer + Sized {
         &self,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
         agg_share_span: DapAggregateSpan<DapAggregateShare>,
     ) -> DapAggregateSpan<Result<(), MergeAggShareError>>;
 
@@ -142,12 +149,20 @@ pub trait DapAggregator<S: Sync>: HpkeProvider + DapReportInitializer + Sized {
         batch_sel: &BatchSelector,
     ) -> Result<DapAggregateShare, DapError>;
 
-    /// Mark a batch as collected.
+    /// Mark a batch as collected by the aggregate share request with the given SHA-256 hash.
+    /// Returns `false`, without marking the batch, if it overlaps with a previously collected
+    /// batch or exceeds its query limit. Marking the batch again for the same request returns
+    /// `true`, so that a retransmitted request is handled like the original. See
+    /// [`DapCollectedBatches`](crate::DapCollectedBatches).
+    ///
+    /// The check must be atomic with the update, so that concurrent requests can't both collect
+    /// the same batch.
     async fn mark_collected(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
-    ) -> Result<(), DapError>;
+        request_hash: &[u8; 32],
+    ) -> Result<bool, DapError>;
 
     /// Access the Prometheus metrics.
     fn metrics(&self) -> &dyn DaphneMetrics;
@@ -180,7 +195,7 @@ where
         let task_config = aggregator
             .get_task_config_for(&task_id)
             .await?
-            .ok_or(DapAbort::UnrecognizedTask)?;
+            .ok_or(DapAbort::UnrecognizedTask { task_id })?;
 
         // Check whether the DAP version in the request matches the task config.
         if task_config.as_ref().version != req.version {
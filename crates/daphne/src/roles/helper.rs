@@ -5,48 +5,122 @@ use std::{collections::HashMap, sync::Once};
 
 use async_trait::async_trait;
 use prio::codec::{Encode, ParameterizedDecode};
-use tracing::error;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
 
-use super::{check_batch, check_request_content_type, resolve_taskprov, DapAggregator};
+use super::{
+    check_batch, check_request_content_type, resolve_taskprov, unauthorized_request, DapAggregator,
+};
 use crate::{
     audit_log::AggregationJobAuditAction,
     constants::DapMediaType,
     error::DapAbort,
+    fatal_error,
     messages::{
-        constant_time_eq, AggregateShare, AggregateShareReq, AggregationJobId,
-        AggregationJobInitReq, AggregationJobResp, PartialBatchSelector, TaskId, TransitionFailure,
-        TransitionVar,
+        constant_time_eq, AggregateShare, AggregateShareReq, AggregationJobContinueReq,
+        AggregationJobId, AggregationJobInitReq, AggregationJobResp, Base64Encode, BatchSelector,
+        PartialBatchSelector, ReportId, TaskId, TransitionFailure, TransitionVar,
     },
     metrics::{DaphneMetrics, DaphneRequestType, ReportStatus},
     protocol::aggregator::ReportProcessedStatus,
     roles::aggregator::MergeAggShareError,
-    DapAggregationJobState, DapAggregationParam, DapError, DapRequest, DapResource, DapResponse,
-    DapTaskConfig, EarlyReportStateInitialized,
+    DapAggregationParam, DapError, DapQueryConfig, DapRequest, DapResource, DapResponse,
+    DapTaskConfig, EarlyReportState, EarlyReportStateInitialized,
 };
 
+/// Helper: What is stored for an aggregation job, so that a retransmission of the
+/// [`AggregationJobInitReq`] by the Leader gets the same response.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct AggregationJobRecord {
+    /// SHA-256 hash of the encoded [`AggregationJobInitReq`].
+    #[serde(with = "hex")]
+    pub request_hash: [u8; 32],
+
+    /// The encoded [`AggregationJobResp`]. This is `None` until the reports of the aggregation
+    /// job have been aggregated: the record is stored beforehand to claim the aggregation job ID
+    /// for the request.
+    #[serde(default, with = "hex_opt")]
+    pub agg_job_resp: Option<Vec<u8>>,
+
+    /// Fixed-size tasks: The reports rejected because they would overfill the batch. This is
+    /// decided before any report is merged, so that resuming the aggregation job does not count
+    /// the reports it already merged into the batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saturated_reports: Option<Vec<ReportId>>,
+}
+
+mod hex_opt {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[allow(clippy::ref_option)]
+    pub(super) fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&hex::encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| hex::decode(hex).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
 /// DAP Helper functionality.
-//
-// TODO draft02 cleanup: Remove methods for mutating aggregation job state. This is not needed
-// until we add support for multi-round VDAFs. Note that we will likely still need this trait for
-// features coming in the next draft.
 #[async_trait]
 pub trait DapHelper<S: Sync>: DapAggregator<S> {
-    /// Store the Helper's aggregation-flow state unless it already exists. Returns a boolean
+    /// Store the record of an aggregation job unless one already exists. Returns a boolean
     /// indicating if the operation succeeded.
-    async fn put_helper_state_if_not_exists(
+    async fn put_agg_job_record_if_not_exists(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-        helper_state: &DapAggregationJobState,
+        record: &AggregationJobRecord,
     ) -> Result<bool, DapError>;
 
-    /// Fetch the Helper's aggregation-flow state. `None` is returned if the Helper has no state
+    /// Store the record of an aggregation job, replacing the existing one.
+    async fn put_agg_job_record(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        record: &AggregationJobRecord,
+    ) -> Result<(), DapError>;
+
+    /// Fetch the record of an aggregation job. `None` is returned if the Helper has no record
     /// associated with the given task and aggregation job.
-    async fn get_helper_state(
+    async fn get_agg_job_record(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-    ) -> Result<Option<DapAggregationJobState>, DapError>;
+    ) -> Result<Option<AggregationJobRecord>, DapError>;
+}
+
+/// Check that a retransmitted [`AggregationJobInitReq`] is the same as the request for which the
+/// aggregation job was first initialized.
+fn check_agg_job_record(
+    task_id: &TaskId,
+    agg_job_id: &AggregationJobId,
+    request_hash: &[u8; 32],
+    record: &AggregationJobRecord,
+) -> Result<(), DapError> {
+    if &record.request_hash != request_hash {
+        return Err(DapAbort::InvalidMessage {
+            detail: format!(
+                "aggregation job {} was already initialized with a different request",
+                agg_job_id.to_base64url()
+            ),
+            task_id: Some(*task_id),
+        }
+        .into());
+    }
+    Ok(())
 }
 
 pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
@@ -69,19 +143,14 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
     let wrapped_task_config = aggregator
         .get_task_config_for(task_id)
         .await?
-        .ok_or(DapAbort::UnrecognizedTask)?;
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
     let task_config = wrapped_task_config.as_ref();
 
     if let Some(reason) = aggregator.unauthorized_reason(task_config, req).await? {
-        error!("aborted unauthorized collect request: {reason}");
-        return Err(DapAbort::UnauthorizedRequest {
-            detail: reason,
-            task_id: *task_id,
-        }
-        .into());
+        return Err(unauthorized_request(aggregator, req, task_id, reason).into());
     }
 
-    let DapResource::AggregationJob(_agg_job_id) = req.resource else {
+    let DapResource::AggregationJob(agg_job_id) = req.resource else {
         return Err(DapAbort::BadRequest("missing aggregation job ID".to_string()).into());
     };
 
@@ -98,19 +167,82 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         &agg_job_init_req.agg_param,
     )?;
 
+    // Claim the aggregation job ID for this request before aggregating anything. If the Leader
+    // retransmits a request we've already handled, then we respond as we did the first time,
+    // since processing the reports again would reject them as replays.
+    let request_hash: [u8; 32] = digest(&SHA256, &req.payload)
+        .as_ref()
+        .try_into()
+        .map_err(|e| fatal_error!(err = ?e, "unexpected digest length"))?;
+    let claim = AggregationJobRecord {
+        request_hash,
+        agg_job_resp: None,
+        saturated_reports: None,
+    };
+    let mut saturated_reports = None;
+    if !aggregator
+        .put_agg_job_record_if_not_exists(task_id, &agg_job_id, &claim)
+        .await?
+    {
+        let record = aggregator
+            .get_agg_job_record(task_id, &agg_job_id)
+            .await?
+            .ok_or_else(|| fatal_error!(err = "aggregation job record disappeared"))?;
+        check_agg_job_record(task_id, &agg_job_id, &request_hash, &record)?;
+        if let Some(agg_job_resp) = record.agg_job_resp {
+            tracing::debug!(agg_job_id = %agg_job_id.to_base64url(), "responding to retransmitted aggregation job");
+            return Ok(DapResponse {
+                version: req.version,
+                media_type: DapMediaType::AggregationJobResp,
+                payload: agg_job_resp,
+            });
+        }
+        // The first attempt was interrupted before it stored its response, or is still in
+        // progress. Aggregating again is safe, since the buckets the job was already merged into
+        // are left unchanged.
+        tracing::debug!(agg_job_id = %agg_job_id.to_base64url(), "resuming aggregation job");
+        saturated_reports = record.saturated_reports;
+    }
+
     let prep_init_count = agg_job_init_req.prep_inits.len();
     let part_batch_sel = agg_job_init_req.part_batch_sel.clone();
     let initialized_reports = task_config
         .consume_agg_job_req(aggregator, aggregator, task_id, agg_job_init_req)
         .await?;
 
+    if saturated_reports.is_none() {
+        saturated_reports = find_saturated_reports(
+            aggregator,
+            task_id,
+            task_config,
+            &part_batch_sel,
+            &initialized_reports,
+        )
+        .await?;
+        if saturated_reports.is_some() {
+            aggregator
+                .put_agg_job_record(
+                    task_id,
+                    &agg_job_id,
+                    &AggregationJobRecord {
+                        request_hash,
+                        agg_job_resp: None,
+                        saturated_reports: saturated_reports.clone(),
+                    },
+                )
+                .await?;
+        }
+    }
+
     let agg_job_resp = {
         let agg_job_resp = finish_agg_job_and_aggregate(
             aggregator,
             task_id,
             task_config,
+            &agg_job_id,
             &part_batch_sel,
             &initialized_reports,
+            saturated_reports.as_deref().unwrap_or_default(),
             metrics,
         )
         .await?;
@@ -120,6 +252,19 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         agg_job_resp
     };
 
+    let agg_job_resp = agg_job_resp.get_encoded().map_err(DapError::encoding)?;
+    aggregator
+        .put_agg_job_record(
+            task_id,
+            &agg_job_id,
+            &AggregationJobRecord {
+                request_hash,
+                agg_job_resp: Some(agg_job_resp.clone()),
+                saturated_reports,
+            },
+        )
+        .await?;
+
     aggregator.audit_log().on_aggregation_job(
         aggregator.host(),
         task_id,
@@ -132,10 +277,74 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
     Ok(DapResponse {
         version: req.version,
         media_type: DapMediaType::AggregationJobResp,
-        payload: agg_job_resp.get_encoded().map_err(DapError::encoding)?,
+        payload: agg_job_resp,
     })
 }
 
+/// Handle a request to continue an aggregation job.
+///
+/// Daphne only supports VDAFs with a single round of preparation, so each aggregation job is
+/// finished once it has been initialized (step 0). There is never a step to continue to, so this
+/// only determines which abort the request warrants.
+pub async fn handle_agg_job_cont_req<'req, S: Sync, A: DapHelper<S>>(
+    aggregator: &A,
+    req: &'req DapRequest<S>,
+) -> Result<DapResponse, DapError> {
+    let task_id = req.task_id()?;
+    let agg_job_cont_req =
+        AggregationJobContinueReq::get_decoded_with_param(&req.version, &req.payload)
+            .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+
+    let wrapped_task_config = aggregator
+        .get_task_config_for(task_id)
+        .await?
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+    let task_config = wrapped_task_config.as_ref();
+
+    if let Some(reason) = aggregator.unauthorized_reason(task_config, req).await? {
+        return Err(unauthorized_request(aggregator, req, task_id, reason).into());
+    }
+
+    let DapResource::AggregationJob(agg_job_id) = req.resource else {
+        return Err(DapAbort::BadRequest("missing aggregation job ID".to_string()).into());
+    };
+
+    // Check whether the DAP version in the request matches the task config.
+    if task_config.version != req.version {
+        return Err(DapAbort::version_mismatch(req.version, task_config.version).into());
+    }
+
+    if aggregator
+        .get_agg_job_record(task_id, &agg_job_id)
+        .await?
+        .is_none()
+    {
+        return Err(DapAbort::UnrecognizedAggregationJob {
+            task_id: *task_id,
+            agg_job_id,
+        }
+        .into());
+    }
+
+    if agg_job_cont_req.step == 0 {
+        return Err(DapAbort::InvalidMessage {
+            detail: "step 0 is reserved for the initialization of the aggregation job".into(),
+            task_id: Some(*task_id),
+        }
+        .into());
+    }
+
+    Err(DapAbort::StepMismatch {
+        detail: format!(
+            "aggregation job finished at step 0, but the request is for step {}",
+            agg_job_cont_req.step
+        ),
+        task_id: *task_id,
+        agg_job_id,
+    }
+    .into())
+}
+
 /// Handle a request pertaining to an aggregation job.
 pub async fn handle_agg_job_req<'req, S: Sync, A: DapHelper<S>>(
     aggregator: &A,
@@ -143,6 +352,9 @@ pub async fn handle_agg_job_req<'req, S: Sync, A: DapHelper<S>>(
 ) -> Result<DapResponse, DapError> {
     match req.media_type {
         Some(DapMediaType::AggregationJobInitReq) => handle_agg_job_init_req(aggregator, req).await,
+        Some(DapMediaType::AggregationJobContinueReq) => {
+            handle_agg_job_cont_req(aggregator, req).await
+        }
         _ => Err(DapAbort::BadRequest("unexpected media type".into()).into()),
     }
 }
@@ -166,16 +378,11 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
     let wrapped_task_config = aggregator
         .get_task_config_for(req.task_id()?)
         .await?
-        .ok_or(DapAbort::UnrecognizedTask)?;
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
     let task_config = wrapped_task_config.as_ref();
 
     if let Some(reason) = aggregator.unauthorized_reason(task_config, req).await? {
-        error!("aborted unauthorized collect request: {reason}");
-        return Err(DapAbort::UnauthorizedRequest {
-            detail: reason,
-            task_id: *task_id,
-        }
-        .into());
+        return Err(unauthorized_request(aggregator, req, task_id, reason).into());
     }
 
     // Check whether the DAP version in the request matches the task config.
@@ -188,10 +395,10 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
 
     let agg_param =
         DapAggregationParam::get_decoded_with_param(&task_config.vdaf, &agg_share_req.agg_param)
-            .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+            .map_err(|e| DapAbort::invalid_agg_param(e, *task_id))?;
 
-    // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
-    // collected batches.
+    // Ensure the batch boundaries are valid. Whether the batch overlaps with previously collected
+    // batches is decided when it is marked as collected.
     check_batch(
         aggregator,
         task_config,
@@ -235,10 +442,19 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
         .into());
     }
 
-    // Mark each aggregated report as collected.
-    aggregator
-        .mark_collected(task_id, &agg_share_req.batch_sel)
-        .await?;
+    // Mark each aggregated report as collected. This fails if an overlapping batch was collected,
+    // or if the batch was queried as often as allowed, but not if this is a retransmission of a
+    // request we've already handled.
+    let request_hash: [u8; 32] = digest(&SHA256, &req.payload)
+        .as_ref()
+        .try_into()
+        .map_err(|e| fatal_error!(err = ?e, "unexpected digest length"))?;
+    if !aggregator
+        .mark_collected(task_id, &agg_share_req.batch_sel, &request_hash)
+        .await?
+    {
+        return Err(DapAbort::batch_overlap(task_id, &agg_share_req.batch_sel).into());
+    }
 
     let encrypted_agg_share = task_config.produce_helper_encrypted_agg_share(
         &task_config.collector_hpke_config,
@@ -253,6 +469,14 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
         encrypted_agg_share,
     };
 
+    aggregator.audit_log().on_aggregate_share(
+        aggregator.host(),
+        task_id,
+        task_config,
+        &agg_share_req.batch_sel,
+        agg_share_req.report_count,
+    );
+
     metrics.report_inc_by(ReportStatus::Collected, agg_share_req.report_count);
     metrics.inbound_req_inc(DaphneRequestType::Collect);
     Ok(DapResponse {
@@ -278,22 +502,64 @@ fn check_part_batch(
 
     // Check that the aggregation parameter is suitable for the given VDAF.
     if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::InvalidMessage {
-            detail: "invalid aggregation parameter".into(),
-            task_id: Some(*task_id),
+        return Err(DapAbort::InvalidAggregationParameter {
+            detail: "The aggregation parameter is not valid for the task's VDAF.".into(),
+            task_id: *task_id,
         });
     }
 
     Ok(())
 }
 
+/// Fixed-size tasks: Find the reports that would cause the batch to exceed the maximum batch
+/// size. Returns `None` if the batch size is not limited.
+///
+/// Note that this check is not atomic with aggregation: Concurrent aggregation jobs for the same
+/// batch may still overfill it, in which case the aggregate share request for the batch fails.
+async fn find_saturated_reports<S: Sync>(
+    helper: &impl DapHelper<S>,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    part_batch_sel: &PartialBatchSelector,
+    initialized_reports: &[EarlyReportStateInitialized],
+) -> Result<Option<Vec<ReportId>>, DapError> {
+    let (
+        DapQueryConfig::FixedSize {
+            max_batch_size: Some(max_batch_size),
+        },
+        PartialBatchSelector::FixedSizeByBatchId { batch_id },
+    ) = (&task_config.query, part_batch_sel)
+    else {
+        return Ok(None);
+    };
+
+    let batch_sel = BatchSelector::FixedSizeByBatchId {
+        batch_id: *batch_id,
+    };
+    let report_count = helper
+        .get_agg_share(task_id, &batch_sel)
+        .await?
+        .report_count;
+    let remaining = max_batch_size.saturating_sub(report_count);
+    Ok(Some(
+        initialized_reports
+            .iter()
+            .filter(|report| report.is_ready())
+            .skip(remaining.try_into().unwrap_or(usize::MAX))
+            .map(|report| report.metadata().id)
+            .collect(),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn finish_agg_job_and_aggregate<S: Sync>(
     helper: &impl DapHelper<S>,
     task_id: &TaskId,
     task_config: &DapTaskConfig,
+    agg_job_id: &AggregationJobId,
     part_batch_sel: &PartialBatchSelector,
     initialized_reports: &[EarlyReportStateInitialized],
+    saturated_reports: &[ReportId],
     metrics: &dyn DaphneMetrics,
 ) -> Result<AggregationJobResp, DapError> {
     // This loop is intended to run at most once on the "happy path". The intent is as follows:
@@ -307,7 +573,15 @@ async fn finish_agg_job_and_aggregate<S: Sync>(
     // against them, as such, even though retrying is possibly very expensive, it probably
     // won't happen often enough that it matters.
     const RETRY_COUNT: u32 = 3;
-    let mut report_status = HashMap::new();
+    let mut report_status: HashMap<_, _> = saturated_reports
+        .iter()
+        .map(|id| {
+            (
+                *id,
+                ReportProcessedStatus::Rejected(TransitionFailure::BatchSaturated),
+            )
+        })
+        .collect();
     for _ in 0..RETRY_COUNT {
         let (agg_span, agg_job_resp) = task_config.produce_agg_job_resp(
             &report_status,
@@ -316,7 +590,7 @@ async fn finish_agg_job_and_aggregate<S: Sync>(
         )?;
 
         let put_shares_result = helper
-            .try_put_agg_share_span(task_id, task_config, agg_span)
+            .try_put_agg_share_span(task_id, task_config, agg_job_id, agg_span)
             .await;
 
         let inc_restart_metric = Once::new();
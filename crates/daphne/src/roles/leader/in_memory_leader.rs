@@ -5,22 +5,27 @@
 //! leader. For a real production implementation this should not be used as it means a machine
 //! crash or shutdown would cause in progress tasks to be lost.
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use rand::{thread_rng, Rng};
 use url::Url;
 
 use crate::{
-    error::DapAbort,
+    error::{DapAbort, PeerAbort},
     fatal_error,
-    messages::{Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId, Report, TaskId},
-    roles::leader::WorkItem,
+    messages::{
+        AggregationJobId, Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId,
+        PartialBatchSelector, Report, TaskId, Time,
+    },
+    roles::leader::{WorkItem, WorkItemRetry},
     DapAggregationParam, DapBatchBucket, DapCollectionJob, DapError, DapQueryConfig, DapTaskConfig,
 };
 
 #[derive(Default)]
 pub struct InMemoryLeaderState {
     work_queue: VecDeque<WorkItem>,
+    dead_letters: BTreeMap<u64, WorkItem>,
+    next_dead_letter_id: u64,
     per_task: HashMap<TaskId, MockLeaderMemoryPerTask>,
 }
 
@@ -43,38 +48,151 @@ impl InMemoryLeaderState {
                 leader_state
                     .batch_queue
                     .iter()
-                    .any(|(queued_batch_id, _)| queued_batch_id == batch_id)
+                    .any(|batch| batch.batch_id == *batch_id)
             })
             .is_some()
     }
 
     pub fn delete_all(&mut self) {
         self.work_queue.clear();
+        self.dead_letters.clear();
+        self.next_dead_letter_id = 0;
         self.per_task.clear();
     }
 
+    /// Move work items to the dead-letter queue. Each item is assigned an ID by which it can be
+    /// replayed.
+    pub fn put_dead_letters(&mut self, items: Vec<WorkItem>) {
+        for item in items {
+            self.dead_letters.insert(self.next_dead_letter_id, item);
+            self.next_dead_letter_id += 1;
+        }
+    }
+
+    /// Work items in the dead-letter queue, in the order in which they were added.
+    pub fn dead_letters(&self) -> impl Iterator<Item = (u64, &WorkItem)> {
+        self.dead_letters.iter().map(|(id, item)| (*id, item))
+    }
+
+    /// Move a work item from the dead-letter queue back to the work queue, resetting its attempt
+    /// counter. Returns `false` if there is no such item.
+    pub fn replay_dead_letter(&mut self, id: u64) -> bool {
+        let Some(mut item) = self.dead_letters.remove(&id) else {
+            return false;
+        };
+        *item.retry_mut() = WorkItemRetry::default();
+        // Aggregation jobs need to be processed before the collection jobs of their task, which
+        // may still be in the queue.
+        self.work_queue.push_front(item);
+        true
+    }
+
+    /// Check whether an aggregation job for reports in the batch failed and is waiting to be
+    /// retried.
+    pub fn has_failed_agg_jobs(&self, task_id: &TaskId, batch_sel: &BatchSelector) -> bool {
+        self.work_queue
+            .iter()
+            .any(|item| item.retry().attempts > 0 && item.is_agg_job_for_batch(task_id, batch_sel))
+    }
+
+    /// Store a report. If the nonce of a Privacy Pass token is given, then its redemption is
+    /// recorded along with the report. Returns `false`, without storing the report, if the token
+    /// was already redeemed.
     pub fn put_report(
         &mut self,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
         report: Report,
-    ) -> Result<(), DapError> {
+        privacy_pass_nonce: Option<&[u8; 32]>,
+    ) -> Result<bool, DapError> {
         let per_task = self.per_task.entry(*task_id).or_default();
-        let bucket = per_task.assign_report_to_bucket(task_config, &report);
+        if let Some(nonce) = privacy_pass_nonce {
+            if !per_task.redeemed_privacy_pass_tokens.insert(*nonce) {
+                return Ok(false);
+            }
+        }
+        let (bucket, batch_full) = per_task.assign_report_to_bucket(task_config, &report);
 
         // Store the report until a collection job is initialized for it. Note that, in a
         // production Leader, it will usually be desirable to start aggregating reports immediately
         // (if allowed by the VDAF).
         per_task
             .pending_reports
-            .entry(bucket)
+            .entry(bucket.clone())
             .or_default()
             .push_back(report);
-        Ok(())
+
+        // Aggregate the reports of a full fixed-size batch right away, so that the batch can be
+        // sealed once we know how many of its reports were aggregated.
+        if batch_full {
+            if let (DapBatchBucket::FixedSize { batch_id }, Some(reports)) =
+                (&bucket, per_task.pending_reports.remove(&bucket))
+            {
+                self.work_queue.push_back(WorkItem::AggregationJob {
+                    task_id: *task_id,
+                    agg_job_id: AggregationJobId(thread_rng().gen()),
+                    part_batch_sel: PartialBatchSelector::FixedSizeByBatchId {
+                        batch_id: *batch_id,
+                    },
+                    agg_param: DapAggregationParam::Empty,
+                    reports: reports.into(),
+                    retry: WorkItemRetry::default(),
+                });
+            }
+        }
+        Ok(true)
     }
 
+    /// Fixed-size tasks: Account for the outcome of an aggregation job. The batch is sealed once
+    /// enough of its reports have been aggregated. Rejected reports no longer count towards the
+    /// size of the batch, so it takes new reports in their place.
+    pub fn finish_agg_job(
+        &mut self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        part_batch_sel: &PartialBatchSelector,
+        aggregated: u64,
+        rejected: u64,
+    ) {
+        let PartialBatchSelector::FixedSizeByBatchId { batch_id } = part_batch_sel else {
+            return;
+        };
+        let Some(batch) = self.per_task.get_mut(task_id).and_then(|per_task| {
+            per_task
+                .batch_queue
+                .iter_mut()
+                .find(|batch| batch.batch_id == *batch_id)
+        }) else {
+            // The batch has already been handed out for collection.
+            return;
+        };
+
+        batch.report_count = batch.report_count.saturating_sub(rejected);
+        batch.aggregated_count += aggregated;
+        if batch.aggregated_count >= batch_size(task_config) {
+            batch.sealed = true;
+        }
+    }
+
+    /// Number of reports for the task that are waiting for a collection job.
+    pub fn pending_reports(&self, task_id: &TaskId) -> usize {
+        self.per_task.get(task_id).map_or(0, |per_task| {
+            per_task.pending_reports.values().map(VecDeque::len).sum()
+        })
+    }
+
+    /// Number of collection jobs for the task.
+    pub fn collection_jobs(&self, task_id: &TaskId) -> usize {
+        self.per_task
+            .get(task_id)
+            .map_or(0, |per_task| per_task.coll_jobs.len())
+    }
+
+    /// Fixed-size tasks: Hand out the oldest sealed batch for collection. If there is none, then
+    /// the oldest batch that has reached the minimum batch size is sealed early. A batch is handed
+    /// out only once, after which no more reports are assigned to it.
     pub fn current_batch(
-        &self,
+        &mut self,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
     ) -> std::result::Result<BatchId, DapError> {
@@ -84,15 +202,35 @@ impl InMemoryLeaderState {
             )));
         }
 
-        let Some(per_task) = self.per_task.get(task_id) else {
-            return Err(DapError::Abort(DapAbort::UnrecognizedTask));
+        let Some(per_task) = self.per_task.get_mut(task_id) else {
+            return Err(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }));
         };
 
-        per_task
+        if per_task.batch_queue.is_empty() {
+            return Err(DapError::Abort(DapAbort::BadRequest(
+                "empty batch queue".into(),
+            )));
+        }
+
+        let Some(i) = per_task
             .batch_queue
-            .front()
-            .map(|(batch_id, _report_count)| *batch_id)
-            .ok_or_else(|| DapError::Abort(DapAbort::BadRequest("empty batch queue".into())))
+            .iter()
+            .position(|batch| batch.sealed)
+            .or_else(|| {
+                per_task
+                    .batch_queue
+                    .iter()
+                    .position(|batch| batch.report_count >= task_config.min_batch_size)
+            })
+        else {
+            return Err(DapError::Abort(DapAbort::BadRequest(
+                "no batch has reached the minimum batch size".into(),
+            )));
+        };
+
+        Ok(per_task.batch_queue.remove(i).unwrap().batch_id)
     }
 
     pub fn enqueue_work(&mut self, work_items: Vec<WorkItem>) -> Result<(), DapError> {
@@ -118,6 +256,7 @@ impl InMemoryLeaderState {
         coll_job_id: &CollectionJobId,
         batch_sel: BatchSelector,
         agg_param: DapAggregationParam,
+        now: Time,
     ) -> Result<Url, DapError> {
         let per_task = self.per_task.entry(*task_id).or_default();
 
@@ -149,9 +288,11 @@ impl InMemoryLeaderState {
             if let Some(reports) = per_task.pending_reports.remove(&bucket) {
                 self.work_queue.push_back(WorkItem::AggregationJob {
                     task_id: *task_id,
+                    agg_job_id: AggregationJobId(thread_rng().gen()),
                     part_batch_sel: batch_sel.clone().into(),
                     agg_param: agg_param.clone(),
                     reports: reports.into(),
+                    retry: WorkItemRetry::default(),
                 });
             }
 
@@ -159,7 +300,7 @@ impl InMemoryLeaderState {
             if let DapBatchBucket::FixedSize { ref batch_id } = bucket {
                 per_task
                     .batch_queue
-                    .retain(|(queued_batch_id, _batch_count)| batch_id != queued_batch_id);
+                    .retain(|batch| batch.batch_id != *batch_id);
             }
         }
 
@@ -169,6 +310,8 @@ impl InMemoryLeaderState {
             coll_job_id: *coll_job_id,
             batch_sel,
             agg_param,
+            created_at: now,
+            retry: WorkItemRetry::default(),
         });
 
         Ok(coll_job_uri)
@@ -186,7 +329,9 @@ impl InMemoryLeaderState {
                 .cloned()
                 .unwrap_or(DapCollectionJob::Unknown))
         } else {
-            Err(DapError::Abort(DapAbort::UnrecognizedTask))
+            Err(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))
         }
     }
 
@@ -195,6 +340,32 @@ impl InMemoryLeaderState {
         task_id: &TaskId,
         coll_job_id: &CollectionJobId,
         collection: &Collection,
+    ) -> Result<(), DapError> {
+        self.complete_collect_job(
+            task_id,
+            coll_job_id,
+            DapCollectionJob::Done(collection.clone()),
+        )
+    }
+
+    pub fn fail_collect_job(
+        &mut self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        failure: &PeerAbort,
+    ) -> Result<(), DapError> {
+        self.complete_collect_job(
+            task_id,
+            coll_job_id,
+            DapCollectionJob::Failed(failure.clone()),
+        )
+    }
+
+    fn complete_collect_job(
+        &mut self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        status: DapCollectionJob,
     ) -> Result<(), DapError> {
         let Some(per_task) = self.per_task.get_mut(task_id) else {
             return Err(fatal_error!(err = "collect job not found for task_id", %task_id));
@@ -207,10 +378,10 @@ impl InMemoryLeaderState {
         match coll_job {
             DapCollectionJob::Pending => {
                 // Mark collection job as complete.
-                *coll_job = DapCollectionJob::Done(collection.clone());
+                *coll_job = status;
                 Ok(())
             }
-            DapCollectionJob::Done(_) => Err(fatal_error!(
+            DapCollectionJob::Done(_) | DapCollectionJob::Failed(_) => Err(fatal_error!(
                 err = "tried to overwrite completed collection job"
             )),
             DapCollectionJob::Unknown => Err(fatal_error!(
@@ -220,44 +391,90 @@ impl InMemoryLeaderState {
     }
 }
 
+/// Fixed-size tasks: Number of aggregated reports at which a batch is sealed.
+fn batch_size(task_config: &DapTaskConfig) -> u64 {
+    match task_config.query {
+        DapQueryConfig::FixedSize {
+            max_batch_size: Some(max_batch_size),
+        } => max_batch_size,
+        _ => task_config.min_batch_size,
+    }
+}
+
+/// A batch of a fixed-size task that has not yet been handed out for collection.
+///
+/// Reports are assigned to a batch until it is full, i.e., it reaches the maximum batch size, or
+/// the minimum batch size if the task has no maximum. The reports of a full batch are aggregated,
+/// and the batch is sealed once enough of them have been aggregated. Reports rejected during
+/// aggregation are not counted, so that the batch takes new reports until it is sealed.
+struct FixedSizeBatch {
+    batch_id: BatchId,
+
+    /// Number of reports assigned to the batch, minus those rejected during aggregation.
+    report_count: u64,
+
+    /// Number of reports aggregated.
+    aggregated_count: u64,
+
+    sealed: bool,
+}
+
 #[derive(Default)]
 struct MockLeaderMemoryPerTask {
     pending_reports: HashMap<DapBatchBucket, VecDeque<Report>>,
     coll_jobs: HashMap<CollectionJobId, DapCollectionJob>,
-    batch_queue: VecDeque<(BatchId, u64)>, // Batch ID, batch size
+    batch_queue: VecDeque<FixedSizeBatch>, // Oldest batch first
+    redeemed_privacy_pass_tokens: HashSet<[u8; 32]>,
 }
 
 impl MockLeaderMemoryPerTask {
+    /// Assign a report to a bucket. For fixed-size tasks, this also returns whether the report
+    /// filled its batch.
     fn assign_report_to_bucket(
         &mut self,
         task_config: &DapTaskConfig,
         report: &Report,
-    ) -> DapBatchBucket {
+    ) -> (DapBatchBucket, bool) {
         let mut rng = thread_rng();
         match task_config.query {
             // For fixed-size queries, the bucket corresponds to a single batch.
             DapQueryConfig::FixedSize { .. } => {
-                // Assign the report to the first unsaturated batch.
-                for (batch_id, report_count) in &mut self.batch_queue {
-                    if *report_count < task_config.min_batch_size {
-                        *report_count += 1;
-                        return DapBatchBucket::FixedSize {
-                            batch_id: *batch_id,
-                        };
-                    }
-                }
-
-                // No unsaturated batch exists, so create a new batch.
-                let batch_id = BatchId(rng.gen());
-                self.batch_queue.push_back((batch_id, 1));
-                DapBatchBucket::FixedSize { batch_id }
+                let batch_size = batch_size(task_config);
+
+                // Assign the report to the oldest batch that isn't full. If there is none, then
+                // open a new batch.
+                let i = self
+                    .batch_queue
+                    .iter()
+                    .position(|batch| !batch.sealed && batch.report_count < batch_size)
+                    .unwrap_or_else(|| {
+                        self.batch_queue.push_back(FixedSizeBatch {
+                            batch_id: BatchId(rng.gen()),
+                            report_count: 0,
+                            aggregated_count: 0,
+                            sealed: false,
+                        });
+                        self.batch_queue.len() - 1
+                    });
+                let batch = &mut self.batch_queue[i];
+                batch.report_count += 1;
+                (
+                    DapBatchBucket::FixedSize {
+                        batch_id: batch.batch_id,
+                    },
+                    batch.report_count == batch_size,
+                )
             }
 
             // For time-interval queries, the bucket is the batch window computed by truncating the
             // report timestamp.
-            DapQueryConfig::TimeInterval => DapBatchBucket::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(report.report_metadata.time),
-            },
+            DapQueryConfig::TimeInterval => (
+                DapBatchBucket::TimeInterval {
+                    batch_window: task_config
+                        .quantized_time_lower_bound(report.report_metadata.time),
+                },
+                false,
+            ),
         }
     }
 }
@@ -3,29 +3,31 @@
 
 pub mod in_memory_leader;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use async_trait::async_trait;
-use futures::future::try_join_all;
+use futures::future::join_all;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
-use rand::{thread_rng, Rng};
-use tracing::{debug, error};
+use ring::digest::{digest, SHA256};
+use tracing::debug;
 use url::Url;
 
 use super::{
     aggregator::MergeAggShareError, check_batch, check_request_content_type, resolve_taskprov,
-    DapAggregator,
+    unauthorized_request, DapAggregator,
 };
 use crate::{
+    audit_log::CollectionJobAuditAction,
     constants::DapMediaType,
-    error::DapAbort,
+    error::{DapAbort, PeerAbort},
     fatal_error,
     messages::{
         AggregateShare, AggregateShareReq, AggregationJobId, AggregationJobResp, Base64Encode,
         BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq, Interval,
-        PartialBatchSelector, Query, Report, TaskId,
+        PartialBatchSelector, Query, Report, TaskId, Time,
     },
     metrics::{DaphneRequestType, ReportStatus},
+    privacy_pass::PrivacyPassToken,
     DapAggregationParam, DapCollectionJob, DapError, DapLeaderProcessTelemetry, DapRequest,
     DapResource, DapResponse, DapTaskConfig,
 };
@@ -77,12 +79,17 @@ async fn leader_send_http_request<S: Sync>(
         ),
         payload: req_data,
         taskprov,
+        privacy_pass_token: None,
     };
 
     let resp = match method {
-        LeaderHttpRequestMethod::Put => role.send_http_put(req, url).await?,
-        LeaderHttpRequestMethod::Post => role.send_http_post(req, url).await?,
+        LeaderHttpRequestMethod::Put => role.send_http_put(req, url).await,
+        LeaderHttpRequestMethod::Post => role.send_http_post(req, url).await,
     };
+    if let Err(DapError::Peer(peer)) = &resp {
+        role.metrics().peer_abort_inc(peer.abort_type());
+    }
+    let resp = resp?;
 
     check_response_content_type(&resp, resp_media_type)?;
     Ok(resp)
@@ -101,24 +108,56 @@ pub trait DapAuthorizedSender<S> {
     ) -> Result<S, DapError>;
 }
 
+/// Maximum number of attempts at processing a work item. Once reached, the work item is moved to
+/// the dead-letter queue.
+pub const WORK_ITEM_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay, in seconds, before a failed work item is processed again. The delay doubles with each
+/// failed attempt, up to [`WORK_ITEM_MAX_BACKOFF`].
+const WORK_ITEM_INITIAL_BACKOFF: u64 = 10;
+
+/// Maximum delay, in seconds, before a failed work item is processed again.
+const WORK_ITEM_MAX_BACKOFF: u64 = 60 * 60;
+
 /// A work item, either an aggregation job or collection job.
 #[derive(Debug)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
 pub enum WorkItem {
     AggregationJob {
         task_id: TaskId,
+        /// The ID is kept across attempts, so that the Helper recognizes a retried aggregation job
+        /// and responds as it did the first time.
+        agg_job_id: AggregationJobId,
         part_batch_sel: PartialBatchSelector,
         agg_param: DapAggregationParam,
         reports: Vec<Report>,
+        retry: WorkItemRetry,
     },
     CollectionJob {
         task_id: TaskId,
         coll_job_id: CollectionJobId,
         batch_sel: BatchSelector,
         agg_param: DapAggregationParam,
+        /// Time at which the collection job was created.
+        created_at: Time,
+        retry: WorkItemRetry,
     },
 }
 
+/// Failed attempts at processing a work item.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct WorkItemRetry {
+    /// Number of failed attempts.
+    pub attempts: u32,
+
+    /// The error of the last failed attempt.
+    pub last_error: Option<String>,
+
+    /// Time before which the work item is not processed again.
+    pub not_before: Time,
+}
+
 impl WorkItem {
     /// Get the ID for the task to which the work item is associated.
     pub fn task_id(&self) -> &TaskId {
@@ -126,15 +165,70 @@ impl WorkItem {
             Self::AggregationJob { task_id, .. } | Self::CollectionJob { task_id, .. } => task_id,
         }
     }
+
+    pub fn retry(&self) -> &WorkItemRetry {
+        match self {
+            Self::AggregationJob { retry, .. } | Self::CollectionJob { retry, .. } => retry,
+        }
+    }
+
+    pub fn retry_mut(&mut self) -> &mut WorkItemRetry {
+        match self {
+            Self::AggregationJob { retry, .. } | Self::CollectionJob { retry, .. } => retry,
+        }
+    }
+
+    /// Check whether the work item is an aggregation job of the task for reports in the batch.
+    pub fn is_agg_job_for_batch(&self, task_id: &TaskId, batch_sel: &BatchSelector) -> bool {
+        let Self::AggregationJob {
+            task_id: agg_job_task_id,
+            part_batch_sel,
+            reports,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if agg_job_task_id != task_id {
+            return false;
+        }
+        match (batch_sel, part_batch_sel) {
+            (
+                BatchSelector::TimeInterval { batch_interval },
+                PartialBatchSelector::TimeInterval,
+            ) => reports.iter().any(|report| {
+                (batch_interval.start..batch_interval.end()).contains(&report.report_metadata.time)
+            }),
+            (
+                BatchSelector::FixedSizeByBatchId { batch_id },
+                PartialBatchSelector::FixedSizeByBatchId {
+                    batch_id: agg_job_batch_id,
+                },
+            ) => batch_id == agg_job_batch_id,
+            _ => false,
+        }
+    }
 }
 
 /// DAP Leader functionality.
 #[async_trait]
 pub trait DapLeader<S: Sync>: DapAuthorizedSender<S> + DapAggregator<S> {
     /// Store a report for use later on.
-    async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError>;
+    ///
+    /// Privacy Pass: If a token is given, then its redemption is recorded if and only if the
+    /// report is stored, so that a Client doesn't lose its token when the report is rejected.
+    /// Redemptions must outlive the process. Returns `false`, without storing the report, if the
+    /// token was already redeemed, i.e., the Client attempted to double-spend it.
+    async fn put_report(
+        &self,
+        report: &Report,
+        task_id: &TaskId,
+        privacy_pass_token: Option<&PrivacyPassToken>,
+    ) -> Result<bool, DapError>;
 
-    /// Fixed-size tasks: Return the ID of the batch currently being filled.
+    /// Fixed-size tasks: Return the ID of the oldest batch that is ready to be collected, i.e.,
+    /// the oldest batch that has been sealed and not yet collected. Each batch is returned only
+    /// once.
     //
     // TODO draft02 cleanup: Consider removing this.
     async fn current_batch(&self, task_id: &TaskId) -> Result<BatchId, DapError>;
@@ -161,6 +255,30 @@ pub trait DapLeader<S: Sync>: DapAuthorizedSender<S> + DapAggregator<S> {
     /// Append `items` to the work queue.
     async fn enqueue_work(&self, items: Vec<WorkItem>) -> Result<(), DapError>;
 
+    /// Move `items` to the dead-letter queue. These are work items that failed too often to be
+    /// retried automatically.
+    async fn put_dead_letters(&self, items: Vec<WorkItem>) -> Result<(), DapError>;
+
+    /// Record the outcome of an aggregation job: the number of its reports that were aggregated
+    /// and the number that were rejected. For fixed-size tasks, this determines when the batch is
+    /// sealed.
+    async fn finish_agg_job(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        aggregated: u64,
+        rejected: u64,
+    ) -> Result<(), DapError>;
+
+    /// Check whether an aggregation job for reports in the batch has failed and is waiting in the
+    /// work queue to be retried. Aggregation jobs in the dead-letter queue are not retried, so
+    /// the batch is collected without their reports.
+    async fn has_failed_agg_jobs(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError>;
+
     /// Complete a collect job by assigning it the completed
     /// [`Collection`](crate::messages::Collection).
     async fn finish_collect_job(
@@ -170,6 +288,14 @@ pub trait DapLeader<S: Sync>: DapAuthorizedSender<S> + DapAggregator<S> {
         collect_resp: &Collection,
     ) -> Result<(), DapError>;
 
+    /// Fail a collect job because the Helper aborted the aggregate share request.
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        failure: &PeerAbort,
+    ) -> Result<(), DapError>;
+
     /// Send an HTTP POST request.
     async fn send_http_post(&self, req: DapRequest<S>, url: Url) -> Result<DapResponse, DapError>;
 
@@ -198,13 +324,30 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
     let task_config = aggregator
         .get_task_config_for(task_id)
         .await?
-        .ok_or(DapAbort::UnrecognizedTask)?;
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
 
     // Check whether the DAP version in the request matches the task config.
     if task_config.as_ref().version != req.version {
         return Err(DapAbort::version_mismatch(req.version, task_config.as_ref().version).into());
     }
 
+    // Privacy Pass: If the task requires a token, then verify it before doing any more work.
+    let privacy_pass_token = if let Some(ref privacy_pass) = task_config.as_ref().privacy_pass {
+        let token = req
+            .privacy_pass_token
+            .as_deref()
+            .ok_or_else(|| "missing Privacy Pass token".to_string())
+            .and_then(|token| {
+                PrivacyPassToken::try_from_base64url(token)
+                    .ok_or_else(|| "malformed Privacy Pass token".to_string())
+            })
+            .and_then(|token| privacy_pass.verify(&token).map(|()| token))
+            .map_err(|reason| unauthorized_request(aggregator, req, task_id, reason))?;
+        Some(token)
+    } else {
+        None
+    };
+
     if report.encrypted_input_shares.len() != 2 {
         return Err(DapAbort::InvalidMessage {
             detail: format!(
@@ -221,21 +364,33 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
         .can_hpke_decrypt(req.task_id()?, report.encrypted_input_shares[0].config_id)
         .await?
     {
-        return Err(DapAbort::ReportRejected {
+        return Err(DapAbort::OutdatedConfig {
             detail: "No current HPKE configuration matches the indicated ID.".into(),
+            task_id: *task_id,
         }
         .into());
     }
 
     // Check that the task has not expired.
     if report.report_metadata.time >= task_config.as_ref().expiration {
-        return Err(DapAbort::ReportTooLate.into());
+        return Err(DapAbort::ReportTooLate { task_id: *task_id }.into());
     }
 
     // Store the report for future processing. At this point, the report may be rejected if
     // the Leader detects that the report was replayed or pertains to a batch that has already
-    // been collected.
-    aggregator.put_report(&report, req.task_id()?).await?;
+    // been collected. The Privacy Pass token, if any, is redeemed only if the report is stored.
+    if !aggregator
+        .put_report(&report, req.task_id()?, privacy_pass_token.as_ref())
+        .await?
+    {
+        return Err(unauthorized_request(
+            aggregator,
+            req,
+            task_id,
+            "Privacy Pass token was already redeemed".into(),
+        )
+        .into());
+    }
 
     metrics.inbound_req_inc(DaphneRequestType::Upload);
     Ok(())
@@ -261,16 +416,11 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     let wrapped_task_config = aggregator
         .get_task_config_for(req.task_id()?)
         .await?
-        .ok_or(DapAbort::UnrecognizedTask)?;
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
     let task_config = wrapped_task_config.as_ref();
 
     if let Some(reason) = aggregator.unauthorized_reason(task_config, req).await? {
-        error!("aborted unauthorized collect request: {reason}");
-        return Err(DapAbort::UnauthorizedRequest {
-            detail: reason,
-            task_id: *task_id,
-        }
-        .into());
+        return Err(unauthorized_request(aggregator, req, task_id, reason).into());
     }
 
     let coll_job_req = CollectionReq::get_decoded_with_param(&req.version, req.payload.as_ref())
@@ -278,15 +428,14 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
 
     let agg_param =
         DapAggregationParam::get_decoded_with_param(&task_config.vdaf, &coll_job_req.agg_param)
-            .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+            .map_err(|e| DapAbort::invalid_agg_param(e, *task_id))?;
 
     // Check whether the DAP version in the request matches the task config.
     if task_config.version != req.version {
         return Err(DapAbort::version_mismatch(req.version, task_config.version).into());
     }
 
-    // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
-    // collected batches.
+    // Ensure the batch boundaries are valid.
     check_batch(
         aggregator,
         task_config,
@@ -297,6 +446,14 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     )
     .await?;
 
+    // Ensure the batch doesn't overlap with previously collected batches. Unlike the Helper, the
+    // Leader rejects a batch that was collected before, even if the query is the same.
+    if let Some(batch_sel) = coll_job_req.query.into_batch_sel() {
+        if aggregator.is_batch_overlapping(task_id, &batch_sel).await? {
+            return Err(DapAbort::batch_overlap(task_id, coll_job_req.query).into());
+        }
+    }
+
     let DapResource::CollectionJob(coll_job_id) = &req.resource else {
         return Err(DapAbort::BadRequest("missing collection ID".into()).into());
     };
@@ -310,9 +467,18 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     };
 
     let collect_job_uri = aggregator
-        .init_collect_job(task_id, coll_job_id, batch_sel, agg_param)
+        .init_collect_job(task_id, coll_job_id, batch_sel.clone(), agg_param)
         .await?;
 
+    aggregator.audit_log().on_collection_job(
+        aggregator.host(),
+        task_id,
+        task_config,
+        coll_job_id,
+        &batch_sel,
+        CollectionJobAuditAction::Create,
+    );
+
     metrics.inbound_req_inc(DaphneRequestType::Collect);
     Ok(collect_job_uri)
 }
@@ -322,17 +488,17 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
 async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     task_id: &TaskId,
+    agg_job_id: &AggregationJobId,
     task_config: &DapTaskConfig,
     part_batch_sel: &PartialBatchSelector,
     agg_param: &DapAggregationParam,
-    reports: Vec<Report>,
+    reports: &[Report],
 ) -> Result<u64, DapError> {
     let metrics = aggregator.metrics();
 
     let taskprov = task_config.resolve_taskprove_advertisement()?;
 
     // Prepare AggregationJobInitReq.
-    let agg_job_id = AggregationJobId(thread_rng().gen());
     let (agg_job_state, agg_job_init_req) = task_config
         .produce_agg_job_req(
             aggregator,
@@ -340,7 +506,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
             task_id,
             part_batch_sel,
             agg_param,
-            futures::stream::iter(reports),
+            futures::stream::iter(reports.iter().cloned()),
             metrics,
         )
         .await?;
@@ -364,7 +530,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
             path: &url_path,
             req_media_type: DapMediaType::AggregationJobInitReq,
             resp_media_type: DapMediaType::AggregationJobResp,
-            resource: DapResource::AggregationJob(agg_job_id),
+            resource: DapResource::AggregationJob(*agg_job_id),
             req_data: agg_job_init_req
                 .get_encoded_with_param(&task_config.version)
                 .map_err(DapError::encoding)?,
@@ -390,7 +556,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     // may end up with a batch mismatch. However, this should only happen if there are multiple
     // aggregation jobs in-flight that include the same report.
     let (replayed, collected) = aggregator
-        .try_put_agg_share_span(task_id, task_config, agg_span)
+        .try_put_agg_share_span(task_id, task_config, agg_job_id, agg_span)
         .await
         .into_iter()
         .map(|(_bucket, (result, _report_metadata))| match result {
@@ -467,6 +633,14 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
         checksum: leader_agg_share.checksum,
     };
 
+    let req_data = agg_share_req
+        .get_encoded_with_param(&task_config.version)
+        .map_err(DapError::encoding)?;
+    let request_hash: [u8; 32] = digest(&SHA256, &req_data)
+        .as_ref()
+        .try_into()
+        .map_err(|e| fatal_error!(err = ?e, "unexpected digest length"))?;
+
     let url_path = format!("tasks/{}/aggregate_shares", task_id.to_base64url());
 
     // Send AggregateShareReq and receive AggregateShareResp.
@@ -479,9 +653,7 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
             req_media_type: DapMediaType::AggregateShareReq,
             resp_media_type: DapMediaType::AggregateShare,
             resource: DapResource::Undefined,
-            req_data: agg_share_req
-                .get_encoded_with_param(&task_config.version)
-                .map_err(DapError::encoding)?,
+            req_data,
             method: LeaderHttpRequestMethod::Post,
             taskprov,
         },
@@ -512,14 +684,31 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
         interval,
         encrypted_agg_shares: [leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
     };
+
+    // Mark reports as collected before releasing the aggregate shares to the Collector. If the
+    // collection job is retried after this, then it sends the same aggregate share request, which
+    // is already recorded, and this succeeds.
+    if !aggregator
+        .mark_collected(task_id, &agg_share_req.batch_sel, &request_hash)
+        .await?
+    {
+        return Err(DapAbort::batch_overlap(task_id, &agg_share_req.batch_sel).into());
+    }
+
     aggregator
         .finish_collect_job(task_id, coll_job_id, &collection)
         .await?;
 
-    // Mark reports as collected.
-    aggregator
-        .mark_collected(task_id, &agg_share_req.batch_sel)
-        .await?;
+    aggregator.audit_log().on_collection_job(
+        aggregator.host(),
+        task_id,
+        task_config,
+        coll_job_id,
+        batch_sel,
+        CollectionJobAuditAction::Complete {
+            report_count: collection.report_count,
+        },
+    );
 
     metrics.report_inc_by(ReportStatus::Collected, agg_share_req.report_count);
     Ok(agg_share_req.report_count)
@@ -532,49 +721,88 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
 ///
 /// Collection jobs are processed in order. If a collection job is still pending once processed, it
 /// is pushed to the back of the work queue.
+///
+/// Work items that fail are pushed to the back of the work queue and processed again after a
+/// backoff. After [`WORK_ITEM_MAX_ATTEMPTS`] attempts, or if retrying is futile, they are moved to
+/// the dead-letter queue instead.
 pub async fn process<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     host: &str,
     num_items: usize,
 ) -> Result<DapLeaderProcessTelemetry, DapError> {
     let mut telem = DapLeaderProcessTelemetry::default();
+    let now = aggregator.get_current_time();
 
     tracing::debug!("RUNNING read_work_stream");
 
     let mut agg_jobs = HashMap::new();
     let mut pending_coll_jobs = Vec::new();
+    let mut requeue = Vec::new();
+    let mut dead_letters = Vec::new();
+
     for work_item in aggregator.dequeue_work(num_items).await? {
+        if work_item.retry().not_before > now {
+            requeue.push(work_item);
+            continue;
+        }
+
         match work_item {
             WorkItem::AggregationJob {
                 task_id,
+                agg_job_id,
                 part_batch_sel,
                 agg_param,
                 reports,
+                retry,
             } => {
                 telem.reports_processed += u64::try_from(reports.len()).unwrap();
                 let agg_jobs_per_task: &mut Vec<_> = agg_jobs.entry(task_id).or_default();
                 agg_jobs_per_task.push(async move {
-                    let task_config = aggregator
-                        .get_task_config_for(&task_id)
-                        .await?
-                        .ok_or(DapAbort::UnrecognizedTask)?;
-
-                    if reports.is_empty() {
-                        return Ok(0);
+                    let result = async {
+                        let task_config = aggregator
+                            .get_task_config_for(&task_id)
+                            .await?
+                            .ok_or(DapAbort::UnrecognizedTask { task_id })?;
+
+                        if reports.is_empty() {
+                            return Ok(0);
+                        }
+
+                        tracing::debug!(
+                            "RUNNING run_agg_job FOR TID {task_id} AND {part_batch_sel:?} AND {host}"
+                        );
+                        let aggregated = run_agg_job(
+                            aggregator,
+                            &task_id,
+                            &agg_job_id,
+                            task_config.as_ref(),
+                            &part_batch_sel,
+                            &agg_param,
+                            &reports,
+                        )
+                        .await?;
+                        let rejected = u64::try_from(reports.len())
+                            .unwrap()
+                            .saturating_sub(aggregated);
+                        aggregator
+                            .finish_agg_job(&task_id, &part_batch_sel, aggregated, rejected)
+                            .await?;
+                        Ok(aggregated)
                     }
-
-                    tracing::debug!(
-                        "RUNNING run_agg_job FOR TID {task_id} AND {part_batch_sel:?} AND {host}"
-                    );
-                    run_agg_job(
-                        aggregator,
-                        &task_id,
-                        task_config.as_ref(),
-                        &part_batch_sel,
-                        &agg_param,
-                        reports,
-                    )
-                    .await
+                    .await;
+
+                    // Hand the work item back so that it can be retried.
+                    result.map_err(|e| {
+                        let work_item = WorkItem::AggregationJob {
+                            task_id,
+                            agg_job_id,
+                            part_batch_sel,
+                            agg_param,
+                            reports,
+                            retry,
+                        };
+                        (work_item, e)
+                    })
                 });
             }
             WorkItem::CollectionJob {
@@ -582,63 +810,163 @@ pub async fn process<S: Sync, A: DapLeader<S>>(
                 coll_job_id,
                 batch_sel,
                 agg_param,
+                created_at,
+                retry,
             } => {
                 // Wait for all pending aggregation jobs for this task to complete before
                 // processing the next collection job. This is to prevent a race condition
                 // involving an aggregate share computed during a collection job and any output
                 // shares computed during an aggregation job.
                 if let Some(agg_jobs_per_task) = agg_jobs.get_mut(&task_id) {
-                    telem.reports_aggregated +=
-                        try_join_all(agg_jobs_per_task.drain(0..agg_jobs_per_task.len()))
-                            .await?
-                            .into_iter()
-                            .sum::<u64>();
+                    for result in join_all(agg_jobs_per_task.drain(..)).await {
+                        match result {
+                            Ok(aggregated) => telem.reports_aggregated += aggregated,
+                            Err((work_item, e)) => {
+                                retry_later(work_item, &e, now, &mut requeue, &mut dead_letters);
+                            }
+                        }
+                    }
+                }
+
+                // Postpone the collection job while an aggregation job for reports in the batch is
+                // waiting to be retried, as the aggregate share would not include its reports.
+                // Those taken from the queue by this call are requeued; the Leader is asked about
+                // the others.
+                let postpone = requeue
+                    .iter()
+                    .any(|work_item| work_item.is_agg_job_for_batch(&task_id, &batch_sel))
+                    || aggregator.has_failed_agg_jobs(&task_id, &batch_sel).await?;
+
+                let coll_job = WorkItem::CollectionJob {
+                    task_id,
+                    coll_job_id,
+                    batch_sel,
+                    agg_param,
+                    created_at,
+                    retry,
+                };
+                if postpone {
+                    pending_coll_jobs.push(coll_job);
+                    continue;
                 }
 
-                let task_config = aggregator
-                    .get_task_config_for(&task_id)
-                    .await?
-                    .ok_or(DapAbort::UnrecognizedTask)?;
-
-                tracing::debug!("RUNNING run_collect_job FOR TID {task_id} AND {coll_job_id} AND {batch_sel:?} AND {agg_param:?} AND {host}");
-                let collected = run_coll_job(
-                    aggregator,
-                    &task_id,
-                    task_config.as_ref(),
-                    &coll_job_id,
-                    &batch_sel,
-                    &agg_param,
-                )
-                .await?;
-
-                if collected > 0 {
-                    telem.reports_collected += collected;
-                } else {
-                    pending_coll_jobs.push(WorkItem::CollectionJob {
+                let WorkItem::CollectionJob {
+                    task_id,
+                    coll_job_id,
+                    batch_sel,
+                    agg_param,
+                    created_at,
+                    ..
+                } = &coll_job
+                else {
+                    unreachable!("collection job expected");
+                };
+                let result = async {
+                    let task_config = aggregator
+                        .get_task_config_for(task_id)
+                        .await?
+                        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+
+                    tracing::debug!("RUNNING run_collect_job FOR TID {task_id} AND {coll_job_id} AND {batch_sel:?} AND {agg_param:?} AND {host}");
+                    run_coll_job(
+                        aggregator,
                         task_id,
+                        task_config.as_ref(),
                         coll_job_id,
                         batch_sel,
                         agg_param,
-                    });
+                    )
+                    .await
+                }
+                .await;
+
+                match result {
+                    Ok(0) => pending_coll_jobs.push(coll_job),
+                    Ok(collected) => {
+                        telem.reports_collected += collected;
+                        aggregator
+                            .metrics()
+                            .coll_job_observe_latency(Duration::from_secs(
+                                now.saturating_sub(*created_at),
+                            ));
+                    }
+                    // The Helper won't ever produce the aggregate share, so the Collector needs
+                    // to know the collection job failed.
+                    Err(DapError::Peer(peer)) if !peer.is_retryable() => {
+                        tracing::warn!(%peer, "Helper aborted aggregate share request");
+                        aggregator
+                            .fail_collect_job(task_id, coll_job_id, &peer)
+                            .await?;
+                    }
+                    Err(e) => retry_later(coll_job, &e, now, &mut requeue, &mut dead_letters),
                 }
             }
         }
     }
 
-    for (_task_id, mut agg_jobs_per_task) in agg_jobs {
-        telem.reports_aggregated +=
-            try_join_all(agg_jobs_per_task.drain(0..agg_jobs_per_task.len()))
-                .await?
-                .into_iter()
-                .sum::<u64>();
+    for (_task_id, agg_jobs_per_task) in agg_jobs {
+        for result in join_all(agg_jobs_per_task).await {
+            match result {
+                Ok(aggregated) => telem.reports_aggregated += aggregated,
+                Err((work_item, e)) => {
+                    retry_later(work_item, &e, now, &mut requeue, &mut dead_letters);
+                }
+            }
+        }
     }
 
-    // Put all pending collection jobs back in the queue.
-    aggregator.enqueue_work(pending_coll_jobs).await?;
+    // Put all failed aggregation jobs and pending collection jobs back in the queue. The
+    // aggregation jobs go first, so that they are processed before the collection jobs of their
+    // tasks.
+    requeue.append(&mut pending_coll_jobs);
+    aggregator.enqueue_work(requeue).await?;
+    if !dead_letters.is_empty() {
+        aggregator.put_dead_letters(dead_letters).await?;
+    }
 
     Ok(telem)
 }
 
+/// Record the failed attempt at processing a work item. The work item is either queued to be
+/// retried after a backoff or, if retrying is futile or the maximum number of attempts is reached,
+/// moved to the dead-letter queue.
+pub(super) fn retry_later(
+    mut work_item: WorkItem,
+    error: &DapError,
+    now: Time,
+    requeue: &mut Vec<WorkItem>,
+    dead_letters: &mut Vec<WorkItem>,
+) {
+    // The Helper would abort the same request again.
+    let futile = matches!(error, DapError::Peer(peer) if !peer.is_retryable());
+
+    let task_id = *work_item.task_id();
+    let retry = work_item.retry_mut();
+    retry.attempts += 1;
+    retry.last_error = Some(error.to_string());
+    if futile || retry.attempts >= WORK_ITEM_MAX_ATTEMPTS {
+        tracing::error!(
+            %task_id,
+            attempts = retry.attempts,
+            %error,
+            "moving work item to the dead-letter queue"
+        );
+        dead_letters.push(work_item);
+    } else {
+        let backoff = WORK_ITEM_INITIAL_BACKOFF
+            .saturating_mul(1 << (retry.attempts - 1).min(32))
+            .min(WORK_ITEM_MAX_BACKOFF);
+        retry.not_before = now.saturating_add(backoff);
+        tracing::warn!(
+            %task_id,
+            attempts = retry.attempts,
+            %error,
+            "work item failed, retrying in {backoff}s"
+        );
+        requeue.push(work_item);
+    }
+}
+
 fn check_response_content_type(resp: &DapResponse, expected: DapMediaType) -> Result<(), DapError> {
     if resp.media_type != expected {
         Err(fatal_error!(
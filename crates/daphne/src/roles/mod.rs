@@ -8,14 +8,15 @@ pub mod helper;
 pub mod leader;
 
 use crate::{
+    audit_log::TaskprovAuditAction,
     constants::DapMediaType,
     messages::{Base64Encode, Query, TaskId, Time},
     taskprov, DapAbort, DapError, DapQueryConfig, DapRequest, DapTaskConfig,
 };
-use tracing::warn;
+use tracing::{error, warn};
 
 pub use aggregator::{DapAggregator, DapReportInitializer};
-pub use helper::DapHelper;
+pub use helper::{AggregationJobRecord, DapHelper};
 pub use leader::{DapAuthorizedSender, DapLeader};
 
 async fn check_batch<S: Sync>(
@@ -30,10 +31,9 @@ async fn check_batch<S: Sync>(
 
     // Check that the aggregation parameter is suitable for the given VDAF.
     if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::InvalidMessage {
-            detail: "invalid aggregation parameter".into(),
-            task_id: Some(*task_id),
+        return Err(DapAbort::InvalidAggregationParameter {
+            detail: "The aggregation parameter is not valid for the task's VDAF.".into(),
+            task_id: *task_id,
         }
         .into());
     }
@@ -83,13 +83,6 @@ async fn check_batch<S: Sync>(
         _ => return Err(DapAbort::query_mismatch(task_id, &task_config.query, query).into()),
     };
 
-    // Check that the batch does not overlap with any previously collected batch.
-    if let Some(batch_sel) = query.into_batch_sel() {
-        if agg.is_batch_overlapping(task_id, &batch_sel).await? {
-            return Err(DapAbort::batch_overlap(task_id, query).into());
-        }
-    }
-
     Ok(())
 }
 
@@ -137,38 +130,81 @@ async fn resolve_taskprov<S: Sync>(
 
     // This is the opt-in / opt-out decision point.
     if let Some(reason) = agg.taskprov_opt_out_reason(&task_config)? {
+        agg.audit_log().on_taskprov(
+            agg.host(),
+            task_id,
+            &task_config,
+            TaskprovAuditAction::OptOut { reason: &reason },
+        );
         return Err(DapError::Abort(DapAbort::InvalidTask {
             detail: reason,
             task_id: *task_id,
         }));
     }
 
+    agg.audit_log().on_taskprov(
+        agg.host(),
+        task_id,
+        &task_config,
+        TaskprovAuditAction::OptIn,
+    );
     agg.taskprov_put(req, task_config).await?;
     Ok(())
 }
 
+/// Record an unauthorized request in the audit log and return the abort to respond with.
+fn unauthorized_request<S: Sync>(
+    agg: &impl DapAggregator<S>,
+    req: &DapRequest<S>,
+    task_id: &TaskId,
+    reason: String,
+) -> DapAbort {
+    error!("aborted unauthorized request: {reason}");
+    agg.audit_log().on_unauthorized_request(
+        agg.host(),
+        task_id,
+        req.version,
+        req.media_type,
+        &reason,
+    );
+    DapAbort::UnauthorizedRequest {
+        detail: reason,
+        task_id: *task_id,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{aggregator, helper, leader, DapAuthorizedSender, DapLeader};
+    use super::{
+        aggregator, helper, leader, DapAggregator, DapAuthorizedSender, DapHelper, DapLeader,
+    };
     use crate::{
         assert_metrics_include, async_test_version, async_test_versions,
         auth::BearerToken,
+        client::{DapClient, DapHpkeConfigSigningKey},
+        collector::{DapCollectionPoll, DapCollector, DapPollPolicy},
         constants::DapMediaType,
+        fatal_error,
         hpke::{HpkeKemId, HpkeProvider, HpkeReceiverConfig},
         messages::{
-            AggregateShareReq, AggregationJobId, AggregationJobInitReq, AggregationJobResp,
-            Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
-            Extension, HpkeCiphertext, Interval, PartialBatchSelector, Query, Report, TaskId, Time,
-            TransitionFailure, TransitionVar,
+            encode_base64url, AggregateShareReq, AggregationJobContinueReq, AggregationJobId,
+            AggregationJobInitReq, AggregationJobResp, Base64Encode, BatchId, BatchSelector,
+            Collection, CollectionJobId, CollectionReq, Extension, HpkeCiphertext, Interval,
+            PartialBatchSelector, Query, Report, TaskId, Time, TransitionFailure, TransitionVar,
         },
-        roles::leader::WorkItem,
+        privacy_pass::test_utils::TestIssuer,
+        roles::leader::{WorkItem, WorkItemRetry},
         testing::InMemoryAggregator,
+        transport::{
+            DapHttpMethod, DapHttpRequest, DapHttpResponse, DapHttpTransport, DapRetryPolicy,
+        },
         vdaf::{mastic::MasticWeight, MasticWeightConfig, Prio3Config, VdafConfig},
-        DapAbort, DapAggregationJobState, DapAggregationParam, DapBatchBucket, DapCollectionJob,
-        DapError, DapGlobalConfig, DapMeasurement, DapQueryConfig, DapRequest, DapResource,
-        DapTaskConfig, DapTaskParameters, DapVersion,
+        DapAbort, DapAggregateResult, DapAggregationJobState, DapAggregationParam, DapBatchBucket,
+        DapCollectionJob, DapError, DapGlobalConfig, DapMeasurement, DapQueryConfig, DapRequest,
+        DapResource, DapTaskConfig, DapTaskParameters, DapVersion,
     };
     use assert_matches::assert_matches;
+    use async_trait::async_trait;
     use matchit::Router;
     use prio::{
         codec::{Decode, Encode, ParameterizedEncode},
@@ -176,7 +212,19 @@ mod test {
         vdaf::poplar1::Poplar1AggregationParam,
     };
     use rand::{thread_rng, Rng};
-    use std::{collections::HashMap, sync::Arc, time::SystemTime, vec};
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::SystemTime,
+        vec,
+    };
     use url::Url;
 
     pub(super) struct TestData {
@@ -244,6 +292,7 @@ mod test {
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
                     method: Default::default(),
+                    privacy_pass: None,
                 },
             );
             tasks.insert(
@@ -262,6 +311,7 @@ mod test {
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
                     method: Default::default(),
+                    privacy_pass: None,
                 },
             );
             tasks.insert(
@@ -278,6 +328,7 @@ mod test {
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
                     method: Default::default(),
+                    privacy_pass: None,
                 },
             );
 
@@ -299,6 +350,7 @@ mod test {
                     vdaf: mastic,
                     vdaf_verify_key: mastic.gen_verify_key(),
                     method: Default::default(),
+                    privacy_pass: None,
                 },
             );
 
@@ -384,6 +436,7 @@ mod test {
                 leader,
                 helper,
                 collector_token: self.collector_token,
+                collector_hpke_receiver_config: self.collector_hpke_receiver_config,
                 taskprov_collector_token: self.taskprov_collector_token,
                 time_interval_task_id: self.time_interval_task_id,
                 fixed_size_task_id: self.fixed_size_task_id,
@@ -400,6 +453,7 @@ mod test {
         leader: Arc<InMemoryAggregator>,
         helper: Arc<InMemoryAggregator>,
         collector_token: BearerToken,
+        collector_hpke_receiver_config: HpkeReceiverConfig,
         taskprov_collector_token: BearerToken,
         time_interval_task_id: TaskId,
         fixed_size_task_id: TaskId,
@@ -718,6 +772,7 @@ mod test {
         );
 
         assert_eq!(t.helper.audit_log.invocations(), 0);
+        assert_eq!(t.helper.audit_log.unauthorized_requests(), 2);
     }
 
     async_test_versions! { handle_agg_job_init_req_unauthorized_request }
@@ -737,7 +792,7 @@ mod test {
 
         assert_matches!(
             aggregator::handle_hpke_config_req(&*t.leader, &req, Some(task_id)).await,
-            Err(DapError::Abort(DapAbort::UnrecognizedTask))
+            Err(DapError::Abort(DapAbort::UnrecognizedTask { .. }))
         );
     }
 
@@ -782,6 +837,9 @@ mod test {
             helper::handle_agg_share_req(&*t.helper, &req).await,
             Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
         );
+
+        assert_eq!(t.helper.audit_log.unauthorized_requests(), 2);
+        assert_eq!(t.helper.audit_log.agg_shares(), 0);
     }
 
     async_test_versions! { handle_agg_share_req_unauthorized_request }
@@ -882,6 +940,9 @@ mod test {
             leader::handle_coll_job_req(&*t.leader, &req).await,
             Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
         );
+
+        assert_eq!(t.leader.audit_log.unauthorized_requests(), 2);
+        assert_eq!(t.leader.audit_log.coll_jobs(), (0, 0));
     }
 
     async_test_versions! { handle_coll_job_req_unauthorized_request }
@@ -915,6 +976,94 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_failure_hpke_decrypt_error }
 
+    async fn handle_agg_job_req_failure_batch_saturated(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.fixed_size_task_id;
+
+        // The maximum batch size of the task is 2.
+        let mut reports = Vec::new();
+        for _ in 0..3 {
+            reports.push(t.gen_test_report(task_id).await);
+        }
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+            .await;
+
+        let agg_job_resp = AggregationJobResp::get_decoded(
+            &helper::handle_agg_job_req(&*t.helper, &req)
+                .await
+                .unwrap()
+                .payload,
+        )
+        .unwrap();
+
+        // Expect the report past the maximum batch size to be rejected.
+        assert_matches!(agg_job_resp.transitions[0].var, TransitionVar::Continued(_));
+        assert_matches!(agg_job_resp.transitions[1].var, TransitionVar::Continued(_));
+        assert_matches!(
+            agg_job_resp.transitions[2].var,
+            TransitionVar::Failed(TransitionFailure::BatchSaturated)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_failure_batch_saturated }
+
+    async fn handle_agg_job_cont_req(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("missing aggregation job ID");
+        };
+        helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+
+        let cont_req = |step| {
+            t.leader_authorized_req(
+                task_id,
+                &task_config,
+                Some(&agg_job_id),
+                DapMediaType::AggregationJobContinueReq,
+                AggregationJobContinueReq {
+                    step,
+                    prep_continues: Vec::new(),
+                },
+            )
+        };
+
+        // The aggregation job finished after initialization.
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &cont_req(1).await)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::StepMismatch { .. })
+        );
+
+        // Step 0 is the initialization step.
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &cont_req(0).await)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::InvalidMessage { .. })
+        );
+
+        // Unrecognized aggregation job.
+        let mut req = cont_req(1).await;
+        req.resource = DapResource::AggregationJob(AggregationJobId(thread_rng().gen()));
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::UnrecognizedAggregationJob { .. })
+        );
+    }
+
+    async_test_versions! { handle_agg_job_cont_req }
+
     async fn handle_agg_job_req_transition_continue(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
@@ -940,6 +1089,165 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_transition_continue }
 
+    async fn handle_agg_job_req_retransmitted(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        let resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+
+        // Expect the same response to a retransmission, rather than the report being rejected
+        // as a replay.
+        let retransmitted_resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+        assert_eq!(retransmitted_resp.payload, resp.payload);
+        let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload).unwrap();
+        assert_matches!(agg_job_resp.transitions[0].var, TransitionVar::Continued(_));
+        assert_metrics_include!(t.helper_registry, {
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
+        });
+
+        // Expect an abort if the aggregation job ID is reused for a different request.
+        let report = t.gen_test_report(task_id).await;
+        let (_, mut conflicting_req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        conflicting_req.resource = req.resource.clone();
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &conflicting_req).await,
+            Err(DapError::Abort(DapAbort::InvalidMessage { .. }))
+        );
+
+        // Expect the conflicting request's report to not have been aggregated.
+        let task_config = t
+            .helper
+            .get_task_config_for(task_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let bucket = DapBatchBucket::TimeInterval {
+            batch_window: task_config.quantized_time_lower_bound(t.now),
+        };
+        let agg_share = t
+            .helper
+            .agg_store
+            .lock()
+            .unwrap()
+            .for_bucket(task_id, &bucket, &DapAggregationParam::Empty)
+            .unwrap()
+            .agg_share
+            .clone();
+        assert_eq!(agg_share.report_count, 1);
+    }
+
+    async_test_versions! { handle_agg_job_req_retransmitted }
+
+    async fn handle_agg_job_req_interrupted(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        let resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+
+        // Forget the response, as if the Helper had failed to store it after aggregating the
+        // reports.
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("missing aggregation job ID");
+        };
+        let mut record = t
+            .helper
+            .get_agg_job_record(task_id, &agg_job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        record.agg_job_resp = None;
+        t.helper
+            .put_agg_job_record(task_id, &agg_job_id, &record)
+            .await
+            .unwrap();
+
+        // Expect the retransmission to finish the aggregation job without rejecting the report
+        // as a replay or aggregating it twice.
+        let retransmitted_resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+        assert_eq!(retransmitted_resp.payload, resp.payload);
+        let task_config = t
+            .helper
+            .get_task_config_for(task_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let bucket = DapBatchBucket::TimeInterval {
+            batch_window: task_config.quantized_time_lower_bound(t.now),
+        };
+        let agg_share = t
+            .helper
+            .agg_store
+            .lock()
+            .unwrap()
+            .for_bucket(task_id, &bucket, &DapAggregationParam::Empty)
+            .unwrap()
+            .agg_share
+            .clone();
+        assert_eq!(agg_share.report_count, 1);
+        assert_eq!(
+            t.helper
+                .get_agg_job_record(task_id, &agg_job_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .agg_job_resp,
+            Some(resp.payload)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_interrupted }
+
+    async fn handle_agg_job_req_interrupted_batch_almost_full(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.fixed_size_task_id;
+
+        // The maximum batch size of the task is 2, so the aggregation job fills the batch.
+        let mut reports = Vec::new();
+        for _ in 0..2 {
+            reports.push(t.gen_test_report(task_id).await);
+        }
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+            .await;
+        let resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+        let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload).unwrap();
+        assert_matches!(agg_job_resp.transitions[0].var, TransitionVar::Continued(_));
+        assert_matches!(agg_job_resp.transitions[1].var, TransitionVar::Continued(_));
+
+        // Forget the response, as if the Helper had failed to store it after aggregating the
+        // reports.
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("missing aggregation job ID");
+        };
+        let mut record = t
+            .helper
+            .get_agg_job_record(task_id, &agg_job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        record.agg_job_resp = None;
+        t.helper
+            .put_agg_job_record(task_id, &agg_job_id, &record)
+            .await
+            .unwrap();
+
+        // Expect the reports this job merged not to count against the batch when it is resumed.
+        let retransmitted_resp = helper::handle_agg_job_req(&*t.helper, &req).await.unwrap();
+        assert_eq!(retransmitted_resp.payload, resp.payload);
+    }
+
+    async_test_versions! { handle_agg_job_req_interrupted_batch_almost_full }
+
     async fn handle_agg_job_req_failure_report_replayed(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
@@ -1048,7 +1356,7 @@ mod test {
         // Expect failure due to invalid task ID in report.
         assert_matches!(
             leader::handle_upload_req(&*t.leader, &req).await,
-            Err(DapError::Abort(DapAbort::UnrecognizedTask))
+            Err(DapError::Abort(DapAbort::UnrecognizedTask { .. }))
         );
     }
 
@@ -1074,7 +1382,7 @@ mod test {
             leader::handle_upload_req(&*t.leader, &req)
                 .await
                 .unwrap_err(),
-            DapError::Abort(DapAbort::ReportTooLate)
+            DapError::Abort(DapAbort::ReportTooLate { .. })
         );
     }
 
@@ -1101,9 +1409,11 @@ mod test {
         assert_eq!(work_items.len(), 1);
         let WorkItem::AggregationJob {
             task_id: returned_task_id,
+            agg_job_id: _,
             part_batch_sel: _,
             agg_param: _,
             reports,
+            retry: _,
         } = work_items.pop().unwrap()
         else {
             panic!("unexpected work item type");
@@ -1119,6 +1429,8 @@ mod test {
             coll_job_id: _,
             batch_sel: _,
             agg_param: _,
+            created_at: _,
+            retry: _,
         } = work_items.pop().unwrap()
         else {
             panic!("unexpected work item type");
@@ -1166,6 +1478,8 @@ mod test {
             coll_job_id,
             batch_sel: _,
             agg_param: _,
+            created_at: _,
+            retry: _,
         } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
         else {
             panic!("unexpected work item type")
@@ -1359,6 +1673,134 @@ mod test {
 
     async_test_versions! { handle_coll_job_req_fail_overlapping_batch_interval }
 
+    async fn handle_coll_job_req_fail_partially_overlapping_batch_interval(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report.clone(), task_id).await;
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        // The collected buckets are forgotten, but not the collected interval.
+        t.leader.agg_store.lock().unwrap().delete_task(task_id);
+
+        // Request an interval that covers the collected one and the one before. Expect failure
+        // due to overlapping batch.
+        let start = task_config.quantized_time_lower_bound(t.now) - task_config.time_precision;
+        let query = Query::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: 2 * task_config.time_precision,
+            },
+        };
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchOverlap { .. })
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_fail_partially_overlapping_batch_interval }
+
+    async fn mark_collected_rejects_overlapping_batch(version: DapVersion) {
+        let t = Test::new(version);
+
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.helper.unchecked_get_task_config(task_id).await;
+        let time_precision = task_config.time_precision;
+        let start = task_config.quantized_time_lower_bound(t.now);
+        let batch_sel = |start, windows| BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: windows * time_precision,
+            },
+        };
+
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel(start, 2), &[1; 32])
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .is_batch_overlapping(task_id, &batch_sel(start - time_precision, 2))
+            .await
+            .unwrap());
+        assert!(!t
+            .helper
+            .mark_collected(task_id, &batch_sel(start + time_precision, 2), &[2; 32])
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel(start + 2 * time_precision, 1), &[3; 32])
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel(start - time_precision, 1), &[4; 32])
+            .await
+            .unwrap());
+
+        // Marking the batch again for the same request, i.e., on retransmission, succeeds.
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel(start, 2), &[1; 32])
+            .await
+            .unwrap());
+
+        // Any other query for the same batch is rejected.
+        assert!(!t
+            .helper
+            .mark_collected(task_id, &batch_sel(start, 2), &[5; 32])
+            .await
+            .unwrap());
+
+        // A fixed-size batch may only be collected once.
+        let task_id = &t.fixed_size_task_id;
+        let batch_sel = BatchSelector::FixedSizeByBatchId {
+            batch_id: BatchId(thread_rng().gen()),
+        };
+        assert!(!t
+            .helper
+            .is_batch_overlapping(task_id, &batch_sel)
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel, &[6; 32])
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .is_batch_overlapping(task_id, &batch_sel)
+            .await
+            .unwrap());
+        assert!(t
+            .helper
+            .mark_collected(task_id, &batch_sel, &[6; 32])
+            .await
+            .unwrap());
+        assert!(!t
+            .helper
+            .mark_collected(task_id, &batch_sel, &[7; 32])
+            .await
+            .unwrap());
+    }
+
+    async_test_versions! { mark_collected_rejects_overlapping_batch }
+
     async fn handle_coll_job_req_fail_unrecongized_batch(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.fixed_size_task_id;
@@ -1409,6 +1851,8 @@ mod test {
             coll_job_id: leader_collect_id,
             batch_sel: leader_batch_sel,
             agg_param: leader_agg_param,
+            created_at: _,
+            retry: _,
         } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
         else {
             panic!("unexpected work item type");
@@ -1492,6 +1936,30 @@ mod test {
 
     async_test_versions! { handle_coll_job_req_invalid_query }
 
+    async fn handle_coll_job_req_invalid_agg_param(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: task_config.query_for_current_batch_window(t.now),
+                // Prio3 doesn't take an aggregation parameter.
+                agg_param: b"invalid".to_vec(),
+            },
+        );
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::InvalidAggregationParameter { task_id: id, .. }) if id == *task_id
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_invalid_agg_param }
+
     async fn handle_upload_req(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
@@ -1506,6 +1974,55 @@ mod test {
 
     async_test_versions! { handle_upload_req }
 
+    async fn handle_upload_req_privacy_pass(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let issuer = TestIssuer::default();
+        t.leader
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .privacy_pass = Some(issuer.config().clone());
+
+        // Expect failure if the token is missing.
+        let report = t.gen_test_report(task_id).await;
+        let mut req = t.gen_test_upload_req(report, task_id).await;
+        assert_matches!(
+            leader::handle_upload_req(&*t.leader, &req).await,
+            Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
+        );
+
+        // Expect failure if the token is invalid.
+        let mut bad_token = issuer.issue();
+        bad_token.authenticator[0] ^= 1;
+        req.privacy_pass_token = Some(bad_token.to_base64url());
+        assert_matches!(
+            leader::handle_upload_req(&*t.leader, &req).await,
+            Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
+        );
+
+        // Expect success if the token is valid.
+        let token = issuer.issue().to_base64url();
+        req.privacy_pass_token = Some(token.clone());
+        leader::handle_upload_req(&*t.leader, &req)
+            .await
+            .expect("upload failed unexpectedly");
+
+        // Expect failure if the token is redeemed again.
+        let report = t.gen_test_report(task_id).await;
+        let mut req = t.gen_test_upload_req(report, task_id).await;
+        req.privacy_pass_token = Some(token);
+        assert_matches!(
+            leader::handle_upload_req(&*t.leader, &req).await,
+            Err(DapError::Abort(DapAbort::UnauthorizedRequest { detail, .. }))
+                if detail == "Privacy Pass token was already redeemed"
+        );
+    }
+
+    async_test_versions! { handle_upload_req_privacy_pass }
+
     async fn e2e_time_interval(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
@@ -1541,7 +2058,10 @@ mod test {
         assert_metrics_include!(t.leader_registry, {
             r#"report_counter{env="test_leader",host="leader.com",status="aggregated"}"#: 1,
             r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 1,
+            r#"collection_job_latency_seconds_count{env="test_leader",host="leader.com"}"#: 1,
         });
+        assert_eq!(t.leader.audit_log.coll_jobs(), (1, 1));
+        assert_eq!(t.helper.audit_log.agg_shares(), 1);
     }
 
     async_test_versions! { e2e_time_interval }
@@ -1577,23 +2097,854 @@ mod test {
         assert_metrics_include!(t.leader_registry, {
             r#"report_counter{env="test_leader",host="leader.com",status="aggregated"}"#: 1,
             r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 1,
+            r#"collection_job_latency_seconds_count{env="test_leader",host="leader.com"}"#: 1,
         });
     }
 
     async_test_versions! { e2e_fixed_size }
 
-    async fn e2e_taskprov(
-        version: DapVersion,
-        vdaf_config: VdafConfig,
-        test_measurement: DapMeasurement,
-    ) {
+    async fn fixed_size_batch_lifecycle(version: DapVersion) {
         let t = Test::new(version);
+        let task_id = &t.fixed_size_task_id;
 
-        let (task_config, task_id, taskprov_advertisement) = DapTaskParameters {
-            version,
-            min_batch_size: 1,
-            query: DapQueryConfig::FixedSize {
-                max_batch_size: Some(2),
+        // The maximum batch size of the task is 2, so the first two reports fill the first batch
+        // and are aggregated right away. The Helper rejects the second report.
+        for i in 0..3 {
+            let mut report = t.gen_test_report(task_id).await;
+            if i == 1 {
+                report.encrypted_input_shares[1].payload[0] ^= 0xff;
+            }
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
+        }
+        let first_batch_id = {
+            let leader_state = t.leader.leader_state_store.lock().unwrap();
+            let Some(WorkItem::AggregationJob {
+                part_batch_sel: PartialBatchSelector::FixedSizeByBatchId { batch_id },
+                ..
+            }) = leader_state.work_queue().front()
+            else {
+                panic!("expected an aggregation job");
+            };
+            *batch_id
+        };
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        // The first batch is not sealed, as only one of its reports was aggregated. It takes the
+        // next report in place of the rejected one.
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        let batch_sel = BatchSelector::FixedSizeByBatchId {
+            batch_id: first_batch_id,
+        };
+        assert_eq!(
+            t.leader
+                .get_agg_share(task_id, &batch_sel)
+                .await
+                .unwrap()
+                .report_count,
+            2
+        );
+
+        // Each batch is handed out once, starting with the oldest sealed batch. The second batch
+        // is sealed early, as it has reached the minimum batch size.
+        assert_eq!(
+            t.leader.current_batch(task_id).await.unwrap(),
+            first_batch_id
+        );
+        let second_batch_id = t.leader.current_batch(task_id).await.unwrap();
+        assert_ne!(second_batch_id, first_batch_id);
+        assert!(t.leader.current_batch(task_id).await.is_err());
+
+        let query = Query::FixedSizeByBatchId {
+            batch_id: first_batch_id,
+        };
+        leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, task_id).await)
+            .await
+            .unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 2,
+        });
+    }
+
+    async_test_versions! { fixed_size_batch_lifecycle }
+
+    async fn e2e_helper_aborts_collection(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // The Helper requires more reports than the Leader, so it aborts the aggregate share
+        // request.
+        t.helper
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .min_batch_size = 2;
+
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        let DapCollectionJob::Failed(peer) = t
+            .leader
+            .poll_collect_job(task_id, req.collection_job_id().unwrap())
+            .await
+            .unwrap()
+        else {
+            panic!("expected the collection job to fail");
+        };
+        assert_eq!(peer.status, 400);
+        assert_eq!(peer.typ.as_deref(), Some("invalidBatchSize"));
+        assert_eq!(peer.task_id.as_ref(), Some(task_id));
+        assert!(!peer.is_retryable());
+        assert_metrics_include!(t.leader_registry, {
+            r#"peer_abort_counter{env="test_leader",host="leader.com",type="invalidBatchSize"}"#: 1,
+        });
+        assert_eq!(t.leader.audit_log.coll_jobs(), (1, 0));
+    }
+
+    async_test_versions! { e2e_helper_aborts_collection }
+
+    async fn e2e_coll_job_retried_after_batch_collected(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        let DapCollectionJob::Done(collection) = t
+            .leader
+            .poll_collect_job(task_id, req.collection_job_id().unwrap())
+            .await
+            .unwrap()
+        else {
+            panic!("expected the collection job to be done");
+        };
+
+        // Process the collection job again, as if the Leader failed to complete it after both
+        // Aggregators marked the batch as collected. The Helper recognizes the batch and responds
+        // again.
+        let coll_job_id = CollectionJobId(thread_rng().gen());
+        t.leader
+            .leader_state_store
+            .lock()
+            .unwrap()
+            .init_collect_job(
+                task_id,
+                &task_config,
+                &coll_job_id,
+                query.into_batch_sel().unwrap(),
+                DapAggregationParam::Empty,
+                t.now,
+            )
+            .unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        assert_matches!(
+            t.leader.poll_collect_job(task_id, &coll_job_id).await.unwrap(),
+            DapCollectionJob::Done(retried) if retried.report_count == collection.report_count
+        );
+    }
+
+    async_test_versions! { e2e_coll_job_retried_after_batch_collected }
+
+    async fn e2e_dead_letter_replay(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // The Helper doesn't know the task, so it aborts the aggregation job.
+        let helper_task_config = t.helper.tasks.lock().unwrap().remove(task_id).unwrap();
+
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        let queued_agg_job_id = {
+            let leader_state = t.leader.leader_state_store.lock().unwrap();
+            let Some(WorkItem::AggregationJob { agg_job_id, .. }) =
+                leader_state.work_queue().front()
+            else {
+                panic!("expected an aggregation job");
+            };
+            *agg_job_id
+        };
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        // Retrying is futile, so the aggregation job is moved to the dead-letter queue right away.
+        // It keeps its ID, so that the Helper recognizes it when it is replayed.
+        let dead_letter_id = {
+            let leader_state = t.leader.leader_state_store.lock().unwrap();
+            let dead_letters = leader_state.dead_letters().collect::<Vec<_>>();
+            assert_eq!(dead_letters.len(), 1);
+            let (id, work_item) = dead_letters[0];
+            assert_matches!(
+                work_item,
+                WorkItem::AggregationJob { agg_job_id, .. } if *agg_job_id == queued_agg_job_id
+            );
+            assert_eq!(work_item.retry().attempts, 1);
+            assert!(work_item
+                .retry()
+                .last_error
+                .as_ref()
+                .unwrap()
+                .contains("unrecognizedTask"));
+            id
+        };
+
+        t.helper
+            .tasks
+            .lock()
+            .unwrap()
+            .insert(*task_id, helper_task_config);
+        assert!(t
+            .leader
+            .leader_state_store
+            .lock()
+            .unwrap()
+            .replay_dead_letter(dead_letter_id));
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        assert_matches!(
+            t.leader
+                .poll_collect_job(task_id, req.collection_job_id().unwrap())
+                .await
+                .unwrap(),
+            DapCollectionJob::Done(..)
+        );
+        assert_eq!(
+            t.leader
+                .leader_state_store
+                .lock()
+                .unwrap()
+                .dead_letters()
+                .count(),
+            0
+        );
+    }
+
+    async_test_versions! { e2e_dead_letter_replay }
+
+    async fn e2e_coll_job_waits_for_failed_agg_job(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Run the aggregation job only, so that the batch is ready to be collected.
+        leader::process(&*t.leader, "leader.com", 1).await.unwrap();
+
+        // Queue a failed aggregation job for the same task behind the collection job, so that
+        // they aren't processed in the same run.
+        let failed_report = t.gen_test_report(task_id).await;
+        t.leader
+            .leader_state_store
+            .lock()
+            .unwrap()
+            .work_queue_mut()
+            .push_back(WorkItem::AggregationJob {
+                task_id: *task_id,
+                agg_job_id: AggregationJobId(thread_rng().gen()),
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                agg_param: DapAggregationParam::Empty,
+                reports: vec![failed_report],
+                retry: WorkItemRetry {
+                    attempts: 1,
+                    last_error: Some("connection reset".into()),
+                    not_before: t.now + 3600,
+                },
+            });
+
+        leader::process(&*t.leader, "leader.com", 1).await.unwrap();
+        assert_eq!(
+            t.leader
+                .poll_collect_job(task_id, req.collection_job_id().unwrap())
+                .await
+                .unwrap(),
+            DapCollectionJob::Pending
+        );
+
+        // Once the aggregation job is gone, the collection job is completed.
+        t.leader
+            .leader_state_store
+            .lock()
+            .unwrap()
+            .work_queue_mut()
+            .retain(|work_item| !matches!(work_item, WorkItem::AggregationJob { .. }));
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        assert_matches!(
+            t.leader
+                .poll_collect_job(task_id, req.collection_job_id().unwrap())
+                .await
+                .unwrap(),
+            DapCollectionJob::Done(..)
+        );
+    }
+
+    async_test_versions! { e2e_coll_job_waits_for_failed_agg_job }
+
+    async fn e2e_coll_job_ignores_other_batches_and_dead_letters(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let Query::TimeInterval { batch_interval } = query else {
+            panic!("time interval query expected");
+        };
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Run the aggregation job only, so that the batch is ready to be collected.
+        leader::process(&*t.leader, "leader.com", 1).await.unwrap();
+
+        let failed_agg_job = |report| WorkItem::AggregationJob {
+            task_id: *task_id,
+            agg_job_id: AggregationJobId(thread_rng().gen()),
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            agg_param: DapAggregationParam::Empty,
+            reports: vec![report],
+            retry: WorkItemRetry {
+                attempts: 1,
+                last_error: Some("connection reset".into()),
+                not_before: t.now + 3600,
+            },
+        };
+
+        // An aggregation job for a report in a later batch is waiting to be retried.
+        let mut later_report = t.gen_test_report(task_id).await;
+        later_report.report_metadata.time = batch_interval.end();
+        t.leader
+            .leader_state_store
+            .lock()
+            .unwrap()
+            .work_queue_mut()
+            .push_back(failed_agg_job(later_report));
+
+        // An aggregation job for a report in the batch was given up on.
+        let dead_report = t.gen_test_report(task_id).await;
+        t.leader
+            .put_dead_letters(vec![failed_agg_job(dead_report)])
+            .await
+            .unwrap();
+
+        // Neither holds up the collection job.
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        assert_matches!(
+            t.leader
+                .poll_collect_job(task_id, req.collection_job_id().unwrap())
+                .await
+                .unwrap(),
+            DapCollectionJob::Done(collection) if collection.report_count == 1
+        );
+    }
+
+    async_test_versions! { e2e_coll_job_ignores_other_batches_and_dead_letters }
+
+    #[test]
+    fn retry_later_backs_off_then_gives_up() {
+        let now = 1_000_000;
+        let mut work_item = WorkItem::CollectionJob {
+            task_id: TaskId([1; 32]),
+            coll_job_id: CollectionJobId([2; 16]),
+            batch_sel: BatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId([3; 32]),
+            },
+            agg_param: DapAggregationParam::Empty,
+            created_at: now,
+            retry: Default::default(),
+        };
+
+        // Transient errors are retried with exponential backoff.
+        for (attempts, backoff) in [(1, 10), (2, 20), (3, 40), (4, 80)] {
+            let (mut requeue, mut dead_letters) = (Vec::new(), Vec::new());
+            leader::retry_later(
+                work_item,
+                &fatal_error!(err = "oops"),
+                now,
+                &mut requeue,
+                &mut dead_letters,
+            );
+            assert!(dead_letters.is_empty());
+            work_item = requeue.pop().unwrap();
+            assert_eq!(work_item.retry().attempts, attempts);
+            assert_eq!(work_item.retry().not_before, now + backoff);
+        }
+
+        // The last attempt moves the work item to the dead-letter queue.
+        let (mut requeue, mut dead_letters) = (Vec::new(), Vec::new());
+        leader::retry_later(
+            work_item,
+            &fatal_error!(err = "oops"),
+            now,
+            &mut requeue,
+            &mut dead_letters,
+        );
+        assert!(requeue.is_empty());
+        let work_item = dead_letters.pop().unwrap();
+        assert_eq!(work_item.retry().attempts, leader::WORK_ITEM_MAX_ATTEMPTS);
+        assert!(work_item
+            .retry()
+            .last_error
+            .as_ref()
+            .unwrap()
+            .contains("oops"));
+    }
+
+    /// Transport that dispatches the requests of a Client or Collector directly to the Leader or
+    /// Helper. Sleeping lets the Leader process its work queue.
+    struct InMemoryTransport {
+        leader: Arc<InMemoryAggregator>,
+        helper: Arc<InMemoryAggregator>,
+        sleeps: Mutex<Vec<std::time::Duration>>,
+
+        /// Key with which the Aggregators sign their HPKE configurations.
+        hpke_config_signer: EcdsaKeyPair,
+
+        /// If set, then the Leader responds to uploads with "503 Service Unavailable".
+        leader_unavailable: AtomicBool,
+    }
+
+    impl InMemoryTransport {
+        fn new(t: &Test) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            Self {
+                leader: Arc::clone(&t.leader),
+                helper: Arc::clone(&t.helper),
+                sleeps: Mutex::default(),
+                hpke_config_signer: EcdsaKeyPair::from_pkcs8(
+                    &ECDSA_P256_SHA256_ASN1_SIGNING,
+                    pkcs8.as_ref(),
+                    &rng,
+                )
+                .unwrap(),
+                leader_unavailable: AtomicBool::new(false),
+            }
+        }
+
+        fn signing_key(&self) -> DapHpkeConfigSigningKey {
+            DapHpkeConfigSigningKey::from_sec1(
+                self.hpke_config_signer.public_key().as_ref().to_vec(),
+            )
+        }
+    }
+
+    #[async_trait]
+    impl DapHttpTransport for InMemoryTransport {
+        async fn send(&self, req: DapHttpRequest) -> Result<DapHttpResponse, DapError> {
+            let aggregator = match req.url.host_str() {
+                Some("leader.com") => &self.leader,
+                Some("helper.org") => &self.helper,
+                host => panic!("unexpected host {host:?}"),
+            };
+
+            // The method of each route may depend on the version.
+            let mut router = Router::<fn(DapVersion) -> DapHttpMethod>::new();
+            router
+                .insert("/:version/hpke_config", |_| DapHttpMethod::Get)
+                .unwrap();
+            router
+                .insert(
+                    "/:version/tasks/:task_id/reports",
+                    DapHttpMethod::for_upload,
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/:version/tasks/:task_id/collection_jobs/:coll_job_id",
+                    |_| DapHttpMethod::Put,
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/:version/collect/task/:task_id/req/:coll_job_id",
+                    DapHttpMethod::for_collection_poll,
+                )
+                .unwrap();
+            let path = req.url.path().to_string();
+            let url_match = router.at(&path).unwrap();
+
+            let Some(task_id) = url_match.params.get("task_id") else {
+                assert_eq!(req.method, DapHttpMethod::Get);
+                let task_id = req
+                    .url
+                    .query_pairs()
+                    .find(|(name, _)| name == "task_id")
+                    .map(|(_, task_id)| TaskId::try_from_base64url(task_id).unwrap())
+                    .unwrap();
+                let task_config = aggregator.unchecked_get_task_config(&task_id).await;
+                let resp = aggregator::handle_hpke_config_req(
+                    &**aggregator,
+                    &DapRequest::<BearerToken> {
+                        version: task_config.version,
+                        task_id: Some(task_id),
+                        ..Default::default()
+                    },
+                    Some(task_id),
+                )
+                .await?;
+                let signature = self
+                    .hpke_config_signer
+                    .sign(&SystemRandom::new(), &resp.payload)
+                    .unwrap();
+                return Ok(DapHttpResponse {
+                    status: 200,
+                    headers: vec![(
+                        "x-hpke-config-signature".into(),
+                        encode_base64url(signature),
+                    )],
+                    body: resp.payload,
+                });
+            };
+
+            let task_id = TaskId::try_from_base64url(task_id).unwrap();
+            let task_config = aggregator.unchecked_get_task_config(&task_id).await;
+            assert_eq!((url_match.value)(task_config.version), req.method);
+            let Some(coll_job_id) = url_match.params.get("coll_job_id") else {
+                // Report upload
+                if self.leader_unavailable.load(Ordering::Relaxed) {
+                    return Ok(DapHttpResponse {
+                        status: 503,
+                        ..Default::default()
+                    });
+                }
+                return match leader::handle_upload_req(
+                    &**aggregator,
+                    &DapRequest {
+                        version: task_config.version,
+                        media_type: Some(DapMediaType::Report),
+                        task_id: Some(task_id),
+                        resource: DapResource::Undefined,
+                        payload: req.body,
+                        ..Default::default()
+                    },
+                )
+                .await
+                {
+                    Ok(()) => Ok(DapHttpResponse {
+                        status: 200,
+                        ..Default::default()
+                    }),
+                    Err(DapError::Abort(_)) => Ok(DapHttpResponse {
+                        status: 400,
+                        ..Default::default()
+                    }),
+                    Err(e) => Err(e),
+                };
+            };
+            let coll_job_id = CollectionJobId::try_from_base64url(coll_job_id).unwrap();
+
+            match req.method {
+                DapHttpMethod::Put => {
+                    let sender_auth = req
+                        .headers
+                        .iter()
+                        .find(|(name, _)| name == "dap-auth-token")
+                        .map(|(_, token)| BearerToken::from(token.as_str()));
+                    let coll_job_uri = leader::handle_coll_job_req(
+                        &**aggregator,
+                        &DapRequest {
+                            version: task_config.version,
+                            media_type: Some(DapMediaType::CollectReq),
+                            task_id: Some(task_id),
+                            resource: DapResource::CollectionJob(coll_job_id),
+                            payload: req.body,
+                            sender_auth,
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+                    Ok(DapHttpResponse {
+                        status: 201,
+                        body: serde_json::to_vec(&coll_job_uri).unwrap(),
+                        ..Default::default()
+                    })
+                }
+                DapHttpMethod::Post | DapHttpMethod::Get => {
+                    match aggregator.poll_collect_job(&task_id, &coll_job_id).await? {
+                        DapCollectionJob::Done(collection) => Ok(DapHttpResponse {
+                            status: 200,
+                            body: collection
+                                .get_encoded_with_param(&task_config.version)
+                                .unwrap(),
+                            ..Default::default()
+                        }),
+                        DapCollectionJob::Pending => Ok(DapHttpResponse {
+                            status: 202,
+                            headers: vec![("Retry-After".into(), "5".into())],
+                            ..Default::default()
+                        }),
+                        DapCollectionJob::Unknown | DapCollectionJob::Failed(_) => {
+                            Ok(DapHttpResponse {
+                                status: 400,
+                                ..Default::default()
+                            })
+                        }
+                    }
+                }
+                DapHttpMethod::Delete => unreachable!(),
+            }
+        }
+
+        async fn sleep(&self, duration: std::time::Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+            leader::process(&*self.leader, "leader.com", 100)
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn e2e_collector(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Client: Send upload request to Leader.
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        let collector = DapCollector {
+            transport: InMemoryTransport::new(&t),
+            auth: t.collector_token.clone(),
+            decrypter: t.collector_hpke_receiver_config.clone(),
+            leader_url: task_config.leader_url.clone(),
+            task_id: *task_id,
+            version: task_config.version,
+            vdaf: task_config.vdaf,
+            min_batch_size: task_config.min_batch_size,
+            poll_policy: DapPollPolicy::default(),
+        };
+
+        // Collector: The collection job is pending until the Leader processes it.
+        let query = task_config.query_for_current_batch_window(t.now);
+        let job = collector
+            .start(query, DapAggregationParam::Empty)
+            .await
+            .unwrap();
+        assert_matches!(
+            collector.poll(&job).await.unwrap(),
+            DapCollectionPoll::Pending { retry_after: Some(d) } if d.as_secs() == 5
+        );
+
+        let collection = collector.wait(&job).await.unwrap();
+        assert_eq!(collection.report_count, 1);
+        assert_eq!(
+            *collector.transport.sleeps.lock().unwrap(),
+            [std::time::Duration::from_secs(5)]
+        );
+
+        assert_eq!(
+            collector.consume(&job, collection).await.unwrap(),
+            DapAggregateResult::U64(1)
+        );
+    }
+
+    async_test_versions! { e2e_collector }
+
+    fn test_client(
+        t: &Test,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> DapClient<InMemoryTransport> {
+        let transport = InMemoryTransport::new(t);
+        let signing_key = transport.signing_key();
+        DapClient::new(
+            transport,
+            task_config.leader_url.clone(),
+            task_config.helper_url.clone(),
+            *task_id,
+            task_config.version,
+            task_config.vdaf,
+            task_config.time_precision,
+        )
+        .with_signing_keys(signing_key.clone(), signing_key)
+    }
+
+    async fn e2e_client(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Client: Upload reports to the Leader.
+        let client = test_client(&t, task_id, &task_config);
+        for _ in 0..2 {
+            assert!(client.upload(t.now, DapMeasurement::U64(1)).await.unwrap());
+        }
+        assert_eq!(client.queued_reports().unwrap(), 0);
+
+        // Collector: Collect the aggregate result.
+        let collector = DapCollector {
+            transport: client.transport,
+            auth: t.collector_token.clone(),
+            decrypter: t.collector_hpke_receiver_config.clone(),
+            leader_url: task_config.leader_url.clone(),
+            task_id: *task_id,
+            version: task_config.version,
+            vdaf: task_config.vdaf,
+            min_batch_size: task_config.min_batch_size,
+            poll_policy: DapPollPolicy::default(),
+        };
+        let query = task_config.query_for_current_batch_window(t.now);
+        assert_eq!(
+            collector
+                .collect(query, DapAggregationParam::Empty)
+                .await
+                .unwrap(),
+            DapAggregateResult::U64(2)
+        );
+    }
+
+    async_test_versions! { e2e_client }
+
+    async fn client_hpke_config_signature_not_verified(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Expect the Client to reject HPKE configs signed by a different key.
+        let client = test_client(&t, task_id, &task_config);
+        let other_signing_key = InMemoryTransport::new(&t).signing_key();
+        let client = client.with_signing_keys(other_signing_key.clone(), other_signing_key);
+        assert_matches!(
+            client.upload(t.now, DapMeasurement::U64(1)).await,
+            Err(DapError::Fatal(..))
+        );
+        assert_eq!(client.queued_reports().unwrap(), 0);
+    }
+
+    async_test_versions! { client_hpke_config_signature_not_verified }
+
+    async fn client_time_precision_zero(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let mut client = test_client(&t, task_id, &task_config);
+        client.time_precision = 0;
+        assert_matches!(
+            client.upload(t.now, DapMeasurement::U64(1)).await,
+            Err(DapError::Fatal(..))
+        );
+        assert_eq!(client.queued_reports().unwrap(), 0);
+    }
+
+    async_test_versions! { client_time_precision_zero }
+
+    async fn client_queues_reports_while_leader_unavailable(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let client = test_client(&t, task_id, &task_config).with_retry_policy(DapRetryPolicy {
+            max_attempts: 3,
+            ..Default::default()
+        });
+        client
+            .transport
+            .leader_unavailable
+            .store(true, Ordering::Relaxed);
+
+        // Expect the report to be queued after retrying with backoff.
+        assert!(!client.upload(t.now, DapMeasurement::U64(1)).await.unwrap());
+        assert_eq!(client.queued_reports().unwrap(), 1);
+        assert_eq!(
+            *client.transport.sleeps.lock().unwrap(),
+            [
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(2)
+            ]
+        );
+        assert_eq!(client.flush_queued_reports().await.unwrap(), 0);
+        assert_eq!(client.queued_reports().unwrap(), 1);
+
+        // Expect the report to be uploaded once the Leader is available again.
+        client
+            .transport
+            .leader_unavailable
+            .store(false, Ordering::Relaxed);
+        assert_eq!(client.flush_queued_reports().await.unwrap(), 1);
+        assert_eq!(client.queued_reports().unwrap(), 0);
+    }
+
+    async_test_versions! { client_queues_reports_while_leader_unavailable }
+
+    async fn e2e_taskprov(
+        version: DapVersion,
+        vdaf_config: VdafConfig,
+        test_measurement: DapMeasurement,
+    ) {
+        let t = Test::new(version);
+
+        let (task_config, task_id, taskprov_advertisement) = DapTaskParameters {
+            version,
+            min_batch_size: 1,
+            query: DapQueryConfig::FixedSize {
+                max_batch_size: Some(2),
             },
             vdaf: vdaf_config,
             ..Default::default()
@@ -1666,6 +3017,8 @@ mod test {
             r#"report_counter{env="test_leader",host="leader.com",status="aggregated"}"#: 1,
             r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 1,
         });
+        assert_eq!(t.leader.audit_log.taskprov_opt_ins(), 1);
+        assert_eq!(t.helper.audit_log.taskprov_opt_ins(), 1);
     }
 
     async fn e2e_taskprov_prio2(version: DapVersion) {
@@ -1818,4 +3171,89 @@ mod test {
             r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 10,
         });
     }
+
+    #[tokio::test]
+    async fn heavy_hitters_helper_rejects_query_with_different_agg_param() {
+        let t = Test::new(DapVersion::Latest);
+        let task_id = &t.heavy_hitters_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let agg_param = |prefixes: &[&[u8]]| {
+            DapAggregationParam::Mastic(
+                Poplar1AggregationParam::try_from_prefixes(
+                    prefixes.iter().map(|p| IdpfInput::from_bytes(p)).collect(),
+                )
+                .unwrap(),
+            )
+        };
+
+        for i in 0..10 {
+            let report = t
+                .gen_test_report_for_measurement(
+                    task_id,
+                    DapMeasurement::Mastic {
+                        input: vec![i],
+                        weight: MasticWeight::Bool(true),
+                    },
+                )
+                .await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
+        }
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let Query::TimeInterval { batch_interval } = query else {
+            unreachable!("unexpected query: {query:?}");
+        };
+        leader::handle_coll_job_req(
+            &*t.leader,
+            &t.gen_test_coll_job_req_for_collection(query, agg_param(&[&[0], &[1]]), task_id)
+                .await,
+        )
+        .await
+        .unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        let batch_sel = BatchSelector::TimeInterval { batch_interval };
+        let agg_share = t.helper.get_agg_share(task_id, &batch_sel).await.unwrap();
+        let agg_share_req = |agg_param: DapAggregationParam| AggregateShareReq {
+            batch_sel: batch_sel.clone(),
+            agg_param: agg_param.get_encoded().unwrap(),
+            report_count: agg_share.report_count,
+            checksum: agg_share.checksum,
+        };
+
+        // A retransmission of the Leader's request is handled like the original.
+        let req = t
+            .leader_authorized_req(
+                task_id,
+                &task_config,
+                None,
+                DapMediaType::AggregateShareReq,
+                agg_share_req(agg_param(&[&[0], &[1]])),
+            )
+            .await;
+        helper::handle_agg_share_req(&*t.helper, &req)
+            .await
+            .unwrap();
+
+        // Querying the batch again with a different aggregation parameter is rejected.
+        let req = t
+            .leader_authorized_req(
+                task_id,
+                &task_config,
+                None,
+                DapMediaType::AggregateShareReq,
+                agg_share_req(agg_param(&[&[7]])),
+            )
+            .await;
+        assert_matches!(
+            helper::handle_agg_share_req(&*t.helper, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchOverlap { .. })
+        );
+    }
 }
@@ -15,7 +15,7 @@ use crate::{
     },
     vdaf::VdafVerifyKey,
     DapAbort, DapError, DapQueryConfig, DapRequest, DapTaskConfig, DapTaskConfigMethod, DapVersion,
-    Prio3Config, VdafConfig,
+    Prio3Config, VdafConfig, DAP_MAX_BATCH_QUERY_COUNT,
 };
 use prio::codec::ParameterizedDecode;
 use ring::{
@@ -98,7 +98,7 @@ fn malformed_task_config(task_id: &TaskId, detail: String) -> DapAbort {
 /// Convert a task config advertised by the peer into a [`DapTaskConfig`].
 ///
 /// The `task_id` is the task ID indicated by the request; if this does not match the derived task
-/// ID, then we return `Err(DapError::Abort(DapAbort::UnrecognizedTask))`.
+/// ID, then we return `Err(DapError::Abort(DapAbort::UnrecognizedTask { .. }))`.
 pub fn resolve_advertised_task_config<S>(
     req: &'_ DapRequest<S>,
     verify_key_init: &[u8; 32],
@@ -138,7 +138,7 @@ fn get_taskprov_task_config<S>(
 
     if compute_task_id(taskprov_data.as_ref()) != *task_id {
         // Return unrecognizedTask following section 5.1 of the taskprov draft.
-        return Err(DapAbort::UnrecognizedTask);
+        return Err(DapAbort::UnrecognizedTask { task_id: *task_id });
     }
 
     // Return unrecognizedMessage if parsing fails following section 5.1 of the taskprov draft.
@@ -253,7 +253,7 @@ impl DapTaskConfig {
         }
 
         // Only one query per batch is currently supported.
-        if task_config.query_config.max_batch_query_count != 1 {
+        if task_config.query_config.max_batch_query_count != DAP_MAX_BATCH_QUERY_COUNT {
             return Err(DapAbort::InvalidTask {
                 detail: format!(
                     "unsupported max batch query count {}",
@@ -280,6 +280,7 @@ impl DapTaskConfig {
             method: DapTaskConfigMethod::Taskprov {
                 info: Some(task_config.task_info),
             },
+            privacy_pass: None,
         })
     }
 }
@@ -503,6 +504,7 @@ mod test {
                 payload: Vec::default(),          // ignored by test
                 sender_auth: None,                // ignored by test
                 taskprov: Some(taskprov_task_config_base64url),
+                privacy_pass_token: None, // ignored by test
             };
 
             (req, task_id)
@@ -4,9 +4,13 @@
 //! Mock backend functionality to test DAP protocol.
 
 use crate::{
-    audit_log::{AggregationJobAuditAction, AuditLog},
+    audit_log::{
+        AggregationJobAuditAction, AuditLog, CollectionJobAuditAction, HpkeConfigAuditAction,
+        TaskprovAuditAction,
+    },
     auth::{BearerToken, BearerTokenProvider},
     constants::DapMediaType,
+    error::{PeerAbort, DEFAULT_RETRYABLE_STATUSES},
     fatal_error,
     hpke::{HpkeConfig, HpkeDecrypter, HpkeKemId, HpkeProvider, HpkeReceiverConfig},
     messages::{
@@ -15,16 +19,18 @@ use crate::{
         ReportId, TaskId, Time, TransitionFailure,
     },
     metrics::{prometheus::DaphnePromMetrics, DaphneMetrics},
+    privacy_pass::PrivacyPassToken,
     protocol::aggregator::{EarlyReportStateConsumed, EarlyReportStateInitialized},
     roles::{
         aggregator::MergeAggShareError,
-        helper,
+        helper::{self, AggregationJobRecord},
         leader::{in_memory_leader::InMemoryLeaderState, WorkItem},
         DapAggregator, DapAuthorizedSender, DapHelper, DapLeader, DapReportInitializer,
     },
     DapAbort, DapAggregateResult, DapAggregateShare, DapAggregateSpan, DapAggregationJobState,
-    DapAggregationParam, DapBatchBucket, DapCollectionJob, DapError, DapGlobalConfig,
-    DapMeasurement, DapQueryConfig, DapRequest, DapResponse, DapTaskConfig, DapVersion, VdafConfig,
+    DapAggregationParam, DapBatchBucket, DapCollectedBatches, DapCollectionJob, DapError,
+    DapGlobalConfig, DapMeasurement, DapQueryConfig, DapRequest, DapResponse, DapTaskConfig,
+    DapVersion, VdafConfig,
 };
 use async_trait::async_trait;
 use deepsize::DeepSizeOf;
@@ -147,6 +153,7 @@ impl AggregationJobTest {
                 vdaf_verify_key,
                 collector_hpke_config,
                 method: Default::default(),
+                privacy_pass: None,
             },
             leader_registry,
             leader_metrics,
@@ -436,14 +443,43 @@ impl From<DapBatchBucket> for PartialBatchSelector {
     }
 }
 
+/// Counts the events recorded in the audit log.
 #[derive(Default)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
-pub struct MockAuditLog(AtomicU32);
+pub struct MockAuditLog {
+    agg_jobs: AtomicU32,
+    coll_jobs_created: AtomicU32,
+    coll_jobs_completed: AtomicU32,
+    agg_shares: AtomicU32,
+    taskprov_opt_ins: AtomicU32,
+    unauthorized_requests: AtomicU32,
+}
 
+#[allow(dead_code)]
 impl MockAuditLog {
-    #[allow(dead_code)]
+    /// Number of aggregation jobs recorded.
     pub(crate) fn invocations(&self) -> u32 {
-        self.0.load(Ordering::Relaxed)
+        self.agg_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Number of collection jobs created and completed.
+    pub(crate) fn coll_jobs(&self) -> (u32, u32) {
+        (
+            self.coll_jobs_created.load(Ordering::Relaxed),
+            self.coll_jobs_completed.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn agg_shares(&self) -> u32 {
+        self.agg_shares.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn taskprov_opt_ins(&self) -> u32 {
+        self.taskprov_opt_ins.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn unauthorized_requests(&self) -> u32 {
+        self.unauthorized_requests.load(Ordering::Relaxed)
     }
 }
 
@@ -456,7 +492,66 @@ impl AuditLog for MockAuditLog {
         _report_count: u64,
         _action: AggregationJobAuditAction,
     ) {
-        self.0.fetch_add(1, Ordering::Relaxed);
+        self.agg_jobs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_collection_job(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _coll_job_id: &CollectionJobId,
+        _batch_sel: &BatchSelector,
+        action: CollectionJobAuditAction,
+    ) {
+        match action {
+            CollectionJobAuditAction::Create => &self.coll_jobs_created,
+            CollectionJobAuditAction::Complete { .. } => &self.coll_jobs_completed,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_aggregate_share(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _batch_sel: &BatchSelector,
+        _report_count: u64,
+    ) {
+        self.agg_shares.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_taskprov(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        action: TaskprovAuditAction<'_>,
+    ) {
+        if let TaskprovAuditAction::OptIn = action {
+            self.taskprov_opt_ins.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_unauthorized_request(
+        &self,
+        _host: &str,
+        _task_id: &TaskId,
+        _version: DapVersion,
+        _media_type: Option<DapMediaType>,
+        _reason: &str,
+    ) {
+        self.unauthorized_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_hpke_config(
+        &self,
+        _host: &str,
+        _version: DapVersion,
+        _config_id: u8,
+        _action: HpkeConfigAuditAction,
+    ) {
     }
 }
 
@@ -476,6 +571,10 @@ pub struct AggregateStore {
     /// already in this set, it will be rejected.
     pub reports: HashSet<ReportId>,
 
+    /// The aggregation jobs merged into the current aggregate share. Merging an aggregation job
+    /// again leaves the aggregate share unchanged.
+    pub agg_job_ids: HashSet<AggregationJobId>,
+
     /// The current aggregation parameter.
     pub(crate) agg_param: DapAggregationParam,
 }
@@ -528,6 +627,7 @@ impl AggregateStore {
             self.agg_share.reset();
             self.collected = false;
             self.reports.clear();
+            self.agg_job_ids.clear();
             self.agg_param = agg_param.clone();
         }
 
@@ -554,11 +654,19 @@ impl InMemoryAggregateStore {
                 agg_share: Default::default(),
                 collected: false,
                 reports: Default::default(),
+                agg_job_ids: Default::default(),
                 agg_param: agg_param.clone(),
             });
 
         agg_store.goto(agg_param)
     }
+
+    /// Delete the aggregate stores of a task, e.g., as if they were garbage collected.
+    #[cfg(test)]
+    pub(crate) fn delete_task(&mut self, task_id: &TaskId) {
+        let prefix = format!("{task_id}/");
+        self.0.retain(|key, _| !key.starts_with(&prefix));
+    }
 }
 
 /// An implementation of a DAP Aggregator without long-term storage. This is intended to be used
@@ -570,8 +678,9 @@ pub struct InMemoryAggregator {
     pub leader_token: BearerToken,
     pub collector_token: Option<BearerToken>, // Not set by Helper
     pub(crate) leader_state_store: Arc<Mutex<InMemoryLeaderState>>,
-    helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, DapAggregationJobState>>>,
+    agg_job_record_store: Arc<Mutex<HashMap<AggregationJobInfo, AggregationJobRecord>>>,
     pub(crate) agg_store: Arc<Mutex<InMemoryAggregateStore>>,
+    collected_batches: Arc<Mutex<HashMap<TaskId, DapCollectedBatches>>>,
     pub collector_hpke_config: HpkeConfig,
     pub metrics: DaphnePromMetrics,
     pub(crate) audit_log: MockAuditLog,
@@ -595,8 +704,9 @@ impl DeepSizeOf for InMemoryAggregator {
                     .deep_size_of_children(context)
                 + self.leader_token.deep_size_of_children(context)
                 + self.collector_token.deep_size_of_children(context)
-                + self.helper_state_store.deep_size_of_children(context)
+                + self.agg_job_record_store.deep_size_of_children(context)
                 + self.agg_store.deep_size_of_children(context)
+                + self.collected_batches.deep_size_of_children(context)
                 + self.collector_hpke_config.deep_size_of_children(context)
                 // + self.metrics.deep_size_of_children(context)
                 // + self.audit_log.deep_size_of_children(context)
@@ -628,8 +738,9 @@ impl InMemoryAggregator {
             leader_token,
             collector_token: None,
             leader_state_store: Default::default(),
-            helper_state_store: Default::default(),
+            agg_job_record_store: Default::default(),
             agg_store: Default::default(),
+            collected_batches: Default::default(),
             collector_hpke_config,
             metrics: DaphnePromMetrics::register(registry).unwrap(),
             audit_log: MockAuditLog::default(),
@@ -661,8 +772,9 @@ impl InMemoryAggregator {
             leader_token,
             collector_token: collector_token.into(),
             leader_state_store: Default::default(),
-            helper_state_store: Default::default(),
+            agg_job_record_store: Default::default(),
             agg_store: Default::default(),
+            collected_batches: Default::default(),
             collector_hpke_config,
             metrics: DaphnePromMetrics::register(registry).unwrap(),
             audit_log: MockAuditLog::default(),
@@ -876,7 +988,9 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
-            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: *task_id,
+            }))?;
         let mut agg_store = self.agg_store.lock().map_err(|e| fatal_error!(err = ?e))?;
         // TODO heavy hitters: Replace this with the agg param specified by the Collector.
         let agg_param = DapAggregationParam::Empty;
@@ -891,7 +1005,12 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
             }
         }
 
-        Ok(false)
+        Ok(self
+            .collected_batches
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .get(task_id)
+            .is_some_and(|collected| collected.is_overlapping(batch_sel)))
     }
 
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError> {
@@ -925,6 +1044,7 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
         &self,
         task_id: &TaskId,
         _task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
         agg_span: DapAggregateSpan<DapAggregateShare>,
     ) -> DapAggregateSpan<Result<(), MergeAggShareError>> {
         let mut agg_store = self.agg_store.lock().unwrap();
@@ -938,6 +1058,10 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
                     todo!("TODO heavy hitters: reject all reports in the bucket due to invalid aggregation parameter");
                 };
 
+                if agg_store_for_bucket.agg_job_ids.contains(agg_job_id) {
+                    return (bucket, (Ok(()), report_metadatas));
+                }
+
                 let replayed = report_metadatas
                     .iter()
                     .map(|(id, _)| *id)
@@ -948,6 +1072,7 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
                     agg_store_for_bucket
                         .reports
                         .extend(report_metadatas.iter().map(|(id, _)| *id));
+                    agg_store_for_bucket.agg_job_ids.insert(*agg_job_id);
                     // Add to aggregate share.
                     if agg_store_for_bucket.collected {
                         Err(MergeAggShareError::AlreadyCollected)
@@ -989,9 +1114,6 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
                         "unexpected aggregation parameter".to_string(),
                     ))
                 })?;
-            if agg_store_for_bucket.collected {
-                return Err(DapError::Abort(DapAbort::batch_overlap(task_id, batch_sel)));
-            }
             agg_share.merge(agg_store_for_bucket.agg_share.clone())?;
         }
 
@@ -1002,8 +1124,20 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
-    ) -> Result<(), DapError> {
+        request_hash: &[u8; 32],
+    ) -> Result<bool, DapError> {
         let task_config = self.unchecked_get_task_config(task_id).await;
+        if !self
+            .collected_batches
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .entry(*task_id)
+            .or_default()
+            .insert(batch_sel, request_hash)
+        {
+            return Ok(false);
+        }
+
         let mut agg_store = self.agg_store.lock().map_err(|e| fatal_error!(err = ?e))?;
         // TODO heavy hitters: Replace this with the agg param specified by the Collector.
         let agg_param = DapAggregationParam::Empty;
@@ -1019,7 +1153,7 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
                 .collected = true;
         }
 
-        Ok(())
+        Ok(true)
     }
 
     fn metrics(&self) -> &dyn DaphneMetrics {
@@ -1037,57 +1171,77 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
 
 #[async_trait]
 impl DapHelper<BearerToken> for InMemoryAggregator {
-    async fn put_helper_state_if_not_exists(
+    async fn put_agg_job_record_if_not_exists(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-        helper_state: &DapAggregationJobState,
+        record: &AggregationJobRecord,
     ) -> Result<bool, DapError> {
-        let helper_state_info = HelperStateInfo {
+        let agg_job_info = AggregationJobInfo {
             task_id: *task_id,
             agg_job_id: *agg_job_id,
         };
 
-        let mut helper_state_store = self
-            .helper_state_store
+        let mut agg_job_record_store = self
+            .agg_job_record_store
             .lock()
             .map_err(|e| fatal_error!(err = ?e))?;
 
-        if helper_state_store.contains_key(&helper_state_info) {
+        if agg_job_record_store.contains_key(&agg_job_info) {
             return Ok(false);
         }
 
-        // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
-        // For VDAFs with more rounds, the helper state blob will need to be updated here.
-        helper_state_store.insert(helper_state_info, helper_state.clone());
+        agg_job_record_store.insert(agg_job_info, record.clone());
 
         Ok(true)
     }
 
-    async fn get_helper_state(
+    async fn put_agg_job_record(
         &self,
         task_id: &TaskId,
         agg_job_id: &AggregationJobId,
-    ) -> Result<Option<DapAggregationJobState>, DapError> {
-        let helper_state_info = HelperStateInfo {
+        record: &AggregationJobRecord,
+    ) -> Result<(), DapError> {
+        let agg_job_info = AggregationJobInfo {
             task_id: *task_id,
             agg_job_id: *agg_job_id,
         };
 
-        let helper_state_store = self
-            .helper_state_store
+        self.agg_job_record_store
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .insert(agg_job_info, record.clone());
+
+        Ok(())
+    }
+
+    async fn get_agg_job_record(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobRecord>, DapError> {
+        let agg_job_info = AggregationJobInfo {
+            task_id: *task_id,
+            agg_job_id: *agg_job_id,
+        };
+
+        let agg_job_record_store = self
+            .agg_job_record_store
             .lock()
             .map_err(|e| fatal_error!(err = ?e))?;
 
-        // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
-        // For VDAFs with more rounds, the helper state blob will need to be updated here.
-        Ok(helper_state_store.get(&helper_state_info).cloned())
+        Ok(agg_job_record_store.get(&agg_job_info).cloned())
     }
 }
 
 #[async_trait]
 impl DapLeader<BearerToken> for InMemoryAggregator {
-    async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError> {
+    async fn put_report(
+        &self,
+        report: &Report,
+        task_id: &TaskId,
+        privacy_pass_token: Option<&PrivacyPassToken>,
+    ) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
@@ -1096,7 +1250,12 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
         self.leader_state_store
             .lock()
             .map_err(|e| fatal_error!(err = ?e))?
-            .put_report(task_id, &task_config, report.clone())
+            .put_report(
+                task_id,
+                &task_config,
+                report.clone(),
+                privacy_pass_token.map(|token| &token.nonce),
+            )
     }
 
     async fn current_batch(&self, task_id: &TaskId) -> std::result::Result<BatchId, DapError> {
@@ -1130,6 +1289,45 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
         Ok(())
     }
 
+    async fn put_dead_letters(&self, items: Vec<WorkItem>) -> Result<(), DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .put_dead_letters(items);
+        Ok(())
+    }
+
+    async fn finish_agg_job(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        aggregated: u64,
+        rejected: u64,
+    ) -> Result<(), DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or_else(|| fatal_error!(err = "task not found"))?;
+
+        self.leader_state_store
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .finish_agg_job(task_id, &task_config, part_batch_sel, aggregated, rejected);
+        Ok(())
+    }
+
+    async fn has_failed_agg_jobs(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError> {
+        Ok(self
+            .leader_state_store
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .has_failed_agg_jobs(task_id, batch_sel))
+    }
+
     // Called after receiving a CollectReq from Collector.
     async fn init_collect_job(
         &self,
@@ -1143,10 +1341,18 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
             .await?
             .ok_or_else(|| fatal_error!(err = "task not found"))?;
 
+        let now = self.get_current_time();
         self.leader_state_store
             .lock()
             .map_err(|e| fatal_error!(err = ?e))?
-            .init_collect_job(task_id, &task_config, coll_job_id, batch_sel, agg_param)
+            .init_collect_job(
+                task_id,
+                &task_config,
+                coll_job_id,
+                batch_sel,
+                agg_param,
+                now,
+            )
     }
 
     async fn poll_collect_job(
@@ -1172,24 +1378,38 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
             .finish_collect_job(task_id, coll_job_id, collection)
     }
 
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        failure: &PeerAbort,
+    ) -> Result<(), DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|e| fatal_error!(err = ?e))?
+            .fail_collect_job(task_id, coll_job_id, failure)
+    }
+
     async fn send_http_post(
         &self,
         req: DapRequest<BearerToken>,
         _url: Url,
     ) -> Result<DapResponse, DapError> {
         match req.media_type {
-            Some(DapMediaType::AggregationJobInitReq) => Ok(helper::handle_agg_job_req(
-                &**self.peer.as_ref().expect("peer not configured"),
-                &req,
-            )
-            .await
-            .expect("peer aborted unexpectedly")),
+            Some(DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq) => {
+                Ok(helper::handle_agg_job_req(
+                    &**self.peer.as_ref().expect("peer not configured"),
+                    &req,
+                )
+                .await
+                .map_err(peer_abort)?)
+            }
             Some(DapMediaType::AggregateShareReq) => Ok(helper::handle_agg_share_req(
                 &**self.peer.as_ref().expect("peer not configured"),
                 &req,
             )
             .await
-            .expect("peer aborted unexpectedly")),
+            .map_err(peer_abort)?),
             _ => unreachable!("unhandled media type: {:?}", req.media_type),
         }
     }
@@ -1205,17 +1425,32 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
                 &req,
             )
             .await
-            .expect("peer aborted unexpectedly"))
+            .map_err(peer_abort)?)
         } else {
             unreachable!("unhandled media type: {:?}", req.media_type)
         }
     }
 }
 
-/// Information associated to a certain helper state for a given task ID and aggregate job ID.
+/// The Leader's view of an error returned by the Helper, i.e., the problem details in the
+/// Helper's response.
+fn peer_abort(e: DapError) -> DapError {
+    let status = if matches!(e, DapError::Abort(..)) {
+        400
+    } else {
+        500
+    };
+    DapError::Peer(PeerAbort::from_problem_details(
+        status,
+        e.into_problem_details(),
+        DEFAULT_RETRYABLE_STATUSES,
+    ))
+}
+
+/// Identifies the record of an aggregation job by its task ID and aggregation job ID.
 #[derive(Clone, Eq, Hash, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
-struct HelperStateInfo {
+struct AggregationJobInfo {
     task_id: TaskId,
     agg_job_id: AggregationJobId,
 }
@@ -0,0 +1,166 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Pluggable HTTP transport and authorization for DAP clients, i.e., the
+//! [`DapClient`](crate::client::DapClient) and [`DapCollector`](crate::collector::DapCollector).
+//! Daphne does not depend on any particular HTTP stack or async runtime: Applications provide an
+//! implementation of [`DapHttpTransport`] that wraps whichever they use.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use url::Url;
+
+use crate::{
+    auth::BearerToken,
+    error::{PeerAbort, DEFAULT_RETRYABLE_STATUSES},
+    messages::TaskId,
+    DapError, DapVersion,
+};
+
+/// Header in which a bearer token is sent.
+const DAP_AUTH_TOKEN: &str = "dap-auth-token";
+
+/// HTTP request method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DapHttpMethod {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl DapHttpMethod {
+    /// Return the method with which a Client uploads a report to the Leader.
+    pub fn for_upload(version: DapVersion) -> Self {
+        match version {
+            DapVersion::Draft09 => Self::Put,
+            DapVersion::Latest => Self::Post,
+        }
+    }
+
+    /// Return the method with which the Collector polls a collection job.
+    pub fn for_collection_poll(version: DapVersion) -> Self {
+        match version {
+            DapVersion::Draft09 => Self::Post,
+            DapVersion::Latest => Self::Get,
+        }
+    }
+}
+
+/// An HTTP request sent by a DAP client.
+#[derive(Clone, Debug)]
+pub struct DapHttpRequest {
+    pub method: DapHttpMethod,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// An HTTP response received by a DAP client.
+#[derive(Clone, Debug, Default)]
+pub struct DapHttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl DapHttpResponse {
+    /// Return the value of the first header with the given name. Header names are compared
+    /// case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Return the delay indicated by the "Retry-After" header, if any. Only the delay-seconds form
+    /// of the header is supported.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.header("retry-after")?
+            .trim()
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+    }
+
+    /// Return an error unless the response status is one of `expected`. If the peer responded
+    /// with a problem document, then it is included in the error.
+    pub fn expect_status(&self, expected: &[u16]) -> Result<(), DapError> {
+        if expected.contains(&self.status) {
+            return Ok(());
+        }
+
+        Err(DapError::Peer(PeerAbort::from_response(
+            self.status,
+            self.header("content-type"),
+            &self.body,
+            DEFAULT_RETRYABLE_STATUSES,
+        )))
+    }
+}
+
+/// How to retry a request that did not succeed, e.g., uploading a report.
+#[derive(Clone, Debug)]
+pub struct DapRetryPolicy {
+    /// Delay before the first retry. The delay is doubled after each attempt, unless the peer
+    /// indicates a delay via the "Retry-After" header.
+    pub initial_delay: Duration,
+
+    /// Upper bound on the delay between attempts.
+    pub max_delay: Duration,
+
+    /// Number of attempts before giving up.
+    pub max_attempts: u32,
+}
+
+impl Default for DapRetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 30,
+        }
+    }
+}
+
+impl DapRetryPolicy {
+    /// Return the delay to use after the given one.
+    pub fn next_delay(&self, delay: Duration) -> Duration {
+        std::cmp::min(delay * 2, self.max_delay)
+    }
+}
+
+/// An HTTP transport used by DAP clients to talk to the Aggregators.
+#[async_trait]
+pub trait DapHttpTransport: Sync {
+    /// Send an HTTP request and return the response. An error should only be returned if no
+    /// response was received; responses with an unsuccessful status are handled by the caller.
+    async fn send(&self, req: DapHttpRequest) -> Result<DapHttpResponse, DapError>;
+
+    /// Wait for the given duration before sending the next request.
+    async fn sleep(&self, duration: Duration);
+}
+
+/// Authorization attached to the requests sent by a DAP client.
+#[async_trait]
+pub trait DapHttpAuth: Sync {
+    /// Return the headers with which to authorize a request pertaining to the given task.
+    async fn headers(&self, task_id: &TaskId) -> Result<Vec<(String, String)>, DapError>;
+}
+
+#[async_trait]
+impl DapHttpAuth for BearerToken {
+    async fn headers(&self, _task_id: &TaskId) -> Result<Vec<(String, String)>, DapError> {
+        Ok(vec![(DAP_AUTH_TOKEN.into(), self.as_str().into())])
+    }
+}
+
+/// No authorization, e.g., for uploading reports.
+#[async_trait]
+impl DapHttpAuth for () {
+    async fn headers(&self, _task_id: &TaskId) -> Result<Vec<(String, String)>, DapError> {
+        Ok(Vec::new())
+    }
+}